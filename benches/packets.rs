@@ -0,0 +1,80 @@
+// Packets per second of decoding requests, matching them against claimed addresses and
+// constructing replies. Run with `cargo bench`.
+use claim_ip::arp::Arp;
+use claim_ip::ether::{self, Header};
+use claim_ip::net::{ClaimSet, IpNet};
+use eui48::MacAddress;
+use std::convert::TryFrom;
+use std::hint::black_box;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+// Repeat f until a second has passed and print how many times per second it ran
+fn bench(name: &str, mut f: impl FnMut()) {
+    for _ in 0..1000 {
+        f();
+    }
+    let start = Instant::now();
+    let mut n = 0u64;
+    while start.elapsed() < Duration::from_secs(1) {
+        for _ in 0..1000 {
+            f();
+        }
+        n += 1000;
+    }
+    let pps = n as f64 / start.elapsed().as_secs_f64();
+    println!("{:<24} {:>8.2} Mpps", name, pps / 1e6);
+}
+
+fn main() {
+    let sha = MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+    let mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    let request = Arp::request(sha, Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 1, 2, 3));
+    let frame = {
+        let header = Header {
+            dst: MacAddress::broadcast(),
+            src: sha,
+            vlan: Some(ether::Vlan {
+                outer: None,
+                id: 100,
+            }),
+            ethertype: 0x0806,
+            snap: false,
+        };
+        let mut frame = vec![0u8; header.size()];
+        header.fill(&mut frame).unwrap();
+        frame.extend(request.to_vec());
+        frame
+    };
+
+    bench("decode", || {
+        let header = Header::try_from(black_box(&frame[..])).unwrap();
+        black_box(Arp::try_from(&frame[header.size()..]).unwrap());
+    });
+
+    // Every other address of a /16, as 32768 separately listed addresses
+    let claimed: Vec<IpNet> = (0..=u16::MAX)
+        .step_by(2)
+        .map(|n| IpNet::from(IpAddr::V4(Ipv4Addr::from(0x0a01_0000 | u32::from(n)))))
+        .collect();
+    let claimed = ClaimSet::new(&claimed, &[]);
+    let mut n = 0u32;
+    bench("match 32768 addresses", || {
+        n = n.wrapping_add(1);
+        black_box(claimed.contains(IpAddr::V4(Ipv4Addr::from(0x0a01_0000 | (n & 0xffff)))));
+    });
+
+    let mut buf = [0u8; 64];
+    bench("reply", || {
+        let reply = black_box(&request).reply(mac).unwrap();
+        let header = Header {
+            dst: reply.tha,
+            src: mac,
+            vlan: None,
+            ethertype: 0x0806,
+            snap: false,
+        };
+        let len = header.fill(&mut buf).unwrap().len();
+        black_box(reply.fill(&mut buf[len..]).unwrap());
+    });
+}
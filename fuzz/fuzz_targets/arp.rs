@@ -0,0 +1,15 @@
+#![no_main]
+use claim_ip::arp::{Arp, ArpPacket};
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pkt) = ArpPacket::try_from(data) {
+        if let Ok(arp) = Arp::try_from(pkt) {
+            // Parsed packets are sent back out the same way
+            let _ = arp.to_string();
+            let mut buf = [0u8; 28];
+            assert_eq!(arp.fill(&mut buf[..]), Ok(&data[..28]));
+        }
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+use claim_ip::ether::Header;
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = Header::try_from(data) {
+        // The payload starts after the header, its VLAN tags and SNAP header
+        let mut buf = [0u8; 30];
+        let filled = header.fill(&mut buf[..]).unwrap();
+        assert_eq!(filled.len(), header.size());
+        assert!(data.len() >= header.size());
+        let _ = claim_ip::arp::Arp::try_from(&data[header.size()..]);
+        let _ = claim_ip::ndp::Ndp::try_from(&data[header.size()..]);
+    }
+});
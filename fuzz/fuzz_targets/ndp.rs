@@ -0,0 +1,8 @@
+#![no_main]
+use claim_ip::ndp::Ndp;
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Ndp::try_from(data);
+});
@@ -0,0 +1,89 @@
+use eui48::MacAddress;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Suspicious packets that may indicate spoofing or misbehaving hosts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Anomaly {
+    // The sender hardware address is a multicast or broadcast address
+    MulticastSender,
+    // The sender hardware address differs from the frame's source address
+    SenderMismatch,
+    // A gratuitous ARP from another host advertises a claimed address
+    ForeignGratuitous,
+    // ARP for a hardware or protocol type other than ethernet and IPv4
+    UnusualType,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Anomaly::MulticastSender => "multicast sender mac",
+            Anomaly::SenderMismatch => "sender mac mismatch",
+            Anomaly::ForeignGratuitous => "foreign gratuitous arp",
+            Anomaly::UnusualType => "unusual arp type",
+        })
+    }
+}
+
+// Counts anomalies, reporting each source at most once per interval so a flood of
+// anomalous packets doesn't flood the log as well
+#[derive(Default)]
+pub struct Anomalies {
+    counts: BTreeMap<Anomaly, u64>,
+    reported: HashMap<(Anomaly, MacAddress), Instant>,
+}
+
+impl Anomalies {
+    // Count an anomaly from a source, returning whether it should be reported
+    pub fn record(&mut self, anomaly: Anomaly, source: MacAddress, now: Instant) -> bool {
+        *self.counts.entry(anomaly).or_default() += 1;
+
+        let key = (anomaly, source);
+        if self
+            .reported
+            .get(&key)
+            .is_some_and(|at| now.duration_since(*at) < REPORT_INTERVAL)
+        {
+            return false;
+        }
+        self.reported
+            .retain(|_, at| now.duration_since(*at) < REPORT_INTERVAL);
+        self.reported.insert(key, now);
+        true
+    }
+
+    pub fn counts(&self) -> impl Iterator<Item = (Anomaly, u64)> + '_ {
+        self.counts
+            .iter()
+            .map(|(anomaly, count)| (*anomaly, *count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let a = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let b = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+        let now = Instant::now();
+        let mut anomalies = Anomalies::default();
+
+        assert!(anomalies.record(Anomaly::SenderMismatch, a, now));
+        assert!(!anomalies.record(Anomaly::SenderMismatch, a, now));
+        assert!(anomalies.record(Anomaly::SenderMismatch, b, now));
+        assert!(anomalies.record(Anomaly::MulticastSender, a, now));
+        assert!(anomalies.record(Anomaly::SenderMismatch, a, now + REPORT_INTERVAL));
+        assert_eq!(
+            anomalies.counts().collect::<Vec<_>>(),
+            [(Anomaly::MulticastSender, 1), (Anomaly::SenderMismatch, 4)]
+        );
+    }
+}
@@ -1,15 +1,103 @@
+// Only depends on core, so firmware can use it without std (and eui48)
+use core::convert::{TryFrom, TryInto};
+use core::fmt;
+use core::net::Ipv4Addr;
+#[cfg(feature = "std")]
 use eui48::MacAddress;
-use std::convert::{TryFrom, TryInto};
-use std::net::Ipv4Addr;
+#[cfg(not(feature = "std"))]
+pub use mac::MacAddress;
 
+#[cfg(not(feature = "std"))]
+mod mac {
+    use core::array::TryFromSliceError;
+    use core::convert::TryInto;
+
+    // The parts of eui48::MacAddress used for ARP
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct MacAddress([u8; 6]);
+
+    impl MacAddress {
+        pub const fn new(eui: [u8; 6]) -> Self {
+            Self(eui)
+        }
+
+        pub const fn nil() -> Self {
+            Self([0x00; 6])
+        }
+
+        pub const fn broadcast() -> Self {
+            Self([0xff; 6])
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, TryFromSliceError> {
+            bytes.try_into().map(Self)
+        }
+
+        pub fn as_bytes(&self) -> &[u8] {
+            &self.0
+        }
+    }
+}
+
+// ARP operation codes as assigned by IANA, with the ones of no interest kept numerically
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ArpOp {
     Request,
     Reply,
+    RarpRequest,
+    RarpReply,
+    DrarpRequest,
+    DrarpReply,
+    DrarpError,
+    InarpRequest,
+    InarpReply,
+    ArpNak,
+    Other(u16),
+}
+
+impl From<u16> for ArpOp {
+    fn from(op: u16) -> Self {
+        match op {
+            1 => ArpOp::Request,
+            2 => ArpOp::Reply,
+            3 => ArpOp::RarpRequest,
+            4 => ArpOp::RarpReply,
+            5 => ArpOp::DrarpRequest,
+            6 => ArpOp::DrarpReply,
+            7 => ArpOp::DrarpError,
+            8 => ArpOp::InarpRequest,
+            9 => ArpOp::InarpReply,
+            10 => ArpOp::ArpNak,
+            op => ArpOp::Other(op),
+        }
+    }
+}
+
+impl From<ArpOp> for u16 {
+    fn from(op: ArpOp) -> Self {
+        match op {
+            ArpOp::Request => 1,
+            ArpOp::Reply => 2,
+            ArpOp::RarpRequest => 3,
+            ArpOp::RarpReply => 4,
+            ArpOp::DrarpRequest => 5,
+            ArpOp::DrarpReply => 6,
+            ArpOp::DrarpError => 7,
+            ArpOp::InarpRequest => 8,
+            ArpOp::InarpReply => 9,
+            ArpOp::ArpNak => 10,
+            ArpOp::Other(op) => op,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Arp {
+    pub htype: u16,
+    pub ptype: u16,
     pub op: ArpOp,
     pub sha: MacAddress,
     pub spa: Ipv4Addr,
@@ -17,39 +105,103 @@ pub struct Arp {
     pub tpa: Ipv4Addr,
 }
 
+// An ARP packet of any hardware and protocol type, with the addresses as their raw bytes
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ArpPacket<'a> {
+    pub htype: u16,
+    pub ptype: u16,
+    pub op: ArpOp,
+    pub sha: &'a [u8],
+    pub spa: &'a [u8],
+    pub tha: &'a [u8],
+    pub tpa: &'a [u8],
+}
+
+pub const HTYPE_ETHERNET: u16 = 1;
+pub const PTYPE_IPV4: u16 = 0x0800;
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum ArpError {
-    UnsupportedType,
+    // Address lengths other than ethernet's and IPv4's, which ArpPacket still parses
+    UnsupportedAddressLength { hlen: usize, plen: usize },
     InvalidArpOp,
-    InvalidSha,
-    InvalidSpa,
-    InvalidTha,
-    InvalidTpa,
     BufferTooSmall,
 }
 
-impl std::fmt::Display for ArpError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for ArpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ArpError::UnsupportedType => write!(f, "Unsupported ARP type (not ethernet/IPv4)"),
+            ArpError::UnsupportedAddressLength { hlen, plen } => write!(
+                f,
+                "Unsupported ARP address lengths {} and {} (not 6 and 4)",
+                hlen, plen
+            ),
             ArpError::InvalidArpOp => write!(f, "Invalid ARP opcode"),
-            ArpError::InvalidSha => write!(f, "Invalid ARP sender hardware address"),
-            ArpError::InvalidSpa => write!(f, "Invalid ARP sender protocol address"),
-            ArpError::InvalidTha => write!(f, "Invalid ARP target hardware address"),
-            ArpError::InvalidTpa => write!(f, "Invalid ARP target protocol address"),
             ArpError::BufferTooSmall => write!(f, "Packet buffer too small"),
         }
     }
 }
 
-impl std::error::Error for ArpError {}
+impl core::error::Error for ArpError {}
+
+impl ArpError {
+    // Where the field the error is about starts in the packet, or where it ended too soon
+    pub fn offset(&self, pkt: &[u8]) -> usize {
+        match self {
+            ArpError::UnsupportedAddressLength { .. } => 4,
+            ArpError::InvalidArpOp => 6,
+            ArpError::BufferTooSmall => pkt.len(),
+        }
+    }
+}
 
 impl Arp {
+    // A request from sha at spa for the hardware address of tpa
+    pub fn request(sha: MacAddress, spa: Ipv4Addr, tpa: Ipv4Addr) -> Self {
+        Self {
+            htype: HTYPE_ETHERNET,
+            ptype: PTYPE_IPV4,
+            op: ArpOp::Request,
+            sha,
+            spa,
+            tha: MacAddress::nil(),
+            tpa,
+        }
+    }
+
+    // ARP probe (RFC 5227): a request for the address without a sender address, so hosts
+    // using it answer without updating their caches
+    pub fn probe(mac: MacAddress, ip: Ipv4Addr) -> Self {
+        Self::request(mac, Ipv4Addr::UNSPECIFIED, ip)
+    }
+
+    // ARP announcement (RFC 5227): a request with the address as both sender and target
+    pub fn announce(mac: MacAddress, ip: Ipv4Addr) -> Self {
+        Self::request(mac, ip, ip)
+    }
+
+    // An unsolicited reply for the address, which some hosts only update their caches from
+    pub fn gratuitous_reply(mac: MacAddress, ip: Ipv4Addr) -> Self {
+        Self {
+            op: ArpOp::Reply,
+            tha: MacAddress::broadcast(),
+            ..Self::announce(mac, ip)
+        }
+    }
+
+    // Whether this is plain ARP for IPv4 over ethernet, rather than some other link or
+    // protocol type that happens to use the same address lengths
+    pub fn is_ethernet_ipv4(&self) -> bool {
+        self.htype == HTYPE_ETHERNET && self.ptype == PTYPE_IPV4
+    }
+
     pub fn reply(&self, ha: MacAddress) -> Result<Self, ArpError> {
         if self.op != ArpOp::Request {
             return Err(ArpError::InvalidArpOp);
         }
         Ok(Self {
+            htype: self.htype,
+            ptype: self.ptype,
             op: ArpOp::Reply,
             sha: ha,
             spa: self.tpa,
@@ -58,57 +210,144 @@ impl Arp {
         })
     }
 
-    pub fn fill<'a, 'b>(&'a self, buf: &'b mut [u8]) -> Result<&'b [u8], ArpError> {
+    pub fn fill<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], ArpError> {
         if buf.len() < 28 {
             return Err(ArpError::BufferTooSmall);
         }
-        buf[0..=6].copy_from_slice(&[0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00]);
-        buf[7] = match self.op {
-            ArpOp::Request => 1,
-            ArpOp::Reply => 2,
-        };
+        buf[0..=1].copy_from_slice(&self.htype.to_be_bytes());
+        buf[2..=3].copy_from_slice(&self.ptype.to_be_bytes());
+        buf[4..=5].copy_from_slice(&[0x06, 0x04]);
+        buf[6..=7].copy_from_slice(&u16::from(self.op).to_be_bytes());
         buf[8..=13].copy_from_slice(self.sha.as_bytes());
         buf[14..=17].copy_from_slice(&self.spa.octets());
         buf[18..=23].copy_from_slice(self.tha.as_bytes());
         buf[24..=27].copy_from_slice(&self.tpa.octets());
         Ok(&buf[0..=27])
     }
+
+    #[cfg(feature = "std")]
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut buf = [0u8; 28];
+        self.fill(&mut buf).expect("ARP packet fits").to_vec()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut buf = [0u8; 28];
+        w.write_all(self.fill(&mut buf).expect("ARP packet fits"))
+    }
 }
 
-impl TryFrom<&'_ [u8]> for Arp {
+// A summary in the style of tcpdump
+impl fmt::Display for Arp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sha = Hex(self.sha.as_bytes());
+        let tha = Hex(self.tha.as_bytes());
+        match self.op {
+            ArpOp::Request => write!(
+                f,
+                "request who-has {} tell {} ({})",
+                self.tpa, self.spa, sha
+            )?,
+            ArpOp::Reply => write!(f, "reply {} is-at {}", self.spa, sha)?,
+            ArpOp::RarpRequest => write!(f, "rarp request who-is {} tell {}", tha, sha)?,
+            ArpOp::RarpReply => write!(f, "rarp reply {} at {}", tha, self.tpa)?,
+            op => write!(
+                f,
+                "{:?} {} ({}) to {} ({})",
+                op, self.spa, sha, self.tpa, tha
+            )?,
+        }
+        if !self.is_ethernet_ipv4() {
+            write!(f, " htype {} ptype {:#06x}", self.htype, self.ptype)?;
+        }
+        Ok(())
+    }
+}
+
+// Colon separated hexadecimal bytes, formatted without allocating
+pub(crate) struct Hex<'a>(pub(crate) &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ArpPacket<'a> {
     type Error = ArpError;
 
-    fn try_from(pkt: &'_ [u8]) -> Result<Self, Self::Error> {
-        if pkt.len() < 28 {
+    fn try_from(pkt: &'a [u8]) -> Result<Self, Self::Error> {
+        if pkt.len() < 8 {
             return Err(ArpError::BufferTooSmall);
         }
-        if !pkt.starts_with(&[0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00]) {
-            return Err(ArpError::UnsupportedType);
+        let hlen = pkt[4] as usize;
+        let plen = pkt[5] as usize;
+        if pkt.len() < 8 + 2 * (hlen + plen) {
+            return Err(ArpError::BufferTooSmall);
         }
+        let (sha, rest) = pkt[8..].split_at(hlen);
+        let (spa, rest) = rest.split_at(plen);
+        let (tha, rest) = rest.split_at(hlen);
+        let tpa = &rest[..plen];
         Ok(Self {
-            op: match pkt[7] {
-                1 => ArpOp::Request,
-                2 => ArpOp::Reply,
-                _ => return Err(ArpError::InvalidArpOp),
-            },
-            sha: MacAddress::from_bytes(&pkt[8..=13]).map_err(|_| ArpError::InvalidSha)?,
-            spa: {
-                let bytes: [u8; 4] = pkt[14..=17].try_into().map_err(|_| ArpError::InvalidSpa)?;
-                bytes.into()
-            },
-            tha: MacAddress::from_bytes(&pkt[18..=23]).map_err(|_| ArpError::InvalidTha)?,
-            tpa: {
-                let bytes: [u8; 4] = pkt[24..=27].try_into().map_err(|_| ArpError::InvalidTpa)?;
-                bytes.into()
-            },
+            htype: u16::from_be_bytes([pkt[0], pkt[1]]),
+            ptype: u16::from_be_bytes([pkt[2], pkt[3]]),
+            op: u16::from_be_bytes([pkt[6], pkt[7]]).into(),
+            sha,
+            spa,
+            tha,
+            tpa,
         })
     }
 }
 
+impl TryFrom<ArpPacket<'_>> for Arp {
+    type Error = ArpError;
+
+    fn try_from(pkt: ArpPacket<'_>) -> Result<Self, Self::Error> {
+        let addrs = (
+            <[u8; 6]>::try_from(pkt.sha),
+            <[u8; 4]>::try_from(pkt.spa),
+            <[u8; 6]>::try_from(pkt.tha),
+            <[u8; 4]>::try_from(pkt.tpa),
+        );
+        match addrs {
+            (Ok(sha), Ok(spa), Ok(tha), Ok(tpa)) => Ok(Self {
+                htype: pkt.htype,
+                ptype: pkt.ptype,
+                op: pkt.op,
+                sha: MacAddress::new(sha),
+                spa: spa.into(),
+                tha: MacAddress::new(tha),
+                tpa: tpa.into(),
+            }),
+            _ => Err(ArpError::UnsupportedAddressLength {
+                hlen: pkt.sha.len(),
+                plen: pkt.spa.len(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<&'_ [u8]> for Arp {
+    type Error = ArpError;
+
+    fn try_from(pkt: &'_ [u8]) -> Result<Self, Self::Error> {
+        ArpPacket::try_from(pkt)?.try_into()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::convert::TryInto;
+    use proptest::prelude::*;
 
     #[test]
     fn t1() {
@@ -121,6 +360,8 @@ mod tests {
         assert_eq!(
             request,
             Arp {
+                htype: HTYPE_ETHERNET,
+                ptype: PTYPE_IPV4,
                 op: ArpOp::Request,
                 sha: MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
                 spa: "10.0.0.1".parse().unwrap(),
@@ -137,6 +378,8 @@ mod tests {
         assert_eq!(
             reply,
             Arp {
+                htype: HTYPE_ETHERNET,
+                ptype: PTYPE_IPV4,
                 op: ArpOp::Reply,
                 sha: MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
                 spa: "10.0.0.2".parse().unwrap(),
@@ -151,5 +394,205 @@ mod tests {
             0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 10, 0, 0, 1, // target
         ];
         assert_eq!(reply.fill(&mut buf[..]), Ok(&reply_pkt[..]));
+        #[cfg(feature = "std")]
+        {
+            assert_eq!(reply.to_vec(), reply_pkt);
+            let mut written = vec![0xff];
+            reply.write_to(&mut written).unwrap();
+            assert_eq!(written[1..], reply_pkt);
+        }
+    }
+
+    #[test]
+    fn opcodes() {
+        let mut pkt: [u8; 28] = [
+            0x00, 0x01, 0x08, 0x00, 6, 4, 0, 8, // arp header
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 10, 0, 0, 1, // sender
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 10, 0, 0, 2, // target
+        ];
+        let inarp: Arp = pkt.as_ref().try_into().unwrap();
+        assert_eq!(inarp.op, ArpOp::InarpRequest);
+        assert_eq!(inarp.reply(MacAddress::nil()), Err(ArpError::InvalidArpOp));
+
+        pkt[6] = 0x01;
+        let other: Arp = pkt.as_ref().try_into().unwrap();
+        assert_eq!(other.op, ArpOp::Other(0x0108));
+        let mut buf = [0u8; 28];
+        assert_eq!(other.fill(&mut buf[..]), Ok(&pkt[..]));
+        assert_eq!(ArpOp::from(u16::from(ArpOp::Reply)), ArpOp::Reply);
+
+        // IEEE 802 networks use the same address lengths as ethernet
+        pkt[1] = 6;
+        let ieee802: Arp = pkt.as_ref().try_into().unwrap();
+        assert_eq!(ieee802.htype, 6);
+        assert!(!ieee802.is_ethernet_ipv4());
+        assert_eq!(ieee802.fill(&mut buf[..]), Ok(&pkt[..]));
+    }
+
+    #[test]
+    fn infiniband() {
+        let mut pkt = vec![0x00, 0x20, 0x08, 0x00, 20, 4, 0, 1];
+        pkt.extend_from_slice(&[0xa5; 20]);
+        pkt.extend_from_slice(&[10, 0, 0, 1]);
+        pkt.extend_from_slice(&[0x00; 20]);
+        pkt.extend_from_slice(&[10, 0, 0, 2]);
+        let parsed = ArpPacket::try_from(pkt.as_slice()).unwrap();
+        assert_eq!(parsed.htype, 32);
+        assert_eq!(parsed.op, ArpOp::Request);
+        assert_eq!(parsed.sha, &[0xa5; 20][..]);
+        assert_eq!(parsed.spa, &[10, 0, 0, 1][..]);
+        assert_eq!(parsed.tha, &[0x00; 20][..]);
+        assert_eq!(parsed.tpa, &[10, 0, 0, 2][..]);
+        let err = || ArpError::UnsupportedAddressLength { hlen: 20, plen: 4 };
+        assert_eq!(Arp::try_from(parsed), Err(err()));
+        assert_eq!(err().offset(&pkt), 4);
+        // Hardware addresses of different lengths can only be put together by hand
+        let mixed = ArpPacket {
+            tha: &[0x00; 6],
+            ..parsed
+        };
+        assert_eq!(Arp::try_from(mixed), Err(err()));
+        assert_eq!(
+            ArpPacket::try_from(&pkt[..pkt.len() - 1]),
+            Err(ArpError::BufferTooSmall)
+        );
+        assert_eq!(ArpError::BufferTooSmall.offset(&pkt[..55]), 55);
+    }
+
+    #[test]
+    fn constructors() {
+        let mac = MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let ip = "10.0.0.1".parse().unwrap();
+        let mut buf = [0u8; 28];
+
+        let probe = Arp::probe(mac, ip);
+        assert_eq!(
+            probe.fill(&mut buf[..]),
+            Ok(&[
+                0x00, 0x01, 0x08, 0x00, 6, 4, 0, 1, // arp header
+                0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0, 0, 0, 0, // sender
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 10, 0, 0, 1, // target
+            ][..])
+        );
+        let announce = Arp::announce(mac, ip);
+        assert_eq!(
+            (announce.op, announce.spa, announce.tpa),
+            (ArpOp::Request, ip, ip)
+        );
+        assert_eq!(announce.tha, MacAddress::nil());
+        assert_eq!(Arp::request(mac, ip, ip), announce);
+        assert_eq!(
+            Arp::gratuitous_reply(mac, ip).fill(&mut buf[..]),
+            Ok(&[
+                0x00, 0x01, 0x08, 0x00, 6, 4, 0, 2, // arp header
+                0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 10, 0, 0, 1, // sender
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 10, 0, 0, 1, // target
+            ][..])
+        );
+    }
+
+    #[test]
+    fn display() {
+        let sha = MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let spa = "10.0.0.1".parse().unwrap();
+        let tpa = "10.0.0.2".parse().unwrap();
+        let request = Arp::request(sha, spa, tpa);
+        assert_eq!(
+            request.to_string(),
+            "request who-has 10.0.0.2 tell 10.0.0.1 (11:22:33:44:55:66)"
+        );
+        let mac = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        assert_eq!(
+            request.reply(mac).unwrap().to_string(),
+            "reply 10.0.0.2 is-at aa:bb:cc:dd:ee:ff"
+        );
+        let inarp = Arp {
+            op: ArpOp::InarpRequest,
+            htype: 15,
+            ..request
+        };
+        assert_eq!(
+            inarp.to_string(),
+            "InarpRequest 10.0.0.1 (11:22:33:44:55:66) to 10.0.0.2 (00:00:00:00:00:00) htype 15 ptype 0x0800"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(
+            htype: u16,
+            ptype: u16,
+            op in 0..16u16,
+            sha: [u8; 6],
+            spa: [u8; 4],
+            tha: [u8; 6],
+            tpa: [u8; 4],
+        ) {
+            let arp = Arp {
+                htype,
+                ptype,
+                op: ArpOp::from(op),
+                sha: MacAddress::new(sha),
+                spa: spa.into(),
+                tha: MacAddress::new(tha),
+                tpa: tpa.into(),
+            };
+            let mut buf = [0u8; 28];
+            let pkt = arp.fill(&mut buf[..]).unwrap();
+            prop_assert!(!arp.to_string().is_empty());
+            prop_assert_eq!(Arp::try_from(pkt), Ok(arp));
+        }
+
+        // Mostly small address lengths, so parsing gets past the lengths check
+        #[test]
+        fn arbitrary_bytes(
+            mut pkt in prop::collection::vec(any::<u8>(), 0..64),
+            lens in prop::option::weighted(0.75, (0..8u8, 0..8u8)),
+        ) {
+            if let (Some((hlen, plen)), true) = (lens, pkt.len() >= 6) {
+                pkt[4] = hlen;
+                pkt[5] = plen;
+            }
+            let parsed = match ArpPacket::try_from(pkt.as_slice()) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    prop_assert_eq!(err, ArpError::BufferTooSmall);
+                    return Ok(());
+                }
+            };
+            prop_assert_eq!(
+                (parsed.sha.len(), parsed.tha.len()),
+                (pkt[4].into(), pkt[4].into())
+            );
+            prop_assert_eq!(
+                (parsed.spa.len(), parsed.tpa.len()),
+                (pkt[5].into(), pkt[5].into())
+            );
+            let mut buf = [0u8; 28];
+            match Arp::try_from(parsed) {
+                Ok(arp) => prop_assert_eq!(arp.fill(&mut buf[..]), Ok(&pkt[..28])),
+                Err(err) => prop_assert_eq!(
+                    err,
+                    ArpError::UnsupportedAddressLength {
+                        hlen: pkt[4].into(),
+                        plen: pkt[5].into()
+                    }
+                ),
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mac = MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let arp = Arp {
+            op: ArpOp::RarpRequest,
+            ..Arp::probe(mac, "10.0.0.1".parse().unwrap())
+        };
+        let s = toml::to_string(&arp).unwrap();
+        assert!(s.contains("op = \"rarp_request\""));
+        assert!(s.contains("spa = \"0.0.0.0\""));
+        assert_eq!(toml::from_str::<Arp>(&s), Ok(arp));
     }
 }
@@ -0,0 +1,268 @@
+use crate::arp::{Arp, ArpOp};
+use eui48::MacAddress;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+// ARP requests for a target address, and which MAC addresses answer them
+#[derive(Debug, Clone, Copy)]
+pub struct Requester {
+    pub mac: MacAddress,
+    pub source: Ipv4Addr,
+    pub target: Ipv4Addr,
+}
+
+impl Requester {
+    pub fn request(&self) -> Arp {
+        Arp::request(self.mac, self.source, self.target)
+    }
+
+    // The MAC address the ARP packet answers the requests with, if it is a reply to them
+    pub fn replier(&self, packet: &[u8]) -> Option<MacAddress> {
+        match Arp::try_from(packet) {
+            Ok(reply)
+                if reply.op == ArpOp::Reply
+                    && reply.is_ethernet_ipv4()
+                    && reply.spa == self.target
+                    && reply.tpa == self.source
+                    && reply.tha == self.mac =>
+            {
+                Some(reply.sha)
+            }
+            _ => None,
+        }
+    }
+}
+
+// A storm of ARP requests sent at a steady rate, counting the replies by the MAC address
+// answering, to see how a responder (or any other device) holds up
+pub struct Bench {
+    requester: Requester,
+    rate: u64,
+    sent: u64,
+    replies: BTreeMap<MacAddress, u64>,
+}
+
+impl Bench {
+    pub fn new(requester: Requester, rate: u64) -> Self {
+        Self {
+            requester,
+            rate: rate.max(1),
+            sent: 0,
+            replies: BTreeMap::new(),
+        }
+    }
+
+    pub fn request(&self) -> Arp {
+        self.requester.request()
+    }
+
+    // The number of requests to send after sending for this long, to keep up the rate with
+    // the first sent right away
+    pub fn due(&self, elapsed: Duration) -> u64 {
+        let due = elapsed.as_nanos() * u128::from(self.rate) / 1_000_000_000 + 1;
+        u64::try_from(due)
+            .unwrap_or(u64::MAX)
+            .saturating_sub(self.sent)
+    }
+
+    // How long after starting the next request is due
+    pub fn next(&self) -> Duration {
+        let nanos = u128::from(self.sent) * 1_000_000_000 / u128::from(self.rate);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    pub fn sent(&mut self, n: u64) {
+        self.sent += n;
+    }
+
+    // Count the ARP packet if it answers the requests, returning whether it did
+    pub fn receive(&mut self, packet: &[u8]) -> bool {
+        match self.requester.replier(packet) {
+            Some(mac) => {
+                *self.replies.entry(mac).or_default() += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn report(&self, elapsed: Duration) -> Report {
+        Report {
+            target: self.requester.target,
+            sent: self.sent,
+            replies: self.replies.clone(),
+            elapsed,
+        }
+    }
+}
+
+// The requests sent and replies received over the time spent sending
+pub struct Report {
+    pub target: Ipv4Addr,
+    pub sent: u64,
+    pub replies: BTreeMap<MacAddress, u64>,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    pub fn received(&self) -> u64 {
+        self.replies.values().sum()
+    }
+
+    // The share of requests without a reply, where replies by several devices can make up
+    // for lost ones
+    pub fn loss(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        self.sent.saturating_sub(self.received()) as f64 / self.sent as f64
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            f,
+            "{} requests for {} in {:.2}s ({:.0}/s), {} replies ({:.0}/s), {:.2}% lost",
+            self.sent,
+            self.target,
+            secs,
+            self.sent as f64 / secs,
+            self.received(),
+            self.received() as f64 / secs,
+            self.loss() * 100.0
+        )?;
+        for (mac, replies) in &self.replies {
+            writeln!(f, "  {} replies from {}", replies, mac.to_hex_string())?;
+        }
+        Ok(())
+    }
+}
+
+// Latencies of the replies to requests sent one at a time, by the MAC address answering,
+// with requests answered by several counting once
+#[derive(Debug, Default)]
+pub struct Latencies {
+    pub sent: u64,
+    pub answered: u64,
+    pub replies: BTreeMap<MacAddress, Vec<Duration>>,
+}
+
+impl Latencies {
+    // Add the replies to a request
+    pub fn add(&mut self, replies: &[(MacAddress, Duration)]) {
+        self.sent += 1;
+        if !replies.is_empty() {
+            self.answered += 1;
+        }
+        for (mac, latency) in replies {
+            self.replies.entry(*mac).or_default().push(*latency);
+        }
+    }
+}
+
+// The lowest, average, 99th percentile and highest of latencies
+fn summarize(latencies: &[Duration]) -> (Duration, Duration, Duration, Duration) {
+    let mut sorted = latencies.to_vec();
+    sorted.sort();
+    let total: Duration = sorted.iter().sum();
+    let avg = total / sorted.len().max(1) as u32;
+    let p99 = sorted[(sorted.len() * 99).div_ceil(100).max(1) - 1];
+    (sorted[0], avg, p99, sorted[sorted.len() - 1])
+}
+
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lost = self.sent - self.answered;
+        writeln!(
+            f,
+            "{} requests, {} answered, {:.2}% lost",
+            self.sent,
+            self.answered,
+            lost as f64 * 100.0 / self.sent.max(1) as f64
+        )?;
+        let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        for (mac, latencies) in &self.replies {
+            let (min, avg, p99, max) = summarize(latencies);
+            writeln!(
+                f,
+                "  {} replies from {}, min/avg/p99/max {:.3}/{:.3}/{:.3}/{:.3} ms",
+                latencies.len(),
+                mac.to_hex_string(),
+                ms(min),
+                ms(avg),
+                ms(p99),
+                ms(max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacing_and_replies() {
+        let mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let (source, target) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let requester = Requester {
+            mac,
+            source,
+            target,
+        };
+        let mut bench = Bench::new(requester, 1000);
+        assert_eq!(bench.due(Duration::ZERO), 1);
+        assert_eq!(bench.due(Duration::from_micros(500)), 1);
+        assert_eq!(bench.due(Duration::from_millis(10)), 11);
+        bench.sent(11);
+        assert_eq!(bench.due(Duration::from_millis(10)), 0);
+        assert_eq!(bench.next(), Duration::from_millis(11));
+
+        // Replies to others, or from others than the target, don't count
+        let responder = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+        let reply = bench.request().reply(responder).unwrap();
+        assert!(bench.receive(&reply.to_vec()));
+        assert!(!bench.receive(&bench.request().to_vec()));
+        let other = Arp::request(responder, target, Ipv4Addr::new(10, 0, 0, 3));
+        assert!(!bench.receive(&other.reply(mac).unwrap().to_vec()));
+
+        let report = bench.report(Duration::from_secs(1));
+        assert_eq!(report.received(), 1);
+        assert_eq!(report.loss(), 10.0 / 11.0);
+        assert_eq!(report.replies[&responder], 1);
+    }
+
+    #[test]
+    fn latencies() {
+        let (primary, backup) = (
+            MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]),
+        );
+        let mut latencies = Latencies::default();
+        for ms in 1..=200 {
+            latencies.add(&[(primary, Duration::from_millis(ms))]);
+        }
+        latencies.add(&[]);
+        latencies.add(&[
+            (primary, Duration::from_millis(1)),
+            (backup, Duration::from_millis(3)),
+        ]);
+        assert_eq!((latencies.sent, latencies.answered), (202, 201));
+        let ms = Duration::from_millis;
+        let summary = summarize(&latencies.replies[&primary]);
+        assert_eq!(
+            summary,
+            (ms(1), Duration::from_nanos(100_004_975), ms(198), ms(200))
+        );
+        assert_eq!(
+            summarize(&latencies.replies[&backup]),
+            (ms(3), ms(3), ms(3), ms(3))
+        );
+    }
+}
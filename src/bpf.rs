@@ -0,0 +1,308 @@
+use crate::ether;
+use crate::iface::{self, PacketIo};
+use crate::link::{
+    self, bpf_jump, bpf_stmt, BpfInsn, LinkAddr, Membership, BPF_JEQ_K, BPF_JGE_K, BPF_LDH_ABS,
+    BPF_RET_K,
+};
+use crate::pcap;
+use eui48::MacAddress;
+use nix::fcntl::{open, OFlag};
+use nix::libc::{c_uint, c_ulong, IFNAMSIZ};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, read, write};
+use std::cell::{Cell, RefCell};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+use net_bpf::*;
+#[cfg(not(any(target_os = "openbsd", target_os = "netbsd")))]
+use nix::libc::{
+    bpf_hdr, BIOCGBLEN, BIOCIMMEDIATE, BIOCPROMISC, BIOCSETF, BIOCSETIF, BIOCSHDRCMPLT,
+    BIOCSSEESENT, BPF_ALIGNMENT,
+};
+
+// What libc lacks for OpenBSD and NetBSD, from their net/bpf.h: the ioctls, encoded like
+// sys/ioccom.h does, and the start of the header of records
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+#[allow(non_camel_case_types)]
+mod net_bpf {
+    use super::{BpfProgram, IfReq};
+    use nix::libc::{c_uint, c_ulong};
+    use std::mem::size_of;
+
+    const fn ioc(inout: c_ulong, num: c_ulong, len: usize) -> c_ulong {
+        inout | ((len as c_ulong & 0x1fff) << 16) | ((b'B' as c_ulong) << 8) | num
+    }
+
+    const IOC_VOID: c_ulong = 0x2000_0000;
+    const IOC_OUT: c_ulong = 0x4000_0000;
+    const IOC_IN: c_ulong = 0x8000_0000;
+
+    pub const BIOCGBLEN: c_ulong = ioc(IOC_OUT, 102, size_of::<c_uint>());
+    pub const BIOCSETF: c_ulong = ioc(IOC_IN, 103, size_of::<BpfProgram>());
+    pub const BIOCPROMISC: c_uint = ioc(IOC_VOID, 105, 0) as c_uint;
+    pub const BIOCSETIF: c_ulong = ioc(IOC_IN, 108, size_of::<IfReq>());
+    pub const BIOCIMMEDIATE: c_ulong = ioc(IOC_IN, 112, size_of::<c_uint>());
+    pub const BIOCSHDRCMPLT: c_ulong = ioc(IOC_IN, 117, size_of::<c_uint>());
+    #[cfg(target_os = "netbsd")]
+    pub const BIOCSSEESENT: c_ulong = ioc(IOC_IN, 121, size_of::<c_uint>());
+    // OpenBSD filters frames by direction instead of hiding those sent
+    #[cfg(target_os = "openbsd")]
+    pub const BIOCSDIRFILT: c_ulong = ioc(IOC_IN, 125, size_of::<c_uint>());
+    #[cfg(target_os = "openbsd")]
+    pub const BPF_DIRECTION_OUT: c_uint = 1 << 1;
+
+    // Records are aligned to a u_int32_t on OpenBSD and to a long on NetBSD, and their
+    // header starts with a timestamp of those
+    #[cfg(target_os = "openbsd")]
+    pub const BPF_ALIGNMENT: usize = size_of::<u32>();
+    #[cfg(target_os = "netbsd")]
+    pub const BPF_ALIGNMENT: usize = size_of::<nix::libc::c_long>();
+
+    #[repr(C)]
+    pub struct bpf_hdr {
+        _bh_tstamp: [BpfWord; 2],
+        pub bh_caplen: u32,
+        _bh_datalen: u32,
+        pub bh_hdrlen: u16,
+    }
+
+    #[cfg(target_os = "openbsd")]
+    type BpfWord = u32;
+    #[cfg(target_os = "netbsd")]
+    type BpfWord = nix::libc::c_long;
+}
+
+// Devices tried in turn where /dev/bpf doesn't hand out a new one each time it's opened
+const DEVICES: usize = 256;
+
+#[repr(C)]
+struct BpfProgram {
+    bf_len: c_uint,
+    bf_insns: *const BpfInsn,
+}
+
+#[repr(C)]
+struct IfReq {
+    name: [u8; IFNAMSIZ],
+    _data: [u8; IFREQ_DATA],
+}
+
+// The size of ifreq's union, which on NetBSD holds a sockaddr_storage
+#[cfg(not(target_os = "netbsd"))]
+const IFREQ_DATA: usize = 16;
+#[cfg(target_os = "netbsd")]
+const IFREQ_DATA: usize = 128;
+
+// Frames read at once, as reads need a buffer of the device's buffer size, handed out one at
+// a time. Each follows its bpf_hdr, with the next header word aligned after it.
+struct Records {
+    buf: Vec<u8>,
+    len: usize,
+    next: usize,
+}
+
+impl Records {
+    fn next(&mut self) -> Option<&[u8]> {
+        if !self.is_pending() {
+            return None;
+        }
+        let hdr =
+            unsafe { std::ptr::read_unaligned(self.buf[self.next..].as_ptr() as *const bpf_hdr) };
+        let start = self.next + hdr.bh_hdrlen as usize;
+        let end = start + hdr.bh_caplen as usize;
+        // An int on macOS
+        #[allow(clippy::unnecessary_cast)]
+        let align = BPF_ALIGNMENT as usize;
+        self.next = (end + align - 1) & !(align - 1);
+        self.buf.get(start..end.min(self.len))
+    }
+
+    fn is_pending(&self) -> bool {
+        self.next + std::mem::size_of::<bpf_hdr>() <= self.len
+    }
+}
+
+// A BPF device (/dev/bpf) attached to an interface, for the BSDs and macOS, which lack
+// packet sockets. Like a tap device it sees frames whole: its kernel filter only passes the
+// protocol and tagged and 802.3 frames, which are told apart in recv_from. BPF devices can't
+// join multicast groups, so the interface is put in promiscuous mode instead, which lasts
+// until the device is closed.
+pub struct BpfDevice {
+    link_addr: LinkAddr,
+    fd: RawFd,
+    vlan: Option<ether::Vlan>,
+    min_len: usize,
+    capture: Option<pcap::Capture>,
+    records: RefCell<Records>,
+    promisc: Cell<bool>,
+}
+
+impl Drop for BpfDevice {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+impl AsRawFd for BpfDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+// Open a free BPF device, from the cloning /dev/bpf or the first of /dev/bpfN not in use
+fn open_device() -> nix::Result<RawFd> {
+    let flags = OFlag::O_RDWR | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC;
+    match open("/dev/bpf", flags, Mode::empty()) {
+        Err(nix::Error::ENOENT) => {}
+        res => return res,
+    }
+    let mut res = Err(nix::Error::ENOENT);
+    for n in 0..DEVICES {
+        res = open(format!("/dev/bpf{}", n).as_str(), flags, Mode::empty());
+        if res != Err(nix::Error::EBUSY) {
+            break;
+        }
+    }
+    res
+}
+
+impl BpfDevice {
+    fn ioctl<T>(&self, request: c_ulong, arg: *mut T) -> nix::Result<()> {
+        let res = unsafe { nix::libc::ioctl(self.fd, request, arg) };
+        nix::Error::result(res).map(drop)
+    }
+
+    fn set_flag(&self, request: c_ulong, on: bool) -> nix::Result<()> {
+        let mut value = c_uint::from(on);
+        self.ioctl(request, &mut value)
+    }
+}
+
+impl PacketIo for BpfDevice {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        _raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        _filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        let name = iface::interface_name(link_addr.ifindex).map_err(|_| nix::Error::ENODEV)?;
+        let mut ifr = IfReq {
+            name: [0; IFNAMSIZ],
+            _data: [0; IFREQ_DATA],
+        };
+        if name.len() >= IFNAMSIZ {
+            return Err(nix::Error::EINVAL);
+        }
+        ifr.name[..name.len()].copy_from_slice(name.as_bytes());
+
+        let device = Self {
+            link_addr: LinkAddr {
+                protocol,
+                ..*link_addr
+            },
+            fd: open_device()?,
+            vlan,
+            min_len,
+            capture,
+            records: RefCell::new(Records {
+                buf: Vec::new(),
+                len: 0,
+                next: 0,
+            }),
+            promisc: Cell::new(false),
+        };
+        device.ioctl(BIOCSETIF, &mut ifr)?;
+        // Frames are handed over as they arrive, are sent with the source address they
+        // have, and frames sent by this host aren't seen
+        device.set_flag(BIOCIMMEDIATE, true)?;
+        device.set_flag(BIOCSHDRCMPLT, true)?;
+        #[cfg(not(target_os = "openbsd"))]
+        device.set_flag(BIOCSSEESENT, false)?;
+        #[cfg(target_os = "openbsd")]
+        {
+            let mut direction = BPF_DIRECTION_OUT;
+            device.ioctl(BIOCSDIRFILT, &mut direction)?;
+        }
+        let mut len: c_uint = 0;
+        device.ioctl(BIOCGBLEN, &mut len)?;
+        device.records.borrow_mut().buf = vec![0; len as usize];
+
+        // Tagged and 802.3 frames pass too, for their VLAN and SNAP headers to be checked in
+        // recv_from
+        let filter = [
+            bpf_stmt(BPF_LDH_ABS, 12),
+            bpf_jump(BPF_JEQ_K, u32::from(protocol), 3, 0),
+            bpf_jump(BPF_JEQ_K, u32::from(ether::ETH_P_8021Q), 2, 0),
+            bpf_jump(BPF_JEQ_K, u32::from(ether::ETH_P_8021AD), 1, 0),
+            bpf_jump(BPF_JGE_K, 0x600, 1, 0),
+            bpf_stmt(BPF_RET_K, 0xffff),
+            bpf_stmt(BPF_RET_K, 0),
+        ];
+        let mut program = BpfProgram {
+            bf_len: filter.len() as c_uint,
+            bf_insns: filter.as_ptr(),
+        };
+        device.ioctl(BIOCSETF, &mut program)?;
+        Ok(device)
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.link_addr
+    }
+
+    fn is_raw(&self) -> bool {
+        true
+    }
+
+    // Frames are only told apart by protocol and VLAN, when opening and in recv_from
+    fn set_filter(&self, _filter: &[BpfInsn]) -> nix::Result<()> {
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let mut records = self.records.borrow_mut();
+        if !records.is_pending() {
+            let records = &mut *records;
+            records.len = 0;
+            records.next = 0;
+            records.len = read(self.fd, &mut records.buf)?;
+        }
+        let frame = records.next().ok_or(nix::Error::EAGAIN)?;
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, frame);
+        }
+        link::raw_payload(&self.link_addr, self.vlan, frame, buf)
+    }
+
+    fn pending(&self) -> bool {
+        self.records.borrow().is_pending()
+    }
+
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        let (len, frame) = link::raw_frame(&self.link_addr, self.vlan, self.min_len, buf, src, to);
+        let size = write(self.fd, &frame)?;
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, &frame);
+        }
+        Ok(size.saturating_sub(len).min(buf.len()))
+    }
+
+    // Promiscuous mode can't be left without closing the device, so leaving is a no-op
+    fn set_membership(&self, _membership: Membership, join: bool) -> nix::Result<()> {
+        if join && !self.promisc.get() {
+            let res = unsafe { nix::libc::ioctl(self.fd, BIOCPROMISC as c_ulong) };
+            nix::Error::result(res)?;
+            self.promisc.set(true);
+        }
+        Ok(())
+    }
+
+    // Every BPF device attached to the interface sees all of its frames
+    fn join_fanout(&self, _id: Option<u16>) -> nix::Result<u16> {
+        Err(nix::Error::EOPNOTSUPP)
+    }
+}
@@ -0,0 +1,753 @@
+use crate::error::Error;
+use crate::ether;
+use crate::ndp::NaFlags;
+use crate::net::{ClaimSet, IpNet};
+use crate::schedule::Window;
+use crate::sys::{self, AsRawFd, RawFd};
+use eui48::MacAddress;
+#[cfg(target_os = "linux")]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "claim")]
+    pub claims: Vec<Claim>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Claim {
+    // Looked up through the routing table when empty
+    #[serde(default)]
+    pub iface: String,
+    // 802.1Q VLAN on the interface to claim the addresses in, with tagged frames
+    #[serde(default)]
+    pub vlan: Option<u16>,
+    // 802.1ad service VLAN the 802.1Q VLAN is stacked in (QinQ)
+    #[serde(default)]
+    pub outer_vlan: Option<u16>,
+    #[serde(default)]
+    pub ip: Vec<IpNet>,
+    // Claimed in addition to ip, with all addresses they resolve to
+    #[serde(default)]
+    pub hostname: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<IpNet>,
+    #[serde(default)]
+    pub mac: Option<MacAddress>,
+    #[serde(default)]
+    pub macs: BTreeMap<IpAddr, MacAddress>,
+    // Requesters answered with a MAC address of their own, overriding mac and macs
+    #[serde(default)]
+    pub reply_macs: BTreeMap<MacAddress, MacAddress>,
+    #[serde(default)]
+    pub announce: bool,
+    // Seconds between re-announcements, with up to announce_jitter seconds added at random
+    #[serde(default)]
+    pub announce_interval: Option<u64>,
+    #[serde(default)]
+    pub announce_jitter: u64,
+    // Announcements sent when claiming an address, announce_spacing milliseconds apart
+    #[serde(default = "default_announce_count")]
+    pub announce_count: u32,
+    #[serde(default = "default_announce_spacing")]
+    pub announce_spacing: u64,
+    #[serde(default)]
+    pub announce_op: AnnounceOp,
+    // Send ARP replies to the broadcast address instead of the requester
+    #[serde(default)]
+    pub broadcast_reply: bool,
+    // Source MAC address of sent frames in raw socket mode, when it should differ from the
+    // MAC address answered with
+    #[serde(default)]
+    pub frame_mac: Option<FrameMac>,
+    // Routers receiving announcements directly in addition to the broadcast ones
+    #[serde(default)]
+    pub routers: Vec<MacAddress>,
+    // Gateways requested from each claimed IPv4 address every keepalive_interval seconds, so
+    // their ARP caches and switch MAC address tables stay fresh without inbound traffic
+    #[serde(default)]
+    pub keepalive: Vec<Ipv4Addr>,
+    #[serde(default = "default_keepalive_interval")]
+    pub keepalive_interval: u64,
+    // Only requests from allow_macs and allow_from (when not empty) and not from deny_macs
+    // are answered
+    #[serde(default)]
+    pub allow_macs: Vec<MacAddress>,
+    #[serde(default)]
+    pub deny_macs: Vec<MacAddress>,
+    #[serde(default)]
+    pub allow_from: Vec<IpNet>,
+    // Only answer requests from senders within the interface's IPv4 subnets
+    #[serde(default)]
+    pub on_link: bool,
+    // Milliseconds to hold replies back, with up to reply_jitter milliseconds added at random
+    #[serde(default)]
+    pub reply_delay: u64,
+    #[serde(default)]
+    pub reply_jitter: u64,
+    #[serde(default)]
+    pub delivery: Delivery,
+    // Only answer requests no other host answers
+    #[serde(default)]
+    pub passive: bool,
+    // Answer for every address in the ranges like a proxy ARP gateway, without enumerating,
+    // announcing or probing them
+    #[serde(default)]
+    pub proxy: bool,
+    // Only answer for addresses the routing table reaches through another interface
+    #[serde(default)]
+    pub proxy_routes: bool,
+    // Only answer for IPv4 addresses requested more than sponge_threshold times per minute
+    // that nobody answers for
+    #[serde(default)]
+    pub sponge: bool,
+    #[serde(default = "default_sponge_threshold")]
+    pub sponge_threshold: u32,
+    // Seconds after which sponged addresses nobody requested are released, and seconds
+    // between probes for sponged addresses, 0 to never do so
+    #[serde(default = "default_sponge_age")]
+    pub sponge_age: u64,
+    #[serde(default = "default_sponge_recheck")]
+    pub sponge_recheck: u64,
+    // Wake the hosts of the claimed addresses with a Wake-on-LAN magic packet to their MAC
+    // address when answering for them
+    #[serde(default)]
+    pub wake: bool,
+    // Answer Reverse ARP requests with the addresses an ethers file maps MAC addresses to,
+    // as the first claimed IPv4 address
+    #[serde(default)]
+    pub rarp: Option<PathBuf>,
+    // Announced for the claimed addresses when terminating
+    #[serde(default)]
+    pub restore_mac: Option<MacAddress>,
+    // Announced for the claimed addresses when handing off to a successor
+    #[serde(default)]
+    pub handoff_mac: Option<MacAddress>,
+    #[serde(default)]
+    pub dad: DadPolicy,
+    #[serde(default)]
+    pub on_probe: ArpProbePolicy,
+    #[serde(default)]
+    pub na_flags: NaFlags,
+    #[serde(default)]
+    pub probe: Option<ProbePolicy>,
+    #[serde(default = "default_takeover_hold")]
+    pub takeover_hold: u64,
+    #[serde(default)]
+    pub on_conflict: Option<ConflictPolicy>,
+    // Daily windows of local time in which the claim is active, always when empty
+    #[serde(default)]
+    pub windows: Vec<Window>,
+}
+
+fn default_announce_count() -> u32 {
+    1
+}
+
+fn default_announce_spacing() -> u64 {
+    1000
+}
+
+fn default_takeover_hold() -> u64 {
+    10
+}
+
+fn default_keepalive_interval() -> u64 {
+    30
+}
+
+fn default_sponge_threshold() -> u32 {
+    50
+}
+
+fn default_sponge_age() -> u64 {
+    600
+}
+
+fn default_sponge_recheck() -> u64 {
+    60
+}
+
+// The ARP operation gratuitous ARP is sent as, as devices differ in which they honor
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnounceOp {
+    Request,
+    #[default]
+    Reply,
+    Both,
+}
+
+impl std::str::FromStr for AnnounceOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "request" => Ok(AnnounceOp::Request),
+            "reply" => Ok(AnnounceOp::Reply),
+            "both" => Ok(AnnounceOp::Both),
+            _ => Err(format!(
+                "invalid announce operation {} (expected request, reply or both)",
+                s
+            )),
+        }
+    }
+}
+
+// Which requests to answer by how they were delivered, where multicast neighbor
+// solicitations count as broadcast
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    #[default]
+    Any,
+    Broadcast,
+    Unicast,
+}
+
+impl Delivery {
+    pub fn allows(self, broadcast: bool) -> bool {
+        match self {
+            Delivery::Any => true,
+            Delivery::Broadcast => broadcast,
+            Delivery::Unicast => !broadcast,
+        }
+    }
+}
+
+impl std::str::FromStr for Delivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Delivery::Any),
+            "broadcast" => Ok(Delivery::Broadcast),
+            "unicast" => Ok(Delivery::Unicast),
+            _ => Err(format!(
+                "invalid delivery {} (expected any, broadcast or unicast)",
+                s
+            )),
+        }
+    }
+}
+
+// The source MAC address of frames: the interface's own or a specific one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameMac {
+    Interface,
+    Mac(MacAddress),
+}
+
+impl std::str::FromStr for FrameMac {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interface" => Ok(FrameMac::Interface),
+            _ => s.parse().map(FrameMac::Mac).map_err(|_| {
+                format!(
+                    "invalid frame MAC address {} (expected interface or a MAC address)",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FrameMac {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// What to do when another host probes (RFC 5227) whether a claimed IPv4 address is in use
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArpProbePolicy {
+    #[default]
+    Defend,
+    Ignore,
+    Alert,
+}
+
+impl std::str::FromStr for ArpProbePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "defend" => Ok(ArpProbePolicy::Defend),
+            "ignore" => Ok(ArpProbePolicy::Ignore),
+            "alert" => Ok(ArpProbePolicy::Alert),
+            _ => Err(format!(
+                "invalid ARP probe policy {} (expected defend, ignore or alert)",
+                s
+            )),
+        }
+    }
+}
+
+// What to do when another host performs duplicate address detection for a claimed
+// IPv6 address
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DadPolicy {
+    #[default]
+    Defend,
+    Alert,
+}
+
+impl std::str::FromStr for DadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "defend" => Ok(DadPolicy::Defend),
+            "alert" => Ok(DadPolicy::Alert),
+            _ => Err(format!(
+                "invalid DAD policy {} (expected defend or alert)",
+                s
+            )),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        std::fs::read_to_string(path)
+            .map_err(|err| Error::file(path, err))?
+            .parse()
+            .map_err(|err| Error::file(path, err))
+    }
+
+    // Merge all *.toml fragments in a directory in lexical order
+    pub fn load_dir(path: &Path) -> Result<Self, Error> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(path).map_err(|err| Error::file(path, err))? {
+            let path = entry.map_err(|err| Error::file(path, err))?.path();
+            if path.extension() == Some("toml".as_ref()) && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let mut config = Self::default();
+        for path in paths {
+            config.merge(Self::load(&path)?);
+        }
+        Ok(config)
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.claims.extend(other.claims);
+    }
+}
+
+// What to do when probing finds an IPv4 address already in use by another host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbePolicy {
+    Abort,
+    Wait,
+    Force,
+    // Wait until the owner stopped using the address for takeover_hold seconds
+    Takeover,
+}
+
+impl std::str::FromStr for ProbePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ProbePolicy::Abort),
+            "wait" => Ok(ProbePolicy::Wait),
+            "force" => Ok(ProbePolicy::Force),
+            "takeover" => Ok(ProbePolicy::Takeover),
+            _ => Err(format!(
+                "invalid probe policy {} (expected abort, wait, force or takeover)",
+                s
+            )),
+        }
+    }
+}
+
+// What to do when another host starts using a claimed IPv4 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    Defend,
+    Yield,
+    Exit,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "defend" => Ok(ConflictPolicy::Defend),
+            "yield" => Ok(ConflictPolicy::Yield),
+            "exit" => Ok(ConflictPolicy::Exit),
+            _ => Err(format!(
+                "invalid conflict policy {} (expected defend, yield or exit)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl Claim {
+    // A copy of the claim with the addresses its hostnames currently resolve to
+    pub fn resolve(&self) -> Result<Self, Error> {
+        let mut claim = self.clone();
+        for hostname in &self.hostname {
+            let addrs =
+                (hostname.as_str(), 0)
+                    .to_socket_addrs()
+                    .map_err(|source| Error::Resolve {
+                        hostname: hostname.clone(),
+                        source,
+                    })?;
+            for addr in addrs {
+                let net = IpNet::from(addr.ip());
+                if !claim.ip.contains(&net) {
+                    log::debug!("resolved {} to {}", hostname, net);
+                    claim.ip.push(net);
+                }
+            }
+        }
+        Ok(claim)
+    }
+
+    // Suffix to the interface name of expanded claims scoped to a VLAN, so they are named
+    // <iface>.<vlan>, or <iface>.<outer_vlan>.<vlan> with stacked tags
+    pub fn vlan_suffix(&self) -> String {
+        match (self.outer_vlan, self.vlan) {
+            (Some(outer), Some(vlan)) => format!(".{}.{}", outer, vlan),
+            (None, Some(vlan)) => format!(".{}", vlan),
+            _ => String::new(),
+        }
+    }
+
+    // The network interface itself, without the VLAN suffix
+    pub fn link(&self) -> &str {
+        self.iface
+            .strip_suffix(&self.vlan_suffix())
+            .unwrap_or(&self.iface)
+    }
+
+    pub fn vlan_tags(&self) -> Option<ether::Vlan> {
+        self.vlan.map(|id| ether::Vlan {
+            outer: self.outer_vlan,
+            id,
+        })
+    }
+
+    pub fn is_active(&self, minute: u32) -> bool {
+        self.windows.is_empty() || self.windows.iter().any(|window| window.contains(minute))
+    }
+
+    // The claimed addresses, for looking up many addresses
+    pub fn claim_set(&self) -> ClaimSet {
+        ClaimSet::new(&self.ip, &self.exclude)
+    }
+
+    pub fn answers(&self, mac: MacAddress, ip: IpAddr) -> bool {
+        (self.allow_macs.is_empty() || self.allow_macs.contains(&mac))
+            && !self.deny_macs.contains(&mac)
+            && (self.allow_from.is_empty() || self.allow_from.iter().any(|net| net.contains(ip)))
+    }
+
+    pub fn mac_for(&self, ip: IpAddr, default: MacAddress) -> MacAddress {
+        self.macs.get(&ip).copied().or(self.mac).unwrap_or(default)
+    }
+
+    pub fn reply_mac(&self, ip: IpAddr, requester: MacAddress, default: MacAddress) -> MacAddress {
+        self.reply_macs
+            .get(&requester)
+            .copied()
+            .unwrap_or_else(|| self.mac_for(ip, default))
+    }
+
+    // Source MAC address of frames carrying the MAC address answered with
+    pub fn frame_src(&self, mac: MacAddress, iface_mac: MacAddress) -> MacAddress {
+        match self.frame_mac {
+            None => mac,
+            Some(FrameMac::Interface) => iface_mac,
+            Some(FrameMac::Mac(src)) => src,
+        }
+    }
+
+    // The address Reverse ARP replies are sent from
+    pub fn rarp_server(&self, claimed: &ClaimSet) -> Option<Ipv4Addr> {
+        self.hosts(claimed).find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+    }
+
+    // The claimed hosts, given the claim's claim_set()
+    pub fn hosts<'a>(&'a self, claimed: &'a ClaimSet) -> impl Iterator<Item = IpAddr> + 'a {
+        self.ip
+            .iter()
+            .filter(move |_| !self.proxy && !self.sponge)
+            .flat_map(IpNet::hosts)
+            .filter(move |ip| claimed.contains(*ip))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct ConfigWatch {
+    inotify: Inotify,
+    file: Option<(WatchDescriptor, OsString)>,
+    dir: Option<WatchDescriptor>,
+}
+
+#[cfg(target_os = "linux")]
+impl ConfigWatch {
+    pub fn new(file: Option<&Path>, dir: Option<&Path>) -> sys::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let flags = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE;
+        // Watch the parent directory, as tools often replace the file instead of writing to it
+        let file = match file {
+            Some(path) => {
+                let parent = match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                let name = path.file_name().unwrap_or_default().to_owned();
+                Some((inotify.add_watch(parent, flags)?, name))
+            }
+            None => None,
+        };
+        let dir = match dir {
+            Some(path) => Some(inotify.add_watch(path, flags)?),
+            None => None,
+        };
+        Ok(Self { inotify, file, dir })
+    }
+
+    pub fn changed(&self) -> bool {
+        let events = match self.inotify.read_events() {
+            Ok(events) => events,
+            Err(err) => {
+                log::error!("failed to read inotify events: {}", err);
+                return false;
+            }
+        };
+        events.iter().any(|event| {
+            let name = event.name.as_deref().map(Path::new);
+            let file_changed = matches!(&self.file, Some((wd, file)) if *wd == event.wd && name == Some(Path::new(file)));
+            let dir_changed = self.dir == Some(event.wd)
+                && name.is_some_and(|name| name.extension() == Some("toml".as_ref()));
+            file_changed || dir_changed
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for ConfigWatch {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
+
+// Without inotify the configuration is only reloaded on SIGHUP, where there is one
+#[cfg(not(target_os = "linux"))]
+pub struct ConfigWatch(RawFd);
+
+#[cfg(not(target_os = "linux"))]
+impl ConfigWatch {
+    pub fn new(_file: Option<&Path>, _dir: Option<&Path>) -> sys::Result<Self> {
+        Err(sys::Error::EOPNOTSUPP)
+    }
+
+    pub fn changed(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl AsRawFd for ConfigWatch {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config: Config = r#"
+            [[claim]]
+            iface = "eth0"
+            ip = ["10.0.0.1", "10.0.5.0/29"]
+            exclude = ["10.0.5.1"]
+            announce = true
+            announce_interval = 30
+            announce_count = 3
+            announce_op = "both"
+            routers = ["02:00:00:00:00:fe"]
+            allow_macs = ["02:00:00:00:00:fe"]
+            windows = ["08:00-12:00", "13:00-17:00"]
+            probe = "wait"
+            on_conflict = "yield"
+
+            [[claim]]
+            iface = "eth0"
+            ip = ["10.0.1.1", "10.0.1.2", "2001:db8::1"]
+            vlan = 100
+            outer_vlan = 10
+            mac = "aa:bb:cc:dd:ee:ff"
+            frame_mac = "interface"
+            dad = "alert"
+            on_probe = "ignore"
+            delivery = "unicast"
+            na_flags = "router,override"
+            deny_macs = ["02:00:00:00:00:fe"]
+            reply_delay = 200
+            allow_from = ["10.0.0.0/24", "2001:db8::/64"]
+
+            [claim.macs]
+            "10.0.1.2" = "02:00:00:00:00:02"
+
+            [claim.reply_macs]
+            "02:00:00:00:00:fd" = "02:00:00:00:00:04"
+
+            [[claim]]
+            hostname = ["localhost"]
+
+            [[claim]]
+            iface = "eth1"
+            ip = ["10.8.0.0/16"]
+            proxy = true
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(config.claims.len(), 4);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(config.claims[0].announce);
+        assert_eq!(config.claims[0].announce_interval, Some(30));
+        assert_eq!(config.claims[0].announce_count, 3);
+        assert_eq!(config.claims[1].announce_count, 1);
+        assert_eq!(config.claims[0].announce_op, AnnounceOp::Both);
+        assert_eq!(config.claims[1].announce_op, AnnounceOp::Reply);
+        assert!(config.claims[0].is_active(9 * 60));
+        assert!(!config.claims[0].is_active(12 * 60 + 30));
+        assert!(config.claims[1].is_active(12 * 60 + 30));
+        assert_eq!(
+            config.claims[0].routers,
+            [MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0xfe])]
+        );
+        let router = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0xfe]);
+        let other = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x03]);
+        assert!(config.claims[0].answers(router, ip("10.0.0.2")));
+        assert!(!config.claims[0].answers(other, ip("10.0.0.2")));
+        assert!(!config.claims[1].answers(router, ip("10.0.0.2")));
+        assert!(config.claims[1].answers(other, ip("10.0.0.2")));
+        assert!(config.claims[2].answers(other, ip("10.0.0.2")));
+        assert!(config.claims[1].answers(other, ip("2001:db8::2")));
+        assert!(!config.claims[1].answers(other, ip("10.0.1.2")));
+        assert!(!config.claims[1].answers(other, ip("0.0.0.0")));
+        assert_eq!(config.claims[0].reply_delay, 0);
+        assert_eq!(config.claims[1].reply_delay, 200);
+        assert_eq!(config.claims[0].dad, DadPolicy::Defend);
+        assert_eq!(config.claims[0].probe, Some(ProbePolicy::Wait));
+        assert_eq!(config.claims[1].probe, None);
+        assert_eq!(config.claims[0].on_conflict, Some(ConflictPolicy::Yield));
+        assert_eq!(config.claims[1].dad, DadPolicy::Alert);
+        assert_eq!(config.claims[0].on_probe, ArpProbePolicy::Defend);
+        assert_eq!(config.claims[1].on_probe, ArpProbePolicy::Ignore);
+        assert_eq!(config.claims[0].delivery, Delivery::Any);
+        assert_eq!(config.claims[1].delivery, Delivery::Unicast);
+        assert_eq!(config.claims[0].na_flags, NaFlags::default());
+        assert_eq!(
+            config.claims[1].na_flags,
+            NaFlags(crate::ndp::NA_ROUTER | crate::ndp::NA_OVERRIDE)
+        );
+        assert_eq!(
+            config.claims[0]
+                .hosts(&config.claims[0].claim_set())
+                .collect::<Vec<_>>(),
+            [
+                ip("10.0.0.1"),
+                ip("10.0.5.2"),
+                ip("10.0.5.3"),
+                ip("10.0.5.4"),
+                ip("10.0.5.5"),
+                ip("10.0.5.6"),
+            ]
+        );
+        let if_mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(config.claims[0].mac_for(ip("10.0.0.1"), if_mac), if_mac);
+        assert_eq!(
+            config.claims[1].mac_for(ip("10.0.1.1"), if_mac),
+            MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff])
+        );
+        assert_eq!(
+            config.claims[1].mac_for(ip("10.0.1.2"), if_mac),
+            MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02])
+        );
+        assert_eq!(
+            config.claims[1].reply_mac(ip("10.0.1.2"), other, if_mac),
+            MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02])
+        );
+        assert_eq!(
+            config.claims[1].reply_mac(
+                ip("10.0.1.2"),
+                MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0xfd]),
+                if_mac
+            ),
+            MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x04])
+        );
+        assert_eq!(config.claims[0].link(), "eth0");
+        assert_eq!(
+            config.claims[1].vlan_tags(),
+            Some(ether::Vlan {
+                outer: Some(10),
+                id: 100
+            })
+        );
+        let vlan = Claim {
+            iface: "eth0.10.100".to_owned(),
+            ..config.claims[1].clone()
+        };
+        assert_eq!(vlan.link(), "eth0");
+        let mac = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        assert_eq!(config.claims[0].frame_src(mac, if_mac), mac);
+        assert_eq!(config.claims[1].frame_src(mac, if_mac), if_mac);
+        let claimed = config.claims[1].claim_set();
+        assert!(claimed.contains(ip("2001:db8::1")));
+        assert!(!claimed.contains(ip("2001:db8::2")));
+        let claimed = config.claims[3].claim_set();
+        assert!(claimed.contains(ip("10.8.3.4")));
+        assert_eq!(config.claims[3].hosts(&claimed).count(), 0);
+
+        let resolved = config.claims[2].resolve().unwrap();
+        assert!(resolved.claim_set().contains(ip("127.0.0.1")));
+        assert!("[[claim]]\niface = \"eth0\"\nip = [\"10.0.0.1\"]\nfoo = 1"
+            .parse::<Config>()
+            .is_err());
+    }
+}
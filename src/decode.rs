@@ -0,0 +1,157 @@
+use crate::arp::{Arp, ArpPacket, Hex};
+use crate::ether;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+const ETH_P_ARP: u16 = 0x0806;
+const ETH_P_RARP: u16 = 0x8035;
+
+// The bytes of a line of hexadecimal, either as one run of digits or as bytes separated by
+// whitespace, colons, dashes or commas, optionally prefixed with 0x, so hex dumps and the
+// slices logged by claim-ip itself ("[ff, ff, ...]") can be pasted as is
+pub fn parse_hex(line: &str) -> Option<Vec<u8>> {
+    let tokens: Vec<&str> = line
+        .split(|c: char| c.is_whitespace() || ":-,[]".contains(c))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.strip_prefix("0x").unwrap_or(token))
+        .collect();
+    let mut bytes = Vec::new();
+    for token in &tokens {
+        if token.len() == 1 && tokens.len() > 1 {
+            bytes.push(u8::from_str_radix(token, 16).ok()?);
+            continue;
+        }
+        if token.len() % 2 != 0 || !token.is_ascii() {
+            return None;
+        }
+        for i in (0..token.len()).step_by(2) {
+            bytes.push(u8::from_str_radix(&token[i..i + 2], 16).ok()?);
+        }
+    }
+    Some(bytes)
+}
+
+// A description of the ARP packet in an ethernet frame or on its own, ending with the error
+// and the offset in the input of the field it's about when it doesn't parse
+pub fn describe(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut offset = 0;
+    if let Ok(header) = ether::Header::try_from(data) {
+        if header.ethertype == ETH_P_ARP || header.ethertype == ETH_P_RARP {
+            let _ = write!(
+                out,
+                "ethernet {} > {}",
+                header.src.to_hex_string(),
+                header.dst.to_hex_string()
+            );
+            match header.vlan {
+                Some(ether::Vlan {
+                    outer: Some(outer),
+                    id,
+                }) => {
+                    let _ = write!(out, " vlan {}.{}", outer, id);
+                }
+                Some(ether::Vlan { outer: None, id }) => {
+                    let _ = write!(out, " vlan {}", id);
+                }
+                None => {}
+            }
+            if header.snap {
+                let _ = write!(out, " llc/snap");
+            }
+            let _ = writeln!(out, " ethertype {:#06x}", header.ethertype);
+            offset = header.size();
+        }
+    }
+
+    let pkt = &data[offset..];
+    let error = match ArpPacket::try_from(pkt) {
+        Ok(parsed) => {
+            let _ = writeln!(
+                out,
+                "arp htype {} ptype {:#06x} hlen {} plen {} op {} ({:?})",
+                parsed.htype,
+                parsed.ptype,
+                parsed.sha.len(),
+                parsed.spa.len(),
+                u16::from(parsed.op),
+                parsed.op
+            );
+            let _ = writeln!(out, "  sender {} {}", Hex(parsed.sha), Hex(parsed.spa));
+            let _ = writeln!(out, "  target {} {}", Hex(parsed.tha), Hex(parsed.tpa));
+            let trailing = pkt.len() - 8 - 2 * (parsed.sha.len() + parsed.spa.len());
+            if trailing > 0 {
+                let _ = writeln!(out, "  {} bytes of padding", trailing);
+            }
+            match Arp::try_from(parsed) {
+                Ok(arp) => {
+                    let _ = writeln!(out, "{}", arp);
+                    None
+                }
+                Err(err) => Some(err),
+            }
+        }
+        Err(err) => Some(err),
+    };
+    if let Some(err) = error {
+        let _ = writeln!(
+            out,
+            "error: {:?} at offset {}: {}",
+            err,
+            offset + err.offset(pkt),
+            err
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        let pkt = [0xff, 0x00, 0x08, 0x06];
+        assert_eq!(parse_hex("ff000806"), Some(pkt.to_vec()));
+        assert_eq!(parse_hex("0xff 0x00 0x08 0x06"), Some(pkt.to_vec()));
+        assert_eq!(parse_hex("ff:00:08:06"), Some(pkt.to_vec()));
+        assert_eq!(parse_hex("[ff, 0, 8, 6]"), Some(pkt.to_vec()));
+        assert_eq!(parse_hex("ff00 0806"), Some(pkt.to_vec()));
+        assert_eq!(parse_hex("ff0"), None);
+        assert_eq!(parse_hex("request"), None);
+    }
+
+    #[test]
+    fn describe_frames() {
+        let request = parse_hex(
+            "ffffffffffff 3e98e3f41ca7 8100 0064 0806 \
+             0001 0800 0604 0001 3e98e3f41ca7 0a090001 000000000000 0a09004d",
+        )
+        .unwrap();
+        assert_eq!(
+            describe(&request),
+            "ethernet 3e:98:e3:f4:1c:a7 > ff:ff:ff:ff:ff:ff vlan 100 ethertype 0x0806\n\
+             arp htype 1 ptype 0x0800 hlen 6 plen 4 op 1 (Request)\n  \
+             sender 3e:98:e3:f4:1c:a7 0a:09:00:01\n  \
+             target 00:00:00:00:00:00 0a:09:00:4d\n\
+             request who-has 10.9.0.77 tell 10.9.0.1 (3e:98:e3:f4:1c:a7)\n"
+        );
+        assert_eq!(
+            describe(&request[..40]),
+            "ethernet 3e:98:e3:f4:1c:a7 > ff:ff:ff:ff:ff:ff vlan 100 ethertype 0x0806\n\
+             error: BufferTooSmall at offset 40: Packet buffer too small\n"
+        );
+
+        let eui64 =
+            parse_hex("0006 0800 0804 0001 0211223344556677 0a000001 0000000000000000 0a000002")
+                .unwrap();
+        assert_eq!(
+            describe(&eui64),
+            "arp htype 6 ptype 0x0800 hlen 8 plen 4 op 1 (Request)\n  \
+             sender 02:11:22:33:44:55:66:77 0a:00:00:01\n  \
+             target 00:00:00:00:00:00:00:00 0a:00:00:02\n\
+             error: UnsupportedAddressLength { hlen: 8, plen: 4 } at offset 4: \
+             Unsupported ARP address lengths 8 and 4 (not 6 and 4)\n"
+        );
+    }
+}
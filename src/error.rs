@@ -0,0 +1,198 @@
+use crate::sys;
+use eui48::MacAddress;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+// Why claiming addresses failed, with the interface, address or file involved, so library
+// users can tell failures apart without parsing messages
+#[derive(Debug)]
+pub enum Error {
+    // Listing the network interfaces and their addresses failed
+    Interfaces(sys::Error),
+    // No interface of this name has a link address
+    InterfaceMissing {
+        iface: String,
+    },
+    // Packet sockets require CAP_NET_RAW
+    PermissionDenied {
+        iface: String,
+    },
+    // Opening or setting up a socket on an interface failed otherwise
+    Socket {
+        iface: String,
+        purpose: &'static str,
+        source: sys::Error,
+    },
+    // Sending a packet on an interface failed
+    Send {
+        iface: String,
+        packet: &'static str,
+        source: sys::Error,
+    },
+    // Receiving a packet on an interface failed
+    Receive {
+        iface: String,
+        source: sys::Error,
+    },
+    // Waiting for packets on the sockets failed
+    Poll(sys::Error),
+    // Another host uses a claimed address, which claims give up on by stopping
+    Conflict {
+        iface: String,
+        ip: IpAddr,
+        mac: MacAddress,
+    },
+    // Another host already used an address being probed for, which claims give up on by
+    // stopping
+    InUse {
+        iface: String,
+        ip: IpAddr,
+        mac: MacAddress,
+    },
+    // The interface routing an address couldn't be looked up
+    Route {
+        ip: IpAddr,
+        message: String,
+    },
+    Resolve {
+        hostname: String,
+        source: std::io::Error,
+    },
+    // A configuration or ethers file couldn't be read or parsed
+    File {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl Error {
+    // Socket errors, classifying missing privileges
+    pub(crate) fn socket(iface: &str, purpose: &'static str, source: sys::Error) -> Self {
+        match source {
+            sys::Error::EPERM | sys::Error::EACCES => Error::PermissionDenied {
+                iface: iface.to_owned(),
+            },
+            source => Error::Socket {
+                iface: iface.to_owned(),
+                purpose,
+                source,
+            },
+        }
+    }
+
+    pub(crate) fn send(iface: &str, packet: &'static str, source: sys::Error) -> Self {
+        Error::Send {
+            iface: iface.to_owned(),
+            packet,
+            source,
+        }
+    }
+
+    pub(crate) fn file(path: &std::path::Path, message: impl fmt::Display) -> Self {
+        Error::File {
+            path: path.to_owned(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Interfaces(err) => write!(f, "failed to list interfaces: {}", err),
+            Error::InterfaceMissing { iface } => write!(f, "interface {} not found", iface),
+            Error::PermissionDenied { iface } => write!(
+                f,
+                "permission denied opening sockets on interface {} (requires CAP_NET_RAW)",
+                iface
+            ),
+            Error::Socket {
+                iface,
+                purpose,
+                source,
+            } => write!(
+                f,
+                "failed to set up {} on interface {}: {}",
+                purpose, iface, source
+            ),
+            Error::Send {
+                iface,
+                packet,
+                source,
+            } => write!(
+                f,
+                "failed to send {} on interface {}: {}",
+                packet, iface, source
+            ),
+            Error::Receive { iface, source } => {
+                write!(
+                    f,
+                    "failed to receive packet on interface {}: {}",
+                    iface, source
+                )
+            }
+            Error::Poll(err) => write!(f, "failed to poll: {}", err),
+            Error::Conflict { iface, ip, mac } => {
+                write!(f, "IP {} on {} is also used by {}", ip, iface, mac)
+            }
+            Error::InUse { iface, ip, mac } => {
+                write!(f, "IP {} on {} is in use by {}", ip, iface, mac)
+            }
+            Error::Route { ip, message } => {
+                write!(f, "failed to lookup interface for {}: {}", ip, message)
+            }
+            Error::Resolve { hostname, source } => {
+                write!(f, "failed to resolve {}: {}", hostname, source)
+            }
+            Error::File { path, message } => {
+                write!(f, "failed to load {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Interfaces(err)
+            | Error::Socket { source: err, .. }
+            | Error::Send { source: err, .. }
+            | Error::Receive { source: err, .. }
+            | Error::Poll(err) => Some(err),
+            Error::Resolve { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket() {
+        assert!(matches!(
+            Error::socket("eth0", "arp socket", sys::Error::EPERM),
+            Error::PermissionDenied { iface } if iface == "eth0"
+        ));
+        let err = Error::socket("eth0", "arp socket", sys::Error::ENODEV);
+        assert!(matches!(err, Error::Socket { .. }));
+        assert_eq!(
+            err.to_string(),
+            "failed to set up arp socket on interface eth0: ENODEV: No such device"
+        );
+        assert_eq!(
+            Error::send("eth0", "arp probe", sys::Error::ENETDOWN).to_string(),
+            "failed to send arp probe on interface eth0: ENETDOWN: Network is down"
+        );
+        let err = Error::Receive {
+            iface: "eth0".to_owned(),
+            source: sys::Error::EIO,
+        };
+        assert_eq!(
+            err.to_string(),
+            "failed to receive packet on interface eth0: EIO: I/O error"
+        );
+    }
+}
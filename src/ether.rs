@@ -0,0 +1,274 @@
+use eui48::MacAddress;
+use std::convert::TryFrom;
+
+pub const HEADER_LEN: usize = 14;
+pub const VLAN_TAG_LEN: usize = 4;
+// Without the frame check sequence
+pub const MIN_FRAME_LEN: usize = 60;
+pub const ETH_P_ARP: u16 = 0x0806;
+pub const ETH_P_RARP: u16 = 0x8035;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+pub const ETH_P_8021Q: u16 = 0x8100;
+pub const ETH_P_8021AD: u16 = 0x88a8;
+// The protocol the kernel reports for 802.3 frames with an LLC header
+pub const ETH_P_802_2: u16 = 0x0004;
+pub const SNAP_LEN: usize = 8;
+// LLC header for SNAP (DSAP, SSAP and control) and the OUI of encapsulated ethertypes
+const SNAP_HEADER: [u8; 6] = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00];
+
+// The VLAN of a frame: the ID of its 802.1Q tag, optionally inside the ID of an 802.1ad
+// service tag (QinQ)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Vlan {
+    pub outer: Option<u16>,
+    pub id: u16,
+}
+
+// The ethernet header, as seen by and sent from raw packet sockets
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Header {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub vlan: Option<Vlan>,
+    pub ethertype: u16,
+    // An 802.3 frame with the ethertype in an LLC/SNAP header (RFC 1042), as some old or
+    // industrial equipment sends ARP
+    pub snap: bool,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum EtherError {
+    BufferTooSmall,
+}
+
+impl std::fmt::Display for EtherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EtherError::BufferTooSmall => write!(f, "Frame buffer too small"),
+        }
+    }
+}
+
+impl std::error::Error for EtherError {}
+
+impl Header {
+    // The tags in the order they appear in the frame, with their tag protocol identifiers
+    fn tags(&self) -> impl Iterator<Item = (u16, u16)> {
+        let outer = self.vlan.and_then(|vlan| vlan.outer);
+        let inner = self.vlan.map(|vlan| vlan.id);
+        outer
+            .map(|outer| (ETH_P_8021AD, outer))
+            .into_iter()
+            .chain(inner.map(|inner| (ETH_P_8021Q, inner)))
+    }
+
+    pub fn size(&self) -> usize {
+        let snap = if self.snap { SNAP_LEN } else { 0 };
+        HEADER_LEN + VLAN_TAG_LEN * self.tags().count() + snap
+    }
+
+    // The 802.3 length of SNAP frames covers the rest of the buffer, which should then hold
+    // the whole frame
+    pub fn fill<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], EtherError> {
+        let len = self.size();
+        if buf.len() < len {
+            return Err(EtherError::BufferTooSmall);
+        }
+        let end = if self.snap { len - SNAP_LEN } else { len };
+        buf[0..=5].copy_from_slice(self.dst.as_bytes());
+        buf[6..=11].copy_from_slice(self.src.as_bytes());
+        for (tag, (tpid, id)) in buf[12..end - 2].chunks_mut(VLAN_TAG_LEN).zip(self.tags()) {
+            tag[0..=1].copy_from_slice(&tpid.to_be_bytes());
+            tag[2..=3].copy_from_slice(&(id & 0xfff).to_be_bytes());
+        }
+        if self.snap {
+            let length = u16::try_from(buf.len() - end).map_err(|_| EtherError::BufferTooSmall)?;
+            buf[end - 2..end].copy_from_slice(&length.to_be_bytes());
+            buf[end..len - 2].copy_from_slice(&SNAP_HEADER);
+        }
+        buf[len - 2..len].copy_from_slice(&self.ethertype.to_be_bytes());
+        Ok(&buf[..len])
+    }
+
+    // The frame with this header and payload
+    pub fn frame(&self, payload: &[u8]) -> Vec<u8> {
+        let len = self.size();
+        let mut frame = vec![0u8; len + payload.len()];
+        frame[len..].copy_from_slice(payload);
+        self.fill(&mut frame)
+            .expect("failed to construct ethernet header");
+        frame
+    }
+}
+
+impl TryFrom<&'_ [u8]> for Header {
+    type Error = EtherError;
+
+    fn try_from(frame: &'_ [u8]) -> Result<Self, Self::Error> {
+        if frame.len() < HEADER_LEN {
+            return Err(EtherError::BufferTooSmall);
+        }
+        let mut header = Self {
+            dst: MacAddress::from_bytes(&frame[0..=5]).map_err(|_| EtherError::BufferTooSmall)?,
+            src: MacAddress::from_bytes(&frame[6..=11]).map_err(|_| EtherError::BufferTooSmall)?,
+            vlan: None,
+            ethertype: u16::from_be_bytes([frame[12], frame[13]]),
+            snap: false,
+        };
+        // An 802.1ad service tag on its own is taken as the VLAN, like a customer tag
+        let mut offset = HEADER_LEN;
+        let mut outer = None;
+        if header.ethertype == ETH_P_8021AD {
+            let (id, ethertype) = tag(frame, offset)?;
+            header.ethertype = ethertype;
+            header.vlan = Some(Vlan { outer: None, id });
+            outer = Some(id);
+            offset += VLAN_TAG_LEN;
+        }
+        if header.ethertype == ETH_P_8021Q {
+            let (id, ethertype) = tag(frame, offset)?;
+            header.ethertype = ethertype;
+            header.vlan = Some(Vlan { outer, id });
+            offset += VLAN_TAG_LEN;
+        }
+        // Values below 0x0600 are the length of 802.3 frames, of which only those with
+        // a SNAP header carry an ethertype
+        if header.ethertype < 0x0600
+            && frame.get(offset..offset + SNAP_HEADER.len()) == Some(&SNAP_HEADER[..])
+        {
+            let offset = offset + SNAP_HEADER.len();
+            let ethertype = frame
+                .get(offset..offset + 2)
+                .ok_or(EtherError::BufferTooSmall)?;
+            header.ethertype = u16::from_be_bytes([ethertype[0], ethertype[1]]);
+            header.snap = true;
+        }
+        Ok(header)
+    }
+}
+
+// The VLAN ID of the tag ending at the offset and the ethertype following it
+fn tag(frame: &[u8], offset: usize) -> Result<(u16, u16), EtherError> {
+    if frame.len() < offset + VLAN_TAG_LEN {
+        return Err(EtherError::BufferTooSmall);
+    }
+    let id = u16::from_be_bytes([frame[offset], frame[offset + 1]]) & 0xfff;
+    let ethertype = u16::from_be_bytes([frame[offset + 2], frame[offset + 3]]);
+    Ok((id, ethertype))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let frame: [u8; 16] = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // destination
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, // source
+            0x08, 0x06, // ethertype
+            0x00, 0x01, // payload
+        ];
+        let header = Header::try_from(&frame[..]).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                dst: MacAddress::broadcast(),
+                src: MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+                vlan: None,
+                ethertype: 0x0806,
+                snap: false,
+            }
+        );
+
+        let mut buf = [0u8; 18];
+        assert_eq!(header.fill(&mut buf[..]), Ok(&frame[..HEADER_LEN]));
+        assert_eq!(
+            Header::try_from(&frame[..13]),
+            Err(EtherError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn vlan() {
+        let frame: [u8; 18] = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // destination
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, // source
+            0x81, 0x00, 0x00, 0x64, // 802.1q tag
+            0x08, 0x06, // ethertype
+        ];
+        let header = Header::try_from(&frame[..]).unwrap();
+        assert_eq!(
+            header.vlan,
+            Some(Vlan {
+                outer: None,
+                id: 100
+            })
+        );
+        assert_eq!(header.ethertype, 0x0806);
+        assert_eq!(header.size(), 18);
+
+        let mut buf = [0u8; 18];
+        assert_eq!(header.fill(&mut buf[..]), Ok(&frame[..]));
+        assert_eq!(
+            Header::try_from(&frame[..16]),
+            Err(EtherError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn qinq() {
+        let frame: [u8; 22] = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // destination
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, // source
+            0x88, 0xa8, 0x00, 0x0a, // 802.1ad tag
+            0x81, 0x00, 0x00, 0x64, // 802.1q tag
+            0x08, 0x06, // ethertype
+        ];
+        let header = Header::try_from(&frame[..]).unwrap();
+        assert_eq!(
+            header.vlan,
+            Some(Vlan {
+                outer: Some(10),
+                id: 100
+            })
+        );
+        assert_eq!(header.ethertype, 0x0806);
+        assert_eq!(header.size(), 22);
+
+        let mut buf = [0u8; 22];
+        assert_eq!(header.fill(&mut buf[..]), Ok(&frame[..]));
+        assert_eq!(
+            Header::try_from(&frame[..20]),
+            Err(EtherError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn snap() {
+        let frame: [u8; 26] = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // destination
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, // source
+            0x00, 0x0c, // length
+            0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, // llc/snap header
+            0x08, 0x06, // ethertype
+            0x00, 0x01, 0x08, 0x00, // payload
+        ];
+        let header = Header::try_from(&frame[..]).unwrap();
+        assert!(header.snap);
+        assert_eq!(header.ethertype, 0x0806);
+        assert_eq!(header.size(), 22);
+        assert_eq!(header.frame(&frame[22..]), frame);
+        assert_eq!(
+            Header::try_from(&frame[..21]),
+            Err(EtherError::BufferTooSmall)
+        );
+
+        // Other 802.2 frames, like spanning tree BPDUs, keep their length
+        let mut stp = frame;
+        stp[14..=16].copy_from_slice(&[0x42, 0x42, 0x03]);
+        let header = Header::try_from(&stp[..]).unwrap();
+        assert!(!header.snap);
+        assert_eq!(header.ethertype, 0x000c);
+    }
+}
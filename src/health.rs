@@ -0,0 +1,444 @@
+use crate::tls::TlsStream;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+use nix::fcntl::FdFlag;
+use nix::sys::socket::{InetAddr, MsgFlags, SockAddr};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::io::RawFd;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+// A health check that has to pass for requests to be answered
+#[derive(Debug, Clone)]
+pub enum Check {
+    // Shell command that has to exit successfully
+    Command(String),
+    // Host and port that have to accept TCP connections
+    Tcp(String),
+    // HTTP(S) URL that has to respond with the expected status code
+    Http {
+        url: String,
+        addr: String,
+        host: String,
+        path: String,
+        tls: bool,
+        status: u16,
+    },
+    // Host that has to answer ICMP echo requests
+    Ping(String),
+}
+
+impl Check {
+    // HTTPS uses libssl, loaded when first checking
+    pub fn http(url: &str, status: u16) -> Result<Self, String> {
+        let (rest, tls) = match url.strip_prefix("https://") {
+            Some(rest) => (rest, true),
+            None => url
+                .strip_prefix("http://")
+                .map(|rest| (rest, false))
+                .ok_or_else(|| format!("unsupported URL {} (expected http:// or https://)", url))?,
+        };
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(format!("URL {} has no host", url));
+        }
+        // Ports are optional, except for IPv6 addresses that are recognized by their brackets
+        let addr = match host.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') => host.to_owned(),
+            _ => format!("{}:{}", host, if tls { 443 } else { 80 }),
+        };
+        Ok(Check::Http {
+            url: url.to_owned(),
+            addr,
+            host: host.to_owned(),
+            path: path.to_owned(),
+            tls,
+            status,
+        })
+    }
+
+    pub fn run(&self, timeout: Duration) -> Result<(), String> {
+        match self {
+            Check::Command(cmd) => run_command(cmd, timeout),
+            Check::Tcp(addr) => connect(addr, timeout).map(drop),
+            Check::Http {
+                addr,
+                host,
+                path,
+                tls,
+                status,
+                ..
+            } => get(addr, host, path, *tls, timeout).and_then(|got| {
+                if got == *status {
+                    Ok(())
+                } else {
+                    Err(format!("responded with status {}", got))
+                }
+            }),
+            Check::Ping(host) => ping(host, timeout),
+        }
+    }
+}
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Check::Command(cmd) => write!(f, "command {:?}", cmd),
+            Check::Tcp(addr) => write!(f, "tcp {}", addr),
+            Check::Http { url, .. } => write!(f, "http {}", url),
+            Check::Ping(host) => write!(f, "ping {}", host),
+        }
+    }
+}
+
+fn run_command(cmd: &str, timeout: Duration) -> Result<(), String> {
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("failed to run: {}", err))?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(status.to_string()),
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err("timed out".to_owned());
+            }
+            Ok(None) => std::thread::sleep(WAIT_INTERVAL),
+            Err(err) => return Err(format!("failed to wait: {}", err)),
+        }
+    }
+}
+
+fn connect(addr: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let addrs = addr
+        .to_socket_addrs()
+        .map_err(|err| format!("failed to resolve: {}", err))?;
+    let mut result = Err("no addresses to connect to".to_owned());
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => result = Err(format!("failed to connect to {}: {}", addr, err)),
+        }
+    }
+    result
+}
+
+// The host name in the host of a URL, without its port or the brackets of IPv6 addresses
+fn hostname(host: &str) -> &str {
+    match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => host.split(':').next().unwrap_or(host),
+    }
+}
+
+// Perform an HTTP GET request, over TLS for HTTPS, returning the response status code
+fn get(addr: &str, host: &str, path: &str, tls: bool, timeout: Duration) -> Result<u16, String> {
+    let stream = connect(addr, timeout)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|err| err.to_string())?;
+    if tls {
+        request(TlsStream::connect(stream, hostname(host))?, host, path)
+    } else {
+        request(stream, host, path)
+    }
+}
+
+fn request(mut stream: impl Read + Write, host: &str, path: &str) -> Result<u16, String> {
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: claim-ip\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|err| format!("failed to send request: {}", err))?;
+
+    // Only the status line is of interest
+    let mut buf = [0u8; 256];
+    let mut size = 0;
+    while size < buf.len() && !buf[..size].contains(&b'\n') {
+        match stream.read(&mut buf[size..]) {
+            Ok(0) => break,
+            Ok(n) => size += n,
+            Err(err) => return Err(format!("failed to receive response: {}", err)),
+        }
+    }
+    let line = String::from_utf8_lossy(&buf[..size]);
+    let mut fields = line.split_whitespace();
+    match (fields.next(), fields.next().map(str::parse)) {
+        (Some(version), Some(Ok(status))) if version.starts_with("HTTP/") => Ok(status),
+        _ => Err("invalid response".to_owned()),
+    }
+}
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+static PING_SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+fn ping(host: &str, timeout: Duration) -> Result<(), String> {
+    let addr = (host, 0)
+        .to_socket_addrs()
+        .map_err(|err| format!("failed to resolve: {}", err))?
+        .next()
+        .ok_or_else(|| "no addresses to ping".to_owned())?;
+    let (family, protocol) = match addr {
+        SocketAddr::V4(_) => (nix::libc::AF_INET, nix::libc::IPPROTO_ICMP),
+        SocketAddr::V6(_) => (nix::libc::AF_INET6, nix::libc::IPPROTO_ICMPV6),
+    };
+    let socket = unsafe { nix::libc::socket(family, nix::libc::SOCK_RAW | SOCK_CLOEXEC, protocol) };
+    let socket =
+        nix::Error::result(socket).map_err(|err| format!("failed to open socket: {}", err))?;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    let _ = nix::fcntl::fcntl(socket, nix::fcntl::FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC));
+    let result = echo(socket, addr, timeout);
+    let _ = nix::unistd::close(socket);
+    result
+}
+
+fn echo(socket: RawFd, addr: SocketAddr, timeout: Duration) -> Result<(), String> {
+    let id = std::process::id() as u16;
+    let seq = PING_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let kind = match addr {
+        SocketAddr::V4(_) => ICMP_ECHO_REQUEST,
+        SocketAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+    };
+    let mut req = [0u8; 16];
+    req[0] = kind;
+    req[4..6].copy_from_slice(&id.to_be_bytes());
+    req[6..8].copy_from_slice(&seq.to_be_bytes());
+    // The kernel fills in the checksum of ICMPv6 messages
+    if addr.is_ipv4() {
+        let sum = checksum(&req);
+        req[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    nix::sys::socket::sendto(
+        socket,
+        &req,
+        &SockAddr::Inet(InetAddr::from_std(&addr)),
+        MsgFlags::empty(),
+    )
+    .map_err(|err| format!("failed to send echo request: {}", err))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("timed out".to_owned());
+        }
+        let mut pfd = [nix::poll::PollFd::new(socket, nix::poll::PollFlags::POLLIN)];
+        match nix::poll::poll(&mut pfd, remaining.as_millis().max(1) as i32) {
+            Ok(0) => return Err("timed out".to_owned()),
+            Ok(_) | Err(nix::Error::EINTR) => {}
+            Err(err) => return Err(format!("failed to poll: {}", err)),
+        }
+        let (size, from) = match nix::sys::socket::recvfrom(socket, &mut buf) {
+            Ok(r) => r,
+            Err(nix::Error::EINTR) | Err(nix::Error::EAGAIN) => continue,
+            Err(err) => return Err(format!("failed to receive echo reply: {}", err)),
+        };
+        let from = match from {
+            Some(SockAddr::Inet(from)) => from.ip().to_std(),
+            _ => continue,
+        };
+        if is_echo_reply(&buf[..size], addr.ip(), from, id, seq) {
+            return Ok(());
+        }
+    }
+}
+
+// Raw ICMP sockets receive the IP header as well, unlike raw ICMPv6 sockets
+fn is_echo_reply(pkt: &[u8], to: IpAddr, from: IpAddr, id: u16, seq: u16) -> bool {
+    let (msg, kind) = match to {
+        IpAddr::V4(_) => match pkt.first() {
+            Some(vhl) => (pkt.get(usize::from(vhl & 0xf) * 4..), ICMP_ECHO_REPLY),
+            None => return false,
+        },
+        IpAddr::V6(_) => (Some(pkt), ICMPV6_ECHO_REPLY),
+    };
+    match msg {
+        Some(msg) if msg.len() >= 8 => {
+            from == to
+                && msg[0] == kind
+                && msg[4..6] == id.to_be_bytes()
+                && msg[6..8] == seq.to_be_bytes()
+        }
+        _ => false,
+    }
+}
+
+fn checksum(msg: &[u8]) -> u16 {
+    let mut sum: u32 = msg
+        .chunks(2)
+        .map(|chunk| u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// File descriptors are closed on exec, so health check commands don't inherit them, which
+// macOS can only set up after opening them
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const SOCK_CLOEXEC: nix::libc::c_int = nix::libc::SOCK_CLOEXEC;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const SOCK_CLOEXEC: nix::libc::c_int = 0;
+
+// The health checks, which pass when all of them do, remembering the health to log changes
+pub struct HealthChecks {
+    checks: Vec<Check>,
+    timeout: Duration,
+    healthy: Option<bool>,
+}
+
+impl HealthChecks {
+    pub fn new(checks: Vec<Check>, timeout: Duration) -> Self {
+        Self {
+            checks,
+            timeout,
+            healthy: None,
+        }
+    }
+
+    // Run the checks until one fails, returning the health if it changed
+    pub fn run(&mut self) -> Option<bool> {
+        let failure = self
+            .checks
+            .iter()
+            .find_map(|check| check.run(self.timeout).err().map(|err| (check, err)));
+        match &failure {
+            Some((check, err)) if self.healthy != Some(false) => {
+                log::warn!("health check {} failed: {}", check, err)
+            }
+            None if self.healthy != Some(true) => log::info!("health checks passed"),
+            _ => {}
+        }
+        let healthy = failure.is_none();
+        if self.healthy == Some(healthy) {
+            return None;
+        }
+        self.healthy = Some(healthy);
+        Some(healthy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command() {
+        let timeout = Duration::from_millis(500);
+        assert_eq!(Check::Command("true".to_owned()).run(timeout), Ok(()));
+        assert!(Check::Command("exit 3".to_owned()).run(timeout).is_err());
+        assert_eq!(
+            Check::Command("sleep 5".to_owned()).run(Duration::from_millis(100)),
+            Err("timed out".to_owned())
+        );
+
+        let mut checks = HealthChecks::new(vec![Check::Command("true".to_owned())], timeout);
+        assert_eq!(checks.run(), Some(true));
+        assert_eq!(checks.run(), None);
+    }
+
+    #[test]
+    fn tcp() {
+        let timeout = Duration::from_millis(500);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert_eq!(Check::Tcp(addr.clone()).run(timeout), Ok(()));
+        drop(listener);
+        assert!(Check::Tcp(addr).run(timeout).is_err());
+    }
+
+    #[test]
+    fn echo_reply() {
+        let to: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut pkt = [0u8; 28];
+        pkt[0] = 0x45;
+        pkt[24..28].copy_from_slice(&[0x12, 0x34, 0x00, 0x01]);
+        assert!(is_echo_reply(&pkt, to, to, 0x1234, 1));
+        assert!(!is_echo_reply(&pkt, to, to, 0x1234, 2));
+        assert!(!is_echo_reply(
+            &pkt,
+            to,
+            "10.0.0.2".parse().unwrap(),
+            0x1234,
+            1
+        ));
+        assert!(!is_echo_reply(&pkt[..24], to, to, 0x1234, 1));
+
+        let to: IpAddr = "2001:db8::1".parse().unwrap();
+        let pkt = [ICMPV6_ECHO_REPLY, 0, 0, 0, 0x12, 0x34, 0x00, 0x01];
+        assert!(is_echo_reply(&pkt, to, to, 0x1234, 1));
+
+        assert_eq!(
+            checksum(&[ICMP_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0, 1]),
+            0xe5ca
+        );
+    }
+
+    #[test]
+    fn http() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 512];
+                let mut size = 0;
+                while !buf[..size].ends_with(b"\r\n\r\n") {
+                    size += stream.read(&mut buf[size..]).unwrap();
+                }
+                assert!(buf[..size].starts_with(b"GET /health HTTP/1.0\r\n"));
+                write!(stream, "HTTP/1.1 {}\r\n\r\n", status).unwrap();
+            }
+        });
+        let check = Check::http(&url, 200).unwrap();
+        let timeout = Duration::from_millis(500);
+        assert_eq!(check.run(timeout), Ok(()));
+        assert_eq!(
+            check.run(timeout),
+            Err("responded with status 503".to_owned())
+        );
+        server.join().unwrap();
+
+        assert!(Check::http("ftp://localhost/", 200).is_err());
+        match Check::http("http://[::1]/", 200).unwrap() {
+            Check::Http { addr, path, .. } => {
+                assert_eq!(addr, "[::1]:80");
+                assert_eq!(path, "/");
+            }
+            _ => unreachable!(),
+        }
+        match Check::http("https://example.com/health", 200).unwrap() {
+            Check::Http {
+                addr, host, tls, ..
+            } => {
+                assert_eq!(addr, "example.com:443");
+                assert_eq!(hostname(&host), "example.com");
+                assert!(tls);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(hostname("[::1]:8443"), "::1");
+        assert_eq!(hostname("10.0.0.1:8443"), "10.0.0.1");
+    }
+}
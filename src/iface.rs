@@ -0,0 +1,1317 @@
+use crate::error::Error;
+use crate::ether;
+use crate::link::{
+    bpf_jump, bpf_stmt, BpfInsn, BPF_JA, BPF_JEQ_K, BPF_JGE_K, BPF_JGT_K, BPF_LDB_ABS, BPF_LDH_ABS,
+    BPF_LD_ABS, BPF_RET_K,
+};
+#[cfg(target_os = "linux")]
+use crate::link::{raw_frame, Membership, PacketType, BPF_AND_K};
+use crate::ndp;
+use crate::net::{ClaimSet, Ipv4Net};
+use crate::pcap;
+use crate::sys;
+use eui48::MacAddress;
+#[cfg(unix)]
+use nix::ifaddrs::getifaddrs;
+#[cfg(unix)]
+use nix::sys::socket::{recv, MsgFlags, SockAddr};
+#[cfg(target_os = "linux")]
+use nix::sys::socket::{socket, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType};
+#[cfg(target_os = "linux")]
+use std::borrow::Cow;
+#[cfg(target_os = "linux")]
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+#[cfg(target_os = "linux")]
+use std::convert::TryFrom;
+#[cfg(unix)]
+use std::net::IpAddr;
+use std::net::{Ipv4Addr, Ipv6Addr};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub use crate::link::{KernelAnswer, LinkAddr, PacketIo};
+
+// Interfaces are looked up and watched through IP Helper on Windows
+#[cfg(windows)]
+pub use crate::iphlpapi::{
+    interface_addrs, interface_name, interface_names, interface_subnets, lookup_link_addr,
+    LinkMonitor,
+};
+
+// How frames are sent and received by default: through packet sockets on Linux, BPF
+// devices on the BSDs and macOS and Npcap on Windows
+#[cfg(target_os = "linux")]
+pub type DefaultPacketIo = PacketSocket;
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub type DefaultPacketIo = crate::bpf::BpfDevice;
+#[cfg(windows)]
+pub type DefaultPacketIo = crate::libpcap::PcapHandle;
+
+// Socket option spreading frames over the sockets of a group in turn, from linux/if_packet.h
+#[cfg(target_os = "linux")]
+const PACKET_FANOUT: nix::libc::c_int = 18;
+#[cfg(target_os = "linux")]
+const PACKET_FANOUT_LB: u32 = 1;
+#[cfg(target_os = "linux")]
+const PACKET_FANOUT_FLAG_UNIQUEID: u32 = 0x2000;
+
+#[cfg(unix)]
+pub fn lookup_link_addr(iface: &str) -> Result<LinkAddr, Error> {
+    for ifaddr in getifaddrs().map_err(Error::Interfaces)? {
+        if ifaddr.interface_name == iface {
+            if let Some(SockAddr::Link(link_addr)) = ifaddr.address {
+                return Ok(LinkAddr::new(
+                    link_addr.ifindex(),
+                    0,
+                    MacAddress::new(link_addr.addr()),
+                ));
+            }
+        }
+    }
+    Err(Error::InterfaceMissing {
+        iface: iface.to_owned(),
+    })
+}
+
+// The IPv4 addresses configured on an interface
+#[cfg(unix)]
+pub fn interface_addrs(iface: &str) -> nix::Result<Vec<Ipv4Addr>> {
+    Ok(getifaddrs()?
+        .filter(|ifaddr| ifaddr.interface_name == iface)
+        .filter_map(|ifaddr| match ifaddr.address {
+            Some(SockAddr::Inet(addr)) => match addr.to_std().ip() {
+                IpAddr::V4(addr) => Some(addr),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect())
+}
+
+// The IPv4 subnets of the addresses configured on an interface
+#[cfg(unix)]
+pub fn interface_subnets(iface: &str) -> nix::Result<Vec<Ipv4Net>> {
+    Ok(getifaddrs()?
+        .filter(|ifaddr| ifaddr.interface_name == iface)
+        .filter_map(|ifaddr| match (ifaddr.address, ifaddr.netmask) {
+            (Some(SockAddr::Inet(addr)), Some(SockAddr::Inet(mask))) => {
+                match (addr.to_std().ip(), mask.to_std().ip()) {
+                    (IpAddr::V4(addr), IpAddr::V4(mask)) => {
+                        Ipv4Net::new(addr, u32::from(mask).count_ones() as u8).ok()
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(unix)]
+pub fn interface_names() -> nix::Result<Vec<String>> {
+    let mut names: Vec<String> = getifaddrs()?
+        .filter(|ifaddr| matches!(ifaddr.address, Some(SockAddr::Link(_))))
+        .map(|ifaddr| ifaddr.interface_name)
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+#[cfg(unix)]
+pub fn interface_name(index: usize) -> Result<String, Error> {
+    for ifaddr in getifaddrs().map_err(Error::Interfaces)? {
+        if let Some(SockAddr::Link(link_addr)) = ifaddr.address {
+            if link_addr.ifindex() == index {
+                return Ok(ifaddr.interface_name);
+            }
+        }
+    }
+    Err(Error::InterfaceMissing {
+        iface: format!("with index {}", index),
+    })
+}
+
+pub fn is_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+// Interface specifications that select interfaces by pattern, permanent MAC address
+// (`mac=<mac>`) or bus identifier (`bus=<id>`) instead of by name
+pub fn is_selector(spec: &str) -> bool {
+    is_pattern(spec) || spec.starts_with("mac=") || spec.starts_with("bus=")
+}
+
+pub fn selects(spec: &str, name: &str) -> bool {
+    if let Some(mac) = spec.strip_prefix("mac=") {
+        match mac.parse::<MacAddress>() {
+            Ok(mac) => permanent_mac(name).is_some_and(|perm| perm == mac),
+            Err(_) => false,
+        }
+    } else if let Some(bus) = spec.strip_prefix("bus=") {
+        bus_id(name).is_some_and(|id| id == bus)
+    } else {
+        matches_pattern(spec, name)
+    }
+}
+
+// The bus identifier (e.g. PCI address) of the device backing an interface
+pub fn bus_id(name: &str) -> Option<String> {
+    let device = std::fs::read_link(format!("/sys/class/net/{}/device", name)).ok()?;
+    Some(device.file_name()?.to_str()?.to_owned())
+}
+
+// The permanent MAC address of an interface, falling back to the current one
+// for interfaces that don't have a permanent address (e.g. virtual interfaces)
+#[cfg(target_os = "linux")]
+pub fn permanent_mac(name: &str) -> Option<MacAddress> {
+    const SIOCETHTOOL: nix::libc::c_ulong = 0x8946;
+    const ETHTOOL_GPERMADDR: u32 = 0x20;
+
+    #[repr(C)]
+    struct EthtoolPermAddr {
+        cmd: u32,
+        size: u32,
+        data: [u8; 32],
+    }
+
+    #[repr(C)]
+    struct IfReq {
+        name: [u8; nix::libc::IFNAMSIZ],
+        data: *mut EthtoolPermAddr,
+        _pad: [u8; 16],
+    }
+
+    let current = lookup_link_addr(name).ok().map(|addr| addr.mac);
+    if name.len() >= nix::libc::IFNAMSIZ {
+        return current;
+    }
+    let mut perm = EthtoolPermAddr {
+        cmd: ETHTOOL_GPERMADDR,
+        size: 32,
+        data: [0; 32],
+    };
+    let mut ifr = IfReq {
+        name: [0; nix::libc::IFNAMSIZ],
+        data: &mut perm,
+        _pad: [0; 16],
+    };
+    ifr.name[..name.len()].copy_from_slice(name.as_bytes());
+    let fd = socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )
+    .ok()?;
+    let res = unsafe { nix::libc::ioctl(fd, SIOCETHTOOL, &mut ifr) };
+    let _ = nix::unistd::close(fd);
+    if res < 0 || perm.size != 6 || perm.data[..6] == [0; 6] {
+        return current;
+    }
+    MacAddress::from_bytes(&perm.data[..6]).ok()
+}
+
+// Without ethtool only the current MAC address is known
+#[cfg(not(target_os = "linux"))]
+pub fn permanent_mac(name: &str) -> Option<MacAddress> {
+    lookup_link_addr(name).ok().map(|addr| addr.mac)
+}
+
+// Match an interface name against a glob pattern supporting `*` and `?`
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    backtrack = Some((bp, bn + 1));
+                    p = bp + 1;
+                    n = bn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// The protocols interfaces have sockets for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Arp,
+    Ndp,
+    Rarp,
+}
+
+// A packet socket bound to a single interface for one ethernet protocol
+#[cfg(target_os = "linux")]
+pub struct PacketSocket {
+    link_addr: LinkAddr,
+    socket: RawFd,
+    // Raw sockets receive and send the ethernet header, instead of the kernel handling it
+    raw: bool,
+    // VLAN whose tagged frames are sent and received, which requires a raw socket
+    vlan: Option<ether::Vlan>,
+    // Length raw frames are padded to, rather than leaving it to the driver, which may
+    // pad with whatever was in its buffer
+    min_len: usize,
+    capture: Option<pcap::Capture>,
+    received: RefCell<Batch>,
+    // Frames held back while sends are deferred
+    deferred: RefCell<Option<Vec<Outgoing>>>,
+}
+
+// A frame and where to send it
+#[cfg(target_os = "linux")]
+type Outgoing = (Vec<u8>, nix::libc::sockaddr_ll);
+
+// Frames received with a single recvmmsg call, handed out one at a time
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct Batch {
+    frames: Vec<Vec<u8>>,
+    // The length and sender of the frames received
+    received: Vec<(usize, nix::libc::sockaddr_ll)>,
+    next: usize,
+}
+
+// Most frames received with one system call, and the longest received
+#[cfg(target_os = "linux")]
+const RECV_BATCH: usize = 16;
+#[cfg(target_os = "linux")]
+const RECV_FRAME_LEN: usize = 1600;
+
+#[cfg(target_os = "linux")]
+impl Batch {
+    // Copy the next frame into buf, receiving a batch first when all were handed out
+    fn next(&mut self, fd: RawFd, buf: &mut [u8]) -> nix::Result<(usize, nix::libc::sockaddr_ll)> {
+        if self.next == self.received.len() {
+            self.receive(fd)?;
+        }
+        let (len, from) = self.received[self.next];
+        let len = len.min(buf.len());
+        buf[..len].copy_from_slice(&self.frames[self.next][..len]);
+        self.next += 1;
+        Ok((len, from))
+    }
+
+    fn is_pending(&self) -> bool {
+        self.next < self.received.len()
+    }
+
+    fn receive(&mut self, fd: RawFd) -> nix::Result<()> {
+        self.frames
+            .resize_with(RECV_BATCH, || vec![0; RECV_FRAME_LEN]);
+        let mut addrs: [nix::libc::sockaddr_ll; RECV_BATCH] = unsafe { std::mem::zeroed() };
+        let mut iovs: Vec<nix::libc::iovec> = self
+            .frames
+            .iter_mut()
+            .map(|frame| nix::libc::iovec {
+                iov_base: frame.as_mut_ptr() as *mut _,
+                iov_len: frame.len(),
+            })
+            .collect();
+        let mut msgs: Vec<nix::libc::mmsghdr> = iovs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| mmsghdr(iov, addr))
+            .collect();
+        let res = unsafe {
+            nix::libc::recvmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                msgs.len() as nix::libc::c_uint,
+                nix::libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            )
+        };
+        let count = nix::Error::result(res)? as usize;
+        self.received = msgs[..count]
+            .iter()
+            .zip(&addrs)
+            .map(|(msg, addr)| (msg.msg_len as usize, *addr))
+            .collect();
+        self.next = 0;
+        match count {
+            0 => Err(nix::Error::EAGAIN),
+            _ => Ok(()),
+        }
+    }
+}
+
+// A message of a single buffer, with the link address it's from or to
+#[cfg(target_os = "linux")]
+fn mmsghdr(iov: &mut nix::libc::iovec, addr: &mut nix::libc::sockaddr_ll) -> nix::libc::mmsghdr {
+    let mut msg: nix::libc::mmsghdr = unsafe { std::mem::zeroed() };
+    msg.msg_hdr.msg_name = addr as *mut _ as *mut _;
+    msg.msg_hdr.msg_namelen = std::mem::size_of_val(addr) as nix::libc::socklen_t;
+    msg.msg_hdr.msg_iov = iov;
+    msg.msg_hdr.msg_iovlen = 1;
+    msg
+}
+
+// Send the frames with as few sendmmsg calls as the kernel allows
+#[cfg(target_os = "linux")]
+fn send_batch(fd: RawFd, frames: &mut [Outgoing]) -> nix::Result<()> {
+    let mut iovs: Vec<nix::libc::iovec> = frames
+        .iter_mut()
+        .map(|(frame, _)| nix::libc::iovec {
+            iov_base: frame.as_mut_ptr() as *mut _,
+            iov_len: frame.len(),
+        })
+        .collect();
+    let mut msgs: Vec<nix::libc::mmsghdr> = iovs
+        .iter_mut()
+        .zip(frames.iter_mut())
+        .map(|(iov, (_, to))| mmsghdr(iov, to))
+        .collect();
+    let mut sent = 0;
+    while sent < msgs.len() {
+        let res = unsafe {
+            nix::libc::sendmmsg(
+                fd,
+                msgs[sent..].as_mut_ptr(),
+                (msgs.len() - sent) as nix::libc::c_uint,
+                nix::libc::MSG_DONTWAIT,
+            )
+        };
+        match nix::Error::result(res)? {
+            0 => return Err(nix::Error::EAGAIN),
+            count => sent += count as usize,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl PacketIo for PacketSocket {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        let raw = raw || vlan.is_some();
+        let socket = socket(
+            AddressFamily::Packet,
+            if raw {
+                SockType::Raw
+            } else {
+                SockType::Datagram
+            },
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let link_addr = LinkAddr {
+            protocol,
+            ..*link_addr
+        };
+        let packet_socket = Self {
+            link_addr,
+            socket,
+            raw,
+            vlan,
+            min_len,
+            capture,
+            received: RefCell::default(),
+            deferred: RefCell::new(None),
+        };
+
+        // Filtered before binding, so no other frames are queued
+        packet_socket.set_filter(filter)?;
+        let bind_addr = LinkAddr {
+            protocol: nix::libc::ETH_P_ALL as u16,
+            ..link_addr
+        };
+        nix::sys::socket::bind(
+            socket,
+            &SockAddr::Link(nix::sys::socket::LinkAddr((&bind_addr).into())),
+        )?;
+        Ok(packet_socket)
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.link_addr
+    }
+
+    fn is_raw(&self) -> bool {
+        self.raw
+    }
+
+    // The kernel strips VLAN tags before handing frames to sockets bound to their protocol,
+    // telling tagged and untagged frames apart only for sockets that see all frames, so
+    // those are filtered instead. Raw sockets also receive ARP in SNAP frames, which the
+    // kernel doesn't count as ARP.
+    fn set_filter(&self, filter: &[BpfInsn]) -> nix::Result<()> {
+        let protocol = self.link_addr.protocol;
+        let snap = self.raw && protocol == ether::ETH_P_ARP;
+        self.attach_filter(&vlan_filter(protocol, self.vlan, snap, filter))
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let (size, from) = self.received.borrow_mut().next(self.socket, buf)?;
+        self.received(buf, size, LinkAddr::from(&from))
+    }
+
+    fn pending(&self) -> bool {
+        self.received.borrow().is_pending()
+    }
+
+    fn defer_sends(&self, defer: bool) -> nix::Result<()> {
+        let frames = std::mem::replace(&mut *self.deferred.borrow_mut(), defer.then(Vec::new));
+        match frames {
+            Some(mut frames) if !frames.is_empty() => send_batch(self.socket, &mut frames),
+            _ => Ok(()),
+        }
+    }
+
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        let (len, frame) = self.outgoing(buf, src, to);
+        let size = self.send_frame(&frame, to)?;
+        self.capture_sent(&frame, to);
+        Ok(size.saturating_sub(len).min(buf.len()))
+    }
+
+    fn set_membership(&self, membership: Membership, join: bool) -> nix::Result<()> {
+        let mr_type = match membership {
+            Membership::Multicast(_) => nix::libc::PACKET_MR_MULTICAST,
+            Membership::AllMulti => nix::libc::PACKET_MR_ALLMULTI,
+            Membership::Promisc => nix::libc::PACKET_MR_PROMISC,
+        };
+        let mut mreq = nix::libc::packet_mreq {
+            mr_ifindex: self.link_addr.ifindex as nix::libc::c_int,
+            mr_type: mr_type as nix::libc::c_ushort,
+            mr_alen: 0,
+            mr_address: [0; 8],
+        };
+        if let Membership::Multicast(mac) = membership {
+            mreq.mr_alen = 6;
+            mreq.mr_address[..6].copy_from_slice(mac.as_bytes());
+        }
+        let option = if join {
+            nix::libc::PACKET_ADD_MEMBERSHIP
+        } else {
+            nix::libc::PACKET_DROP_MEMBERSHIP
+        };
+        let res = unsafe {
+            nix::libc::setsockopt(
+                self.socket,
+                nix::libc::SOL_PACKET,
+                option,
+                &mreq as *const _ as *const nix::libc::c_void,
+                std::mem::size_of_val(&mreq) as nix::libc::socklen_t,
+            )
+        };
+        nix::Error::result(res).map(drop)
+    }
+
+    fn set_busy_poll(&self, usecs: u32) -> nix::Result<()> {
+        let res = unsafe {
+            nix::libc::setsockopt(
+                self.socket,
+                nix::libc::SOL_SOCKET,
+                nix::libc::SO_BUSY_POLL,
+                &usecs as *const _ as *const nix::libc::c_void,
+                std::mem::size_of_val(&usecs) as nix::libc::socklen_t,
+            )
+        };
+        nix::Error::result(res).map(drop)
+    }
+
+    // Frames are handed to the sockets of a group in turn, as ARP hashes alike
+    fn join_fanout(&self, id: Option<u16>) -> nix::Result<u16> {
+        let flags = match id {
+            Some(_) => PACKET_FANOUT_LB,
+            None => PACKET_FANOUT_LB | PACKET_FANOUT_FLAG_UNIQUEID,
+        };
+        let mut arg = u32::from(id.unwrap_or(0)) | flags << 16;
+        let mut len = std::mem::size_of_val(&arg) as nix::libc::socklen_t;
+        let res = unsafe {
+            nix::libc::setsockopt(
+                self.socket,
+                nix::libc::SOL_PACKET,
+                PACKET_FANOUT,
+                &arg as *const _ as *const nix::libc::c_void,
+                len,
+            )
+        };
+        nix::Error::result(res)?;
+        let res = unsafe {
+            nix::libc::getsockopt(
+                self.socket,
+                nix::libc::SOL_PACKET,
+                PACKET_FANOUT,
+                &mut arg as *mut _ as *mut nix::libc::c_void,
+                &mut len,
+            )
+        };
+        nix::Error::result(res).map(|_| arg as u16)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl PacketSocket {
+    // What to send for the payload, which is the frame with the length of its header on raw
+    // sockets and the payload itself otherwise
+    pub(crate) fn outgoing<'a>(
+        &self,
+        buf: &'a [u8],
+        src: MacAddress,
+        to: &LinkAddr,
+    ) -> (usize, Cow<'a, [u8]>) {
+        if !self.raw {
+            return (0, Cow::Borrowed(buf));
+        }
+        let (len, frame) = raw_frame(&self.link_addr, self.vlan, self.min_len, buf, src, to);
+        (len, Cow::Owned(frame))
+    }
+
+    // Write what was sent, as returned by outgoing, to the capture
+    pub(crate) fn capture_sent(&self, sent: &[u8], to: &LinkAddr) {
+        if self.raw {
+            self.write_capture(sent);
+            return;
+        }
+        // The kernel sends from the interface's MAC address
+        let header = ether::Header {
+            dst: to.mac,
+            src: self.link_addr.mac,
+            vlan: None,
+            ethertype: to.protocol,
+            snap: false,
+        };
+        self.capture(&header, sent);
+    }
+
+    // Send a frame now, or once sends are no longer deferred
+    fn send_frame(&self, frame: &[u8], to: &LinkAddr) -> nix::Result<usize> {
+        if let Some(deferred) = &mut *self.deferred.borrow_mut() {
+            deferred.push((frame.to_vec(), to.into()));
+            return Ok(frame.len());
+        }
+        nix::sys::socket::sendto(
+            self.socket,
+            frame,
+            &SockAddr::Link(nix::sys::socket::LinkAddr(to.into())),
+            MsgFlags::MSG_DONTWAIT,
+        )
+    }
+
+    // Make sense of a frame of size bytes received into buf from the sender, like
+    // recv_from, for receiving through other means than recvfrom on the socket
+    pub(crate) fn received(
+        &self,
+        buf: &mut [u8],
+        size: usize,
+        from: LinkAddr,
+    ) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        if !self.raw {
+            if self.capture.is_some() {
+                // The kernel only tells whether frames were sent to this host or broadcast
+                let dst = match from.packet_type {
+                    PacketType::Host => self.link_addr.mac,
+                    PacketType::Broadcast => MacAddress::broadcast(),
+                    _ => MacAddress::nil(),
+                };
+                let header = ether::Header {
+                    dst,
+                    src: from.mac,
+                    vlan: None,
+                    ethertype: self.link_addr.protocol,
+                    snap: false,
+                };
+                self.capture(&header, &buf[..size]);
+            }
+            return Ok((size, from, None));
+        }
+
+        // Truncated frames, or frames of another protocol or VLAN, are dropped as if nothing
+        // was received
+        let mut header = match ether::Header::try_from(&buf[..size]) {
+            Ok(header)
+                if header.ethertype == self.link_addr.protocol
+                    && header
+                        .vlan
+                        .is_none_or(|tags| self.vlan.is_some_and(|vlan| vlan.id == tags.id)) =>
+            {
+                header
+            }
+            _ => return Err(nix::Error::EAGAIN),
+        };
+        let len = header.size();
+        buf.copy_within(len..size, 0);
+
+        // Tags stripped by the kernel were already checked by the socket's filter, which
+        // leaves only the inner tag of stacked ones
+        header.vlan = self.vlan;
+        self.capture(&header, &buf[..size - len]);
+        // The kernel reports the inner tag's protocol for stacked ones, while replies are
+        // sent with the protocol of the frame itself, or as SNAP frames to SNAP senders
+        let protocol = if header.snap {
+            ether::ETH_P_802_2
+        } else {
+            header.ethertype
+        };
+        Ok((size - len, LinkAddr { protocol, ..from }, Some(header)))
+    }
+
+    // Write a frame to the capture, if any, with the current time
+    fn capture(&self, header: &ether::Header, payload: &[u8]) {
+        if self.capture.is_some() {
+            self.write_capture(&header.frame(payload));
+        }
+    }
+
+    pub(crate) fn write_capture(&self, frame: &[u8]) {
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, frame);
+        }
+    }
+
+    // Attach a classic BPF program so only relevant packets are queued on the socket
+    pub fn attach_filter(&self, filter: &[BpfInsn]) -> nix::Result<()> {
+        let prog = nix::libc::sock_fprog {
+            len: filter.len() as nix::libc::c_ushort,
+            filter: filter.as_ptr() as *mut _,
+        };
+        let res = unsafe {
+            nix::libc::setsockopt(
+                self.socket,
+                nix::libc::SOL_SOCKET,
+                nix::libc::SO_ATTACH_FILTER,
+                &prog as *const _ as *const nix::libc::c_void,
+                std::mem::size_of_val(&prog) as nix::libc::socklen_t,
+            )
+        };
+        nix::Error::result(res).map(drop)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.socket);
+    }
+}
+
+// Filter programs are limited to BPF_MAXINSNS instructions
+const BPF_MAXINSNS: usize = 4096;
+
+// Ancillary data loaded from negative offsets, from linux/filter.h
+#[cfg(target_os = "linux")]
+const SKF_AD_OFF: u32 = (-0x1000i32) as u32;
+#[cfg(target_os = "linux")]
+const SKF_AD_PROTOCOL: u32 = 0;
+#[cfg(target_os = "linux")]
+const SKF_AD_PKTTYPE: u32 = 4;
+#[cfg(target_os = "linux")]
+const SKF_AD_VLAN_TAG: u32 = 44;
+#[cfg(target_os = "linux")]
+const SKF_AD_VLAN_TAG_PRESENT: u32 = 48;
+#[cfg(target_os = "linux")]
+const PACKET_OUTGOING: u32 = 4;
+
+// Only pass frames received for a protocol in a VLAN (or untagged or priority tagged
+// ones) to the given program, using the tag the kernel stripped from them. Of stacked
+// tags only the outer one is stripped, so the inner one is checked in the frame itself.
+// With snap, 802.3 frames carrying the protocol in a SNAP header pass too, except in
+// stacked tags.
+#[cfg(target_os = "linux")]
+fn vlan_filter(
+    protocol: u16,
+    vlan: Option<ether::Vlan>,
+    snap: bool,
+    filter: &[BpfInsn],
+) -> Vec<BpfInsn> {
+    let outer = vlan.and_then(|vlan| vlan.outer);
+    let snap = snap && outer.is_none();
+    let prefix = match (outer, snap) {
+        (Some(_), _) => 14,
+        (None, true) => 14,
+        (None, false) => 9,
+    };
+    // Jump offsets to the drop appended after the program, from each instruction
+    let drop = |at: usize| (prefix + filter.len() - at - 1) as u8;
+    let mut prog = vec![
+        bpf_stmt(BPF_LD_ABS, SKF_AD_OFF + SKF_AD_PKTTYPE),
+        bpf_jump(BPF_JEQ_K, PACKET_OUTGOING, drop(1), 0),
+        bpf_stmt(BPF_LD_ABS, SKF_AD_OFF + SKF_AD_PROTOCOL),
+    ];
+    match (outer, vlan) {
+        (Some(outer), Some(vlan)) => prog.extend_from_slice(&[
+            bpf_jump(BPF_JEQ_K, u32::from(ether::ETH_P_8021Q), 0, drop(3)),
+            bpf_stmt(BPF_LD_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG_PRESENT),
+            bpf_jump(BPF_JEQ_K, 0, drop(5), 0),
+            bpf_stmt(BPF_LD_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG),
+            bpf_stmt(BPF_AND_K, 0xfff),
+            bpf_jump(BPF_JEQ_K, u32::from(outer), 0, drop(8)),
+            bpf_stmt(BPF_LDH_ABS, ether::HEADER_LEN as u32),
+            bpf_stmt(BPF_AND_K, 0xfff),
+            bpf_jump(BPF_JEQ_K, u32::from(vlan.id), 0, drop(11)),
+            bpf_stmt(BPF_LDH_ABS, (ether::HEADER_LEN + 2) as u32),
+            bpf_jump(BPF_JEQ_K, u32::from(protocol), 0, drop(13)),
+        ]),
+        _ => {
+            if snap {
+                // The LLC header and OUI, and the protocol after them
+                prog.extend_from_slice(&[
+                    bpf_jump(BPF_JEQ_K, u32::from(protocol), 5, 0),
+                    bpf_jump(BPF_JEQ_K, u32::from(ether::ETH_P_802_2), 0, drop(4)),
+                    bpf_stmt(BPF_LD_ABS, ether::HEADER_LEN as u32),
+                    bpf_jump(BPF_JEQ_K, 0xaaaa_0300, 0, drop(6)),
+                    bpf_stmt(BPF_LD_ABS, (ether::HEADER_LEN + 4) as u32),
+                    bpf_jump(BPF_JEQ_K, u32::from(protocol), 0, drop(8)),
+                ]);
+            } else {
+                prog.push(bpf_jump(BPF_JEQ_K, u32::from(protocol), 0, drop(3)));
+            }
+            let at = prog.len();
+            let untagged = if vlan.is_some() { drop(at + 1) } else { 3 };
+            prog.extend_from_slice(&[
+                bpf_stmt(BPF_LD_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG_PRESENT),
+                bpf_jump(BPF_JEQ_K, 0, untagged, 0),
+                bpf_stmt(BPF_LD_ABS, SKF_AD_OFF + SKF_AD_VLAN_TAG),
+                bpf_stmt(BPF_AND_K, 0xfff),
+                bpf_jump(
+                    BPF_JEQ_K,
+                    u32::from(vlan.map_or(0, |vlan| vlan.id)),
+                    0,
+                    drop(at + 4),
+                ),
+            ]);
+        }
+    }
+    debug_assert_eq!(prog.len(), prefix);
+    prog.extend_from_slice(filter);
+    prog.push(bpf_stmt(BPF_RET_K, 0));
+    prog
+}
+
+// Only pass ARP packets with the target or sender address in the ranges, with the ARP
+// packet at the given offset, so hosts on busy segments aren't woken for every request.
+// Packets with other address lengths pass too, to be reported as unusual, as does
+// everything when the ranges don't fit in a program.
+fn arp_filter(offset: u32, ranges: &[(Ipv4Addr, Ipv4Addr)]) -> Vec<BpfInsn> {
+    let accept = bpf_stmt(BPF_RET_K, 0xffff);
+    // The header check, a load and 3 instructions per range for both addresses, and the
+    // returns, with up to 15 instructions of the vlan filter around them
+    let len = 3 + 2 * (1 + 3 * ranges.len()) + 2;
+    if len + 15 > BPF_MAXINSNS {
+        return vec![accept];
+    }
+    let mut prog = vec![
+        bpf_stmt(BPF_LDH_ABS, offset + 4),
+        bpf_jump(BPF_JEQ_K, 0x0604, 1, 0),
+        accept,
+    ];
+    // Ranges jump to the accept at the end when the address is within them
+    for field in [24, 14] {
+        prog.push(bpf_stmt(BPF_LD_ABS, offset + field));
+        for (first, last) in ranges {
+            prog.push(bpf_jump(BPF_JGE_K, u32::from(*first), 0, 2));
+            prog.push(bpf_jump(BPF_JGT_K, u32::from(*last), 1, 0));
+            prog.push(bpf_stmt(BPF_JA, (len - prog.len() - 2) as u32));
+        }
+    }
+    prog.push(bpf_stmt(BPF_RET_K, 0));
+    prog.push(accept);
+    debug_assert_eq!(prog.len(), len);
+    prog
+}
+
+// Only pass ICMPv6 neighbor solicitations and advertisements without extension headers,
+// with the IPv6 header at the given offset (after the ethernet header on raw sockets)
+const fn ndp_filter(offset: u32) -> [BpfInsn; 7] {
+    [
+        bpf_stmt(BPF_LDB_ABS, offset + 6),
+        bpf_jump(BPF_JEQ_K, 58, 0, 4),
+        bpf_stmt(BPF_LDB_ABS, offset + 40),
+        bpf_jump(BPF_JEQ_K, 135, 1, 0),
+        bpf_jump(BPF_JEQ_K, 136, 0, 1),
+        bpf_stmt(BPF_RET_K, 0xffff),
+        bpf_stmt(BPF_RET_K, 0),
+    ]
+}
+
+// Interface name and index and protocol of sockets in a fanout group
+type FanoutKey = (String, usize, u16);
+
+// Fanout groups shared by the sockets of responders in different threads, so each frame is
+// received by only one of them
+#[derive(Clone, Default)]
+pub struct Fanout {
+    groups: Arc<Mutex<HashMap<FanoutKey, u16>>>,
+}
+
+impl Fanout {
+    fn join<S: PacketIo>(&self, name: &str, socket: &S) -> sys::Result<()> {
+        let link_addr = socket.link_addr();
+        let key = (name.to_owned(), link_addr.ifindex, link_addr.protocol);
+        let mut groups = self.groups.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = socket.join_fanout(groups.get(&key).copied())?;
+        groups.insert(key, id);
+        Ok(())
+    }
+}
+
+// The sockets for sending and receiving ARP and neighbor discovery packets on an interface
+pub struct Interface<S = DefaultPacketIo> {
+    pub name: String,
+    // The network interface itself, which differs from name for VLANs on it
+    pub link: String,
+    pub vlan: Option<ether::Vlan>,
+    pub index: usize,
+    pub mac: MacAddress,
+    link_addr: LinkAddr,
+    pub arp: S,
+    pub ndp: Option<S>,
+    pub rarp: Option<S>,
+    pub subnets: Vec<Ipv4Net>,
+    multicast: BTreeSet<[u8; 6]>,
+    allmulti: bool,
+    promisc: bool,
+    // Addresses the ARP socket's filter passes packets for, all when None
+    arp_ranges: Option<Vec<(Ipv4Addr, Ipv4Addr)>>,
+    min_len: usize,
+    capture: Option<pcap::Capture>,
+    fanout: Option<Fanout>,
+    // Microseconds the sockets busy poll for, if at all
+    busy_poll: Option<u32>,
+}
+
+impl<S: PacketIo> Interface<S> {
+    pub fn open(
+        name: &str,
+        link: &str,
+        vlan: Option<ether::Vlan>,
+        raw: bool,
+        min_len: usize,
+        capture: Option<pcap::Capture>,
+        fanout: Option<Fanout>,
+    ) -> Result<Self, Error> {
+        // Lookup interface and it's corresponding MAC-address
+        let link_addr = lookup_link_addr(link)?;
+
+        // Open a raw socket for sending and receiving ARP packets
+        let protocol = ether::ETH_P_ARP;
+        let accept = [bpf_stmt(BPF_RET_K, 0xffff)];
+        let arp = S::open(
+            &link_addr,
+            protocol,
+            raw,
+            vlan,
+            min_len,
+            &accept,
+            capture.clone(),
+        )
+        .map_err(|err| Error::socket(name, "arp socket", err))?;
+        if let Some(fanout) = &fanout {
+            fanout
+                .join(name, &arp)
+                .map_err(|err| Error::socket(name, "arp fanout", err))?;
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            link: link.to_owned(),
+            vlan,
+            index: link_addr.ifindex,
+            mac: link_addr.mac,
+            link_addr,
+            arp,
+            ndp: None,
+            rarp: None,
+            subnets: Vec::new(),
+            multicast: BTreeSet::new(),
+            allmulti: false,
+            promisc: false,
+            arp_ranges: None,
+            min_len,
+            capture,
+            fanout,
+            busy_poll: None,
+        })
+    }
+
+    // Where packets start in the frames the sockets' filters see
+    fn payload_offset(&self) -> usize {
+        // The inner tag of stacked ones stays in the frame
+        match self.vlan {
+            Some(ether::Vlan { outer: Some(_), .. }) => ether::HEADER_LEN + ether::VLAN_TAG_LEN,
+            _ if self.arp.is_raw() => ether::HEADER_LEN,
+            _ => 0,
+        }
+    }
+
+    // Only receive ARP packets about the claimed IPv4 addresses
+    pub fn set_arp_addrs(&mut self, claimed: &ClaimSet) -> Result<(), Error> {
+        let ranges: Vec<_> = claimed.ipv4_ranges().collect();
+        if self.arp_ranges.as_ref() == Some(&ranges) {
+            return Ok(());
+        }
+        let filter = arp_filter(self.payload_offset() as u32, &ranges);
+        self.arp
+            .set_filter(&filter)
+            .map_err(|err| Error::socket(&self.name, "arp filter", err))?;
+        self.arp
+            .set_arp_ranges(&ranges)
+            .map_err(|err| Error::socket(&self.name, "arp ranges", err))?;
+        self.arp_ranges = Some(ranges);
+        Ok(())
+    }
+
+    // Listen for neighbor solicitations for the given addresses, only keeping a socket
+    // for neighbor discovery open when there are any. Solicitations for addresses within
+    // prefixes arrive on too many multicast groups to join, so all multicast traffic is
+    // received instead.
+    pub fn set_ndp_addrs(&mut self, addrs: &[Ipv6Addr], prefixes: bool) -> Result<(), Error> {
+        let name = &self.name;
+        let err = |err| Error::socket(name, "ipv6 socket", err);
+        if addrs.is_empty() && !prefixes {
+            self.ndp = None;
+            self.multicast.clear();
+            self.allmulti = false;
+            return Ok(());
+        }
+        let ndp = match &mut self.ndp {
+            Some(ndp) => ndp,
+            None => {
+                let ndp = S::open(
+                    &self.link_addr,
+                    ether::ETH_P_IPV6,
+                    self.arp.is_raw(),
+                    self.vlan,
+                    self.min_len,
+                    &ndp_filter(self.payload_offset() as u32),
+                    self.capture.clone(),
+                )
+                .map_err(err)?;
+                if let Some(fanout) = &self.fanout {
+                    fanout.join(&self.name, &ndp).map_err(err)?;
+                }
+                if let Some(usecs) = self.busy_poll {
+                    ndp.set_busy_poll(usecs).map_err(err)?;
+                }
+                self.multicast.clear();
+                self.allmulti = false;
+                self.ndp.insert(ndp)
+            }
+        };
+        let wanted: BTreeSet<[u8; 6]> = addrs
+            .iter()
+            .map(|addr| ndp::multicast_mac(ndp::solicited_node(*addr)).to_array())
+            .collect();
+        for mac in self.multicast.difference(&wanted) {
+            ndp.drop_membership(MacAddress::new(*mac)).map_err(err)?;
+        }
+        for mac in wanted.difference(&self.multicast) {
+            ndp.add_membership(MacAddress::new(*mac)).map_err(err)?;
+        }
+        self.multicast = wanted;
+        if prefixes != self.allmulti {
+            if prefixes {
+                ndp.add_allmulti().map_err(err)?;
+            } else {
+                ndp.drop_allmulti().map_err(err)?;
+            }
+            self.allmulti = prefixes;
+        }
+        Ok(())
+    }
+
+    // Only keep a socket for Reverse ARP open when answering its requests
+    pub fn set_rarp(&mut self, rarp: bool) -> Result<(), Error> {
+        if !rarp {
+            self.rarp = None;
+        } else if self.rarp.is_none() {
+            let accept = [bpf_stmt(BPF_RET_K, 0xffff)];
+            let socket = S::open(
+                &self.link_addr,
+                ether::ETH_P_RARP,
+                self.arp.is_raw(),
+                self.vlan,
+                self.min_len,
+                &accept,
+                self.capture.clone(),
+            )
+            .map_err(|err| Error::socket(&self.name, "rarp socket", err))?;
+            if let Some(fanout) = &self.fanout {
+                fanout
+                    .join(&self.name, &socket)
+                    .map_err(|err| Error::socket(&self.name, "rarp fanout", err))?;
+            }
+            if let Some(usecs) = self.busy_poll {
+                socket
+                    .set_busy_poll(usecs)
+                    .map_err(|err| Error::socket(&self.name, "rarp busy poll", err))?;
+            }
+            self.rarp = Some(socket);
+        }
+        Ok(())
+    }
+
+    // Busy poll on the sockets, including those opened later, for up to this many
+    // microseconds, or not at all
+    pub fn set_busy_poll(&mut self, usecs: Option<u32>) -> sys::Result<()> {
+        if usecs != self.busy_poll {
+            let sockets = std::iter::once(&self.arp)
+                .chain(&self.ndp)
+                .chain(&self.rarp);
+            for socket in sockets {
+                socket.set_busy_poll(usecs.unwrap_or(0))?;
+            }
+            self.busy_poll = usecs;
+        }
+        Ok(())
+    }
+
+    pub fn set_promisc(&mut self, promisc: bool) -> sys::Result<()> {
+        if promisc != self.promisc {
+            if promisc {
+                self.arp.add_promisc()?;
+            } else {
+                self.arp.drop_promisc()?;
+            }
+            self.promisc = promisc;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+const RTMGRP_LINK: u32 = 0x1;
+#[cfg(unix)]
+const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+#[cfg(unix)]
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+#[cfg(unix)]
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+// Netlink socket notifying about interfaces and their IPv4 addresses (or routes) being
+// added, removed or changed, or a routing socket notifying about all of them elsewhere
+#[cfg(unix)]
+pub struct LinkMonitor {
+    socket: RawFd,
+}
+
+#[cfg(unix)]
+impl LinkMonitor {
+    pub fn new() -> nix::Result<Self> {
+        Self::subscribe(RTMGRP_LINK | RTMGRP_IPV4_IFADDR)
+    }
+
+    pub fn routes() -> nix::Result<Self> {
+        Self::subscribe(RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn subscribe(groups: u32) -> nix::Result<Self> {
+        let socket = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            SockProtocol::NetlinkRoute,
+        )?;
+        let monitor = Self { socket };
+        nix::sys::socket::bind(socket, &SockAddr::Netlink(NetlinkAddr::new(0, groups)))?;
+        Ok(monitor)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn subscribe(_groups: u32) -> nix::Result<Self> {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+        let socket = unsafe {
+            nix::libc::socket(
+                nix::libc::PF_ROUTE,
+                nix::libc::SOCK_RAW,
+                nix::libc::AF_UNSPEC,
+            )
+        };
+        let monitor = Self {
+            socket: nix::Error::result(socket)?,
+        };
+        fcntl(monitor.socket, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        fcntl(monitor.socket, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        Ok(monitor)
+    }
+
+    // Drain pending notifications, returning whether there were any
+    pub fn changed(&self) -> bool {
+        let mut buf = [0u8; 8192];
+        let mut changed = false;
+        loop {
+            match recv(self.socket, &mut buf, MsgFlags::empty()) {
+                Ok(0) => return changed,
+                Ok(_) => changed = true,
+                Err(nix::Error::EINTR) => {}
+                // The kernel dropped notifications, so assume something changed
+                Err(nix::Error::ENOBUFS) => changed = true,
+                Err(nix::Error::EAGAIN) => return changed,
+                Err(err) => {
+                    log::error!("failed to receive link notifications: {}", err);
+                    return changed;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for LinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket
+    }
+}
+
+#[cfg(unix)]
+impl Drop for LinkMonitor {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.socket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(is_pattern("eth*"));
+        assert!(!is_pattern("eth0"));
+        assert!(matches_pattern("eth*", "eth0"));
+        assert!(matches_pattern("eth*", "eth"));
+        assert!(!matches_pattern("eth*", "veth0"));
+        assert!(matches_pattern("vlan1??", "vlan100"));
+        assert!(!matches_pattern("vlan1??", "vlan10"));
+        assert!(!matches_pattern("vlan1??", "vlan1000"));
+        assert!(matches_pattern("*.1*", "eth0.100"));
+        assert!(matches_pattern("eth0", "eth0"));
+        assert!(!matches_pattern("eth0", "eth01"));
+        assert!(is_selector("mac=00:11:22:33:44:55"));
+        assert!(is_selector("bus=0000:00:1f.6"));
+        assert!(!is_selector("eth0"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn batch() {
+        use nix::sys::socket::{send, socketpair};
+        let (rx, tx) = socketpair(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .unwrap();
+        for frame in [&b"first"[..], b"second", b"third"] {
+            send(tx, frame, MsgFlags::empty()).unwrap();
+        }
+
+        // All frames are received at once and handed out in order
+        let mut batch = Batch::default();
+        let mut buf = [0u8; 5];
+        assert_eq!(batch.next(rx, &mut buf).unwrap().0, 5);
+        assert_eq!(&buf, b"first");
+        assert!(batch.is_pending());
+        assert_eq!(batch.next(rx, &mut buf).unwrap().0, 5);
+        assert_eq!(&buf, b"secon");
+        assert_eq!(batch.next(rx, &mut buf).unwrap().0, 5);
+        assert!(!batch.is_pending());
+        assert_eq!(batch.next(rx, &mut buf), Err(nix::Error::EAGAIN));
+        let _ = (nix::unistd::close(rx), nix::unistd::close(tx));
+    }
+
+    // Run a program like the kernel would, for the instructions used by arp_filter
+    fn run_filter(prog: &[BpfInsn], pkt: &[u8]) -> u32 {
+        let (mut a, mut pc) = (0u32, 0);
+        loop {
+            let ins = prog[pc];
+            pc += 1;
+            let (k, len) = (ins.k, if ins.code == BPF_LD_ABS { 4 } else { 2 });
+            match ins.code {
+                BPF_LD_ABS | BPF_LDH_ABS => match pkt.get(k as usize..k as usize + len) {
+                    Some(bytes) => a = bytes.iter().fold(0, |a, b| a << 8 | u32::from(*b)),
+                    None => return 0,
+                },
+                BPF_JEQ_K | BPF_JGT_K | BPF_JGE_K => {
+                    let taken = match ins.code {
+                        BPF_JEQ_K => a == k,
+                        BPF_JGT_K => a > k,
+                        _ => a >= k,
+                    };
+                    pc += usize::from(if taken { ins.jt } else { ins.jf });
+                }
+                BPF_JA => pc += k as usize,
+                BPF_RET_K => return k,
+                code => panic!("unexpected instruction {:#x}", code),
+            }
+        }
+    }
+
+    #[test]
+    fn arp_filter_ranges() {
+        let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+        let ranges = [
+            (ip("10.0.0.5"), ip("10.0.0.5")),
+            (ip("10.1.0.0"), ip("10.1.255.255")),
+        ];
+        let prog = arp_filter(0, &ranges);
+        let mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let request = |spa, tpa| crate::arp::Arp::request(mac, ip(spa), ip(tpa)).to_vec();
+        for (spa, tpa, passes) in [
+            ("10.9.9.9", "10.0.0.5", true),
+            ("10.9.9.9", "10.0.0.4", false),
+            ("10.9.9.9", "10.0.0.6", false),
+            ("10.1.2.3", "10.9.9.9", true),
+            ("0.0.0.0", "10.1.255.255", true),
+            ("10.2.0.0", "10.0.255.255", false),
+        ] {
+            assert_eq!(
+                run_filter(&prog, &request(spa, tpa)) > 0,
+                passes,
+                "{} {}",
+                spa,
+                tpa
+            );
+        }
+
+        // Unusual address lengths pass, truncated packets don't
+        let mut unusual = request("10.9.9.9", "10.9.9.9");
+        unusual[4] = 8;
+        assert!(run_filter(&prog, &unusual) > 0);
+        assert_eq!(run_filter(&prog, &request("10.9.9.9", "10.0.0.5")[..27]), 0);
+
+        // Ranges that don't fit in a program pass everything
+        assert_eq!(arp_filter(0, &[ranges[0]; 1000]).len(), 1);
+    }
+}
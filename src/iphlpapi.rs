@@ -0,0 +1,343 @@
+use crate::error::Error;
+use crate::link::LinkAddr;
+use crate::net::Ipv4Net;
+use crate::sys::{self, AsRawFd, RawFd};
+use eui48::MacAddress;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::raw::{c_char, c_void};
+
+// Address families, flags and errors, from ws2def.h, iptypes.h and winerror.h
+const AF_UNSPEC: u16 = 0;
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 23;
+const GAA_FLAG_SKIP_ANYCAST: u32 = 0x2;
+const GAA_FLAG_SKIP_MULTICAST: u32 = 0x4;
+const GAA_FLAG_SKIP_DNS_SERVER: u32 = 0x8;
+const NO_ERROR: u32 = 0;
+const ERROR_BUFFER_OVERFLOW: u32 = 111;
+const ERROR_NO_DATA: u32 = 232;
+
+#[repr(C)]
+struct SockaddrIn {
+    family: u16,
+    port: u16,
+    addr: [u8; 4],
+    zero: [u8; 8],
+}
+
+#[repr(C)]
+struct SockaddrIn6 {
+    family: u16,
+    port: u16,
+    flowinfo: u32,
+    addr: [u8; 16],
+    scope_id: u32,
+}
+
+#[repr(C)]
+struct SocketAddress {
+    sockaddr: *const SockaddrIn,
+    len: i32,
+}
+
+// The start of IP_ADAPTER_UNICAST_ADDRESS_LH, up to the fields read
+#[repr(C)]
+struct UnicastAddress {
+    length: u32,
+    flags: u32,
+    next: *const UnicastAddress,
+    address: SocketAddress,
+    prefix_origin: i32,
+    suffix_origin: i32,
+    dad_state: i32,
+    valid_lifetime: u32,
+    preferred_lifetime: u32,
+    lease_lifetime: u32,
+    on_link_prefix_length: u8,
+}
+
+// The start of IP_ADAPTER_ADDRESSES_LH, up to the fields read
+#[repr(C)]
+struct AdapterAddresses {
+    length: u32,
+    if_index: u32,
+    next: *const AdapterAddresses,
+    adapter_name: *const c_char,
+    first_unicast_address: *const UnicastAddress,
+    first_anycast_address: *const c_void,
+    first_multicast_address: *const c_void,
+    first_dns_server_address: *const c_void,
+    dns_suffix: *const u16,
+    description: *const u16,
+    friendly_name: *const u16,
+    physical_address: [u8; 8],
+    physical_address_length: u32,
+    flags: u32,
+    mtu: u32,
+    if_type: u32,
+    oper_status: i32,
+    ipv6_if_index: u32,
+}
+
+type Callback = extern "system" fn(*mut c_void, *const c_void, u32);
+
+type Notify = unsafe extern "system" fn(u16, Callback, *mut c_void, u8, *mut RawFd) -> u32;
+
+#[link(name = "iphlpapi")]
+extern "system" {
+    fn GetAdaptersAddresses(
+        family: u32,
+        flags: u32,
+        reserved: *mut c_void,
+        addresses: *mut AdapterAddresses,
+        size: *mut u32,
+    ) -> u32;
+    fn GetBestInterfaceEx(dest: *const c_void, index: *mut u32) -> u32;
+    fn NotifyIpInterfaceChange(
+        family: u16,
+        callback: Callback,
+        context: *mut c_void,
+        initial: u8,
+        handle: *mut RawFd,
+    ) -> u32;
+    fn NotifyUnicastIpAddressChange(
+        family: u16,
+        callback: Callback,
+        context: *mut c_void,
+        initial: u8,
+        handle: *mut RawFd,
+    ) -> u32;
+    fn NotifyRouteChange2(
+        family: u16,
+        callback: Callback,
+        context: *mut c_void,
+        initial: u8,
+        handle: *mut RawFd,
+    ) -> u32;
+    fn CancelMibChangeNotify2(handle: RawFd) -> u32;
+}
+
+// A network adapter, named by its friendly name (like "Ethernet") rather than its GUID
+struct Adapter {
+    name: String,
+    guid: String,
+    index: usize,
+    mac: Option<MacAddress>,
+    addrs: Vec<(Ipv4Addr, u8)>,
+}
+
+fn wide_string(s: *const u16) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    let len = (0..).take_while(|&n| unsafe { *s.add(n) } != 0).count();
+    String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(s, len) })
+}
+
+fn adapters() -> sys::Result<Vec<Adapter>> {
+    let flags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER;
+    let mut size: u32 = 16 * 1024;
+    // The list of adapters is written into buf, growing it while adapters are added
+    let buf = loop {
+        let mut buf = vec![0u64; (size as usize).div_ceil(8)];
+        let res = unsafe {
+            GetAdaptersAddresses(
+                u32::from(AF_UNSPEC),
+                flags,
+                std::ptr::null_mut(),
+                buf.as_mut_ptr() as *mut AdapterAddresses,
+                &mut size,
+            )
+        };
+        match res {
+            NO_ERROR => break buf,
+            ERROR_BUFFER_OVERFLOW => {}
+            ERROR_NO_DATA => return Ok(Vec::new()),
+            err => return Err(sys::Error::from_win32(err)),
+        }
+    };
+
+    let mut adapters = Vec::new();
+    let mut next = buf.as_ptr() as *const AdapterAddresses;
+    while let Some(adapter) = unsafe { next.as_ref() } {
+        next = adapter.next;
+        let mut addrs = Vec::new();
+        let mut unicast = adapter.first_unicast_address;
+        while let Some(addr) = unsafe { unicast.as_ref() } {
+            unicast = addr.next;
+            let sockaddr = unsafe { &*addr.address.sockaddr };
+            if sockaddr.family == AF_INET {
+                addrs.push((Ipv4Addr::from(sockaddr.addr), addr.on_link_prefix_length));
+            }
+        }
+        let len = adapter.physical_address_length as usize;
+        let guid = unsafe { std::ffi::CStr::from_ptr(adapter.adapter_name) };
+        adapters.push(Adapter {
+            name: wide_string(adapter.friendly_name),
+            guid: guid.to_string_lossy().into_owned(),
+            // Adapters without IPv4 only have an IPv6 index, which is the same otherwise
+            index: match adapter.if_index {
+                0 => adapter.ipv6_if_index,
+                index => index,
+            } as usize,
+            mac: match len {
+                6 => MacAddress::from_bytes(&adapter.physical_address[..len]).ok(),
+                _ => None,
+            },
+            addrs,
+        });
+    }
+    Ok(adapters)
+}
+
+fn adapter(index: usize) -> Result<Adapter, Error> {
+    adapters()
+        .map_err(Error::Interfaces)?
+        .into_iter()
+        .find(|adapter| adapter.index == index)
+        .ok_or_else(|| Error::InterfaceMissing {
+            iface: format!("with index {}", index),
+        })
+}
+
+pub fn lookup_link_addr(iface: &str) -> Result<LinkAddr, Error> {
+    adapters()
+        .map_err(Error::Interfaces)?
+        .into_iter()
+        .find(|adapter| adapter.name == iface)
+        .and_then(|adapter| Some(LinkAddr::new(adapter.index, 0, adapter.mac?)))
+        .ok_or_else(|| Error::InterfaceMissing {
+            iface: iface.to_owned(),
+        })
+}
+
+// The IPv4 addresses configured on an interface
+pub fn interface_addrs(iface: &str) -> sys::Result<Vec<Ipv4Addr>> {
+    Ok(adapters()?
+        .into_iter()
+        .filter(|adapter| adapter.name == iface)
+        .flat_map(|adapter| adapter.addrs)
+        .map(|(addr, _)| addr)
+        .collect())
+}
+
+// The IPv4 subnets of the addresses configured on an interface
+pub fn interface_subnets(iface: &str) -> sys::Result<Vec<Ipv4Net>> {
+    Ok(adapters()?
+        .into_iter()
+        .filter(|adapter| adapter.name == iface)
+        .flat_map(|adapter| adapter.addrs)
+        .filter_map(|(addr, prefix)| Ipv4Net::new(addr, prefix).ok())
+        .collect())
+}
+
+pub fn interface_names() -> sys::Result<Vec<String>> {
+    let mut names: Vec<String> = adapters()?
+        .into_iter()
+        .filter(|adapter| adapter.mac.is_some())
+        .map(|adapter| adapter.name)
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+pub fn interface_name(index: usize) -> Result<String, Error> {
+    adapter(index).map(|adapter| adapter.name)
+}
+
+// The name Windows knows an interface by internally, its GUID, which Npcap devices are
+// named after
+pub fn adapter_name(index: usize) -> Result<String, Error> {
+    adapter(index).map(|adapter| adapter.guid)
+}
+
+// The index of the interface Windows would use to reach an IP address
+pub fn best_interface(ip: IpAddr) -> sys::Result<usize> {
+    let mut index = 0;
+    let res = match ip {
+        IpAddr::V4(ip) => {
+            let addr = SockaddrIn {
+                family: AF_INET,
+                port: 0,
+                addr: ip.octets(),
+                zero: [0; 8],
+            };
+            unsafe { GetBestInterfaceEx(&addr as *const _ as *const c_void, &mut index) }
+        }
+        IpAddr::V6(ip) => {
+            let addr = SockaddrIn6 {
+                family: AF_INET6,
+                port: 0,
+                flowinfo: 0,
+                addr: ip.octets(),
+                scope_id: 0,
+            };
+            unsafe { GetBestInterfaceEx(&addr as *const _ as *const c_void, &mut index) }
+        }
+    };
+    match res {
+        NO_ERROR => Ok(index as usize),
+        err => Err(sys::Error::from_win32(err)),
+    }
+}
+
+extern "system" fn notified(event: *mut c_void, _row: *const c_void, _kind: u32) {
+    unsafe { sys::SetEvent(event) };
+}
+
+// Notifications about interfaces and their addresses (or routes) being added, removed or
+// changed, signaling an event for the main loop to wait for
+pub struct LinkMonitor {
+    event: RawFd,
+    notifications: Vec<RawFd>,
+}
+
+impl LinkMonitor {
+    pub fn new() -> sys::Result<Self> {
+        Self::subscribe(&[NotifyIpInterfaceChange, NotifyUnicastIpAddressChange])
+    }
+
+    pub fn routes() -> sys::Result<Self> {
+        Self::subscribe(&[NotifyRouteChange2])
+    }
+
+    fn subscribe(notifiers: &[Notify]) -> sys::Result<Self> {
+        let event = unsafe { sys::CreateEventW(std::ptr::null_mut(), 1, 0, std::ptr::null()) };
+        if event.is_null() {
+            return Err(sys::Error::from_win32(unsafe { sys::GetLastError() }));
+        }
+        let mut monitor = Self {
+            event,
+            notifications: Vec::new(),
+        };
+        for notify in notifiers {
+            let mut handle = std::ptr::null_mut();
+            match unsafe { notify(AF_UNSPEC, notified, event, 0, &mut handle) } {
+                NO_ERROR => monitor.notifications.push(handle),
+                err => return Err(sys::Error::from_win32(err)),
+            }
+        }
+        Ok(monitor)
+    }
+
+    // Take pending notifications, returning whether there were any
+    pub fn changed(&self) -> bool {
+        sys::take_event(self.event)
+    }
+}
+
+impl AsRawFd for LinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.event
+    }
+}
+
+impl Drop for LinkMonitor {
+    fn drop(&mut self) {
+        for handle in &self.notifications {
+            unsafe { CancelMibChangeNotify2(*handle) };
+        }
+        unsafe { sys::CloseHandle(self.event) };
+    }
+}
@@ -0,0 +1,79 @@
+// Only the ARP packet code is available without std, e.g. for firmware
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "std")]
+pub mod anomaly;
+pub mod arp;
+#[cfg(feature = "std")]
+pub mod bench;
+#[cfg(all(
+    feature = "std",
+    any(
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "macos",
+        target_os = "ios"
+    )
+))]
+pub mod bpf;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod decode;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod ether;
+#[cfg(all(feature = "std", unix))]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod iface;
+#[cfg(all(feature = "std", windows))]
+pub mod iphlpapi;
+#[cfg(any(feature = "libpcap", all(feature = "std", windows)))]
+pub mod libpcap;
+#[cfg(feature = "std")]
+pub mod link;
+#[cfg(feature = "std")]
+pub mod monitor;
+#[cfg(feature = "std")]
+pub mod ndp;
+#[cfg(feature = "std")]
+pub mod net;
+#[cfg(feature = "std")]
+pub mod pcap;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod queue;
+#[cfg(feature = "std")]
+pub mod rarp;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod responder;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod ring;
+#[cfg(feature = "std")]
+pub mod route;
+#[cfg(feature = "std")]
+pub mod schedule;
+#[cfg(feature = "std")]
+pub mod sponge;
+#[cfg(feature = "std")]
+pub mod sys;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod tap;
+#[cfg(all(feature = "std", unix))]
+pub mod tls;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+pub mod xdp;
+
+#[cfg(feature = "std")]
+pub use error::Error;
+#[cfg(feature = "std")]
+pub use responder::Responder;
@@ -0,0 +1,427 @@
+use crate::ether;
+use crate::iface::PacketIo;
+use crate::link::{self, BpfInsn, LinkAddr, Membership};
+use crate::pcap;
+use crate::sys::{self, AsRawFd, RawFd};
+use eui48::MacAddress;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_void};
+use std::sync::OnceLock;
+
+// Sizes, errors and directions, from pcap/pcap.h
+const PCAP_ERRBUF_SIZE: usize = 256;
+const PCAP_ERROR_NO_SUCH_DEVICE: c_int = -5;
+const PCAP_ERROR_PERM_DENIED: c_int = -8;
+const PCAP_D_IN: c_int = 1;
+const PCAP_NETMASK_UNKNOWN: u32 = 0xffff_ffff;
+
+#[cfg(unix)]
+const LIBRARIES: [&[u8]; 3] = [b"libpcap.so.1\0", b"libpcap.so\0", b"libpcap.so.0.8\0"];
+
+// What libpcap hands out to wait for frames with: a descriptor, or an event on Windows
+#[cfg(unix)]
+const SELECTABLE: &str = "get_selectable_fd";
+#[cfg(windows)]
+const SELECTABLE: &str = "getevent";
+#[cfg(unix)]
+const NO_FD: RawFd = -1;
+#[cfg(windows)]
+const NO_FD: RawFd = std::ptr::null_mut();
+
+// Only Linux has an errno for a library failing to load, which Windows borrows
+#[cfg(any(target_os = "linux", windows))]
+const NO_LIBRARY: sys::Error = sys::Error::ELIBACC;
+#[cfg(not(any(target_os = "linux", windows)))]
+const NO_LIBRARY: sys::Error = sys::Error::ENOENT;
+
+// Frames taken from libpcap at once, and the longest frame received whole
+const BATCH: c_int = 64;
+const SNAPLEN: c_int = 1600;
+
+#[repr(C)]
+struct Pcap {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct BpfProgram {
+    bf_len: c_uint,
+    bf_insns: *mut c_void,
+}
+
+#[cfg(unix)]
+type Timeval = nix::libc::timeval;
+
+// A struct timeval from winsock2.h, of two longs
+#[cfg(windows)]
+#[repr(C)]
+struct Timeval {
+    tv_sec: i32,
+    tv_usec: i32,
+}
+
+#[repr(C)]
+struct PktHdr {
+    ts: Timeval,
+    caplen: u32,
+    len: u32,
+}
+
+type Handler = extern "C" fn(*mut c_uchar, *const PktHdr, *const c_uchar);
+
+// The functions of libpcap (or Npcap's wpcap.dll), loaded when first opening a handle rather
+// than linked, so that the binary runs without it
+struct Library {
+    create: unsafe extern "C" fn(*const c_char, *mut c_char) -> *mut Pcap,
+    set_snaplen: unsafe extern "C" fn(*mut Pcap, c_int) -> c_int,
+    set_promisc: unsafe extern "C" fn(*mut Pcap, c_int) -> c_int,
+    set_immediate_mode: unsafe extern "C" fn(*mut Pcap, c_int) -> c_int,
+    activate: unsafe extern "C" fn(*mut Pcap) -> c_int,
+    setdirection: unsafe extern "C" fn(*mut Pcap, c_int) -> c_int,
+    setnonblock: unsafe extern "C" fn(*mut Pcap, c_int, *mut c_char) -> c_int,
+    compile: unsafe extern "C" fn(*mut Pcap, *mut BpfProgram, *const c_char, c_int, u32) -> c_int,
+    setfilter: unsafe extern "C" fn(*mut Pcap, *mut BpfProgram) -> c_int,
+    freecode: unsafe extern "C" fn(*mut BpfProgram),
+    selectable: unsafe extern "C" fn(*mut Pcap) -> RawFd,
+    dispatch: unsafe extern "C" fn(*mut Pcap, c_int, Handler, *mut c_uchar) -> c_int,
+    inject: unsafe extern "C" fn(*mut Pcap, *const c_void, usize) -> c_int,
+    geterr: unsafe extern "C" fn(*mut Pcap) -> *mut c_char,
+    close: unsafe extern "C" fn(*mut Pcap),
+}
+
+#[cfg(unix)]
+fn dlerror() -> String {
+    let err = unsafe { nix::libc::dlerror() };
+    if err.is_null() {
+        return "unknown error".to_string();
+    }
+    unsafe { CStr::from_ptr(err) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(unix)]
+fn open_library() -> Result<*mut c_void, String> {
+    LIBRARIES
+        .iter()
+        .map(|name| unsafe {
+            nix::libc::dlopen(
+                name.as_ptr() as *const c_char,
+                nix::libc::RTLD_NOW | nix::libc::RTLD_LOCAL,
+            )
+        })
+        .find(|handle| !handle.is_null())
+        .ok_or_else(dlerror)
+}
+
+#[cfg(unix)]
+fn lookup(handle: *mut c_void, symbol: &CStr) -> Result<*mut c_void, String> {
+    let address = unsafe { nix::libc::dlsym(handle, symbol.as_ptr()) };
+    if address.is_null() {
+        Err(dlerror())
+    } else {
+        Ok(address)
+    }
+}
+
+// Npcap installs wpcap.dll in a directory of its own under System32, with the Packet.dll
+// next to it that it loads, falling back to System32 itself for Npcap in WinPcap compatible
+// mode
+#[cfg(windows)]
+fn open_library() -> Result<*mut c_void, String> {
+    const LOAD_WITH_ALTERED_SEARCH_PATH: u32 = 0x8;
+    let mut dir = [0u16; 260];
+    let len = unsafe { sys::GetSystemDirectoryW(dir.as_mut_ptr(), dir.len() as u32) } as usize;
+    let npcap = String::from_utf16_lossy(&dir[..len.min(dir.len())]) + "\\Npcap\\wpcap.dll";
+    for (name, flags) in [
+        (npcap.as_str(), LOAD_WITH_ALTERED_SEARCH_PATH),
+        ("wpcap.dll", 0),
+    ] {
+        let name: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+        let handle = unsafe { sys::LoadLibraryExW(name.as_ptr(), std::ptr::null_mut(), flags) };
+        if !handle.is_null() {
+            return Ok(handle);
+        }
+    }
+    Err(format!(
+        "wpcap.dll not found (error {}), is Npcap installed?",
+        unsafe { sys::GetLastError() }
+    ))
+}
+
+#[cfg(windows)]
+fn lookup(handle: *mut c_void, symbol: &CStr) -> Result<*mut c_void, String> {
+    let address = unsafe { sys::GetProcAddress(handle, symbol.as_ptr()) };
+    if address.is_null() {
+        Err(format!("{} not found", symbol.to_string_lossy()))
+    } else {
+        Ok(address)
+    }
+}
+
+impl Library {
+    fn load() -> Result<Self, String> {
+        let handle = open_library()?;
+        let symbol = |name: &str| {
+            let symbol = CString::new(format!("pcap_{}", name)).unwrap();
+            lookup(handle, &symbol)
+        };
+        // The symbols are the functions declared by the fields they are assigned to
+        unsafe {
+            Ok(Self {
+                create: std::mem::transmute_copy(&symbol("create")?),
+                set_snaplen: std::mem::transmute_copy(&symbol("set_snaplen")?),
+                set_promisc: std::mem::transmute_copy(&symbol("set_promisc")?),
+                set_immediate_mode: std::mem::transmute_copy(&symbol("set_immediate_mode")?),
+                activate: std::mem::transmute_copy(&symbol("activate")?),
+                setdirection: std::mem::transmute_copy(&symbol("setdirection")?),
+                setnonblock: std::mem::transmute_copy(&symbol("setnonblock")?),
+                compile: std::mem::transmute_copy(&symbol("compile")?),
+                setfilter: std::mem::transmute_copy(&symbol("setfilter")?),
+                freecode: std::mem::transmute_copy(&symbol("freecode")?),
+                selectable: std::mem::transmute_copy(&symbol(SELECTABLE)?),
+                dispatch: std::mem::transmute_copy(&symbol("dispatch")?),
+                inject: std::mem::transmute_copy(&symbol("inject")?),
+                geterr: std::mem::transmute_copy(&symbol("geterr")?),
+                close: std::mem::transmute_copy(&symbol("close")?),
+            })
+        }
+    }
+}
+
+fn library() -> sys::Result<&'static Library> {
+    static LIBRARY: OnceLock<Option<Library>> = OnceLock::new();
+    LIBRARY
+        .get_or_init(|| match Library::load() {
+            Ok(library) => Some(library),
+            Err(err) => {
+                log::error!("failed to load libpcap: {}", err);
+                None
+            }
+        })
+        .as_ref()
+        .ok_or(NO_LIBRARY)
+}
+
+extern "C" fn queue_frame(user: *mut c_uchar, header: *const PktHdr, bytes: *const c_uchar) {
+    let received = unsafe { &mut *(user as *mut VecDeque<Vec<u8>>) };
+    let len = unsafe { (*header).caplen } as usize;
+    received.push_back(unsafe { std::slice::from_raw_parts(bytes, len) }.to_vec());
+}
+
+// A libpcap handle on the interface, for where packet sockets aren't available and on
+// Windows, through Npcap. Frames arrive whole, in promiscuous mode as libpcap doesn't join
+// multicast groups, passing a filter on the protocol before being told apart like on tap
+// devices.
+pub struct PcapHandle {
+    library: &'static Library,
+    pcap: *mut Pcap,
+    fd: RawFd,
+    link_addr: LinkAddr,
+    vlan: Option<ether::Vlan>,
+    min_len: usize,
+    capture: Option<pcap::Capture>,
+    received: RefCell<VecDeque<Vec<u8>>>,
+}
+
+// The handle is only used by one thread at a time
+unsafe impl Send for PcapHandle {}
+
+impl Drop for PcapHandle {
+    fn drop(&mut self) {
+        unsafe { (self.library.close)(self.pcap) };
+    }
+}
+
+// The device libpcap knows an interface as, which Npcap names after its GUID
+#[cfg(unix)]
+fn device_name(ifindex: usize) -> Result<String, crate::error::Error> {
+    crate::iface::interface_name(ifindex)
+}
+
+#[cfg(windows)]
+fn device_name(ifindex: usize) -> Result<String, crate::error::Error> {
+    crate::iphlpapi::adapter_name(ifindex).map(|guid| format!("\\Device\\NPF_{}", guid))
+}
+
+impl PcapHandle {
+    // Fail with the handle's error if libpcap returned one
+    fn check(&self, what: &str, res: c_int) -> sys::Result<c_int> {
+        if res >= 0 {
+            return Ok(res);
+        }
+        let err = unsafe { CStr::from_ptr((self.library.geterr)(self.pcap)) };
+        log::error!(
+            "failed to {} through libpcap: {}",
+            what,
+            err.to_string_lossy()
+        );
+        Err(match res {
+            PCAP_ERROR_NO_SUCH_DEVICE => sys::Error::ENODEV,
+            PCAP_ERROR_PERM_DENIED => sys::Error::EPERM,
+            _ => sys::Error::EIO,
+        })
+    }
+
+    // Only receive frames sent by others
+    #[cfg(unix)]
+    fn set_direction(&self) -> sys::Result<()> {
+        let res = unsafe { (self.library.setdirection)(self.pcap, PCAP_D_IN) };
+        self.check("set the direction", res).map(drop)
+    }
+
+    // Older versions of Npcap can't leave out frames sent by this host, which are then received
+    // like any other
+    #[cfg(windows)]
+    fn set_direction(&self) -> sys::Result<()> {
+        if unsafe { (self.library.setdirection)(self.pcap, PCAP_D_IN) } < 0 {
+            let err = unsafe { CStr::from_ptr((self.library.geterr)(self.pcap)) };
+            log::warn!(
+                "receiving frames sent by this host too: {}",
+                err.to_string_lossy()
+            );
+        }
+        Ok(())
+    }
+
+    fn set_filter_expression(&self, expression: &str) -> sys::Result<()> {
+        let expression = CString::new(expression).unwrap();
+        let mut program = BpfProgram {
+            bf_len: 0,
+            bf_insns: std::ptr::null_mut(),
+        };
+        let res = unsafe {
+            (self.library.compile)(
+                self.pcap,
+                &mut program,
+                expression.as_ptr(),
+                1,
+                PCAP_NETMASK_UNKNOWN,
+            )
+        };
+        self.check("compile the filter", res)?;
+        let res = unsafe { (self.library.setfilter)(self.pcap, &mut program) };
+        unsafe { (self.library.freecode)(&mut program) };
+        self.check("set the filter", res).map(drop)
+    }
+}
+
+impl AsRawFd for PcapHandle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl PacketIo for PcapHandle {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        _raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        _filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> sys::Result<Self> {
+        let library = library()?;
+        let name = device_name(link_addr.ifindex).map_err(|_| sys::Error::ENODEV)?;
+        let name = CString::new(name).map_err(|_| sys::Error::EINVAL)?;
+        let mut errbuf = [0 as c_char; PCAP_ERRBUF_SIZE];
+        let pcap = unsafe { (library.create)(name.as_ptr(), errbuf.as_mut_ptr()) };
+        if pcap.is_null() {
+            let err = unsafe { CStr::from_ptr(errbuf.as_ptr()) };
+            log::error!("failed to open libpcap handle: {}", err.to_string_lossy());
+            return Err(sys::Error::ENODEV);
+        }
+        let link_addr = LinkAddr {
+            protocol,
+            ..*link_addr
+        };
+        let mut handle = Self {
+            library,
+            pcap,
+            fd: NO_FD,
+            link_addr,
+            vlan,
+            min_len,
+            capture,
+            received: RefCell::new(VecDeque::new()),
+        };
+        unsafe {
+            handle.check(
+                "set the snapshot length",
+                (library.set_snaplen)(pcap, SNAPLEN),
+            )?;
+            handle.check("set promiscuous mode", (library.set_promisc)(pcap, 1))?;
+            handle.check("set immediate mode", (library.set_immediate_mode)(pcap, 1))?;
+            handle.check("activate", (library.activate)(pcap))?;
+            handle.set_direction()?;
+            handle.check(
+                "set non-blocking mode",
+                (library.setnonblock)(pcap, 1, errbuf.as_mut_ptr()),
+            )?;
+            handle.fd = (library.selectable)(pcap);
+        }
+        if handle.fd == NO_FD {
+            return Err(sys::Error::EOPNOTSUPP);
+        }
+
+        // Tagged and 802.3 frames pass too, for their VLAN and SNAP headers to be checked in
+        // recv_from
+        handle.set_filter_expression(&format!("ether proto {} or llc or vlan", protocol))?;
+        Ok(handle)
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.link_addr
+    }
+
+    fn is_raw(&self) -> bool {
+        true
+    }
+
+    // Frames are only told apart by protocol and VLAN, when opening and in recv_from
+    fn set_filter(&self, _filter: &[BpfInsn]) -> sys::Result<()> {
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> sys::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let mut received = self.received.borrow_mut();
+        if received.is_empty() {
+            let user = &mut *received as *mut VecDeque<Vec<u8>> as *mut c_uchar;
+            let res = unsafe { (self.library.dispatch)(self.pcap, BATCH, queue_frame, user) };
+            self.check("receive", res)?;
+        }
+        let frame = received.pop_front().ok_or(sys::Error::EAGAIN)?;
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, &frame);
+        }
+        link::raw_payload(&self.link_addr, self.vlan, &frame, buf)
+    }
+
+    fn pending(&self) -> bool {
+        !self.received.borrow().is_empty()
+    }
+
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> sys::Result<usize> {
+        let (len, frame) = link::raw_frame(&self.link_addr, self.vlan, self.min_len, buf, src, to);
+        let res = unsafe {
+            (self.library.inject)(self.pcap, frame.as_ptr() as *const c_void, frame.len())
+        };
+        let size = self.check("send", res)? as usize;
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, &frame);
+        }
+        Ok(size.saturating_sub(len).min(buf.len()))
+    }
+
+    // The handle is in promiscuous mode already
+    fn set_membership(&self, _membership: Membership, _join: bool) -> sys::Result<()> {
+        Ok(())
+    }
+
+    fn join_fanout(&self, _id: Option<u16>) -> sys::Result<u16> {
+        Err(sys::Error::EOPNOTSUPP)
+    }
+}
@@ -0,0 +1,412 @@
+use crate::ether;
+use crate::pcap;
+use crate::sys::{self, AsRawFd, PollFd};
+use eui48::MacAddress;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::net::Ipv4Addr;
+use std::os::raw::c_int;
+
+// How a received frame was addressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    // To the interface's own MAC address
+    Host,
+    Broadcast,
+    Multicast,
+    // To another host, as received in promiscuous mode
+    OtherHost,
+    // Sent from this host
+    Outgoing,
+}
+
+// Where frames of an ethernet protocol are sent to or were received from on an interface,
+// independent of how the platform represents link-level addresses (sockaddr_ll on Linux,
+// sockaddr_dl and BPF devices on the BSDs and macOS)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkAddr {
+    pub ifindex: usize,
+    pub protocol: u16,
+    // The other end's MAC address, or the interface's own for the interface itself
+    pub mac: MacAddress,
+    // How a received frame was addressed, Host for the addresses frames are sent to
+    pub packet_type: PacketType,
+}
+
+impl LinkAddr {
+    pub fn new(ifindex: usize, protocol: u16, mac: MacAddress) -> Self {
+        Self {
+            ifindex,
+            protocol,
+            mac,
+            packet_type: PacketType::Host,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<&nix::libc::sockaddr_ll> for LinkAddr {
+    fn from(addr: &nix::libc::sockaddr_ll) -> Self {
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&addr.sll_addr[..6]);
+        Self {
+            ifindex: addr.sll_ifindex as usize,
+            protocol: u16::from_be(addr.sll_protocol),
+            mac: MacAddress::new(mac),
+            packet_type: match addr.sll_pkttype {
+                PACKET_HOST => PacketType::Host,
+                PACKET_BROADCAST => PacketType::Broadcast,
+                PACKET_MULTICAST => PacketType::Multicast,
+                PACKET_OTHERHOST => PacketType::OtherHost,
+                _ => PacketType::Outgoing,
+            },
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<&LinkAddr> for nix::libc::sockaddr_ll {
+    fn from(addr: &LinkAddr) -> Self {
+        let mut sll_addr = [0; 8];
+        sll_addr[..6].copy_from_slice(addr.mac.as_bytes());
+        nix::libc::sockaddr_ll {
+            sll_family: nix::libc::AF_PACKET as u16,
+            sll_protocol: addr.protocol.to_be(),
+            sll_ifindex: addr.ifindex as i32,
+            sll_hatype: nix::libc::ARPHRD_ETHER,
+            sll_pkttype: match addr.packet_type {
+                PacketType::Host => PACKET_HOST,
+                PacketType::Broadcast => PACKET_BROADCAST,
+                PacketType::Multicast => PACKET_MULTICAST,
+                PacketType::OtherHost => PACKET_OTHERHOST,
+                PacketType::Outgoing => PACKET_OUTGOING,
+            },
+            sll_halen: 6,
+            sll_addr,
+        }
+    }
+}
+
+// Packet types of sockaddr_ll, from linux/if_packet.h
+#[cfg(target_os = "linux")]
+const PACKET_HOST: u8 = 0;
+#[cfg(target_os = "linux")]
+const PACKET_BROADCAST: u8 = 1;
+#[cfg(target_os = "linux")]
+const PACKET_MULTICAST: u8 = 2;
+#[cfg(target_os = "linux")]
+const PACKET_OTHERHOST: u8 = 3;
+#[cfg(target_os = "linux")]
+const PACKET_OUTGOING: u8 = 4;
+
+// Frames an interface receives besides those sent to its own MAC address and broadcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Membership {
+    // Sent to an ethernet multicast address
+    Multicast(MacAddress),
+    // Sent to any ethernet multicast address
+    AllMulti,
+    // Sent to other hosts as well
+    Promisc,
+}
+
+// A classic BPF instruction, laid out like Linux's sock_filter and the BSDs' bpf_insn
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfInsn {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+pub(crate) const fn bpf_stmt(code: u16, k: u32) -> BpfInsn {
+    BpfInsn {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+pub(crate) const fn bpf_jump(code: u16, k: u32, jt: u8, jf: u8) -> BpfInsn {
+    BpfInsn { code, jt, jf, k }
+}
+
+pub(crate) const BPF_LD_ABS: u16 = 0x20;
+pub(crate) const BPF_LDH_ABS: u16 = 0x28;
+pub(crate) const BPF_LDB_ABS: u16 = 0x30;
+#[cfg(target_os = "linux")]
+pub(crate) const BPF_AND_K: u16 = 0x54;
+pub(crate) const BPF_JEQ_K: u16 = 0x15;
+pub(crate) const BPF_JGT_K: u16 = 0x25;
+pub(crate) const BPF_JGE_K: u16 = 0x35;
+pub(crate) const BPF_JA: u16 = 0x05;
+pub(crate) const BPF_RET_K: u16 = 0x06;
+
+// How the kernel answers ARP requests for an address by itself: with the MAC address, in a
+// frame from src, to the broadcast address instead of the requester when broadcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelAnswer {
+    pub mac: MacAddress,
+    pub src: MacAddress,
+    pub broadcast: bool,
+}
+
+// Sending and receiving the frames of one ethernet protocol on an interface, through packet
+// sockets (iface::PacketSocket), BPF devices (bpf::BpfDevice), libpcap or Npcap
+// (libpcap::PcapHandle) or alternatives like mocks in tests
+pub trait PacketIo: AsRawFd + Sized {
+    // Open for the protocol in the VLAN (or untagged), passing the frames through the
+    // filter program, padding frames sent raw with zeros to min_len, and writing the frames
+    // received and sent to the capture
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> sys::Result<Self>;
+
+    // Link address of the interface, with the protocol
+    fn link_addr(&self) -> LinkAddr;
+
+    // Whether the ethernet header is received and sent, instead of the kernel handling it
+    fn is_raw(&self) -> bool;
+
+    // Replace the filter program given when opening
+    fn set_filter(&self, filter: &[BpfInsn]) -> sys::Result<()>;
+
+    // Receive a packet into the start of buf, with its sender and the ethernet header it
+    // came with when raw
+    fn recv_from(&self, buf: &mut [u8]) -> sys::Result<(usize, LinkAddr, Option<ether::Header>)>;
+
+    // Whether another packet can be received right away, without polling first, e.g. from
+    // a ring shared with the kernel
+    fn pending(&self) -> bool {
+        false
+    }
+
+    // Hold back sends until no longer deferred, then send them together, e.g. the replies
+    // to a batch of requests
+    fn defer_sends(&self, _defer: bool) -> sys::Result<()> {
+        Ok(())
+    }
+
+    // Send with the given source MAC address in the ethernet header, which only raw
+    // sockets control (the kernel uses the interface's address otherwise)
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> sys::Result<usize>;
+
+    // Join or leave multicast groups or promiscuous mode
+    fn set_membership(&self, membership: Membership, join: bool) -> sys::Result<()>;
+
+    // Join the fanout group with this ID, or a new one when None, returning its ID
+    fn join_fanout(&self, id: Option<u16>) -> sys::Result<u16>;
+
+    // Take ARP packets about addresses in the ranges away from the kernel, for sockets
+    // receiving them before it does, like through XDP, so it still sees its own
+    fn set_arp_ranges(&self, _ranges: &[(Ipv4Addr, Ipv4Addr)]) -> sys::Result<()> {
+        Ok(())
+    }
+
+    // Have the kernel answer ARP requests for the addresses by itself, e.g. from an XDP
+    // program, leaving the requests it doesn't answer to be received as usual
+    fn set_arp_answers(&self, _answers: &BTreeMap<Ipv4Addr, KernelAnswer>) -> sys::Result<()> {
+        Err(sys::Error::EOPNOTSUPP)
+    }
+
+    // Busy poll the device's receive queue for up to this many microseconds when no packet
+    // is queued yet (SO_BUSY_POLL), 0 to stop
+    fn set_busy_poll(&self, _usecs: u32) -> sys::Result<()> {
+        Err(sys::Error::EOPNOTSUPP)
+    }
+
+    // Number of ARP replies the kernel sent by itself since last called
+    fn kernel_replies(&self) -> u64 {
+        0
+    }
+
+    // Wait like poll(2) for the sockets and other file descriptors, for sockets completing
+    // their I/O elsewhere to wait for both at once, like through io_uring
+    fn poll(fds: &mut [PollFd], timeout: c_int) -> sys::Result<c_int> {
+        sys::poll(fds, timeout)
+    }
+
+    // Link address for sending to the given MAC address on this interface
+    fn link_addr_to(&self, mac: MacAddress) -> LinkAddr {
+        LinkAddr {
+            mac,
+            ..self.link_addr()
+        }
+    }
+
+    fn send_to(&self, buf: &[u8], to: &LinkAddr) -> sys::Result<usize> {
+        self.send_from(buf, self.link_addr().mac, to)
+    }
+
+    // Receive frames sent to an ethernet multicast address
+    fn add_membership(&self, mac: MacAddress) -> sys::Result<()> {
+        self.set_membership(Membership::Multicast(mac), true)
+    }
+
+    fn drop_membership(&self, mac: MacAddress) -> sys::Result<()> {
+        self.set_membership(Membership::Multicast(mac), false)
+    }
+
+    // Receive frames sent to any ethernet multicast address
+    fn add_allmulti(&self) -> sys::Result<()> {
+        self.set_membership(Membership::AllMulti, true)
+    }
+
+    fn drop_allmulti(&self) -> sys::Result<()> {
+        self.set_membership(Membership::AllMulti, false)
+    }
+
+    // Receive frames sent to other hosts as well
+    fn add_promisc(&self) -> sys::Result<()> {
+        self.set_membership(Membership::Promisc, true)
+    }
+
+    fn drop_promisc(&self) -> sys::Result<()> {
+        self.set_membership(Membership::Promisc, false)
+    }
+}
+
+// The frame sending the payload from src to the link address on a raw socket, as an 802.3
+// SNAP frame when sent to the 802.2 protocol, padded with zeros to min_len, with the length
+// of its header
+pub(crate) fn raw_frame(
+    link_addr: &LinkAddr,
+    vlan: Option<ether::Vlan>,
+    min_len: usize,
+    buf: &[u8],
+    src: MacAddress,
+    to: &LinkAddr,
+) -> (usize, Vec<u8>) {
+    let snap = to.protocol == ether::ETH_P_802_2;
+    let header = ether::Header {
+        dst: to.mac,
+        src,
+        vlan,
+        ethertype: if snap {
+            link_addr.protocol
+        } else {
+            to.protocol
+        },
+        snap,
+    };
+    let mut frame = header.frame(buf);
+    if frame.len() < min_len {
+        frame.resize(min_len, 0);
+    }
+    (header.size(), frame)
+}
+
+// Copy the payload of a frame received whole into buf, with the link address it came from
+// and its header, for devices without a kernel filter: frames of other protocols than the
+// link address's or of other VLANs are dropped as if nothing was received, with priority
+// tagged frames counting as untagged
+pub(crate) fn raw_payload(
+    link_addr: &LinkAddr,
+    vlan: Option<ether::Vlan>,
+    frame: &[u8],
+    buf: &mut [u8],
+) -> sys::Result<(usize, LinkAddr, Option<ether::Header>)> {
+    let header = match ether::Header::try_from(frame) {
+        Ok(header)
+            if header.ethertype == link_addr.protocol
+                && match (header.vlan, vlan) {
+                    (None, None) => true,
+                    (Some(tags), None) => tags.outer.is_none() && tags.id == 0,
+                    (tags, vlan) => tags == vlan,
+                } =>
+        {
+            header
+        }
+        _ => return Err(sys::Error::EAGAIN),
+    };
+    let payload = &frame[header.size()..];
+    let len = payload.len().min(buf.len());
+    buf[..len].copy_from_slice(&payload[..len]);
+
+    let from = LinkAddr {
+        mac: header.src,
+        protocol: if header.snap {
+            ether::ETH_P_802_2
+        } else {
+            link_addr.protocol
+        },
+        packet_type: packet_type(link_addr, header.dst),
+        ..*link_addr
+    };
+    Ok((len, from, Some(header)))
+}
+
+// How a frame to dst received on the interface was addressed
+pub(crate) fn packet_type(link_addr: &LinkAddr, dst: MacAddress) -> PacketType {
+    if dst.is_broadcast() {
+        PacketType::Broadcast
+    } else if dst.is_multicast() {
+        PacketType::Multicast
+    } else if dst == link_addr.mac {
+        PacketType::Host
+    } else {
+        PacketType::OtherHost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_payloads() {
+        let arp = ether::ETH_P_ARP;
+        let link_addr = LinkAddr::new(2, arp, MacAddress::new([0x02, 0, 0, 0, 0, 0x02]));
+        let src = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let frame = |vlan, ethertype| {
+            let header = ether::Header {
+                dst: MacAddress::broadcast(),
+                src,
+                vlan,
+                ethertype,
+                snap: false,
+            };
+            header.frame(&[1, 2, 3])
+        };
+        let tag = |id| Some(ether::Vlan { outer: None, id });
+        let mut buf = [0u8; 2];
+
+        let (len, from, header) =
+            raw_payload(&link_addr, None, &frame(None, arp), &mut buf).unwrap();
+        assert_eq!((len, buf), (2, [1, 2]));
+        assert_eq!(from.mac, src);
+        assert_eq!(from.packet_type, PacketType::Broadcast);
+        assert_eq!(header.unwrap().src, src);
+
+        // Priority tagged frames count as untagged, other protocols and VLANs are dropped
+        assert!(raw_payload(&link_addr, None, &frame(tag(0), arp), &mut buf).is_ok());
+        assert!(raw_payload(&link_addr, tag(5), &frame(tag(5), arp), &mut buf).is_ok());
+        for (vlan, tags, ethertype) in [
+            (None, None, ether::ETH_P_IPV6),
+            (None, tag(5), arp),
+            (tag(5), None, arp),
+            (tag(5), tag(6), arp),
+        ] {
+            assert_eq!(
+                raw_payload(&link_addr, vlan, &frame(tags, ethertype), &mut buf).unwrap_err(),
+                sys::Error::EAGAIN
+            );
+        }
+
+        // Linux link addresses carry the protocol in network byte order
+        #[cfg(target_os = "linux")]
+        {
+            let sll = nix::libc::sockaddr_ll::from(&from);
+            assert_eq!(sll.sll_protocol, arp.to_be());
+            assert_eq!(LinkAddr::from(&sll), from);
+        }
+    }
+}
@@ -1,48 +1,1463 @@
-use eui48::MacAddress;
-use nix::ifaddrs::getifaddrs;
-use nix::sys::socket::{
-    recvfrom, sendto, socket, AddressFamily, LinkAddr, MsgFlags, SockAddr, SockFlag, SockType,
+use claim_ip::bench::{Bench, Latencies, Requester};
+use claim_ip::config::{
+    self, AnnounceOp, ArpProbePolicy, Claim, ConfigWatch, ConflictPolicy, DadPolicy, FrameMac,
+    ProbePolicy,
 };
-use std::convert::TryFrom;
+#[cfg(unix)]
+use claim_ip::health;
+use claim_ip::iface::PacketIo;
+#[cfg(feature = "libpcap")]
+use claim_ip::libpcap::PcapHandle;
+use claim_ip::link::BpfInsn;
+use claim_ip::net::{self, IpNet};
+#[cfg(target_os = "linux")]
+use claim_ip::queue::QueuedSocket;
+use claim_ip::replay::Replay;
+use claim_ip::responder::expand_claims;
+#[cfg(target_os = "linux")]
+use claim_ip::ring::RingSocket;
+use claim_ip::sys::{self, AsRawFd, PollFd, PollFlags, RawFd};
+#[cfg(target_os = "linux")]
+use claim_ip::tap::TapDevice;
+#[cfg(feature = "uring")]
+use claim_ip::uring::UringSocket;
+#[cfg(feature = "xdp")]
+use claim_ip::xdp::XdpSocket;
+use claim_ip::{ether, iface, ndp, pcap, schedule, Responder};
+use eui48::MacAddress;
+#[cfg(target_os = "linux")]
+use nix::sched::{sched_setaffinity, CpuSet};
+#[cfg(unix)]
+use nix::sys::signal::SigSet;
+#[cfg(target_os = "linux")]
+use nix::unistd::Pid;
+use std::convert::TryInto;
+#[cfg(unix)]
+use std::future::{poll_fn, Future};
+use std::io::{Read, Write};
+use std::net::Ipv4Addr;
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(unix)]
+use std::task::Poll;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
+#[cfg(unix)]
+use tokio::io::unix::AsyncFd;
+#[cfg(unix)]
+use tokio::io::Interest;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+#[cfg(unix)]
+use tokio::time::{sleep_until, MissedTickBehavior};
+
+struct IpList(Vec<IpNet>);
+
+// Requester MAC address and the MAC address to answer it with
+struct ReplyMac(MacAddress, MacAddress);
+
+// IP addresses, CIDR ranges and hostnames to claim
+struct AddrList {
+    ip: Vec<IpNet>,
+    hostname: Vec<String>,
+}
+
+impl FromStr for IpList {
+    type Err = net::NetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|ip| ip.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(IpList)
+    }
+}
+
+impl FromStr for ReplyMac {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requester, mac) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid reply MAC {} (expected <requester>=<mac>)", s))?;
+        let parse = |mac: &str| {
+            mac.trim()
+                .parse::<MacAddress>()
+                .map_err(|err| format!("invalid MAC address {}: {}", mac, err))
+        };
+        Ok(ReplyMac(parse(requester)?, parse(mac)?))
+    }
+}
+
+impl FromStr for AddrList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut list = AddrList {
+            ip: Vec::new(),
+            hostname: Vec::new(),
+        };
+        for addr in s.split(',').map(str::trim) {
+            match addr.parse() {
+                Ok(ip) => list.ip.push(ip),
+                Err(_) if is_hostname(addr) => list.hostname.push(addr.to_owned()),
+                Err(err) => return Err(format!("{}: {}", addr, err)),
+            }
+        }
+        Ok(list)
+    }
+}
+
+// CPU numbers and ranges of them
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct CpuList(Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cpus = Vec::new();
+        for range in s.split(',').map(str::trim) {
+            let (first, last) = range.split_once('-').unwrap_or((range, range));
+            let parse = |cpu: &str| {
+                cpu.trim()
+                    .parse::<usize>()
+                    .map_err(|err| format!("invalid CPU {}: {}", cpu, err))
+            };
+            let (first, last) = (parse(first)?, parse(last)?);
+            if first > last {
+                return Err(format!("invalid CPU range {}", range));
+            }
+            cpus.extend(first..=last);
+        }
+        Ok(CpuList(cpus))
+    }
+}
+
+// How frames are received and sent, with auto trying the backends that could work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IoBackend {
+    Auto,
+    Packet,
+    Ring,
+    #[cfg(feature = "xdp")]
+    Xdp,
+    #[cfg(feature = "uring")]
+    Uring,
+    #[cfg(feature = "libpcap")]
+    Libpcap,
+}
+
+// In the order auto tries them, fastest first
+const IO_BACKENDS: &[(&str, IoBackend)] = &[
+    ("auto", IoBackend::Auto),
+    #[cfg(feature = "xdp")]
+    ("xdp", IoBackend::Xdp),
+    #[cfg(feature = "uring")]
+    ("uring", IoBackend::Uring),
+    ("packet", IoBackend::Packet),
+    ("ring", IoBackend::Ring),
+    #[cfg(feature = "libpcap")]
+    ("libpcap", IoBackend::Libpcap),
+];
+
+impl FromStr for IoBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match IO_BACKENDS.iter().find(|(name, _)| *name == s) {
+            Some((_, backend)) => Ok(*backend),
+            None if ["xdp", "uring", "libpcap"].contains(&s) => {
+                Err(format!("{} needs building with the {} feature", s, s))
+            }
+            None => Err(format!("unknown I/O backend {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for IoBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (name, _) = IO_BACKENDS
+            .iter()
+            .find(|(_, backend)| backend == self)
+            .unwrap();
+        f.write_str(name)
+    }
+}
+
+// The backends auto tries in turn, skipping those the options rule out and the
+// memory-mapped ring, which delays replies
+#[cfg_attr(
+    not(any(feature = "xdp", feature = "uring", feature = "libpcap")),
+    allow(unused_variables)
+)]
+fn auto_backends(opt: &Opt) -> Vec<IoBackend> {
+    IO_BACKENDS
+        .iter()
+        .map(|(_, backend)| *backend)
+        .filter(|backend| match backend {
+            IoBackend::Auto | IoBackend::Ring => false,
+            IoBackend::Packet => true,
+            #[cfg(feature = "xdp")]
+            IoBackend::Xdp => opt.workers == 1 && !opt.rx_thread,
+            #[cfg(feature = "uring")]
+            IoBackend::Uring => !opt.rx_thread,
+            #[cfg(feature = "libpcap")]
+            IoBackend::Libpcap => opt.workers == 1 && !opt.rx_thread,
+        })
+        .collect()
+}
+
+// Open an ARP socket of the backend on each of the claims' interfaces there are already,
+// or on loopback without any, to see whether it works here
+fn probe<S: iface::PacketIo>(claims: &[Claim], raw: bool) -> sys::Result<()> {
+    let mut link_addrs: Vec<_> = claims
+        .iter()
+        .filter_map(|claim| iface::lookup_link_addr(&claim.iface).ok())
+        .collect();
+    link_addrs.dedup_by_key(|link_addr| link_addr.ifindex);
+    if link_addrs.is_empty() {
+        link_addrs.extend(iface::lookup_link_addr("lo").ok());
+    }
+    // Accept everything
+    let filter = [BpfInsn {
+        code: 0x06,
+        jt: 0,
+        jf: 0,
+        k: 0xffff,
+    }];
+    for link_addr in link_addrs {
+        S::open(&link_addr, ether::ETH_P_ARP, raw, None, 0, &filter, None)?;
+    }
+    Ok(())
+}
+
+// Run the calling thread with the realtime FIFO policy at the priority, leaving threads
+// and processes it starts, like health check commands, to the normal policy
+#[cfg(target_os = "linux")]
+fn set_sched_fifo(priority: i32) -> sys::Result<()> {
+    let param = nix::libc::sched_param {
+        sched_priority: priority,
+    };
+    let policy = nix::libc::SCHED_FIFO | nix::libc::SCHED_RESET_ON_FORK;
+    let res = unsafe { nix::libc::sched_setscheduler(0, policy, &param) };
+    nix::Error::result(res).map(drop)
+}
+
+// Only Linux resets the policy of processes started
+#[cfg(not(target_os = "linux"))]
+fn set_sched_fifo(_priority: i32) -> sys::Result<()> {
+    Err(sys::Error::EOPNOTSUPP)
+}
+
+fn is_hostname(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars().any(|c| c.is_ascii_alphabetic())
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+fn load_claims(
+    opt: &Opt,
+    cli_claim: Option<&Claim>,
+) -> Result<Vec<Claim>, Box<dyn std::error::Error>> {
+    let mut claims = Vec::new();
+    if let Some(path) = &opt.config {
+        let config = config::Config::load(path)?;
+        claims.extend(config.claims);
+    }
+    if let Some(path) = &opt.config_dir {
+        let config = config::Config::load_dir(path)?;
+        claims.extend(config.claims);
+    }
+    claims.extend(cli_claim.cloned());
+    if claims.is_empty() {
+        return Err("no claims configured".into());
+    }
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.ip.is_empty() && claim.hostname.is_empty())
+    {
+        return Err(format!("claim on {} has no IP addresses", claim.iface).into());
+    }
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.sponge && claim.ip.iter().any(IpNet::is_ipv6))
+    {
+        return Err(format!("sponge claim on {} has IPv6 addresses", claim.iface).into());
+    }
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.wake && claim.mac.is_none() && claim.macs.is_empty())
+    {
+        return Err(format!("wake claim on {} has no MAC addresses", claim.iface).into());
+    }
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.rarp.is_some() && claim.rarp_server(&claim.claim_set()).is_none())
+    {
+        return Err(format!("rarp claim on {} has no IPv4 addresses", claim.iface).into());
+    }
+    if let Some(claim) = claims.iter().find(|claim| {
+        claim
+            .vlan
+            .into_iter()
+            .chain(claim.outer_vlan)
+            .any(|vlan| vlan == 0 || vlan > 4094)
+    }) {
+        return Err(format!(
+            "VLAN of claim on {} is out of range (expected 1 to 4094)",
+            claim.iface
+        )
+        .into());
+    }
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.outer_vlan.is_some() && claim.vlan.is_none())
+    {
+        return Err(format!("outer VLAN of claim on {} requires a VLAN", claim.iface).into());
+    }
+    if let Some(claim) = claims
+        .iter()
+        .find(|claim| claim.frame_mac.is_some() && claim.vlan.is_none() && !opt.raw && !opt.tap)
+    {
+        return Err(format!(
+            "frame MAC of claim on {} requires --raw or a VLAN",
+            claim.iface
+        )
+        .into());
+    }
+    // Workers only see some of the packets of other hosts
+    if let Some(claim) = claims.iter().find(|claim| {
+        opt.workers > 1
+            && (claim.probe.is_some()
+                || claim.passive
+                || claim.sponge
+                || claim.on_conflict == Some(ConflictPolicy::Yield))
+    }) {
+        return Err(format!(
+            "claim on {} probes, is passive, sponges or yields, which requires a single worker",
+            claim.iface
+        )
+        .into());
+    }
+    Ok(claims)
+}
 
-pub mod arp;
+// A packet socket for ARP on the interface, with requests for the target from the source,
+// the interface's first IPv4 address or none
+fn requester(
+    name: &str,
+    target: Ipv4Addr,
+    source: Option<Ipv4Addr>,
+) -> Result<(iface::DefaultPacketIo, Requester), Box<dyn std::error::Error>> {
+    let link_addr = iface::lookup_link_addr(name)?;
+    let source = match source {
+        Some(source) => source,
+        None => iface::interface_addrs(name)?
+            .first()
+            .copied()
+            .unwrap_or(Ipv4Addr::UNSPECIFIED),
+    };
+    // Accept everything
+    let filter = [BpfInsn {
+        code: 0x06,
+        jt: 0,
+        jf: 0,
+        k: 0xffff,
+    }];
+    let socket =
+        iface::DefaultPacketIo::open(&link_addr, ether::ETH_P_ARP, false, None, 0, &filter, None)
+            .map_err(|err| format!("failed to open a packet socket on {}: {}", name, err))?;
+    let requester = Requester {
+        mac: link_addr.mac,
+        source,
+        target,
+    };
+    Ok((socket, requester))
+}
+
+// Send ARP requests for the target at the rate for the duration, then report the replies
+// that arrived until waiting for stragglers
+fn bench(
+    name: &str,
+    target: Ipv4Addr,
+    rate: u64,
+    duration: Duration,
+    wait: Duration,
+    source: Option<Ipv4Addr>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if rate == 0 {
+        return Err("the rate must be at least 1 request per second".into());
+    }
+    let (socket, requester) = requester(name, target, source)?;
+    let mut bench = Bench::new(requester, rate);
+    let request = bench.request().to_vec();
+    let to = socket.link_addr_to(MacAddress::broadcast());
+    let mut rbuf = [0u8; 500];
+    let start = Instant::now();
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= duration + wait {
+            break;
+        }
+        let mut timeout = duration + wait - elapsed;
+        if elapsed < duration {
+            // Requests the socket has no room for are sent when there is
+            for _ in 0..bench.due(elapsed) {
+                match socket.send_to(&request, &to) {
+                    Ok(_) => bench.sent(1),
+                    Err(sys::Error::EAGAIN) | Err(sys::Error::ENOBUFS) => break,
+                    Err(err) => return Err(format!("failed to send on {}: {}", name, err).into()),
+                }
+            }
+            timeout = bench.next().saturating_sub(elapsed);
+        }
+        let timeout = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        wait_readable(socket.as_raw_fd(), timeout)?;
+        while let Ok((size, _, _)) = socket.recv_from(&mut rbuf) {
+            bench.receive(&rbuf[..size]);
+        }
+    }
+    print!("{}", bench.report(duration.min(start.elapsed())));
+    Ok(())
+}
 
-fn lookup_link_addr(iface: &str) -> Result<LinkAddr, Box<dyn std::error::Error>> {
-    for ifaddr in getifaddrs()? {
-        if ifaddr.interface_name == iface {
-            if let Some(SockAddr::Link(link_addr)) = ifaddr.address {
-                return Ok(link_addr);
+// Send a request for the target every interval, printing the replies with how long they
+// took, until sending count requests or interrupted, then summarize the latencies
+fn ping(
+    name: &str,
+    target: Ipv4Addr,
+    count: u64,
+    interval: Duration,
+    source: Option<Ipv4Addr>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (socket, requester) = requester(name, target, source)?;
+    let request = requester.request().to_vec();
+    let to = socket.link_addr_to(MacAddress::broadcast());
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{signal, SigHandler, Signal};
+        for signo in [Signal::SIGINT, Signal::SIGTERM] {
+            unsafe { signal(signo, SigHandler::Handler(signal_termination_handler)) }?;
+        }
+    }
+    #[cfg(windows)]
+    sys::set_console_handler(signal_termination_handler)?;
+    let mut latencies = Latencies::default();
+    let mut answering = None;
+    let mut rbuf = [0u8; 500];
+    while (count == 0 || latencies.sent < count) && TERMINATE.load(Ordering::SeqCst) == 0 {
+        // Replies to earlier requests came too late
+        while socket.recv_from(&mut rbuf).is_ok() {}
+        let sent = Instant::now();
+        socket
+            .send_to(&request, &to)
+            .map_err(|err| format!("failed to send on {}: {}", name, err))?;
+        let mut replies = Vec::new();
+        loop {
+            let elapsed = sent.elapsed();
+            if elapsed >= interval || TERMINATE.load(Ordering::SeqCst) != 0 {
+                break;
+            }
+            let timeout = (interval - elapsed).as_micros().div_ceil(1000);
+            wait_readable(socket.as_raw_fd(), timeout.try_into().unwrap_or(i32::MAX))?;
+            while let Ok((size, _, _)) = socket.recv_from(&mut rbuf) {
+                if let Some(mac) = requester.replier(&rbuf[..size]) {
+                    let latency = sent.elapsed();
+                    if answering.is_some_and(|answering| answering != mac) {
+                        println!("{} moved to {}", target, mac.to_hex_string());
+                    }
+                    answering = Some(mac);
+                    println!(
+                        "reply from {} is-at {} in {:.3} ms",
+                        target,
+                        mac.to_hex_string(),
+                        latency.as_secs_f64() * 1000.0
+                    );
+                    replies.push((mac, latency));
+                }
             }
         }
+        // Requests interrupted before their time was up aren't lost
+        if replies.is_empty() && TERMINATE.load(Ordering::SeqCst) != 0 {
+            break;
+        }
+        if replies.is_empty() {
+            println!("no reply for {}", target);
+        }
+        latencies.add(&replies);
+    }
+    print!("{}", latencies);
+    Ok(())
+}
+
+// Wait for up to timeout milliseconds until the descriptor is readable or a signal arrived
+fn wait_readable(fd: RawFd, timeout: c_int) -> sys::Result<()> {
+    let mut fds = vec![PollFd::new(fd, PollFlags::POLLIN)];
+    // Console control events stand in for signals on Windows
+    #[cfg(windows)]
+    fds.extend(sys::console_event().map(|event| PollFd::new(event, PollFlags::POLLIN)));
+    match sys::poll(&mut fds, timeout) {
+        Ok(_) | Err(sys::Error::EINTR) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+// Print the packets in hexadecimal lines, or the raw packet when the input isn't hexadecimal
+fn decode(path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    match path {
+        Some(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut data)),
+        None => std::io::stdin().lock().read_to_end(&mut data),
+    }
+    .map_err(|err| match path {
+        Some(path) => format!("failed to read {}: {}", path.display(), err),
+        None => format!("failed to read standard input: {}", err),
+    })?;
+    let packets = std::str::from_utf8(&data).ok().and_then(|text| {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(claim_ip::decode::parse_hex)
+            .collect::<Option<Vec<_>>>()
+    });
+    let packets = packets.unwrap_or_else(|| vec![data]);
+    let mut out = std::io::stdout().lock();
+    for (n, packet) in packets.iter().enumerate() {
+        if n > 0 {
+            writeln!(out)?;
+        }
+        write!(out, "{}", claim_ip::decode::describe(packet))?;
     }
-    Err("interface not found".into())
+    Ok(())
 }
 
-extern "C" fn signal_termination_handler(signo: nix::libc::c_int) {
-    log::info!("Terminating due to signal {}", signo);
-    std::process::exit(0);
+// Print what the claims would have done about the ARP requests in a capture
+fn replay(path: &Path, claims: &[Claim]) -> Result<(), Box<dyn std::error::Error>> {
+    let claims = claims
+        .iter()
+        .map(Claim::resolve)
+        .collect::<Result<Vec<_>, _>>()?;
+    let replay = Replay::new(claims);
+    let err = |err| format!("failed to read {}: {}", path.display(), err);
+    let file = std::fs::File::open(path).map_err(err)?;
+    let reader = pcap::Reader::new(std::io::BufReader::new(file)).map_err(err)?;
+    if reader.linktype() != pcap::LINKTYPE_ETHERNET {
+        return Err(format!(
+            "capture {} has link type {} (expected ethernet)",
+            path.display(),
+            reader.linktype()
+        )
+        .into());
+    }
+    let mut out = std::io::stdout().lock();
+    for record in reader {
+        let record = record.map_err(err)?;
+        if let Some((header, req, verdict)) = replay.decide(&record.data) {
+            let vlan = match header.vlan {
+                Some(ether::Vlan {
+                    outer: Some(outer),
+                    id,
+                }) => format!("vlan {}.{} ", outer, id),
+                Some(ether::Vlan { outer: None, id }) => format!("vlan {} ", id),
+                None => String::new(),
+            };
+            // Stop quietly when piped into e.g. head
+            match writeln!(
+                out,
+                "{}.{:06} {}{} -> {}",
+                record.time.as_secs(),
+                record.time.subsec_micros(),
+                vlan,
+                req,
+                verdict
+            ) {
+                Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => break,
+                result => result?,
+            }
+        }
+    }
+    Ok(())
+}
+
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+// The signal that requested termination, handled in the main loop
+static TERMINATE: AtomicI32 = AtomicI32::new(0);
+
+static HANDOFF: AtomicBool = AtomicBool::new(false);
+
+// How often the pause file is checked for
+const PAUSE_FILE_INTERVAL: Duration = Duration::from_secs(1);
+
+static PAUSE: AtomicBool = AtomicBool::new(false);
+
+// Exit codes when terminating because another host uses a claimed address, and because
+// addresses were already in use before claiming them (errors exit with 1)
+const EXIT_CONFLICT: i32 = 2;
+const EXIT_IN_USE: i32 = 3;
+
+// Log why the responder stopped, returning the exit code to terminate with
+fn stopped(err: &claim_ip::Error) -> i32 {
+    match err {
+        claim_ip::Error::Conflict { .. } => {
+            log::error!("{}, exiting", err);
+            EXIT_CONFLICT
+        }
+        claim_ip::Error::InUse { .. } => {
+            log::error!("{}, exiting", err);
+            EXIT_IN_USE
+        }
+        err => {
+            log::error!("{}", err);
+            1
+        }
+    }
+}
+
+// How long workers wait for packets before running the tasks sent to them
+const WORKER_INTERVAL: Duration = Duration::from_millis(100);
+
+// Something for a worker to do with its responder, like applying changed claims
+type Task<S> = Box<dyn FnOnce(&mut Responder<S>) + Send>;
+
+// A responder answering requests in a thread of its own, following the main one's claims
+struct Worker<S> {
+    tasks: mpsc::Sender<Task<S>>,
+    replies: Arc<AtomicU64>,
+}
+
+impl<S: iface::PacketIo + Send + 'static> Worker<S> {
+    fn spawn(
+        id: usize,
+        mut responder: Responder<S>,
+        sched_fifo: Option<i32>,
+    ) -> std::io::Result<Self> {
+        let (tasks, received) = mpsc::channel::<Task<S>>();
+        let replies = Arc::new(AtomicU64::new(0));
+        let counter = replies.clone();
+        std::thread::Builder::new()
+            .name(format!("worker {}", id))
+            .spawn(move || {
+                // Signals are left to the main loop
+                #[cfg(unix)]
+                if let Err(err) = SigSet::all().thread_block() {
+                    log::warn!("failed to block signals in worker {}: {}", id, err);
+                }
+                if let Some(priority) = sched_fifo {
+                    if let Err(err) = set_sched_fifo(priority) {
+                        log::warn!("failed to set realtime priority of worker {}: {}", id, err);
+                    }
+                }
+                loop {
+                    if let Err(err) = responder.step(Some(WORKER_INTERVAL)) {
+                        std::process::exit(stopped(&err));
+                    }
+                    counter.store(responder.replies(), Ordering::Relaxed);
+                    for task in received.try_iter() {
+                        task(&mut responder);
+                    }
+                }
+            })?;
+        Ok(Self { tasks, replies })
+    }
+
+    fn run(&self, task: impl FnOnce(&mut Responder<S>) + Send + 'static) {
+        let _ = self.tasks.send(Box::new(task));
+    }
+}
+
+// A descriptor owned elsewhere, like a socket of the responder, waited for through the
+// runtime of the main loop, or by the main loop itself on Windows, where the runtime can't
+// wait for event handles
+struct Fd(RawFd);
+
+#[cfg(unix)]
+type Waitable = AsyncFd<Fd>;
+#[cfg(windows)]
+type Waitable = Fd;
+
+impl Fd {
+    #[cfg(unix)]
+    fn register(fd: RawFd) -> std::io::Result<Waitable> {
+        AsyncFd::with_interest(Fd(fd), Interest::READABLE)
+    }
+
+    #[cfg(windows)]
+    fn register(fd: RawFd) -> std::io::Result<Waitable> {
+        Ok(Fd(fd))
+    }
+
+    // Whether reading wouldn't block, i.e. whether the descriptor is still readable
+    #[cfg(unix)]
+    fn is_readable(&self) -> bool {
+        let mut fds = [PollFd::new(self.0, PollFlags::POLLIN)];
+        matches!(sys::poll(&mut fds, 0), Ok(1))
+    }
+}
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+// Wait until one of the handles is readable, a console control event arrived or the
+// deadline passed, returning which are readable. Handles aren't Send, so they are passed
+// as addresses for waiting on the blocking pool.
+#[cfg(windows)]
+fn wait_ready(handles: Vec<usize>, deadline: Option<Instant>) -> Vec<bool> {
+    let timeout = deadline.map_or(-1, |deadline| {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        timeout
+            .as_micros()
+            .div_ceil(1000)
+            .try_into()
+            .unwrap_or(i32::MAX)
+    });
+    let mut pollfds: Vec<PollFd> = handles
+        .iter()
+        .map(|handle| *handle as RawFd)
+        .chain(sys::console_event())
+        .map(|fd| PollFd::new(fd, PollFlags::POLLIN))
+        .collect();
+    if let Err(err) = sys::poll(&mut pollfds, timeout) {
+        log::error!("failed to wait for sockets and monitors: {}", err);
+        std::process::exit(1);
+    }
+    pollfds[..handles.len()]
+        .iter()
+        .map(|fd| fd.revents().is_some_and(|revents| !revents.is_empty()))
+        .collect()
+}
+
+// What the tasks of the main loop report to it
+enum Event {
+    // The configuration stopped changing
+    Reload,
+    // The claim duration passed
+    Expired,
+    Stats,
+    PauseFile(bool),
+    #[cfg(unix)]
+    Healthy(bool),
+}
+
+// Sends events to the main loop, waking it up on Windows, where it waits for event handles
+// instead of through the runtime
+#[derive(Clone)]
+struct Events {
+    tx: UnboundedSender<Event>,
+    #[cfg(windows)]
+    waker: Arc<sys::Waker>,
+}
+
+impl Events {
+    // Whether the main loop is still there to receive the event
+    fn send(&self, event: Event) -> bool {
+        let sent = self.tx.send(event).is_ok();
+        #[cfg(windows)]
+        self.waker.wake();
+        sent
+    }
+
+    fn after(&self, delay: Duration, event: Event) -> JoinHandle<()> {
+        let events = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            events.send(event);
+        })
+    }
+
+    // Send the events made every interval from the start, when there is one
+    fn every(
+        &self,
+        start: Instant,
+        interval: Duration,
+        mut event: impl FnMut() -> Option<Event> + Send + 'static,
+    ) {
+        let events = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval_at(start.into(), interval);
+            loop {
+                ticks.tick().await;
+                if let Some(event) = event() {
+                    if !events.send(event) {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Run the health checks every interval on the blocking pool, reporting changes in health
+#[cfg(unix)]
+fn spawn_health_checks(mut checks: health::HealthChecks, interval: Duration, events: Events) {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let ran = tokio::task::spawn_blocking(move || {
+                let changed = checks.run();
+                (checks, changed)
+            });
+            let changed = match ran.await {
+                Ok((ran, changed)) => {
+                    checks = ran;
+                    changed
+                }
+                Err(_) => return,
+            };
+            if let Some(healthy) = changed {
+                if !events.send(Event::Healthy(healthy)) {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+// Apply the claims active in the minute to the responder and then to its workers
+fn apply<S: iface::PacketIo + Send + 'static>(
+    daemon: &mut Responder<S>,
+    workers: &[Worker<S>],
+    claims: &[Claim],
+    minute: u32,
+) -> Result<(), claim_ip::Error> {
+    let expanded = expand_claims(claims, minute)?;
+    daemon.apply(expanded.clone())?;
+    for worker in workers {
+        let expanded = expanded.clone();
+        worker.run(move |responder| {
+            if let Err(err) = responder.apply(expanded) {
+                log::error!("failed to update worker: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+extern "C" fn signal_pause_handler(_signo: c_int) {
+    PAUSE.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn signal_handoff_handler(_signo: c_int) {
+    HANDOFF.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn signal_termination_handler(signo: c_int) {
+    TERMINATE.store(signo, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn signal_reload_handler(_signo: c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
 }
 
 #[derive(StructOpt)]
-#[structopt(about)]
+#[structopt(about, setting = structopt::clap::AppSettings::SubcommandsNegateReqs)]
 struct Opt {
-    #[structopt(help = "Send ARP announcement (gratuitous ARP) on start", short, long)]
+    #[structopt(
+        help = "Send ARP announcement (gratuitous ARP) or unsolicited neighbor advertisement on start",
+        short,
+        long
+    )]
     announce: bool,
-    #[structopt(help = "Network interface on which to claim the IP")]
-    iface: String,
-    #[structopt(help = "IP address to claim")]
-    ip: std::net::Ipv4Addr,
+    #[structopt(
+        help = "Re-send the announcement every number of seconds",
+        long,
+        requires = "announce"
+    )]
+    announce_interval: Option<u64>,
+    #[structopt(
+        help = "Maximum number of seconds added at random to each announcement interval",
+        long,
+        default_value = "0"
+    )]
+    announce_jitter: u64,
+    #[structopt(
+        help = "Number of times to send the announcement when claiming an address",
+        long,
+        default_value = "1"
+    )]
+    announce_count: u32,
+    #[structopt(
+        help = "Milliseconds between repeated announcements when claiming an address",
+        long,
+        default_value = "1000"
+    )]
+    announce_spacing: u64,
+    #[structopt(
+        help = "ARP operation to send gratuitous ARP as (request, reply or both)",
+        long,
+        default_value = "reply"
+    )]
+    announce_op: AnnounceOp,
+    #[structopt(
+        help = "Send ARP replies to the broadcast address instead of the requester, for devices that only refresh their caches from broadcast replies",
+        long
+    )]
+    broadcast_reply: bool,
+    #[structopt(
+        help = "Answer requests from a requester MAC address with another MAC address, so different devices learn different MAC addresses for the same IP (<requester>=<mac>, may be repeated)",
+        long = "reply-mac",
+        number_of_values = 1
+    )]
+    reply_macs: Vec<ReplyMac>,
+    #[structopt(
+        help = "MAC address of a router to also send announcements to directly (may be repeated)",
+        long = "router",
+        number_of_values = 1
+    )]
+    routers: Vec<MacAddress>,
+    #[structopt(
+        help = "IPv4 address of a gateway to periodically send requests to from the claimed addresses, keeping its ARP cache and the switches' MAC address tables fresh (may be repeated)",
+        long = "keepalive",
+        number_of_values = 1
+    )]
+    keepalive: Vec<Ipv4Addr>,
+    #[structopt(
+        help = "Seconds between requests to keepalive gateways",
+        long,
+        default_value = "30"
+    )]
+    keepalive_interval: u64,
+    #[structopt(
+        help = "Only answer requests from this MAC address (may be repeated)",
+        long = "allow-mac",
+        number_of_values = 1
+    )]
+    allow_macs: Vec<MacAddress>,
+    #[structopt(
+        help = "Never answer requests from this MAC address (may be repeated)",
+        long = "deny-mac",
+        number_of_values = 1
+    )]
+    deny_macs: Vec<MacAddress>,
+    #[structopt(
+        help = "MAC address of the original owner to announce the claimed IP addresses for when terminating",
+        long
+    )]
+    restore_mac: Option<MacAddress>,
+    #[structopt(
+        help = "MAC address of a successor to announce the claimed IP addresses for when handing off on SIGUSR1",
+        long
+    )]
+    handoff_mac: Option<MacAddress>,
+    #[structopt(
+        help = "Stop answering requests while this file exists",
+        long,
+        parse(from_os_str)
+    )]
+    pause_file: Option<PathBuf>,
+    #[structopt(
+        help = "Announce the --restore-mac (or restore_mac of claims) when pausing",
+        long
+    )]
+    restore_on_pause: bool,
+    #[structopt(
+        help = "Terminate with exit code 2 when another host uses a claimed address, or 3 when probing with --probe abort finds one in use, regardless of --on-conflict",
+        long
+    )]
+    exit_on_conflict: bool,
+    #[structopt(
+        help = "Use raw packet sockets, sending replies from the answering MAC address instead of the interface's",
+        long
+    )]
+    raw: bool,
+    #[structopt(
+        help = "Claim the addresses in this 802.1Q VLAN on the interface, receiving and sending tagged frames without a VLAN subinterface",
+        long
+    )]
+    vlan: Option<u16>,
+    #[structopt(
+        help = "Stack the --vlan tag inside this 802.1ad service VLAN (QinQ)",
+        long,
+        requires = "vlan"
+    )]
+    outer_vlan: Option<u16>,
+    #[structopt(
+        help = "Pad frames sent with --raw or --vlan with zeros to this length in bytes (without frame check sequence), 0 to leave padding to the network driver",
+        long,
+        default_value = "60"
+    )]
+    min_frame_len: usize,
+    #[structopt(
+        help = "Source MAC address of sent frames with --raw or --vlan (interface or a MAC address), e.g. the interface's own while answering with a virtual MAC address",
+        long
+    )]
+    frame_mac: Option<FrameMac>,
+    #[structopt(
+        help = "Attach to the interfaces as tap devices through /dev/net/tun, answering ARP as the host at their far end (like a virtual machine) instead of on the interfaces themselves",
+        long,
+        conflicts_with = "workers"
+    )]
+    tap: bool,
+    #[structopt(
+        help = "Receive frames through a memory-mapped ring shared with the kernel (TPACKET_V3) instead of a system call per frame, e.g. when sponging for thousands of addresses",
+        long,
+        conflicts_with = "tap"
+    )]
+    ring: bool,
+    #[structopt(
+        help = "Receive frames in a thread of their own per socket, queueing up to 4096 for the threads answering them, so that logging, hooks or other slow work never delays receiving and the kernel dropping frames",
+        long,
+        conflicts_with = "tap"
+    )]
+    rx_thread: bool,
+    #[cfg(feature = "xdp")]
+    #[structopt(
+        help = "Receive and send ARP for the claimed addresses through an AF_XDP socket on the interfaces' first queue, handed over by an XDP program before the kernel processes it, for extremely high ARP rates",
+        long,
+        conflicts_with_all = &["tap", "ring", "rx-thread", "workers"]
+    )]
+    xdp: bool,
+    #[cfg(feature = "xdp")]
+    #[structopt(
+        help = "With --xdp, answer ARP requests for claimed addresses in the XDP program, leaving requests of claims filtering, delaying or holding back replies, probes and conflicts to userspace",
+        long,
+        requires = "xdp"
+    )]
+    xdp_answer: bool,
+    #[cfg(feature = "uring")]
+    #[structopt(
+        help = "Receive and send through io_uring, with a ring per socket, and wait for them, timers and other events with a single system call",
+        long,
+        conflicts_with_all = &["tap", "ring", "rx-thread", "xdp"]
+    )]
+    uring: bool,
+    #[structopt(
+        help = "How to receive and send frames: packet (sockets, the default), ring, xdp, uring or libpcap as their options select, or auto for the first of xdp, uring, packet and libpcap that this build has and that opens on the interfaces",
+        long,
+        conflicts_with_all = &["tap", "ring", "xdp", "uring", "libpcap"]
+    )]
+    io_backend: Option<IoBackend>,
+    #[cfg(feature = "libpcap")]
+    #[structopt(
+        help = "Receive and send whole frames through libpcap instead of packet sockets, e.g. where those aren't available, in promiscuous mode",
+        long,
+        conflicts_with_all = &["tap", "ring", "rx-thread", "xdp", "uring", "workers"]
+    )]
+    libpcap: bool,
+    #[structopt(
+        help = "Answer requests in this many threads, each with its own sockets in PACKET_FANOUT groups spreading the received packets over them",
+        long,
+        default_value = "1"
+    )]
+    workers: usize,
+    #[structopt(
+        help = "Only run on these CPUs, e.g. 2 or 0,2-3, to keep other load from delaying replies",
+        long
+    )]
+    cpu_affinity: Option<CpuList>,
+    #[structopt(
+        help = "Busy poll the network device for up to this many microseconds when waiting for packets (SO_BUSY_POLL), burning CPU time for consistently low reply latency; waiting for several sockets also needs the net.core.busy_poll sysctl",
+        long,
+        conflicts_with = "tap"
+    )]
+    busy_poll: Option<u32>,
+    #[structopt(
+        help = "Run with the realtime FIFO scheduling policy at this priority (1-99), so replies aren't delayed behind other processes (requires CAP_SYS_NICE)",
+        long
+    )]
+    sched_fifo: Option<i32>,
+    #[structopt(
+        help = "Only answer requests while this shell command exits successfully",
+        long
+    )]
+    check_cmd: Option<String>,
+    #[structopt(
+        help = "Only answer requests while this host:port accepts TCP connections",
+        long
+    )]
+    check_tcp: Option<String>,
+    #[structopt(
+        help = "Only answer requests while this http:// or https:// URL responds with the expected status code",
+        long
+    )]
+    check_http: Option<String>,
+    #[cfg_attr(windows, allow(dead_code))]
+    #[structopt(
+        help = "Status code the --check-http URL is expected to respond with",
+        long,
+        default_value = "200"
+    )]
+    check_http_status: u16,
+    #[structopt(
+        help = "Only answer requests while this host answers ICMP echo requests",
+        long
+    )]
+    check_ping: Option<String>,
+    #[cfg_attr(windows, allow(dead_code))]
+    #[structopt(
+        help = "Number of seconds between health checks",
+        long,
+        default_value = "5"
+    )]
+    check_interval: u64,
+    #[cfg_attr(windows, allow(dead_code))]
+    #[structopt(
+        help = "Number of seconds after which a health check fails",
+        long,
+        default_value = "5"
+    )]
+    check_timeout: u64,
+    #[structopt(
+        help = "Whether to defend claimed IPv6 addresses against duplicate address detection by other hosts or only alert (defend or alert)",
+        long,
+        default_value = "defend"
+    )]
+    dad: DadPolicy,
+    #[structopt(
+        help = "Whether to defend claimed IPv4 addresses against ARP probes by other hosts, ignore them or ignore them with a warning (defend, ignore or alert)",
+        long,
+        default_value = "defend"
+    )]
+    on_probe: ArpProbePolicy,
+    #[structopt(
+        help = "Which requests to answer by how they were delivered (any, broadcast or unicast)",
+        long,
+        default_value = "any"
+    )]
+    delivery: config::Delivery,
+    #[structopt(
+        help = "Probe whether IPv4 addresses are in use before claiming them and what to do when they are (abort, wait, force or takeover)",
+        long
+    )]
+    probe: Option<ProbePolicy>,
+    #[structopt(
+        help = "Wait for the current owner of the IPv4 addresses to stop using them before claiming them (same as --probe takeover)",
+        long,
+        conflicts_with = "probe"
+    )]
+    takeover: bool,
+    #[structopt(
+        help = "Seconds the current owner has to be gone before taking over its addresses",
+        long,
+        default_value = "10"
+    )]
+    takeover_hold: u64,
+    #[structopt(
+        help = "What to do when another host uses a claimed IPv4 address (defend, yield or exit)",
+        long
+    )]
+    on_conflict: Option<ConflictPolicy>,
+    #[structopt(
+        help = "Minimum number of seconds between defending a claimed IPv4 address",
+        long,
+        default_value = "10"
+    )]
+    defend_interval: u64,
+    #[structopt(
+        help = "Number of seconds after which to stop claiming and terminate (announcing the --restore-mac when set)",
+        long
+    )]
+    duration: Option<u64>,
+    #[structopt(
+        help = "Number of seconds after starting during which requests are observed but not answered",
+        long,
+        default_value = "0"
+    )]
+    grace: u64,
+    #[structopt(
+        help = "Maximum number of requests per second to answer from each requesting MAC address",
+        long
+    )]
+    rate_limit: Option<u32>,
+    #[structopt(
+        help = "Number of milliseconds to wait before answering requests, e.g. for standby instances",
+        long,
+        default_value = "0"
+    )]
+    reply_delay: u64,
+    #[structopt(
+        help = "Maximum number of milliseconds to add at random to the reply delay",
+        long,
+        default_value = "0"
+    )]
+    reply_jitter: u64,
+    #[structopt(
+        help = "Only answer requests when no other host answers them (puts the interface in promiscuous mode)",
+        long
+    )]
+    passive: bool,
+    #[structopt(
+        help = "Answer for every address in the CIDR ranges like a proxy ARP gateway, without announcing or probing them",
+        long
+    )]
+    proxy: bool,
+    #[structopt(
+        help = "Only answer for addresses in the CIDR ranges the routing table reaches through another interface (implies --proxy)",
+        long
+    )]
+    proxy_routes: bool,
+    #[structopt(
+        help = "Only answer for IPv4 addresses in the CIDR ranges that are requested often but nobody answers for, like arpsponge",
+        long
+    )]
+    sponge: bool,
+    #[structopt(
+        help = "Number of requests per minute for an address after which to probe whether it's dead and sponge it",
+        long,
+        default_value = "50"
+    )]
+    sponge_threshold: u32,
+    #[structopt(
+        help = "Seconds after which sponged addresses nobody requested are released, 0 to keep them",
+        long,
+        default_value = "600"
+    )]
+    sponge_age: u64,
+    #[structopt(
+        help = "Seconds between probes for sponged addresses to notice hosts returning, 0 to disable",
+        long,
+        default_value = "60"
+    )]
+    sponge_recheck: u64,
+    #[structopt(
+        help = "Send a Wake-on-LAN magic packet to the MAC address when answering for the addresses, to wake the sleeping host they belong to",
+        long,
+        requires = "mac"
+    )]
+    wake: bool,
+    #[structopt(
+        help = "Answer Reverse ARP requests with the addresses this ethers file maps MAC addresses to, as the first claimed IPv4 address",
+        long,
+        parse(from_os_str)
+    )]
+    rarp: Option<PathBuf>,
+    #[structopt(
+        help = "Print which ARP requests in this pcap capture the claims would have answered, instead of claiming the addresses",
+        long,
+        parse(from_os_str)
+    )]
+    replay: Option<PathBuf>,
+    #[structopt(
+        help = "Write the ARP and neighbor discovery frames received and sent to this pcap file",
+        long,
+        parse(from_os_str)
+    )]
+    capture: Option<PathBuf>,
+    #[structopt(
+        help = "Log the number of replies sent and anomalies seen every this many seconds",
+        long
+    )]
+    stats_interval: Option<u64>,
+    #[structopt(
+        help = "Wait until none of the IPv4 addresses are in use by other hosts before claiming them",
+        long
+    )]
+    wait_free: bool,
+    #[structopt(
+        help = "Give up waiting for the IPv4 addresses to be free after this many seconds",
+        long,
+        requires = "wait-free"
+    )]
+    wait_free_timeout: Option<u64>,
+    #[structopt(help = "Terminate after replying to this number of requests", long)]
+    max_replies: Option<u64>,
+    #[structopt(
+        help = "Daily window of local time (HH:MM-HH:MM) in which to claim the IP (may be repeated)",
+        long = "window",
+        number_of_values = 1
+    )]
+    windows: Vec<schedule::Window>,
+    #[structopt(
+        help = "Flags to set on neighbor advertisements (comma-separated list of router, solicited and override)",
+        long,
+        default_value = "solicited,override"
+    )]
+    na_flags: ndp::NaFlags,
+    #[structopt(
+        help = "Configuration file with additional claims (reloaded on SIGHUP)",
+        short,
+        long,
+        parse(from_os_str)
+    )]
+    config: Option<PathBuf>,
+    #[structopt(
+        help = "Directory with configuration files (*.toml) with additional claims (reloaded on SIGHUP)",
+        short = "C",
+        long,
+        parse(from_os_str)
+    )]
+    config_dir: Option<PathBuf>,
+    #[structopt(
+        help = "Automatically reload the configuration when it changes",
+        short,
+        long
+    )]
+    watch: bool,
+    #[structopt(
+        help = "IP address(es) or CIDR range(s) never to answer for (comma-separated, may be repeated)",
+        short,
+        long,
+        number_of_values = 1
+    )]
+    exclude: Vec<IpList>,
+    #[structopt(
+        help = "Only answer requests from senders in these IP address(es) or CIDR range(s) (comma-separated, may be repeated)",
+        long,
+        number_of_values = 1
+    )]
+    allow_from: Vec<IpList>,
+    #[structopt(
+        help = "Only answer requests from senders within the IPv4 subnets configured on the interface",
+        long
+    )]
+    on_link: bool,
+    #[structopt(
+        help = "Network interface on which to claim the IP (looked up through the routing table when omitted)",
+        required_unless_one = &["config", "config-dir"]
+    )]
+    iface: Option<String>,
+    #[structopt(help = "IP address(es), CIDR range(s) or hostname(s) to claim (comma-separated)")]
+    ip: Option<AddrList>,
     #[structopt(
         help = "MAC address to use when claiming the IP address (defaults to the MAC address of the interface)"
     )]
     mac: Option<MacAddress>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(
+        about = "Print the ARP packets or ethernet frames in a file as parsed, or where parsing them failed"
+    )]
+    Decode {
+        #[structopt(
+            help = "File with a packet in hexadecimal per line or a single raw packet (standard input when omitted)",
+            parse(from_os_str)
+        )]
+        file: Option<PathBuf>,
+    },
+    #[structopt(
+        about = "Flood ARP requests for an address at a steady rate and report how many were answered, by which MAC addresses"
+    )]
+    Bench {
+        #[structopt(help = "Interface to send the requests on")]
+        iface: String,
+        #[structopt(help = "IPv4 address to ask for")]
+        target: Ipv4Addr,
+        #[structopt(help = "Requests per second", long, default_value = "1000")]
+        rate: u64,
+        #[structopt(help = "Seconds to send requests for", long, default_value = "10")]
+        duration: u64,
+        #[structopt(
+            help = "Seconds to wait for replies after sending the last request",
+            long,
+            default_value = "1"
+        )]
+        wait: u64,
+        #[structopt(
+            help = "Sender IPv4 address of the requests (the interface's first address by default, 0.0.0.0 to probe)",
+            long
+        )]
+        source: Option<Ipv4Addr>,
+    },
+    #[structopt(
+        about = "Send ARP requests for an address one at a time and report how long the hosts answering took, e.g. to see a failover complete"
+    )]
+    Ping {
+        #[structopt(help = "Interface to send the requests on")]
+        iface: String,
+        #[structopt(help = "IPv4 address to ask for")]
+        target: Ipv4Addr,
+        #[structopt(
+            help = "Requests to send, until interrupted when 0",
+            long,
+            default_value = "0"
+        )]
+        count: u64,
+        #[structopt(
+            help = "Milliseconds between requests, which is how long replies are waited for",
+            long,
+            default_value = "1000"
+        )]
+        interval: u64,
+        #[structopt(
+            help = "Sender IPv4 address of the requests (the interface's first address by default, 0.0.0.0 to probe)",
+            long
+        )]
+        source: Option<Ipv4Addr>,
+    },
 }
 
 fn main() {
     env_logger::init();
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
+    if let Some(Command::Decode { file }) = &opt.command {
+        if let Err(err) = decode(file.as_deref()) {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if let Some(Command::Bench {
+        iface,
+        target,
+        rate,
+        duration,
+        wait,
+        source,
+    }) = &opt.command
+    {
+        let duration = Duration::from_secs(*duration);
+        let wait = Duration::from_secs(*wait);
+        if let Err(err) = bench(iface, *target, *rate, duration, wait, *source) {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if let Some(Command::Ping {
+        iface,
+        target,
+        count,
+        interval,
+        source,
+    }) = &opt.command
+    {
+        let interval = Duration::from_millis(*interval);
+        if let Err(err) = ping(iface, *target, *count, interval, *source) {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
 
+    // With a single positional argument the interface is looked up through the routing table
+    if let (Some(iface), None) = (&opt.iface, &opt.ip) {
+        match iface.parse::<IpList>() {
+            Ok(ip) => {
+                opt.ip = Some(AddrList {
+                    ip: ip.0,
+                    hostname: Vec::new(),
+                });
+                opt.iface = Some(String::new());
+            }
+            Err(_) => structopt::clap::Error::with_description(
+                "The following required arguments were not provided:\n    <ip>",
+                structopt::clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit(),
+        }
+    }
+
+    #[cfg(unix)]
     {
         // Explicitly set terminate on signals in case we're running as PID 1 in a container
         use nix::sys::signal::{signal, SigHandler, Signal};
@@ -61,116 +1476,723 @@ fn main() {
             }
         }
     }
+    #[cfg(unix)]
+    if opt.config.is_some() || opt.config_dir.is_some() {
+        // Reload the configuration on SIGHUP, interrupting the blocking receive
+        use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+        let action = SigAction::new(
+            SigHandler::Handler(signal_reload_handler),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        if let Err(err) = unsafe { sigaction(Signal::SIGHUP, &action) } {
+            log::error!("Failed to set signal handler for SIGHUP: {}", err);
+        }
+    }
+    #[cfg(unix)]
+    {
+        // Hand off to a successor on SIGUSR1
+        use nix::sys::signal::{signal, SigHandler, Signal};
+        if let Err(err) =
+            unsafe { signal(Signal::SIGUSR1, SigHandler::Handler(signal_handoff_handler)) }
+        {
+            log::error!("Failed to set signal handler for SIGUSR1: {}", err);
+        }
 
-    // Lookup interface and it's corresponding MAC-address
-    let ifaddr = lookup_link_addr(&opt.iface).expect("failed to lookup link address");
-    let ifindex = ifaddr.ifindex();
-    let mac = opt.mac.unwrap_or_else(|| MacAddress::new(ifaddr.addr()));
-    log::info!(
-        "Claiming IP {} on {}[{}] for {}",
-        opt.ip,
-        opt.iface,
-        ifindex,
-        mac
-    );
+        // Toggle answering on SIGUSR2
+        if let Err(err) =
+            unsafe { signal(Signal::SIGUSR2, SigHandler::Handler(signal_pause_handler)) }
+        {
+            log::error!("Failed to set signal handler for SIGUSR2: {}", err);
+        }
+    }
+    // Console control events (Ctrl+C, closing the console, shutting down) are all there is
+    // on Windows
+    #[cfg(windows)]
+    if let Err(err) = sys::set_console_handler(signal_termination_handler) {
+        log::error!("Failed to set console control handler: {}", err);
+    }
+
+    // Collect claims from the configuration file and the command line
+    let cli_claim = match (&opt.iface, &opt.ip) {
+        (Some(iface), Some(ip)) => Some(Claim {
+            iface: iface.clone(),
+            vlan: opt.vlan,
+            outer_vlan: opt.outer_vlan,
+            ip: ip.ip.clone(),
+            hostname: ip.hostname.clone(),
+            exclude: opt
+                .exclude
+                .iter()
+                .flat_map(|l| l.0.iter().copied())
+                .collect(),
+            allow_from: opt
+                .allow_from
+                .iter()
+                .flat_map(|l| l.0.iter().copied())
+                .collect(),
+            on_link: opt.on_link,
+            mac: opt.mac,
+            macs: Default::default(),
+            reply_macs: opt
+                .reply_macs
+                .iter()
+                .map(|ReplyMac(requester, mac)| (*requester, *mac))
+                .collect(),
+            announce: opt.announce,
+            announce_interval: opt.announce_interval,
+            announce_jitter: opt.announce_jitter,
+            reply_delay: opt.reply_delay,
+            reply_jitter: opt.reply_jitter,
+            passive: opt.passive,
+            proxy: opt.proxy || opt.proxy_routes,
+            proxy_routes: opt.proxy_routes,
+            sponge: opt.sponge,
+            sponge_threshold: opt.sponge_threshold,
+            sponge_age: opt.sponge_age,
+            sponge_recheck: opt.sponge_recheck,
+            wake: opt.wake,
+            rarp: opt.rarp.clone(),
+            announce_count: opt.announce_count,
+            announce_spacing: opt.announce_spacing,
+            announce_op: opt.announce_op,
+            broadcast_reply: opt.broadcast_reply,
+            frame_mac: opt.frame_mac,
+            routers: opt.routers.clone(),
+            keepalive: opt.keepalive.clone(),
+            keepalive_interval: opt.keepalive_interval,
+            allow_macs: opt.allow_macs.clone(),
+            deny_macs: opt.deny_macs.clone(),
+            restore_mac: opt.restore_mac,
+            handoff_mac: opt.handoff_mac,
+            dad: opt.dad,
+            on_probe: opt.on_probe,
+            delivery: opt.delivery,
+            na_flags: opt.na_flags,
+            probe: opt
+                .probe
+                .or(Some(ProbePolicy::Takeover).filter(|_| opt.takeover)),
+            takeover_hold: opt.takeover_hold,
+            on_conflict: opt.on_conflict,
+            windows: opt.windows.clone(),
+        }),
+        _ => None,
+    };
+    let claims = match load_claims(&opt, cli_claim.as_ref()) {
+        Ok(claims) => claims,
+        Err(err) => {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(path) = &opt.replay {
+        if let Err(err) = replay(path, &claims) {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    let mut backend = opt.io_backend.unwrap_or(IoBackend::Packet);
+    if opt.ring {
+        backend = IoBackend::Ring;
+    }
+    #[cfg(feature = "xdp")]
+    if opt.xdp {
+        backend = IoBackend::Xdp;
+    }
+    #[cfg(feature = "uring")]
+    if opt.uring {
+        backend = IoBackend::Uring;
+    }
+    #[cfg(feature = "libpcap")]
+    if opt.libpcap {
+        backend = IoBackend::Libpcap;
+    }
+    if backend == IoBackend::Auto {
+        // Without any that works, packet sockets report what failed
+        backend = IoBackend::Packet;
+        for candidate in auto_backends(&opt) {
+            let res = match candidate {
+                #[cfg(feature = "xdp")]
+                IoBackend::Xdp => probe::<XdpSocket>(&claims, true),
+                #[cfg(feature = "uring")]
+                IoBackend::Uring => probe::<UringSocket>(&claims, opt.raw),
+                #[cfg(feature = "libpcap")]
+                IoBackend::Libpcap => probe::<PcapHandle>(&claims, true),
+                _ => probe::<iface::DefaultPacketIo>(&claims, opt.raw),
+            };
+            match res {
+                Ok(()) => {
+                    backend = candidate;
+                    break;
+                }
+                Err(err) => log::info!("Not using {} for I/O: {}", candidate, err),
+            }
+        }
+        log::info!("Using {} for I/O", backend);
+    }
+    if opt.rx_thread && ![IoBackend::Packet, IoBackend::Ring].contains(&backend) {
+        log::error!("--rx-thread only works with packet sockets or --ring");
+        std::process::exit(1);
+    }
+    #[cfg(target_os = "linux")]
+    if opt.tap {
+        let daemon = responder::<TapDevice>(&opt, true);
+        run(opt, cli_claim, claims, daemon)
+    }
+    #[cfg(not(target_os = "linux"))]
+    if opt.tap || opt.rx_thread || backend == IoBackend::Ring {
+        log::error!("--tap, --rx-thread and --ring only work on Linux");
+        std::process::exit(1);
+    }
+    match backend {
+        #[cfg(feature = "xdp")]
+        IoBackend::Xdp => {
+            let mut daemon = responder::<XdpSocket>(&opt, true);
+            daemon.set_kernel_answers(opt.xdp_answer);
+            run(opt, cli_claim, claims, daemon)
+        }
+        #[cfg(feature = "uring")]
+        IoBackend::Uring => {
+            let daemon = responder::<UringSocket>(&opt, opt.raw);
+            run(opt, cli_claim, claims, daemon)
+        }
+        #[cfg(feature = "libpcap")]
+        IoBackend::Libpcap => {
+            let daemon = responder::<PcapHandle>(&opt, true);
+            run(opt, cli_claim, claims, daemon)
+        }
+        #[cfg(target_os = "linux")]
+        IoBackend::Ring if opt.rx_thread => {
+            let daemon = responder::<QueuedSocket<RingSocket>>(&opt, opt.raw);
+            run(opt, cli_claim, claims, daemon)
+        }
+        #[cfg(target_os = "linux")]
+        IoBackend::Ring => {
+            let daemon = responder::<RingSocket>(&opt, opt.raw);
+            run(opt, cli_claim, claims, daemon)
+        }
+        #[cfg(target_os = "linux")]
+        _ if opt.rx_thread => {
+            let daemon = responder::<QueuedSocket<iface::PacketSocket>>(&opt, opt.raw);
+            run(opt, cli_claim, claims, daemon)
+        }
+        _ => {
+            let daemon = responder::<iface::DefaultPacketIo>(&opt, opt.raw);
+            run(opt, cli_claim, claims, daemon)
+        }
+    }
+}
 
-    // Open a raw socket for sending and receiving ARP packets
-    let socket = socket(
-        AddressFamily::Packet,
-        SockType::Datagram,
-        SockFlag::empty(),
-        None,
+// A responder receiving and sending through S, raw or not
+fn responder<S: iface::PacketIo>(opt: &Opt, raw: bool) -> Responder<S> {
+    Responder::with_packet_io(
+        Duration::from_secs(opt.defend_interval),
+        Duration::from_secs(opt.grace),
+        opt.rate_limit,
+        opt.restore_on_pause,
+        opt.exit_on_conflict,
+        raw,
     )
-    .expect("failed to create packet socket");
-    {
-        let mut bind_addr = ifaddr;
-        bind_addr.0.sll_protocol = (nix::libc::ETH_P_ARP as u16).to_be();
-        nix::sys::socket::bind(socket, &SockAddr::Link(bind_addr))
-            .expect("failed to bind to interface for arp data");
+}
+
+// Claim the addresses until terminated
+fn run<S: iface::PacketIo + Send + 'static>(
+    opt: Opt,
+    cli_claim: Option<Claim>,
+    mut claims: Vec<Claim>,
+    mut daemon: Responder<S>,
+) -> ! {
+    // Threads started from here on, like workers, run on the same CPUs
+    #[cfg(target_os = "linux")]
+    if let Some(cpus) = &opt.cpu_affinity {
+        let mut set = CpuSet::new();
+        let res = cpus.0.iter().try_for_each(|cpu| set.set(*cpu));
+        if let Err(err) = res.and_then(|()| sched_setaffinity(Pid::from_raw(0), &set)) {
+            log::error!("failed to set CPU affinity: {}", err);
+            std::process::exit(1);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    if opt.cpu_affinity.is_some() {
+        log::error!("--cpu-affinity only works on Linux");
+        std::process::exit(1);
+    }
+    if let Some(priority) = opt.sched_fifo {
+        if let Err(err) = set_sched_fifo(priority) {
+            log::error!("failed to set realtime priority {}: {}", priority, err);
+            std::process::exit(1);
+        }
+    }
+    daemon.set_min_frame_len(opt.min_frame_len);
+    daemon.set_busy_poll(opt.busy_poll);
+    if let Some(path) = &opt.capture {
+        let writer = std::fs::File::create(path)
+            .and_then(|file| pcap::Writer::new(Box::new(file) as Box<dyn Write + Send>));
+        match writer {
+            Ok(writer) => daemon.set_capture(Arc::new(Mutex::new(writer))),
+            Err(err) => {
+                log::error!("failed to create {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if opt.workers > 1 {
+        daemon.set_fanout(iface::Fanout::default());
+    }
+    let mut workers = Vec::new();
+    let mut minute = schedule::local_time().0;
+    if let Err(err) = apply(&mut daemon, &workers, &claims, minute) {
+        log::error!("{}", err);
+        std::process::exit(1);
     }
 
-    // Main loop
-    let mut rbuf = [0u8; 500];
-    let mut wbuf = [0u8; 500];
-    if opt.announce {
-        let bcast_mac = MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
-        let mut bcast_lladdr = ifaddr;
-        bcast_lladdr.0.sll_addr = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00];
-        let garp = arp::Arp {
-            op: arp::ArpOp::Reply,
-            sha: mac,
-            spa: opt.ip,
-            tha: bcast_mac,
-            tpa: opt.ip,
+    // Deployment scripts can avoid split ownership by not claiming addresses that are in use
+    if opt.wait_free {
+        let deadline = opt
+            .wait_free_timeout
+            .map(|timeout| Instant::now() + Duration::from_secs(timeout));
+        let interrupted = || TERMINATE.load(Ordering::SeqCst) != 0;
+        let free = match daemon.wait_free(deadline, interrupted) {
+            Ok(free) => free,
+            Err(err) => std::process::exit(stopped(&err)),
         };
-        log::debug!("sending gratuitous arp");
-        if let Err(err) = sendto(
-            socket,
-            garp.fill(&mut wbuf)
-                .expect("failed to construct reply packet"),
-            &SockAddr::Link(bcast_lladdr),
-            MsgFlags::MSG_DONTWAIT,
-        ) {
-            log::error!("failed to send gratuitous arp: {}", err);
+        if !free {
+            if interrupted() {
+                log::info!("Terminating while waiting for addresses to be free");
+                std::process::exit(0);
+            }
+            log::error!("Claimed addresses are still in use, giving up");
+            std::process::exit(EXIT_IN_USE);
         }
     }
-    loop {
-        // Receive an ARP packet
-        let (size, from) = match recvfrom(socket, &mut rbuf) {
-            Ok(r) => r,
+    let routes = match iface::LinkMonitor::routes() {
+        Ok(routes) => Some(routes),
+        Err(err) => {
+            log::warn!("failed to monitor route changes: {}", err);
+            None
+        }
+    };
+    let links = match iface::LinkMonitor::new() {
+        Ok(links) => Some(links),
+        Err(err) => {
+            log::warn!("failed to monitor interface changes: {}", err);
+            None
+        }
+    };
+    let watch = if opt.watch {
+        match ConfigWatch::new(opt.config.as_deref(), opt.config_dir.as_deref()) {
+            Ok(watch) => Some(watch),
+            Err(err) => {
+                log::error!("failed to watch configuration: {}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Only answer while the health checks pass, which they haven't yet
+    #[cfg(windows)]
+    if opt.check_cmd.is_some()
+        || opt.check_tcp.is_some()
+        || opt.check_ping.is_some()
+        || opt.check_http.is_some()
+    {
+        log::error!("health checks only work on Unix");
+        std::process::exit(1);
+    }
+    #[cfg(unix)]
+    let mut checks: Vec<health::Check> = opt
+        .check_cmd
+        .iter()
+        .cloned()
+        .map(health::Check::Command)
+        .chain(opt.check_tcp.iter().cloned().map(health::Check::Tcp))
+        .chain(opt.check_ping.iter().cloned().map(health::Check::Ping))
+        .collect();
+    #[cfg(unix)]
+    if let Some(url) = &opt.check_http {
+        match health::Check::http(url, opt.check_http_status) {
+            Ok(check) => checks.push(check),
+            Err(err) => {
+                log::error!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(unix)]
+    let health = if checks.is_empty() {
+        None
+    } else {
+        daemon.set_paused("health check", true);
+        Some(health::HealthChecks::new(
+            checks,
+            Duration::from_secs(opt.check_timeout),
+        ))
+    };
+
+    // Workers start answering once the addresses are free, paused like the main responder
+    for id in 1..opt.workers {
+        let mut worker = daemon.worker(id);
+        if let Err(err) = apply(&mut worker, &[], &claims, minute) {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+        match Worker::spawn(id, worker, opt.sched_fifo) {
+            Ok(worker) => workers.push(worker),
             Err(err) => {
-                log::error!("failed to receive packet: {}", err);
+                log::error!("failed to start worker {}: {}", id, err);
                 std::process::exit(1);
             }
+        }
+    }
+    let set_paused = |daemon: &mut Responder<S>, reason: &'static str, paused: bool| {
+        daemon.set_paused(reason, paused);
+        for worker in &workers {
+            worker.run(move |responder| responder.set_paused(reason, paused));
+        }
+    };
+
+    // The main loop waits for the sockets, monitors, signals and timers all at once through
+    // a runtime on this thread
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            log::error!("failed to start runtime: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let code = runtime.block_on(async {
+        // Signals are handled by the handlers set in main, these only wake up the loop
+        #[cfg(unix)]
+        let mut signals = Vec::new();
+        #[cfg(unix)]
+        for signo in [
+            nix::libc::SIGHUP,
+            nix::libc::SIGINT,
+            nix::libc::SIGTERM,
+            nix::libc::SIGQUIT,
+            nix::libc::SIGUSR1,
+            nix::libc::SIGUSR2,
+        ] {
+            match signal(SignalKind::from_raw(signo)) {
+                Ok(signal) => signals.push(signal),
+                Err(err) => log::warn!("failed to listen for signal {}: {}", signo, err),
+            }
+        }
+        let monitors = [
+            watch.as_ref().map(AsRawFd::as_raw_fd),
+            links.as_ref().map(AsRawFd::as_raw_fd),
+            routes.as_ref().map(AsRawFd::as_raw_fd),
+        ];
+        let monitors: Vec<Waitable> = match monitors
+            .iter()
+            .flatten()
+            .copied()
+            .map(Fd::register)
+            .collect()
+        {
+            Ok(monitors) => monitors,
+            Err(err) => {
+                log::error!("failed to wait for monitors: {}", err);
+                return 1;
+            }
         };
-        let pkt = &rbuf[0..size];
-        let from = match from {
-            Some(SockAddr::Link(from)) => from,
-            _ => {
-                log::error!("received packet without link address sender: {:?}", from);
-                continue;
+
+        // Timers and health checks run as tasks of their own, reporting back through events
+        let (tx, mut received) = unbounded_channel();
+        #[cfg(windows)]
+        let waker = match sys::Waker::new() {
+            Ok(waker) => Arc::new(waker),
+            Err(err) => {
+                log::error!("failed to set up waking the main loop: {}", err);
+                return 1;
             }
         };
-        let from_mac = MacAddress::new(from.addr());
-        log::trace!("received packet from {}: {:x?}", from_mac, pkt);
-
-        // Try to decode the ARP packet
-        match arp::Arp::try_from(pkt) {
-            // Process ARP requests
-            Ok(req) if req.op == arp::ArpOp::Request => {
-                log::trace!("received arp request: {:x?}", req);
-                if from_mac != req.sha {
-                    log::warn!(
-                        "received arp with sender mac {} from mac {}",
-                        from_mac,
-                        req.sha
-                    );
+        let events = Events {
+            tx,
+            #[cfg(windows)]
+            waker: waker.clone(),
+        };
+        if let Some(duration) = opt.duration {
+            events.after(Duration::from_secs(duration), Event::Expired);
+        }
+        if let Some(path) = opt.pause_file.clone() {
+            let mut exists = None;
+            events.every(Instant::now(), PAUSE_FILE_INTERVAL, move || {
+                let now = path.exists();
+                (exists.replace(now) != Some(now)).then_some(Event::PauseFile(now))
+            });
+        }
+        if let Some(interval) = opt.stats_interval.map(Duration::from_secs) {
+            events.every(Instant::now() + interval, interval, || Some(Event::Stats));
+        }
+        #[cfg(unix)]
+        if let Some(checks) = health {
+            let interval = Duration::from_secs(opt.check_interval);
+            spawn_health_checks(checks, interval, events.clone());
+        }
+        // Debouncing configuration changes, restarted by every change
+        let mut reload: Option<JoinHandle<()>> = None;
+
+        let mut rbuf = [0u8; 500];
+        // Terminating once the claimed addresses are handed over
+        let mut terminating = false;
+        // The sockets of the responder as registered with the runtime, registered again
+        // whenever claims are applied as that may close and reopen them
+        let mut registered: Vec<((String, iface::Protocol, RawFd), Waitable)> = Vec::new();
+        loop {
+            // Hand claimed addresses back to their original owner before terminating
+            let signo = TERMINATE.load(Ordering::SeqCst);
+            if signo != 0 && !terminating {
+                log::info!("Terminating due to signal {}", signo);
+                daemon.hand_over("Restoring", |claim| claim.restore_mac);
+                terminating = true;
+            }
+
+            // Point claimed addresses at their successor and terminate
+            if HANDOFF.swap(false, Ordering::SeqCst) && !terminating {
+                log::info!("Handing off claimed addresses");
+                daemon.hand_over("Handing off", |claim| claim.handoff_mac);
+                terminating = true;
+            }
+            if terminating && !daemon.is_handing_over() {
+                return 0;
+            }
+
+            if PAUSE.swap(false, Ordering::SeqCst) {
+                let paused = daemon.is_paused("SIGUSR2");
+                set_paused(&mut daemon, "SIGUSR2", !paused);
+            }
+
+            // Apply configuration changes without giving up the sockets
+            if RELOAD.swap(false, Ordering::SeqCst) {
+                log::info!("Reloading configuration");
+                match load_claims(&opt, cli_claim.as_ref()) {
+                    Ok(new_claims) => {
+                        minute = schedule::local_time().0;
+                        registered.clear();
+                        match apply(&mut daemon, &workers, &new_claims, minute) {
+                            Ok(()) => claims = new_claims,
+                            Err(err) => log::error!("failed to reload configuration: {}", err),
+                        }
+                    }
+                    Err(err) => log::error!("failed to reload configuration: {}", err),
                 }
+            }
 
-                // Reply to ARP requests for the specified IP address
-                if req.tpa == opt.ip {
-                    log::debug!("sending arp reply");
-                    if let Err(err) = sendto(
-                        socket,
-                        req.reply(mac)
-                            .expect("ARP reply")
-                            .fill(&mut wbuf)
-                            .expect("failed to construct reply packet"),
-                        &SockAddr::Link(from),
-                        MsgFlags::MSG_DONTWAIT,
-                    ) {
-                        log::error!("failed to send arp reply: {}", err);
+            // Claims with time windows are re-evaluated every minute
+            let (now_minute, second) = schedule::local_time();
+            if now_minute != minute {
+                let changed = claims
+                    .iter()
+                    .any(|claim| claim.is_active(now_minute) != claim.is_active(minute));
+                minute = now_minute;
+                if changed {
+                    log::debug!("claim windows changed");
+                    registered.clear();
+                    if let Err(err) = apply(&mut daemon, &workers, &claims, minute) {
+                        log::error!("failed to update claims: {}", err);
                     }
                 }
             }
+            let window_at = if claims.iter().any(|claim| !claim.windows.is_empty()) {
+                Some(Instant::now() + Duration::from_secs(u64::from(60 - second)))
+            } else {
+                None
+            };
 
-            // Ignore other ARP packets
-            Ok(_) => {}
+            let deadline = [daemon.deadline(), window_at]
+                .iter()
+                .flatten()
+                .min()
+                .copied();
+            let sockets = daemon.sockets();
+            if registered.len() != sockets.len()
+                || registered
+                    .iter()
+                    .zip(&sockets)
+                    .any(|((socket, _), s)| socket != s)
+            {
+                registered.clear();
+                for socket in sockets {
+                    match Fd::register(socket.2) {
+                        Ok(fd) => registered.push((socket, fd)),
+                        Err(err) => {
+                            log::error!("failed to wait for {} socket: {}", socket.0, err);
+                            return 1;
+                        }
+                    }
+                }
+            }
 
-            // Report ARP packet decoding errors
-            Err(_) => {
-                log::warn!("failed to decode arp packet");
+            // Wait until a socket or monitor is readable, a signal or event arrived or the
+            // deadline passed, then handle whatever is ready
+            #[cfg(unix)]
+            let mut sleep = deadline.map(|deadline| Box::pin(sleep_until(deadline.into())));
+            let fds: Vec<&Waitable> = registered
+                .iter()
+                .map(|(_, fd)| fd)
+                .chain(&monitors)
+                .collect();
+            let mut events_ready = Vec::new();
+            #[cfg(windows)]
+            let ready = {
+                let handles = fds
+                    .iter()
+                    .map(|fd| fd.0)
+                    .chain([waker.as_raw_fd()])
+                    .map(|handle| handle as usize)
+                    .collect();
+                let mut ready = match tokio::task::spawn_blocking(move || {
+                    wait_ready(handles, deadline)
+                })
+                .await
+                {
+                    Ok(ready) => ready,
+                    Err(err) => {
+                        log::error!("failed to wait for sockets and monitors: {}", err);
+                        return 1;
+                    }
+                };
+                ready.truncate(fds.len());
+                while let Ok(event) = received.try_recv() {
+                    events_ready.push(event);
+                }
+                ready
+            };
+            #[cfg(unix)]
+            let ready = poll_fn(|cx| {
+                let mut woken = false;
+                for signal in &mut signals {
+                    woken |= signal.poll_recv(cx).is_ready();
+                }
+                while let Poll::Ready(Some(event)) = received.poll_recv(cx) {
+                    events_ready.push(event);
+                    woken = true;
+                }
+                if let Some(sleep) = &mut sleep {
+                    woken |= sleep.as_mut().poll(cx).is_ready();
+                }
+                let ready: Vec<bool> = fds
+                    .iter()
+                    .map(|fd| fd.poll_read_ready(cx).is_ready())
+                    .collect();
+                if woken || ready.contains(&true) {
+                    Poll::Ready(ready)
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+            drop(fds);
+            let (sockets_ready, monitors_ready) = ready.split_at(registered.len());
+            let mut monitor_ready = monitors_ready.iter().copied();
+
+            for event in events_ready {
+                match event {
+                    Event::Reload => RELOAD.store(true, Ordering::SeqCst),
+
+                    // Give up time-limited claims
+                    Event::Expired => {
+                        if !terminating {
+                            log::info!("Claim duration expired");
+                            daemon.hand_over("Restoring", |claim| claim.restore_mac);
+                            terminating = true;
+                        }
+                    }
+
+                    Event::Stats => {
+                        daemon.log_stats();
+                        for worker in &workers {
+                            worker.run(|responder| responder.log_stats());
+                        }
+                    }
+
+                    // External tooling can pause answering by creating the pause file
+                    Event::PauseFile(exists) => set_paused(&mut daemon, "pause file", exists),
+
+                    // Stop or start answering when health changes
+                    #[cfg(unix)]
+                    Event::Healthy(healthy) => set_paused(&mut daemon, "health check", !healthy),
+                }
+            }
+
+            // Debounce configuration file changes
+            if let Some(watch) = &watch {
+                if monitor_ready.next() == Some(true) && watch.changed() {
+                    log::debug!("configuration changed");
+                    if let Some(reload) =
+                        reload.replace(events.after(RELOAD_DEBOUNCE, Event::Reload))
+                    {
+                        reload.abort();
+                    }
+                }
+            }
+
+            // Re-evaluate the claimed interfaces when interfaces (or their addresses) come and
+            // go
+            if let Some(links) = &links {
+                if monitor_ready.next() == Some(true) && links.changed() {
+                    log::debug!("interfaces changed");
+                    minute = schedule::local_time().0;
+                    registered.clear();
+                    if let Err(err) = apply(&mut daemon, &workers, &claims, minute) {
+                        log::error!("failed to update interfaces: {}", err);
+                    }
+                }
+            }
+
+            // Look up routes again once they changed
+            if let Some(routes) = &routes {
+                if monitor_ready.next() == Some(true) && routes.changed() {
+                    log::debug!("routes changed");
+                    daemon.routes_changed();
+                    for worker in &workers {
+                        worker.run(|responder| responder.routes_changed());
+                    }
+                }
+            }
+
+            // Receive ARP, neighbor discovery and RARP packets, unless the sockets changed
+            // since waiting for them
+            for (((name, protocol, _), _), ready) in registered.iter().zip(sockets_ready) {
+                if *ready {
+                    if let Err(err) = daemon.receive(name, *protocol, &mut rbuf) {
+                        return stopped(&err);
+                    }
+                }
+            }
+            daemon.tick();
+
+            // The runtime only reports descriptors becoming readable, so they are waited for
+            // again once there is nothing left to read
+            #[cfg(unix)]
+            let fds = registered.iter().map(|(_, fd)| fd).zip(sockets_ready);
+            #[cfg(unix)]
+            for (fd, ready) in fds.chain(monitors.iter().zip(monitors_ready)) {
+                if *ready && !fd.get_ref().is_readable() {
+                    if let Ok(mut guard) = fd.readable().await {
+                        guard.clear_ready();
+                    }
+                }
+            }
+
+            // One-shot usage terminates once enough requests have been answered
+            let replies = daemon.replies()
+                + workers
+                    .iter()
+                    .map(|worker| worker.replies.load(Ordering::Relaxed))
+                    .sum::<u64>();
+            if opt.max_replies.is_some_and(|max| replies >= max) {
+                log::info!("Sent {} replies, terminating", replies);
+                return 0;
             }
         }
-    }
+    });
+    std::process::exit(code)
 }
@@ -0,0 +1,96 @@
+use eui48::MacAddress;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Keeps track of other hosts answering for claimed addresses, so duplicate addresses
+// are reported when first seen and then periodically with how often they were seen
+#[derive(Default)]
+pub struct Monitor {
+    competitors: HashMap<(String, IpAddr, MacAddress), Sighting>,
+}
+
+struct Sighting {
+    reported: Instant,
+    count: u32,
+}
+
+// How often a competing host was seen since it was last reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Report {
+    First,
+    Repeated { count: u32, period: Duration },
+}
+
+impl Monitor {
+    pub fn observe(
+        &mut self,
+        name: &str,
+        ip: IpAddr,
+        mac: MacAddress,
+        now: Instant,
+    ) -> Option<Report> {
+        // Forget competitors that went away
+        self.competitors
+            .retain(|_, sighting| now.duration_since(sighting.reported) < 2 * REPORT_INTERVAL);
+
+        match self.competitors.get_mut(&(name.to_owned(), ip, mac)) {
+            None => {
+                self.competitors.insert(
+                    (name.to_owned(), ip, mac),
+                    Sighting {
+                        reported: now,
+                        count: 0,
+                    },
+                );
+                Some(Report::First)
+            }
+            Some(sighting) => {
+                sighting.count += 1;
+                let period = now.duration_since(sighting.reported);
+                if period < REPORT_INTERVAL {
+                    return None;
+                }
+                let count = sighting.count;
+                sighting.reported = now;
+                sighting.count = 0;
+                Some(Report::Repeated { count, period })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let now = Instant::now();
+        let mut monitor = Monitor::default();
+
+        assert!(matches!(
+            monitor.observe("eth0", ip, mac, now),
+            Some(Report::First)
+        ));
+        assert!(monitor.observe("eth0", ip, mac, now).is_none());
+        assert!(matches!(
+            monitor.observe("eth1", ip, mac, now),
+            Some(Report::First)
+        ));
+        assert!(matches!(
+            monitor.observe("eth0", ip, mac, now + REPORT_INTERVAL),
+            Some(Report::Repeated { count: 2, .. })
+        ));
+        assert!(matches!(
+            monitor.observe("eth0", ip, mac, now + 4 * REPORT_INTERVAL),
+            Some(Report::First)
+        ));
+    }
+}
@@ -0,0 +1,347 @@
+use eui48::MacAddress;
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv6Addr;
+
+pub const NA_ROUTER: u8 = 0x80;
+pub const NA_SOLICITED: u8 = 0x40;
+pub const NA_OVERRIDE: u8 = 0x20;
+
+// Flags set on generated neighbor advertisements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NaFlags(pub u8);
+
+impl Default for NaFlags {
+    fn default() -> Self {
+        NaFlags(NA_SOLICITED | NA_OVERRIDE)
+    }
+}
+
+impl std::str::FromStr for NaFlags {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = 0;
+        for flag in s.split(',').map(str::trim).filter(|flag| !flag.is_empty()) {
+            flags |= match flag {
+                "router" => NA_ROUTER,
+                "solicited" => NA_SOLICITED,
+                "override" => NA_OVERRIDE,
+                _ => {
+                    return Err(format!(
+                        "invalid advertisement flag {} (expected router, solicited or override)",
+                        flag
+                    ))
+                }
+            };
+        }
+        Ok(NaFlags(flags))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for NaFlags {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+pub const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+const IPV6_HEADER_LEN: usize = 40;
+const ICMPV6: u8 = 58;
+const HOP_LIMIT: u8 = 255;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NdpOp {
+    Solicitation,
+    Advertisement,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ndp {
+    pub op: NdpOp,
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    pub flags: u8,
+    pub target: Ipv6Addr,
+    pub lladdr: Option<MacAddress>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum NdpError {
+    UnsupportedType,
+    InvalidNdpOp,
+    InvalidHopLimit,
+    InvalidChecksum,
+    InvalidOption,
+    BufferTooSmall,
+}
+
+impl std::fmt::Display for NdpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NdpError::UnsupportedType => write!(f, "Unsupported packet type (not ICMPv6)"),
+            NdpError::InvalidNdpOp => write!(f, "Invalid neighbor discovery message type"),
+            NdpError::InvalidHopLimit => write!(f, "Invalid neighbor discovery hop limit"),
+            NdpError::InvalidChecksum => write!(f, "Invalid ICMPv6 checksum"),
+            NdpError::InvalidOption => write!(f, "Invalid neighbor discovery option"),
+            NdpError::BufferTooSmall => write!(f, "Packet buffer too small"),
+        }
+    }
+}
+
+impl std::error::Error for NdpError {}
+
+// The solicited-node multicast address on which solicitations for an address arrive
+pub fn solicited_node(addr: Ipv6Addr) -> Ipv6Addr {
+    let o = addr.octets();
+    Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | u16::from(o[13]),
+        u16::from_be_bytes([o[14], o[15]]),
+    )
+}
+
+// The Ethernet multicast address for an IPv6 multicast address
+pub fn multicast_mac(addr: Ipv6Addr) -> MacAddress {
+    let o = addr.octets();
+    MacAddress::new([0x33, 0x33, o[12], o[13], o[14], o[15]])
+}
+
+fn checksum(src: &Ipv6Addr, dst: &Ipv6Addr, msg: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            sum += u32::from(u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]));
+        }
+    };
+    add(&src.octets());
+    add(&dst.octets());
+    add(&(msg.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, ICMPV6]);
+    add(msg);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+impl Ndp {
+    // Solicitations from the unspecified address are sent by hosts performing duplicate
+    // address detection before configuring the target address
+    pub fn is_dad(&self) -> bool {
+        self.op == NdpOp::Solicitation && self.src.is_unspecified()
+    }
+
+    pub fn advertise(&self, ha: MacAddress, flags: NaFlags) -> Result<Self, NdpError> {
+        if self.op != NdpOp::Solicitation {
+            return Err(NdpError::InvalidNdpOp);
+        }
+        // Solicitations from an unspecified address (duplicate address detection) are
+        // answered to all nodes and must not be marked as solicited
+        let (dst, flags) = if self.is_dad() {
+            (ALL_NODES, flags.0 & !NA_SOLICITED)
+        } else {
+            (self.src, flags.0)
+        };
+        Ok(Self {
+            op: NdpOp::Advertisement,
+            src: self.target,
+            dst,
+            flags,
+            target: self.target,
+            lladdr: Some(ha),
+        })
+    }
+
+    pub fn fill<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], NdpError> {
+        let len = 24 + if self.lladdr.is_some() { 8 } else { 0 };
+        if buf.len() < IPV6_HEADER_LEN + len {
+            return Err(NdpError::BufferTooSmall);
+        }
+        buf[0..4].copy_from_slice(&[0x60, 0, 0, 0]);
+        buf[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+        buf[6] = ICMPV6;
+        buf[7] = HOP_LIMIT;
+        buf[8..24].copy_from_slice(&self.src.octets());
+        buf[24..40].copy_from_slice(&self.dst.octets());
+
+        let msg = &mut buf[IPV6_HEADER_LEN..IPV6_HEADER_LEN + len];
+        msg[0] = match self.op {
+            NdpOp::Solicitation => 135,
+            NdpOp::Advertisement => 136,
+        };
+        msg[1..8].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0]);
+        if self.op == NdpOp::Advertisement {
+            msg[4] = self.flags;
+        }
+        msg[8..24].copy_from_slice(&self.target.octets());
+        if let Some(lladdr) = self.lladdr {
+            msg[24] = match self.op {
+                NdpOp::Solicitation => 1,
+                NdpOp::Advertisement => 2,
+            };
+            msg[25] = 1;
+            msg[26..32].copy_from_slice(lladdr.as_bytes());
+        }
+        let sum = checksum(&self.src, &self.dst, msg);
+        msg[2..4].copy_from_slice(&sum.to_be_bytes());
+        Ok(&buf[..IPV6_HEADER_LEN + len])
+    }
+}
+
+impl TryFrom<&'_ [u8]> for Ndp {
+    type Error = NdpError;
+
+    fn try_from(pkt: &'_ [u8]) -> Result<Self, Self::Error> {
+        if pkt.len() < IPV6_HEADER_LEN {
+            return Err(NdpError::BufferTooSmall);
+        }
+        if pkt[0] >> 4 != 6 || pkt[6] != ICMPV6 {
+            return Err(NdpError::UnsupportedType);
+        }
+        let len = u16::from_be_bytes([pkt[4], pkt[5]]) as usize;
+        let msg = pkt
+            .get(IPV6_HEADER_LEN..IPV6_HEADER_LEN + len)
+            .ok_or(NdpError::BufferTooSmall)?;
+        if msg.len() < 24 {
+            return Err(NdpError::BufferTooSmall);
+        }
+        let op = match (msg[0], msg[1]) {
+            (135, 0) => NdpOp::Solicitation,
+            (136, 0) => NdpOp::Advertisement,
+            _ => return Err(NdpError::InvalidNdpOp),
+        };
+        if pkt[7] != HOP_LIMIT {
+            return Err(NdpError::InvalidHopLimit);
+        }
+        let src: [u8; 16] = pkt[8..24]
+            .try_into()
+            .map_err(|_| NdpError::BufferTooSmall)?;
+        let dst: [u8; 16] = pkt[24..40]
+            .try_into()
+            .map_err(|_| NdpError::BufferTooSmall)?;
+        let (src, dst) = (Ipv6Addr::from(src), Ipv6Addr::from(dst));
+        if checksum(&src, &dst, msg) != 0 {
+            return Err(NdpError::InvalidChecksum);
+        }
+        let target: [u8; 16] = msg[8..24]
+            .try_into()
+            .map_err(|_| NdpError::BufferTooSmall)?;
+
+        // Look for the source (solicitation) or target (advertisement) link-layer address
+        let lladdr_type = match op {
+            NdpOp::Solicitation => 1,
+            NdpOp::Advertisement => 2,
+        };
+        let mut lladdr = None;
+        let mut options = &msg[24..];
+        while !options.is_empty() {
+            let opt_len = 8 * *options.get(1).ok_or(NdpError::InvalidOption)? as usize;
+            if opt_len == 0 || opt_len > options.len() {
+                return Err(NdpError::InvalidOption);
+            }
+            if options[0] == lladdr_type {
+                lladdr = Some(
+                    MacAddress::from_bytes(&options[2..8]).map_err(|_| NdpError::InvalidOption)?,
+                );
+            }
+            options = &options[opt_len..];
+        }
+
+        Ok(Self {
+            op,
+            src,
+            dst,
+            flags: if op == NdpOp::Advertisement {
+                msg[4] & (NA_ROUTER | NA_SOLICITED | NA_OVERRIDE)
+            } else {
+                0
+            },
+            target: target.into(),
+            lladdr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn t1() {
+        let target: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let request = Ndp {
+            op: NdpOp::Solicitation,
+            src: "2001:db8::1".parse().unwrap(),
+            dst: solicited_node(target),
+            flags: 0,
+            target,
+            lladdr: Some(MacAddress::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66])),
+        };
+        assert_eq!(request.dst, "ff02::1:ff00:2".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(
+            multicast_mac(request.dst),
+            MacAddress::new([0x33, 0x33, 0xff, 0x00, 0x00, 0x02])
+        );
+
+        let mut buf = [0u8; 128];
+        let request_pkt = request.fill(&mut buf[..]).unwrap().to_vec();
+        assert_eq!(request_pkt.len(), 72);
+        assert_eq!(&request_pkt[40..44], &[135, 0, 0x84, 0x5b]);
+        let parsed: Ndp = request_pkt.as_slice().try_into().unwrap();
+        assert_eq!(parsed, request);
+
+        let mac = MacAddress::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        let reply = request
+            .advertise(mac, NaFlags::default())
+            .expect("NDP advertisement");
+        assert_eq!(
+            reply,
+            Ndp {
+                op: NdpOp::Advertisement,
+                src: target,
+                dst: "2001:db8::1".parse().unwrap(),
+                flags: NA_SOLICITED | NA_OVERRIDE,
+                target,
+                lladdr: Some(mac),
+            }
+        );
+        let reply_pkt = reply.fill(&mut buf[..]).unwrap().to_vec();
+        assert_eq!(Ndp::try_from(reply_pkt.as_slice()), Ok(reply));
+
+        let mut corrupt = request_pkt.clone();
+        corrupt[60] ^= 1;
+        assert_eq!(
+            Ndp::try_from(corrupt.as_slice()),
+            Err(NdpError::InvalidChecksum)
+        );
+        corrupt[7] = 64;
+        assert_eq!(
+            Ndp::try_from(corrupt.as_slice()),
+            Err(NdpError::InvalidHopLimit)
+        );
+
+        let dad = Ndp {
+            src: Ipv6Addr::UNSPECIFIED,
+            lladdr: None,
+            ..request
+        };
+        assert!(dad.is_dad());
+        let reply = dad
+            .advertise(mac, "router,solicited".parse().unwrap())
+            .unwrap();
+        assert_eq!(reply.dst, ALL_NODES);
+        assert_eq!(reply.flags, NA_ROUTER);
+        assert!("router,bogus".parse::<NaFlags>().is_err());
+    }
+}
@@ -0,0 +1,494 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Ipv4Net {
+    addr: Ipv4Addr,
+    prefix: u8,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Ipv6Net {
+    addr: Ipv6Addr,
+    prefix: u8,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum IpNet {
+    V4(Ipv4Net),
+    V6(Ipv6Net),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum NetParseError {
+    InvalidAddr,
+    InvalidPrefix,
+}
+
+impl std::fmt::Display for NetParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetParseError::InvalidAddr => write!(f, "Invalid IP address"),
+            NetParseError::InvalidPrefix => write!(f, "Invalid IP prefix length"),
+        }
+    }
+}
+
+impl std::error::Error for NetParseError {}
+
+impl Ipv4Net {
+    pub fn new(addr: Ipv4Addr, prefix: u8) -> Result<Self, NetParseError> {
+        if prefix > 32 {
+            return Err(NetParseError::InvalidPrefix);
+        }
+        let mask = Self::mask_of(prefix);
+        Ok(Self {
+            addr: (u32::from(addr) & mask).into(),
+            prefix,
+        })
+    }
+
+    fn mask_of(prefix: u8) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        (u32::from(self.addr) | !Self::mask_of(self.prefix)).into()
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & Self::mask_of(self.prefix) == u32::from(self.addr)
+    }
+
+    // The network and broadcast addresses are not usable hosts, except for /31 and /32
+    pub fn contains_host(&self, ip: Ipv4Addr) -> bool {
+        self.contains(ip) && (self.prefix >= 31 || (ip != self.network() && ip != self.broadcast()))
+    }
+
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let (first, last) = if self.prefix >= 31 {
+            (u32::from(self.network()), u32::from(self.broadcast()))
+        } else {
+            (
+                u32::from(self.network()) + 1,
+                u32::from(self.broadcast()) - 1,
+            )
+        };
+        (first..=last).map(Ipv4Addr::from)
+    }
+}
+
+impl From<Ipv4Addr> for Ipv4Net {
+    fn from(addr: Ipv4Addr) -> Self {
+        Self { addr, prefix: 32 }
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = NetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (
+                addr,
+                prefix.parse().map_err(|_| NetParseError::InvalidPrefix)?,
+            ),
+            None => (s, 32),
+        };
+        Self::new(
+            addr.parse().map_err(|_| NetParseError::InvalidAddr)?,
+            prefix,
+        )
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Ipv4Net {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.prefix == 32 {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl Ipv6Net {
+    pub fn new(addr: Ipv6Addr, prefix: u8) -> Result<Self, NetParseError> {
+        if prefix > 128 {
+            return Err(NetParseError::InvalidPrefix);
+        }
+        Ok(Self {
+            addr: (u128::from(addr) & Self::mask_of(prefix)).into(),
+            prefix,
+        })
+    }
+
+    fn mask_of(prefix: u8) -> u128 {
+        u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv6Addr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, ip: Ipv6Addr) -> bool {
+        u128::from(ip) & Self::mask_of(self.prefix) == u128::from(self.addr)
+    }
+}
+
+impl From<Ipv6Addr> for Ipv6Net {
+    fn from(addr: Ipv6Addr) -> Self {
+        Self { addr, prefix: 128 }
+    }
+}
+
+impl FromStr for Ipv6Net {
+    type Err = NetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (
+                addr,
+                prefix.parse().map_err(|_| NetParseError::InvalidPrefix)?,
+            ),
+            None => (s, 128),
+        };
+        Self::new(
+            addr.parse().map_err(|_| NetParseError::InvalidAddr)?,
+            prefix,
+        )
+    }
+}
+
+impl std::fmt::Display for Ipv6Net {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.prefix == 128 {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self, ip) {
+            (IpNet::V4(net), IpAddr::V4(ip)) => net.contains(ip),
+            (IpNet::V6(net), IpAddr::V6(ip)) => net.contains(ip),
+            _ => false,
+        }
+    }
+
+    pub fn contains_host(&self, ip: IpAddr) -> bool {
+        match (self, ip) {
+            (IpNet::V4(net), IpAddr::V4(ip)) => net.contains_host(ip),
+            (IpNet::V6(net), IpAddr::V6(ip)) => net.contains(ip),
+            _ => false,
+        }
+    }
+
+    // IPv6 networks are too large to enumerate, so only single addresses are yielded for them
+    pub fn hosts(&self) -> Box<dyn Iterator<Item = IpAddr>> {
+        match *self {
+            IpNet::V4(net) => Box::new(net.hosts().map(IpAddr::V4)),
+            IpNet::V6(net) if net.prefix() == 128 => {
+                Box::new(std::iter::once(IpAddr::V6(net.network())))
+            }
+            IpNet::V6(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    // The first and last address of the network, or of its usable hosts
+    fn range(&self, hosts: bool) -> (u128, u128) {
+        match *self {
+            IpNet::V4(net) => {
+                let (first, last) = (u32::from(net.network()), u32::from(net.broadcast()));
+                if hosts && net.prefix() < 31 {
+                    (u128::from(first + 1), u128::from(last - 1))
+                } else {
+                    (u128::from(first), u128::from(last))
+                }
+            }
+            IpNet::V6(net) => {
+                let first = u128::from(net.network());
+                (first, first | !Ipv6Net::mask_of(net.prefix()))
+            }
+        }
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self, IpNet::V6(_))
+    }
+
+    // The address used to represent this network, e.g. for route lookups
+    pub fn addr(&self) -> IpAddr {
+        match self {
+            IpNet::V4(net) => net.network().into(),
+            IpNet::V6(net) => net.network().into(),
+        }
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => IpNet::V4(addr.into()),
+            IpAddr::V6(addr) => IpNet::V6(addr.into()),
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = NetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            s.parse().map(IpNet::V6)
+        } else {
+            s.parse().map(IpNet::V4)
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for IpNet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for IpNet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpNet::V4(net) => net.fmt(f),
+            IpNet::V6(net) => net.fmt(f),
+        }
+    }
+}
+
+// The addresses in some networks but not in others, as sorted disjoint ranges, so looking
+// up whether an address is claimed is a binary search however many networks are claimed and
+// excluded. Like contains_host, the network and broadcast addresses of IPv4 networks are left
+// out of claimed networks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClaimSet {
+    v4: Vec<(u128, u128)>,
+    v6: Vec<(u128, u128)>,
+}
+
+impl ClaimSet {
+    pub fn new(claimed: &[IpNet], excluded: &[IpNet]) -> Self {
+        let ranges = |nets: &[IpNet], v6: bool, hosts: bool| {
+            merge(
+                nets.iter()
+                    .filter(|net| net.is_ipv6() == v6)
+                    .map(|net| net.range(hosts))
+                    .collect(),
+            )
+        };
+        Self {
+            v4: subtract(
+                ranges(claimed, false, true),
+                &ranges(excluded, false, false),
+            ),
+            v6: subtract(ranges(claimed, true, true), &ranges(excluded, true, false)),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (ranges, ip) = match ip {
+            IpAddr::V4(ip) => (&self.v4, u128::from(u32::from(ip))),
+            IpAddr::V6(ip) => (&self.v6, u128::from(ip)),
+        };
+        // The last range starting at or before the address
+        let n = ranges.partition_point(|&(first, _)| first <= ip);
+        n > 0 && ip <= ranges[n - 1].1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    // The addresses claimed by any of the sets
+    pub fn union<'a>(sets: impl IntoIterator<Item = &'a ClaimSet>) -> Self {
+        let (mut v4, mut v6) = (Vec::new(), Vec::new());
+        for set in sets {
+            v4.extend_from_slice(&set.v4);
+            v6.extend_from_slice(&set.v6);
+        }
+        Self {
+            v4: merge(v4),
+            v6: merge(v6),
+        }
+    }
+
+    // The first and last addresses of the claimed IPv4 ranges, in order
+    pub fn ipv4_ranges(&self) -> impl Iterator<Item = (Ipv4Addr, Ipv4Addr)> + '_ {
+        self.v4
+            .iter()
+            .map(|&(first, last)| (Ipv4Addr::from(first as u32), Ipv4Addr::from(last as u32)))
+    }
+}
+
+// Sort ranges and join the ones overlapping or adjacent
+fn merge(mut ranges: Vec<(u128, u128)>) -> Vec<(u128, u128)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    merged
+}
+
+// Cut merged excluded ranges out of merged ranges
+fn subtract(ranges: Vec<(u128, u128)>, excluded: &[(u128, u128)]) -> Vec<(u128, u128)> {
+    let mut result = Vec::with_capacity(ranges.len());
+    let mut i = 0;
+    for (first, last) in ranges {
+        // Exclusions ending before this range end before all following ranges too
+        while excluded.get(i).is_some_and(|&(_, end)| end < first) {
+            i += 1;
+        }
+        let mut rest = Some(first);
+        let mut j = i;
+        while let Some(start) = rest {
+            match excluded.get(j) {
+                Some(&(ex_first, ex_last)) if ex_first <= last => {
+                    if ex_first > start {
+                        result.push((start, ex_first - 1));
+                    }
+                    rest = if ex_last < last {
+                        Some(ex_last + 1)
+                    } else {
+                        None
+                    };
+                    j += 1;
+                }
+                _ => {
+                    result.push((start, last));
+                    rest = None;
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        let net: Ipv4Net = "10.0.5.3/28".parse().unwrap();
+        assert_eq!(net.network(), Ipv4Addr::new(10, 0, 5, 0));
+        assert_eq!(net.broadcast(), Ipv4Addr::new(10, 0, 5, 15));
+        let hosts: Vec<Ipv4Addr> = net.hosts().collect();
+        assert_eq!(hosts.len(), 14);
+        assert_eq!(hosts[0], Ipv4Addr::new(10, 0, 5, 1));
+        assert_eq!(hosts[13], Ipv4Addr::new(10, 0, 5, 14));
+        assert!(net.contains_host(Ipv4Addr::new(10, 0, 5, 7)));
+        assert!(!net.contains_host(Ipv4Addr::new(10, 0, 5, 0)));
+        assert!(!net.contains_host(Ipv4Addr::new(10, 0, 5, 15)));
+        assert!(!net.contains_host(Ipv4Addr::new(10, 0, 6, 1)));
+
+        let single: Ipv4Net = "10.0.0.1".parse().unwrap();
+        assert_eq!(
+            single.hosts().collect::<Vec<_>>(),
+            [Ipv4Addr::new(10, 0, 0, 1)]
+        );
+        assert!(single.contains_host(Ipv4Addr::new(10, 0, 0, 1)));
+
+        assert_eq!(
+            "10.0.0.0/33".parse::<Ipv4Net>(),
+            Err(NetParseError::InvalidPrefix)
+        );
+        assert_eq!(
+            "10.0.0/8".parse::<Ipv4Net>(),
+            Err(NetParseError::InvalidAddr)
+        );
+    }
+
+    #[test]
+    fn ipv6() {
+        let net: IpNet = "2001:db8::1/64".parse().unwrap();
+        assert_eq!(net.to_string(), "2001:db8::/64");
+        assert!(net.contains_host("2001:db8::ffff".parse().unwrap()));
+        assert!(!net.contains_host("2001:db8:1::1".parse().unwrap()));
+        assert!(!net.contains_host("10.0.0.1".parse().unwrap()));
+        assert_eq!(net.hosts().count(), 0);
+        assert_eq!(
+            "2001:db8::/129".parse::<IpNet>(),
+            Err(NetParseError::InvalidPrefix)
+        );
+        let single: IpNet = "2001:db8::5".parse().unwrap();
+        assert!(single.contains_host("2001:db8::5".parse().unwrap()));
+        assert!(!single.contains_host("2001:db8::6".parse().unwrap()));
+        assert_eq!(single.hosts().collect::<Vec<_>>(), [single.addr()]);
+    }
+
+    #[test]
+    fn claim_set() {
+        let nets =
+            |nets: &[&str]| -> Vec<IpNet> { nets.iter().map(|net| net.parse().unwrap()).collect() };
+        let set = ClaimSet::new(
+            &nets(&["10.0.0.0/24", "10.0.1.0/24", "10.0.0.7", "2001:db8::/64"]),
+            &nets(&["10.0.0.16/28", "10.0.1.255", "2001:db8::5"]),
+        );
+        assert_eq!(
+            set.ipv4_ranges().collect::<Vec<_>>(),
+            [
+                (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 15)),
+                (Ipv4Addr::new(10, 0, 0, 32), Ipv4Addr::new(10, 0, 0, 254)),
+                (Ipv4Addr::new(10, 0, 1, 1), Ipv4Addr::new(10, 0, 1, 254)),
+            ]
+        );
+        for (ip, claimed) in [
+            ("10.0.0.7", true),
+            ("10.0.0.0", false),
+            ("10.0.0.20", false),
+            ("10.0.0.32", true),
+            ("10.0.1.254", true),
+            ("10.0.2.1", false),
+            ("2001:db8::4", true),
+            ("2001:db8::5", false),
+            ("2001:db8:1::4", false),
+        ] {
+            assert_eq!(set.contains(ip.parse().unwrap()), claimed, "{}", ip);
+        }
+        assert!(ClaimSet::new(&nets(&["10.0.0.1"]), &nets(&["10.0.0.0/8"])).is_empty());
+
+        let union = ClaimSet::union(&[set, ClaimSet::new(&nets(&["10.0.0.16"]), &[])]);
+        assert_eq!(
+            union.ipv4_ranges().collect::<Vec<_>>(),
+            [
+                (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 16)),
+                (Ipv4Addr::new(10, 0, 0, 32), Ipv4Addr::new(10, 0, 0, 254)),
+                (Ipv4Addr::new(10, 0, 1, 1), Ipv4Addr::new(10, 0, 1, 254)),
+            ]
+        );
+        assert!(union.contains("2001:db8::4".parse().unwrap()));
+    }
+}
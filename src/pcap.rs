@@ -0,0 +1,213 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+// Link type of captures of ethernet frames
+pub const LINKTYPE_ETHERNET: u32 = 1;
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+// A captured frame, with the time since the epoch it was captured at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time: Duration,
+    pub data: Vec<u8>,
+}
+
+// Reads frames from files in the classic pcap format written by tcpdump and wireshark (not
+// pcapng), in either byte order and with microsecond or nanosecond timestamps
+pub struct Reader<R> {
+    input: R,
+    swapped: bool,
+    nanos: bool,
+    linktype: u32,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        input.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(invalid(format!("not a pcap file (magic {:#010x})", magic))),
+        };
+        let mut reader = Self {
+            input,
+            swapped,
+            nanos,
+            linktype: 0,
+        };
+        reader.linktype = reader.u32(&header[20..24]) & 0x0fff_ffff;
+        Ok(reader)
+    }
+
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let n = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if self.swapped {
+            n.swap_bytes()
+        } else {
+            n
+        }
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8; 16];
+        // A file ending at a record boundary has no more records
+        match self.input.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut header[1..])?,
+        }
+        let secs = self.u32(&header[0..4]);
+        let frac = self.u32(&header[4..8]);
+        let len = self.u32(&header[8..12]) as usize;
+        if len > 0x40000 {
+            return Err(invalid(format!("record of {} bytes is too large", len)));
+        }
+        let mut data = vec![0u8; len];
+        self.input.read_exact(&mut data)?;
+        let frac = if self.nanos {
+            Duration::from_nanos(frac.into())
+        } else {
+            Duration::from_micros(frac.into())
+        };
+        Ok(Some(Record {
+            time: Duration::from_secs(secs.into()) + frac,
+            data,
+        }))
+    }
+}
+
+// Writes frames to a file in the classic pcap format, with microsecond timestamps
+pub struct Writer<W> {
+    output: W,
+}
+
+// A pcap file the sockets of all interfaces write the frames they receive and send to
+pub type Capture = Arc<Mutex<Writer<Box<dyn Write + Send>>>>;
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut output: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC_MICROS.to_le_bytes());
+        header.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+        header.extend_from_slice(&[0u8; 8]);
+        header.extend_from_slice(&0xffffu32.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        output.write_all(&header)?;
+        Ok(Self { output })
+    }
+
+    // Records are written at once, so the file is complete whenever the process ends
+    pub fn write(&mut self, time: Duration, data: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(16 + data.len());
+        record.extend_from_slice(&(time.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&time.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+        self.output.write_all(&record)
+    }
+}
+
+// Write a frame to the capture with the current time
+pub fn record(capture: &Capture, frame: &[u8]) {
+    let time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let result = match capture.lock() {
+        Ok(mut writer) => writer.write(time, frame),
+        Err(_) => return,
+    };
+    if let Err(err) = result {
+        log::warn!("failed to write frame to capture: {}", err);
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reader() {
+        let mut file = vec![
+            0xd4, 0xc3, 0xb2, 0xa1, 0x02, 0x00, 0x04, 0x00, // magic, version
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // timezone, accuracy
+            0xff, 0xff, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // snaplen, link type
+            0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, // time
+            0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, // lengths
+            0xaa, 0xbb, 0xcc, // data
+        ];
+        let mut reader = Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_ETHERNET);
+        assert_eq!(
+            reader.next().unwrap().unwrap(),
+            Record {
+                time: Duration::new(1, 2000),
+                data: vec![0xaa, 0xbb, 0xcc],
+            }
+        );
+        assert!(reader.next().is_none());
+
+        // Truncated records are errors, instead of the end of the file
+        file.pop();
+        let mut reader = Reader::new(&file[..]).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        // Big endian with nanosecond timestamps
+        let file = [
+            0xa1, 0xb2, 0x3c, 0x4d, 0x00, 0x02, 0x00, 0x04, // magic, version
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // timezone, accuracy
+            0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, // snaplen, link type
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, // time
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // lengths
+        ];
+        let mut reader = Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().time, Duration::new(1, 2));
+        assert!(Reader::new(&file[4..]).is_err());
+    }
+
+    #[test]
+    fn writer() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(Duration::new(1, 2000), &[0xaa, 0xbb]).unwrap();
+        writer.write(Duration::new(3, 0), &[]).unwrap();
+        let records: Vec<Record> = Reader::new(&writer.output[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            [
+                Record {
+                    time: Duration::new(1, 2000),
+                    data: vec![0xaa, 0xbb],
+                },
+                Record {
+                    time: Duration::new(3, 0),
+                    data: vec![],
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use crate::ether;
+use crate::iface::{self, PacketIo};
+use crate::link::{bpf_stmt, BpfInsn, LinkAddr, Membership, BPF_RET_K};
+use crate::pcap;
+use eui48::MacAddress;
+use nix::poll::{PollFd, PollFlags};
+use nix::sys::eventfd::{eventfd, EfdFlags};
+use nix::sys::signal::SigSet;
+use nix::unistd::{close, read, write};
+use std::collections::VecDeque;
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Frames queued before dropping further ones, and the largest frame received whole
+const QUEUE_FRAMES: usize = 4096;
+const FRAME_LEN: usize = 1600;
+// Frames received while holding the socket, before letting others change it
+const BATCH: usize = 64;
+// Pause after a failure to receive, which could keep the socket readable
+const ERROR_PAUSE: Duration = Duration::from_millis(100);
+
+type Received = nix::Result<(Vec<u8>, LinkAddr, Option<ether::Header>)>;
+
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        let _ = close(self.0);
+    }
+}
+
+// Received frames waiting to be processed, with an eventfd readable while there are any
+struct Queue {
+    frames: Mutex<VecDeque<Received>>,
+    capacity: usize,
+    event: Fd,
+}
+
+impl Queue {
+    fn new(capacity: usize) -> nix::Result<Self> {
+        let event = Fd(eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)?);
+        Ok(Self {
+            frames: Mutex::new(VecDeque::new()),
+            capacity,
+            event,
+        })
+    }
+
+    // Queue the frame unless full, returning whether it was
+    fn push(&self, received: Received) -> bool {
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() >= self.capacity {
+            return false;
+        }
+        if frames.is_empty() {
+            let _ = write(self.event.0, &1u64.to_ne_bytes());
+        }
+        frames.push_back(received);
+        true
+    }
+
+    fn pop(&self) -> Option<Received> {
+        let mut frames = self.frames.lock().unwrap();
+        let received = frames.pop_front();
+        if received.is_some() && frames.is_empty() {
+            let _ = read(self.event.0, &mut [0u8; 8]);
+        }
+        received
+    }
+
+    fn is_empty(&self) -> bool {
+        self.frames.lock().unwrap().is_empty()
+    }
+}
+
+struct Shared<S> {
+    rx: Mutex<S>,
+    queue: Queue,
+    stop: Fd,
+    stopped: AtomicBool,
+}
+
+// Receive in a thread of its own into a bounded queue, so that processing the frames, or
+// anything else the thread processing them does, never keeps the kernel waiting and
+// dropping frames; frames are sent through a second socket receiving nothing
+pub struct QueuedSocket<S: PacketIo + Send + 'static> {
+    tx: S,
+    shared: Arc<Shared<S>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<S: PacketIo + Send + 'static> Drop for QueuedSocket<S> {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        let _ = write(self.shared.stop.0, &1u64.to_ne_bytes());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Receive from the socket until stopped, waiting for it without holding it so that it can
+// be changed meanwhile
+fn receive<S: PacketIo>(shared: &Shared<S>, name: &str) {
+    let fd = shared.rx.lock().unwrap().as_raw_fd();
+    let mut buf = [0u8; FRAME_LEN];
+    let mut full = false;
+    while !shared.stopped.load(Ordering::SeqCst) {
+        let mut fds = [
+            PollFd::new(fd, PollFlags::POLLIN),
+            PollFd::new(shared.stop.0, PollFlags::POLLIN),
+        ];
+        match S::poll(&mut fds, -1) {
+            Ok(_) | Err(nix::Error::EINTR) => (),
+            Err(err) => {
+                log::error!("failed to wait for frames on {}: {}", name, err);
+                return;
+            }
+        }
+        let rx = shared.rx.lock().unwrap();
+        for _ in 0..BATCH {
+            let received = match rx.recv_from(&mut buf) {
+                Ok((size, from, header)) => Ok((buf[..size].to_vec(), from, header)),
+                Err(nix::Error::EAGAIN) | Err(nix::Error::EINTR) => break,
+                Err(err) => Err(err),
+            };
+            let failed = received.is_err();
+            if shared.queue.push(received) {
+                full = false;
+            } else if !full {
+                log::warn!("receive queue full on {}, dropping frames", name);
+                full = true;
+            }
+            if failed {
+                std::thread::sleep(ERROR_PAUSE);
+                break;
+            }
+        }
+    }
+}
+
+impl<S: PacketIo + Send + 'static> AsRawFd for QueuedSocket<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.shared.queue.event.0
+    }
+}
+
+impl<S: PacketIo + Send + 'static> PacketIo for QueuedSocket<S> {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        let nothing = [bpf_stmt(BPF_RET_K, 0)];
+        let tx = S::open(
+            link_addr,
+            protocol,
+            raw,
+            vlan,
+            min_len,
+            &nothing,
+            capture.clone(),
+        )?;
+        let rx = S::open(link_addr, protocol, raw, vlan, min_len, filter, capture)?;
+        let shared = Arc::new(Shared {
+            rx: Mutex::new(rx),
+            queue: Queue::new(QUEUE_FRAMES)?,
+            stop: Fd(eventfd(0, EfdFlags::EFD_CLOEXEC)?),
+            stopped: AtomicBool::new(false),
+        });
+        let name = iface::interface_name(link_addr.ifindex)
+            .unwrap_or_else(|_| link_addr.ifindex.to_string());
+        let thread = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name(format!("rx {}", name))
+                .spawn(move || {
+                    // Signals are for the threads handling them
+                    let _ = SigSet::all().thread_block();
+                    receive(&shared, &name)
+                })
+                .map_err(|err| {
+                    nix::Error::from_i32(err.raw_os_error().unwrap_or(nix::libc::EAGAIN))
+                })?
+        };
+        Ok(Self {
+            tx,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.tx.link_addr()
+    }
+
+    fn is_raw(&self) -> bool {
+        self.tx.is_raw()
+    }
+
+    fn set_filter(&self, filter: &[BpfInsn]) -> nix::Result<()> {
+        self.shared.rx.lock().unwrap().set_filter(filter)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let (frame, from, header) = self.shared.queue.pop().ok_or(nix::Error::EAGAIN)??;
+        let size = frame.len().min(buf.len());
+        buf[..size].copy_from_slice(&frame[..size]);
+        Ok((size, from, header))
+    }
+
+    fn pending(&self) -> bool {
+        !self.shared.queue.is_empty()
+    }
+
+    fn defer_sends(&self, defer: bool) -> nix::Result<()> {
+        self.tx.defer_sends(defer)
+    }
+
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        self.tx.send_from(buf, src, to)
+    }
+
+    fn set_membership(&self, membership: Membership, join: bool) -> nix::Result<()> {
+        self.shared
+            .rx
+            .lock()
+            .unwrap()
+            .set_membership(membership, join)
+    }
+
+    fn join_fanout(&self, id: Option<u16>) -> nix::Result<u16> {
+        self.shared.rx.lock().unwrap().join_fanout(id)
+    }
+
+    fn set_arp_ranges(&self, ranges: &[(Ipv4Addr, Ipv4Addr)]) -> nix::Result<()> {
+        self.shared.rx.lock().unwrap().set_arp_ranges(ranges)
+    }
+
+    fn set_busy_poll(&self, usecs: u32) -> nix::Result<()> {
+        self.shared.rx.lock().unwrap().set_busy_poll(usecs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readable(queue: &Queue) -> bool {
+        nix::poll::poll(&mut [PollFd::new(queue.event.0, PollFlags::POLLIN)], 0).unwrap() > 0
+    }
+
+    #[test]
+    fn bounded() {
+        let queue = Queue::new(2).unwrap();
+        let from = LinkAddr::new(1, ether::ETH_P_ARP, MacAddress::nil());
+        assert!(!readable(&queue));
+        assert!(queue.push(Ok((vec![1], from, None))));
+        assert!(queue.push(Err(nix::Error::ENETDOWN)));
+        assert!(!queue.push(Ok((vec![3], from, None))));
+        assert!(readable(&queue));
+        assert_eq!(queue.pop().unwrap().unwrap().0, vec![1]);
+        assert!(readable(&queue));
+        assert_eq!(queue.pop().unwrap().unwrap_err(), nix::Error::ENETDOWN);
+        assert!(!readable(&queue));
+        assert!(queue.pop().is_none());
+        assert!(queue.push(Ok((vec![4], from, None))));
+        assert!(readable(&queue));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::error::Error;
+use eui48::MacAddress;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::path::Path;
+
+// The IPv4 addresses of hosts by their MAC address, for answering Reverse ARP requests of
+// hosts that don't know their own address, e.g. legacy equipment netbooting. Read from
+// files in the format of /etc/ethers: a MAC address and an IPv4 address or hostname per
+// line, with # starting a comment.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Ethers {
+    hosts: BTreeMap<MacAddress, Ipv4Addr>,
+}
+
+impl Ethers {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        std::fs::read_to_string(path)
+            .map_err(|err| Error::file(path, err))?
+            .parse()
+            .map_err(|err| Error::file(path, err))
+    }
+
+    pub fn get(&self, mac: MacAddress) -> Option<Ipv4Addr> {
+        self.hosts.get(&mac).copied()
+    }
+}
+
+impl std::str::FromStr for Ethers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hosts = BTreeMap::new();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let (mac, host) = match (fields.next(), fields.next()) {
+                (None, _) => continue,
+                (Some(mac), Some(host)) => (mac, host),
+                (Some(_), None) => return Err(format!("line {}: missing address", n + 1)),
+            };
+            let mac = MacAddress::parse_str(mac)
+                .map_err(|err| format!("line {}: invalid MAC address {}: {}", n + 1, mac, err))?;
+            let ip = match host.parse() {
+                Ok(ip) => ip,
+                Err(_) => resolve(host).map_err(|err| format!("line {}: {}", n + 1, err))?,
+            };
+            hosts.entry(mac).or_insert(ip);
+        }
+        Ok(Self { hosts })
+    }
+}
+
+// The first IPv4 address a hostname resolves to
+fn resolve(hostname: &str) -> Result<Ipv4Addr, String> {
+    (hostname, 0)
+        .to_socket_addrs()
+        .map_err(|err| format!("failed to resolve {}: {}", hostname, err))?
+        .find_map(|addr| match addr.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| format!("{} has no IPv4 address", hostname))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ethers() {
+        let ethers: Ethers = r#"
+            # netbooted hosts
+            02:00:00:00:00:01 10.0.0.1
+            02-00-00-00-00-02   10.0.0.2 # comment
+            02:00:00:00:00:01 10.0.0.3
+        "#
+        .parse()
+        .unwrap();
+        let mac = |n| MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, n]);
+        assert_eq!(ethers.get(mac(1)), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(ethers.get(mac(2)), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(ethers.get(mac(3)), None);
+
+        assert!("02:00:00:00:00:01".parse::<Ethers>().is_err());
+        assert!("02:00:00:00:00 10.0.0.1".parse::<Ethers>().is_err());
+    }
+}
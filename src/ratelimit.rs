@@ -0,0 +1,99 @@
+use eui48::MacAddress;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+// Limits the number of replies sent to each requester per second, so scanners and
+// misbehaving hosts hammering the claimed addresses don't get every request answered
+pub struct RateLimiter {
+    limit: Option<u32>,
+    requesters: HashMap<MacAddress, Requester>,
+}
+
+struct Requester {
+    since: Instant,
+    replies: u32,
+    limited: bool,
+}
+
+impl Requester {
+    fn new(since: Instant) -> Self {
+        Self {
+            since,
+            replies: 0,
+            limited: false,
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limit,
+            requesters: HashMap::new(),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    // Whether a reply may be sent to the requester, counting it when it may
+    pub fn allow(&mut self, mac: MacAddress, now: Instant) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        // Forget requesters that have been quiet for a while, so the table doesn't grow
+        // without bounds when scanned from many MAC addresses
+        if !self.requesters.contains_key(&mac) {
+            self.requesters
+                .retain(|_, requester| now.duration_since(requester.since) < WINDOW);
+        }
+
+        let requester = self
+            .requesters
+            .entry(mac)
+            .or_insert_with(|| Requester::new(now));
+        if now.duration_since(requester.since) >= WINDOW {
+            *requester = Requester::new(now);
+        }
+        if requester.replies < limit {
+            requester.replies += 1;
+            return true;
+        }
+        if !requester.limited {
+            log::warn!(
+                "{} sent more than {} requests per second, rate limiting",
+                mac,
+                limit
+            );
+            requester.limited = true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let a = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let b = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x02]);
+        let now = Instant::now();
+
+        let mut limiter = RateLimiter::new(Some(2));
+        assert!(limiter.allow(a, now));
+        assert!(limiter.allow(a, now));
+        assert!(!limiter.allow(a, now + Duration::from_millis(500)));
+        assert!(limiter.allow(b, now + Duration::from_millis(500)));
+        assert!(limiter.allow(a, now + WINDOW));
+
+        let mut unlimited = RateLimiter::new(None);
+        assert!((0..100).all(|_| unlimited.allow(a, now)));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::arp::{Arp, ArpOp};
+use crate::config::{ArpProbePolicy, Claim};
+use crate::ether;
+use crate::net::ClaimSet;
+use eui48::MacAddress;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+
+// What the claims would have done about an ARP request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    // Answered with the MAC address, or with the interface's when None
+    Reply(Option<MacAddress>),
+    Unclaimed,
+    // Claimed, but not answered for the reason
+    Ignored(&'static str),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Reply(Some(mac)) => write!(f, "reply is-at {}", mac.to_hex_string()),
+            Verdict::Reply(None) => write!(f, "reply is-at the interface's MAC address"),
+            Verdict::Unclaimed => write!(f, "not claimed"),
+            Verdict::Ignored(reason) => write!(f, "ignored ({})", reason),
+        }
+    }
+}
+
+// Decides which ARP requests in frames captured elsewhere the claims would have answered,
+// for troubleshooting offline. Claims of all interfaces are matched by VLAN only, and what
+// isn't known offline (on_link subnets, proxy_routes routes, sponged addresses, rate limits
+// and pauses) isn't taken into account.
+pub struct Replay {
+    claims: Vec<(Claim, ClaimSet)>,
+}
+
+impl Replay {
+    pub fn new(claims: Vec<Claim>) -> Self {
+        let claims = claims
+            .into_iter()
+            .map(|claim| {
+                let claimed = claim.claim_set();
+                (claim, claimed)
+            })
+            .collect();
+        Self { claims }
+    }
+
+    // The ARP request in an ethernet frame with what would have been done about it, None
+    // for other frames
+    pub fn decide(&self, frame: &[u8]) -> Option<(ether::Header, Arp, Verdict)> {
+        let header = ether::Header::try_from(frame).ok()?;
+        if header.ethertype != ether::ETH_P_ARP {
+            return None;
+        }
+        let req = Arp::try_from(&frame[header.size()..]).ok()?;
+        if req.op != ArpOp::Request || !req.is_ethernet_ipv4() {
+            return None;
+        }
+        let tpa = IpAddr::V4(req.tpa);
+        let broadcast = header.dst.is_multicast();
+        let claims: Vec<&Claim> = self
+            .claims
+            .iter()
+            .filter(|(claim, claimed)| claim.vlan_tags() == header.vlan && claimed.contains(tpa))
+            .map(|(claim, _)| claim)
+            .collect();
+        let verdict = match claims
+            .iter()
+            .find(|claim| ignored(claim, &req, broadcast).is_none())
+        {
+            Some(claim) => Verdict::Reply(
+                claim
+                    .reply_macs
+                    .get(&req.sha)
+                    .or_else(|| claim.macs.get(&tpa))
+                    .copied()
+                    .or(claim.mac),
+            ),
+            None => claims
+                .first()
+                .and_then(|claim| ignored(claim, &req, broadcast))
+                .map_or(Verdict::Unclaimed, Verdict::Ignored),
+        };
+        Some((header, req, verdict))
+    }
+}
+
+// Why a claim of the requested address wouldn't answer the request, like the responder
+fn ignored(claim: &Claim, req: &Arp, broadcast: bool) -> Option<&'static str> {
+    let probe = req.spa.is_unspecified();
+    if !claim.answers(req.sha, IpAddr::V4(req.spa)) {
+        Some("requester not allowed")
+    } else if !claim.delivery.allows(broadcast) {
+        Some(if broadcast {
+            "broadcast request"
+        } else {
+            "unicast request"
+        })
+    } else if probe && (claim.proxy || claim.sponge || claim.on_probe != ArpProbePolicy::Defend) {
+        Some("address probe")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn decide() {
+        let config: Config = r#"
+            [[claim]]
+            iface = "eth0"
+            ip = ["10.0.0.0/29"]
+            exclude = ["10.0.0.2"]
+            deny_macs = ["02:00:00:00:00:66"]
+
+            [[claim]]
+            iface = "eth0"
+            vlan = 100
+            ip = ["10.0.0.1"]
+            mac = "02:00:00:00:00:64"
+            on_probe = "ignore"
+        "#
+        .parse()
+        .unwrap();
+        let replay = Replay::new(config.claims);
+        let sha = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        let frame = |vlan: Option<u16>, arp: Arp| {
+            let header = ether::Header {
+                dst: MacAddress::broadcast(),
+                src: arp.sha,
+                vlan: vlan.map(|id| ether::Vlan { outer: None, id }),
+                ethertype: ether::ETH_P_ARP,
+                snap: false,
+            };
+            let mut frame = vec![0u8; header.size()];
+            header.fill(&mut frame).unwrap();
+            frame.extend(arp.to_vec());
+            frame
+        };
+        let verdict = |vlan, arp| {
+            replay
+                .decide(&frame(vlan, arp))
+                .map(|(_, _, verdict)| verdict)
+        };
+        let ip = |n| std::net::Ipv4Addr::new(10, 0, 0, n);
+
+        assert_eq!(
+            verdict(None, Arp::request(sha, ip(9), ip(1))),
+            Some(Verdict::Reply(None))
+        );
+        assert_eq!(
+            verdict(None, Arp::request(sha, ip(9), ip(2))),
+            Some(Verdict::Unclaimed)
+        );
+        let denied = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x66]);
+        assert_eq!(
+            verdict(None, Arp::request(denied, ip(9), ip(1))),
+            Some(Verdict::Ignored("requester not allowed"))
+        );
+        assert_eq!(
+            verdict(Some(100), Arp::request(sha, ip(9), ip(1))),
+            Some(Verdict::Reply(Some(MacAddress::new([
+                0x02, 0x00, 0x00, 0x00, 0x00, 0x64
+            ]))))
+        );
+        assert_eq!(
+            verdict(Some(100), Arp::probe(sha, ip(1))),
+            Some(Verdict::Ignored("address probe"))
+        );
+        assert_eq!(
+            verdict(Some(100), Arp::request(sha, ip(9), ip(3))),
+            Some(Verdict::Unclaimed)
+        );
+        assert_eq!(verdict(None, Arp::gratuitous_reply(sha, ip(1))), None);
+    }
+}
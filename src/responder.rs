@@ -0,0 +1,2667 @@
+use crate::config::{
+    AnnounceOp, ArpProbePolicy, Claim, ConflictPolicy, DadPolicy, Delivery, ProbePolicy,
+};
+use crate::error::Error;
+use crate::iface::{self, DefaultPacketIo, Interface, KernelAnswer, PacketIo, Protocol};
+use crate::link::{LinkAddr, PacketType};
+use crate::net::{ClaimSet, IpNet};
+use crate::sys::{self, PollFd, PollFlags, RawFd};
+use crate::{anomaly, arp, ether, monitor, ndp, pcap, rarp, ratelimit, route, sponge};
+use eui48::MacAddress;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+// Resolve interface selectors into one claim per matching interface and look up the
+// interface through the routing table for claims that don't specify one, leaving out
+// claims outside their time windows at the given minute of the day. Claims scoped to a
+// VLAN get <iface>.<vlan> (or <iface>.<outer_vlan>.<vlan>) as their interface, so each
+// VLAN is served separately.
+pub fn expand_claims(claims: &[Claim], minute: u32) -> Result<Vec<Claim>, Error> {
+    let names = if claims.iter().any(|claim| iface::is_selector(&claim.iface)) {
+        iface::interface_names().unwrap_or_else(|err| {
+            log::error!("failed to list interfaces: {}", err);
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    let mut expanded = Vec::new();
+    for claim in claims.iter().filter(|claim| claim.is_active(minute)) {
+        let claim = &claim.resolve()?;
+        if claim.iface.is_empty() {
+            let ip = match claim.ip.first() {
+                Some(ip) => ip.addr(),
+                None => continue,
+            };
+            let name = route::lookup_oif(ip).and_then(|index| {
+                iface::interface_name(index).map_err(|err| Error::Route {
+                    ip,
+                    message: err.to_string(),
+                })
+            })?;
+            log::debug!("using interface {} for {}", name, ip);
+            expanded.push(Claim {
+                iface: name,
+                ..claim.clone()
+            });
+        } else if iface::is_selector(&claim.iface) {
+            let before = expanded.len();
+            for name in names
+                .iter()
+                .filter(|name| iface::selects(&claim.iface, name))
+            {
+                expanded.push(Claim {
+                    iface: name.clone(),
+                    ..claim.clone()
+                });
+            }
+            if expanded.len() == before {
+                log::debug!("no interfaces match {}", claim.iface);
+            }
+        } else {
+            expanded.push(claim.clone());
+        }
+    }
+    for claim in expanded.iter_mut() {
+        claim.iface.push_str(&claim.vlan_suffix());
+    }
+    Ok(expanded)
+}
+
+// Address conflict detection timing from RFC 5227
+const PROBE_WAIT: Duration = Duration::from_secs(1);
+const PROBE_NUM: u32 = 3;
+const PROBE_MIN: Duration = Duration::from_secs(1);
+const PROBE_MAX: Duration = Duration::from_secs(2);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+const MAX_CONFLICTS: u32 = 10;
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Progress of probing whether an IPv4 address is in use before claiming it
+struct Probe {
+    mac: MacAddress,
+    // Source MAC address of the frames, which may differ from mac in raw socket mode
+    src: MacAddress,
+    policy: ProbePolicy,
+    sent: u32,
+    conflicts: u32,
+    // None once probing was aborted due to a conflict
+    next: Option<Instant>,
+    // Taking over requires the address to be unused for the hold time since the last conflict
+    hold: Duration,
+    last_conflict: Option<Instant>,
+}
+
+// Requests to gateways keeping their ARP caches fresh for a claimed address
+struct Keepalive {
+    mac: MacAddress,
+    src: MacAddress,
+    gateways: Vec<Ipv4Addr>,
+    interval: Duration,
+    next: Instant,
+}
+
+// Announcement of a claimed address, repeated a number of times when claimed and
+// optionally periodically afterwards
+struct Announcement {
+    mac: MacAddress,
+    src: MacAddress,
+    op: AnnounceOp,
+    na_flags: ndp::NaFlags,
+    routers: Vec<MacAddress>,
+    remaining: u32,
+    spacing: Duration,
+    interval: Option<Duration>,
+    jitter: Duration,
+    next: Instant,
+}
+
+impl Announcement {
+    fn new(claim: &Claim, mac: MacAddress, src: MacAddress, now: Instant) -> Self {
+        Self {
+            mac,
+            src,
+            op: claim.announce_op,
+            na_flags: claim.na_flags,
+            routers: claim.routers.clone(),
+            remaining: claim.announce_count,
+            spacing: Duration::from_millis(claim.announce_spacing),
+            interval: claim.announce_interval.map(Duration::from_secs),
+            jitter: Duration::from_secs(claim.announce_jitter),
+            next: now,
+        }
+    }
+}
+
+// A reply held back so faster responders get to answer first
+struct PendingReply {
+    at: Instant,
+    iface: String,
+    ndp: bool,
+    src: MacAddress,
+    to: LinkAddr,
+    frame: Vec<u8>,
+    // Passive replies are dropped when another host is seen answering for ip to dst
+    passive: bool,
+    ip: IpAddr,
+    dst: IpAddr,
+}
+
+// Maximum number of cached route lookups
+const MAX_ROUTES: usize = 4096;
+
+// Most packets handled for a socket per poll, so a busy one doesn't hold up the others
+const RECEIVE_BATCH: usize = 64;
+
+// How long passive claims listen for other hosts answering a request
+const PASSIVE_WAIT: Duration = Duration::from_millis(200);
+
+// Backup responders deliberately answer slower than the primary
+fn reply_delay(claim: &Claim) -> Duration {
+    let delay = Duration::from_millis(claim.reply_delay)
+        + jitter(Duration::from_millis(claim.reply_jitter));
+    if claim.passive {
+        delay.max(PASSIVE_WAIT)
+    } else {
+        delay
+    }
+}
+
+fn sponge_settings(claim: &Claim) -> sponge::Settings {
+    let secs = |secs| Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+    sponge::Settings {
+        threshold: claim.sponge_threshold,
+        age: secs(claim.sponge_age),
+        recheck: secs(claim.sponge_recheck),
+    }
+}
+
+// Proxies only answer for addresses that are reachable through another interface
+fn routed_elsewhere(routes: &mut HashMap<IpAddr, Option<usize>>, ip: IpAddr, index: usize) -> bool {
+    if routes.len() >= MAX_ROUTES {
+        routes.clear();
+    }
+    let oif = *routes
+        .entry(ip)
+        .or_insert_with(|| match route::lookup_oif(ip) {
+            Ok(oif) => Some(oif),
+            Err(err) => {
+                log::debug!("{}", err);
+                None
+            }
+        });
+    oif.is_some_and(|oif| oif != index)
+}
+
+// Duplicate addresses are reported when first seen and periodically afterwards
+fn report_competitor(report: Option<monitor::Report>, name: &str, ip: IpAddr, mac: MacAddress) {
+    match report {
+        Some(monitor::Report::First) => log::warn!("IP {} on {} is also used by {}", ip, name, mac),
+        Some(monitor::Report::Repeated { count, period }) => log::warn!(
+            "IP {} on {} is also used by {}, seen {} times in the last {}s",
+            ip,
+            name,
+            mac,
+            count,
+            period.as_secs()
+        ),
+        None => {}
+    }
+}
+
+fn report_anomaly(
+    anomalies: &mut anomaly::Anomalies,
+    anomaly: anomaly::Anomaly,
+    name: &str,
+    from: MacAddress,
+    pkt: &arp::Arp,
+) {
+    if anomalies.record(anomaly, from, Instant::now()) {
+        log::warn!("{} on {}: {} from {}", anomaly, name, pkt, from);
+    }
+}
+
+// The claims on an interface claiming an address, in order
+fn claiming<'a: 'b, 'b>(
+    claims: &'a [Claim],
+    claimed: &'a [ClaimSet],
+    name: &'b str,
+    ip: IpAddr,
+) -> impl Iterator<Item = &'a Claim> + 'b {
+    claims
+        .iter()
+        .zip(claimed)
+        .filter(move |(claim, claimed)| claim.iface == name && claimed.contains(ip))
+        .map(|(claim, _)| claim)
+}
+
+// The claim answering for a host on an interface, the first one claiming it wins. Proxy and
+// sponge claims answer for addresses without claiming them as hosts.
+fn host_claim<'a>(
+    claims: &'a [Claim],
+    claimed: &'a [ClaimSet],
+    name: &str,
+    ip: IpAddr,
+) -> Option<&'a Claim> {
+    claiming(claims, claimed, name, ip).find(|claim| !claim.proxy && !claim.sponge)
+}
+
+// Whether requests for the claim's addresses can be answered by the kernel, which only
+// knows the MAC address and frame source of each address
+fn answerable_in_kernel(claim: &Claim) -> bool {
+    claim.reply_macs.is_empty()
+        && claim.allow_macs.is_empty()
+        && claim.deny_macs.is_empty()
+        && claim.allow_from.is_empty()
+        && !claim.on_link
+        && claim.reply_delay == 0
+        && claim.reply_jitter == 0
+        && claim.delivery == Delivery::Any
+        && !(claim.passive || claim.proxy || claim.sponge || claim.wake)
+}
+
+// Another host answered for ip to dst, so passive claims don't need to
+fn cancel_passive(pending: &mut Vec<PendingReply>, name: &str, ip: IpAddr, dst: IpAddr) {
+    pending.retain(|reply| {
+        let answered = reply.passive && reply.iface == name && reply.ip == ip && reply.dst == dst;
+        if answered {
+            log::debug!("another host answered for {} on {}, not replying", ip, name);
+        }
+        !answered
+    });
+}
+
+// A random duration up to max, to avoid hosts probing in lockstep
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    max.mul_f64(f64::from(nanos % 1_000_000) / 1_000_000.0)
+}
+
+// Answers requests for claimed addresses on their interfaces and announces them, driven
+// by polling its sockets and calling tick whenever they are readable or the deadline passed
+pub struct Responder<S = DefaultPacketIo> {
+    interfaces: BTreeMap<String, Interface<S>>,
+    claims: Vec<Claim>,
+    // The addresses of each claim, looked up for every request
+    claimed: Vec<ClaimSet>,
+    probes: HashMap<(String, Ipv4Addr), Probe>,
+    // Claimed addresses no longer answered for since another host started using them
+    yielded: HashSet<(String, Ipv4Addr)>,
+    // When claimed addresses were last defended against conflicting hosts
+    defended: HashMap<(String, Ipv4Addr), Instant>,
+    defend_interval: Duration,
+    announcements: HashMap<(String, IpAddr), Announcement>,
+    // Claimed hosts announced for another MAC address, no longer answered for until resuming
+    handed_over: HashSet<(String, IpAddr)>,
+    keepalives: HashMap<(String, Ipv4Addr), Keepalive>,
+    // Number of ARP and RARP replies and neighbor advertisements sent in reply to requests
+    replies: u64,
+    // Let the sockets of interfaces answer ARP requests in the kernel where they can
+    kernel_answers: bool,
+    // Whether requests were silenced and the number of probed and yielded addresses when
+    // the addresses answered in the kernel were last updated
+    kernel_answered: Option<(bool, usize, usize, usize)>,
+    // Number of the replies sent by the kernel
+    kernel_replies: u64,
+    // Requests are only observed, not answered, until the grace period ends
+    grace_until: Instant,
+    // Reasons requests are currently not answered for, e.g. a signal
+    paused: BTreeSet<&'static str>,
+    restore_on_pause: bool,
+    // Stop with an error on any conflict instead of following the claims' policies
+    exit_on_conflict: bool,
+    // Open interfaces with raw sockets, handling the ethernet header ourselves
+    raw: bool,
+    // Length frames sent on raw sockets are padded to with zeros
+    min_frame_len: usize,
+    // Where the sockets of interfaces write the frames they receive and send
+    capture: Option<pcap::Capture>,
+    // Fanout groups the sockets of interfaces join, shared with workers
+    fanout: Option<iface::Fanout>,
+    // Microseconds the sockets of interfaces busy poll for, if at all
+    busy_poll: Option<u32>,
+    // The ID of workers, which only answer requests
+    worker: Option<usize>,
+    limiter: ratelimit::RateLimiter,
+    pending: Vec<PendingReply>,
+    monitor: monitor::Monitor,
+    // Output interface of routes to addresses, cleared whenever routes change
+    routes: HashMap<IpAddr, Option<usize>>,
+    sponge: sponge::Sponge,
+    anomalies: anomaly::Anomalies,
+    // When sleeping hosts were last woken
+    woken: HashMap<(String, IpAddr), Instant>,
+    // Ethers files of claims answering Reverse ARP requests, read when applying claims
+    ethers: HashMap<PathBuf, rarp::Ethers>,
+    // Packets are built here, and as ARP and NDP packets are at most 72 bytes filling it
+    // can't fail
+    wbuf: [u8; 500],
+}
+
+impl Responder {
+    pub fn new(
+        defend_interval: Duration,
+        grace: Duration,
+        rate_limit: Option<u32>,
+        restore_on_pause: bool,
+        exit_on_conflict: bool,
+        raw: bool,
+    ) -> Self {
+        Self::with_packet_io(
+            defend_interval,
+            grace,
+            rate_limit,
+            restore_on_pause,
+            exit_on_conflict,
+            raw,
+        )
+    }
+}
+
+impl<S: PacketIo> Responder<S> {
+    // Like new, sending and receiving through another implementation than packet sockets
+    pub fn with_packet_io(
+        defend_interval: Duration,
+        grace: Duration,
+        rate_limit: Option<u32>,
+        restore_on_pause: bool,
+        exit_on_conflict: bool,
+        raw: bool,
+    ) -> Self {
+        Self {
+            interfaces: BTreeMap::new(),
+            claims: Vec::new(),
+            claimed: Vec::new(),
+            probes: HashMap::new(),
+            yielded: HashSet::new(),
+            defended: HashMap::new(),
+            defend_interval,
+            announcements: HashMap::new(),
+            handed_over: HashSet::new(),
+            keepalives: HashMap::new(),
+            replies: 0,
+            kernel_answers: false,
+            kernel_answered: None,
+            kernel_replies: 0,
+            grace_until: Instant::now() + grace,
+            paused: BTreeSet::new(),
+            restore_on_pause,
+            exit_on_conflict,
+            raw,
+            min_frame_len: ether::MIN_FRAME_LEN,
+            capture: None,
+            fanout: None,
+            busy_poll: None,
+            worker: None,
+            limiter: ratelimit::RateLimiter::new(rate_limit),
+            pending: Vec::new(),
+            monitor: monitor::Monitor::default(),
+            routes: HashMap::new(),
+            sponge: sponge::Sponge::default(),
+            anomalies: anomaly::Anomalies::default(),
+            woken: HashMap::new(),
+            ethers: HashMap::new(),
+            wbuf: [0u8; 500],
+        }
+    }
+
+    // Write the frames received and sent on interfaces opened from now on to a pcap file
+    pub fn set_capture(&mut self, capture: pcap::Capture) {
+        self.capture = Some(capture);
+    }
+
+    // Pad frames sent on raw sockets of interfaces opened from now on to this length (60
+    // bytes by default), 0 to leave padding to the network driver
+    pub fn set_min_frame_len(&mut self, len: usize) {
+        self.min_frame_len = len;
+    }
+
+    // Answer ARP requests in the kernel, for sockets that can, for addresses of claims that
+    // don't filter, delay or hold back replies, while not rate limiting. Other requests are
+    // still answered here, as are probes, conflicts and announcements.
+    pub fn set_kernel_answers(&mut self, enabled: bool) {
+        self.kernel_answers = enabled;
+        self.kernel_answered = None;
+        self.answer_in_kernel();
+    }
+
+    // Busy poll the network devices for up to this many microseconds when waiting on the
+    // sockets of interfaces, trading CPU time for lower latency, from when claims are
+    // applied next
+    pub fn set_busy_poll(&mut self, usecs: Option<u32>) {
+        self.busy_poll = usecs;
+    }
+
+    // Spread the packets received on interfaces opened from now on over the sockets of the
+    // workers created from this responder, instead of each receiving all of them
+    pub fn set_fanout(&mut self, fanout: iface::Fanout) {
+        self.fanout = Some(fanout);
+    }
+
+    // A responder answering requests alongside this one, e.g. in another thread, leaving
+    // probes, announcements and keepalives to this one. Workers keep their own state, so
+    // conflicts, rate limits and replies of other hosts are only seen by the worker that
+    // received the packet, which probing, passive, sponge and yielding claims rely on.
+    pub fn worker(&self, id: usize) -> Self {
+        Self {
+            grace_until: self.grace_until,
+            paused: self.paused.clone(),
+            min_frame_len: self.min_frame_len,
+            capture: self.capture.clone(),
+            fanout: self.fanout.clone(),
+            busy_poll: self.busy_poll,
+            worker: Some(id),
+            ..Self::with_packet_io(
+                self.defend_interval,
+                Duration::ZERO,
+                self.limiter.limit(),
+                false,
+                self.exit_on_conflict,
+                self.raw,
+            )
+        }
+    }
+
+    // The sockets to poll, with the name of their interface and their protocol
+    pub fn sockets(&self) -> Vec<(String, Protocol, RawFd)> {
+        let mut sockets = Vec::new();
+        for iface in self.interfaces.values() {
+            sockets.push((iface.name.clone(), Protocol::Arp, iface.arp.as_raw_fd()));
+            if let Some(ndp) = &iface.ndp {
+                sockets.push((iface.name.clone(), Protocol::Ndp, ndp.as_raw_fd()));
+            }
+            if let Some(rarp) = &iface.rarp {
+                sockets.push((iface.name.clone(), Protocol::Rarp, rarp.as_raw_fd()));
+            }
+        }
+        sockets
+    }
+
+    // Handle a packet on a socket that polled readable, and up to a batch of those that
+    // can be received after it without polling again, sending the replies to them together
+    pub fn receive(
+        &mut self,
+        name: &str,
+        protocol: Protocol,
+        rbuf: &mut [u8],
+    ) -> Result<(), Error> {
+        if let Some(socket) = self.socket(name, protocol) {
+            let _ = socket.defer_sends(true);
+        }
+        let mut received = Ok(());
+        for _ in 0..RECEIVE_BATCH {
+            received = match protocol {
+                Protocol::Arp => self.receive_arp(name, rbuf),
+                Protocol::Ndp => self.receive_ndp(name, rbuf),
+                Protocol::Rarp => self.receive_rarp(name, rbuf),
+            };
+            if received.is_err() || !self.socket(name, protocol).is_some_and(PacketIo::pending) {
+                break;
+            }
+        }
+        // Replies to the packets received before a failure are still sent
+        if let Some(socket) = self.socket(name, protocol) {
+            if let Err(err) = socket.defer_sends(false) {
+                log::error!("failed to send replies on {}: {}", name, err);
+            }
+        }
+        received
+    }
+
+    fn socket(&self, name: &str, protocol: Protocol) -> Option<&S> {
+        let iface = self.interfaces.get(name)?;
+        match protocol {
+            Protocol::Arp => Some(&iface.arp),
+            Protocol::Ndp => iface.ndp.as_ref(),
+            Protocol::Rarp => iface.rarp.as_ref(),
+        }
+    }
+
+    // Send the probes, announcements and replies that are due
+    pub fn tick(&mut self) {
+        self.probe();
+        self.sponge();
+        self.announce();
+        self.keepalive();
+        self.reply();
+        self.answer_in_kernel();
+    }
+
+    // Update the addresses answered in the kernel once probing, yielding or silencing
+    // changed which addresses are answered, and count the replies it sent
+    fn answer_in_kernel(&mut self) {
+        if !self.kernel_answers {
+            return;
+        }
+        for iface in self.interfaces.values() {
+            let replies = iface.arp.kernel_replies();
+            self.replies += replies;
+            self.kernel_replies += replies;
+        }
+        let state = (
+            self.is_silenced(),
+            self.probes.len(),
+            self.yielded.len(),
+            self.handed_over.len(),
+        );
+        if self.kernel_answered == Some(state) {
+            return;
+        }
+        self.kernel_answered = Some(state);
+        let answering = !self.is_silenced() && self.limiter.limit().is_none();
+        for iface in self.interfaces.values() {
+            let mut answers = BTreeMap::new();
+            let hosts = self
+                .claims
+                .iter()
+                .zip(&self.claimed)
+                .filter(|(claim, _)| answering && claim.iface == iface.name)
+                .flat_map(|(claim, claimed)| claim.hosts(claimed));
+            for ip in hosts {
+                let v4 = match ip {
+                    IpAddr::V4(v4) if !answers.contains_key(&v4) => v4,
+                    _ => continue,
+                };
+                let claim = claiming(&self.claims, &self.claimed, &iface.name, ip).next();
+                match claim {
+                    Some(claim)
+                        if answerable_in_kernel(claim)
+                            && !self.is_suspended(&iface.name, ip)
+                            && !self.is_handed_over(&iface.name, ip) =>
+                    {
+                        let mac = claim.mac_for(ip, iface.mac);
+                        let answer = KernelAnswer {
+                            mac,
+                            src: claim.frame_src(mac, iface.mac),
+                            broadcast: claim.broadcast_reply,
+                        };
+                        answers.insert(v4, answer);
+                    }
+                    _ => {}
+                }
+            }
+            match iface.arp.set_arp_answers(&answers) {
+                Ok(()) => {}
+                Err(sys::Error::EOPNOTSUPP) if answers.is_empty() => {}
+                Err(err) => log::warn!(
+                    "failed to answer arp in the kernel on {}: {}",
+                    iface.name,
+                    err
+                ),
+            }
+        }
+    }
+
+    // Poll the sockets for up to the timeout (or the deadline when earlier), handling
+    // received packets and sending whatever is due, for embedding without an event loop
+    pub fn step(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        let deadline = self.deadline().into_iter();
+        let deadline = deadline
+            .chain(timeout.map(|timeout| Instant::now() + timeout))
+            .min();
+        let timeout = match deadline {
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis()
+                .try_into()
+                .unwrap_or(i32::MAX),
+            None => -1,
+        };
+        let sockets = self.sockets();
+        let mut fds: Vec<PollFd> = sockets
+            .iter()
+            .map(|(_, _, fd)| PollFd::new(*fd, PollFlags::POLLIN))
+            .collect();
+        match S::poll(&mut fds, timeout) {
+            Ok(_) | Err(sys::Error::EINTR) => {}
+            Err(err) => return Err(Error::Poll(err)),
+        }
+        let mut rbuf = [0u8; 500];
+        for ((name, protocol, _), fd) in sockets.iter().zip(&fds) {
+            if fd.revents().is_some_and(|ev| !ev.is_empty()) {
+                self.receive(name, *protocol, &mut rbuf)?;
+            }
+        }
+        self.tick();
+        Ok(())
+    }
+
+    pub fn is_paused(&self, reason: &str) -> bool {
+        self.paused.contains(reason)
+    }
+
+    // Number of ARP and RARP replies and neighbor advertisements sent in reply to requests
+    pub fn replies(&self) -> u64 {
+        self.replies
+    }
+
+    // Forget looked up routes once they changed
+    pub fn routes_changed(&mut self) {
+        self.routes.clear();
+    }
+
+    // The MAC address a claimed host is answered with, if it is claimed
+    fn claimed_mac(&self, name: &str, ip: IpAddr) -> Option<MacAddress> {
+        let iface = self.interfaces.get(name)?;
+        host_claim(&self.claims, &self.claimed, name, ip).map(|claim| claim.mac_for(ip, iface.mac))
+    }
+
+    // Switch to a new set of claims, opening and closing interfaces as needed
+    pub fn apply(&mut self, mut claims: Vec<Claim>) -> Result<(), Error> {
+        // Interfaces that were recreated have a new index and need a new socket
+        let mut opened = BTreeMap::new();
+        let mut failed = BTreeMap::new();
+        for claim in &claims {
+            if opened.contains_key(&claim.iface) || failed.contains_key(&claim.iface) {
+                continue;
+            }
+            let current = self.interfaces.get(&claim.iface).map(|iface| iface.index);
+            let index = iface::lookup_link_addr(claim.link()).map(|addr| addr.ifindex);
+            if current.is_none() || current != index.ok() {
+                match Interface::open(
+                    &claim.iface,
+                    claim.link(),
+                    claim.vlan_tags(),
+                    self.raw,
+                    self.min_frame_len,
+                    self.capture.clone(),
+                    self.fanout.clone(),
+                ) {
+                    Ok(iface) => {
+                        opened.insert(claim.iface.clone(), iface);
+                    }
+                    Err(err) => {
+                        failed.insert(claim.iface.clone(), err);
+                    }
+                }
+            }
+        }
+
+        // Leave out the claims on interfaces that failed to open until interfaces change
+        // again, keeping the claims on the others, unless that leaves nothing to claim
+        if !failed.is_empty() {
+            claims.retain(|claim| !failed.contains_key(&claim.iface));
+            if claims.is_empty() {
+                if let Some((_, err)) = failed.into_iter().next() {
+                    return Err(err);
+                }
+            } else {
+                for (name, err) in &failed {
+                    log::error!("leaving out claims on {}: {}", name, err);
+                }
+            }
+        }
+
+        // Read the ethers files of claims answering Reverse ARP requests
+        let mut ethers = HashMap::new();
+        for path in claims.iter().filter_map(|claim| claim.rarp.as_ref()) {
+            if !ethers.contains_key(path) {
+                ethers.insert(path.clone(), rarp::Ethers::load(path)?);
+            }
+        }
+
+        // Workers leave logging the claims to their responder
+        let log = self.worker.is_none();
+        for claim in self
+            .claims
+            .iter()
+            .filter(|claim| log && !claims.contains(claim))
+        {
+            let iface = &self.interfaces[&claim.iface];
+            for ip in &claim.ip {
+                log::info!("Releasing IP {} on {}[{}]", ip, iface.name, iface.index);
+            }
+        }
+
+        let old_claims = std::mem::replace(&mut self.claims, claims);
+        let old_claimed = std::mem::replace(
+            &mut self.claimed,
+            self.claims.iter().map(Claim::claim_set).collect(),
+        );
+        let old_macs: HashMap<String, MacAddress> = self
+            .interfaces
+            .iter()
+            .map(|(name, iface)| (name.clone(), iface.mac))
+            .collect();
+        let old_mac = |name: &str, ip: IpAddr| {
+            let iface_mac = *old_macs.get(name)?;
+            host_claim(&old_claims, &old_claimed, name, ip)
+                .map(|claim| claim.mac_for(ip, iface_mac))
+        };
+        self.interfaces.extend(opened);
+        self.ethers = ethers;
+        let claims = &self.claims;
+        let claimed = &self.claimed;
+        self.interfaces
+            .retain(|name, _| claims.iter().any(|claim| &claim.iface == name));
+
+        // Only listen for neighbor solicitations on interfaces with claimed IPv6 addresses
+        for iface in self.interfaces.values_mut() {
+            iface
+                .set_busy_poll(self.busy_poll)
+                .map_err(|err| Error::socket(&iface.name, "busy poll", err))?;
+            match iface::interface_subnets(&iface.link) {
+                Ok(subnets) => iface.subnets = subnets,
+                Err(err) => log::warn!("failed to look up subnets of {}: {}", iface.name, err),
+            }
+
+            let addrs: Vec<Ipv6Addr> = claims
+                .iter()
+                .zip(claimed)
+                .filter(|(claim, _)| claim.iface == iface.name)
+                .flat_map(|(claim, claimed)| claim.hosts(claimed))
+                .filter_map(|ip| match ip {
+                    IpAddr::V6(ip) => Some(ip),
+                    IpAddr::V4(_) => None,
+                })
+                .collect();
+            let prefixes = claims.iter().any(|claim| {
+                claim.iface == iface.name
+                    && claim.ip.iter().any(
+                        |net| matches!(net, IpNet::V6(net) if net.prefix() < 128 || claim.proxy),
+                    )
+            });
+            iface.set_ndp_addrs(&addrs, prefixes)?;
+
+            // Only wake up for ARP about claimed IPv4 addresses on busy segments
+            let arp_addrs = ClaimSet::union(
+                claims
+                    .iter()
+                    .zip(claimed)
+                    .filter(|(claim, _)| claim.iface == iface.name)
+                    .map(|(_, claimed)| claimed),
+            );
+            iface.set_arp_addrs(&arp_addrs)?;
+
+            // Passive claims need to see replies other hosts send to requesters
+            let passive = claims
+                .iter()
+                .any(|claim| claim.iface == iface.name && claim.passive);
+            iface
+                .set_promisc(passive)
+                .map_err(|err| Error::socket(&iface.name, "promiscuous mode", err))?;
+
+            let rarp = claims
+                .iter()
+                .any(|claim| claim.iface == iface.name && claim.rarp.is_some());
+            iface.set_rarp(rarp)?;
+        }
+        for claim in self
+            .claims
+            .iter()
+            .filter(|claim| log && !old_claims.contains(claim))
+        {
+            let iface = &self.interfaces[&claim.iface];
+            let mac = claim.mac.unwrap_or(iface.mac);
+            for ip in &claim.ip {
+                log::info!(
+                    "Claiming IP {} on {}[{}] for {}",
+                    ip,
+                    iface.name,
+                    iface.index,
+                    mac
+                );
+            }
+            for (ip, mac) in &claim.macs {
+                log::info!(
+                    "Claiming IP {} on {}[{}] for {}",
+                    ip,
+                    iface.name,
+                    iface.index,
+                    mac
+                );
+            }
+            for ip in &claim.exclude {
+                log::info!("Excluding IP {} on {}[{}]", ip, iface.name, iface.index);
+            }
+        }
+
+        // Probe newly claimed IPv4 addresses for conflicts and announce hosts that are newly
+        // claimed or answered with a different MAC address, keeping the schedule of hosts
+        // that were announced before
+        let (claims, claimed) = (&self.claims, &self.claimed);
+        let is_host =
+            |name: &str, ip: Ipv4Addr| host_claim(claims, claimed, name, IpAddr::V4(ip)).is_some();
+        self.probes.retain(|(name, ip), _| is_host(name, *ip));
+        self.yielded.retain(|(name, ip)| is_host(name, *ip));
+        self.defended.retain(|(name, ip), _| is_host(name, *ip));
+        self.sponge.retain(|name, ip| {
+            claiming(claims, claimed, name, IpAddr::V4(ip)).any(|claim| claim.sponge)
+        });
+        if self.worker.is_some() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let handed_over = &self.handed_over;
+        let (mut announcements, mut scheduled): (HashMap<_, _>, HashMap<_, _>) =
+            std::mem::take(&mut self.announcements)
+                .into_iter()
+                .partition(|(key, _)| handed_over.contains(key));
+        for (claim, claimed) in self
+            .claims
+            .iter()
+            .zip(&self.claimed)
+            .filter(|(claim, _)| claim.announce || claim.probe.is_some())
+        {
+            let iface = &self.interfaces[&claim.iface];
+            for ip in claim.hosts(claimed) {
+                let key = (claim.iface.clone(), ip);
+                let mac = claim.mac_for(ip, iface.mac);
+                if self.claimed_mac(&claim.iface, ip) != Some(mac)
+                    || announcements.contains_key(&key)
+                {
+                    continue;
+                }
+                let old = old_mac(&claim.iface, ip);
+                let changed = old != Some(mac);
+                let src = claim.frame_src(mac, iface.mac);
+                match (claim.probe, ip) {
+                    (Some(policy), IpAddr::V4(ip)) if old.is_none() => {
+                        log::info!("Probing IP {} on {}[{}]", ip, iface.name, iface.index);
+                        let probe = Probe {
+                            mac,
+                            src: claim.frame_src(mac, iface.mac),
+                            policy,
+                            sent: 0,
+                            conflicts: 0,
+                            hold: Duration::from_secs(claim.takeover_hold),
+                            last_conflict: None,
+                            next: Some(now + jitter(PROBE_WAIT)),
+                        };
+                        self.probes.insert((claim.iface.clone(), ip), probe);
+                        continue;
+                    }
+                    (_, IpAddr::V4(ip)) if self.probes.contains_key(&(claim.iface.clone(), ip)) => {
+                        continue
+                    }
+                    _ if !claim.announce => continue,
+                    _ => {}
+                }
+                let announcement = match scheduled.remove(&key) {
+                    Some(announcement) if !changed => announcement,
+                    _ if changed => Announcement::new(claim, mac, src, now),
+                    _ => match claim.announce_interval {
+                        Some(interval) => Announcement {
+                            remaining: 0,
+                            next: now
+                                + Duration::from_secs(interval)
+                                + jitter(Duration::from_secs(claim.announce_jitter)),
+                            ..Announcement::new(claim, mac, src, now)
+                        },
+                        None => continue,
+                    },
+                };
+                announcements.insert(key, announcement);
+            }
+        }
+        self.announcements = announcements;
+
+        // Keep requesting gateways on the schedule of hosts that kept them before
+        let mut keepalives = HashMap::new();
+        for (claim, claimed) in self
+            .claims
+            .iter()
+            .zip(&self.claimed)
+            .filter(|(claim, _)| !claim.keepalive.is_empty())
+        {
+            let iface = &self.interfaces[&claim.iface];
+            for ip in claim.hosts(claimed) {
+                let mac = claim.mac_for(ip, iface.mac);
+                if self.claimed_mac(&claim.iface, ip) != Some(mac) {
+                    continue;
+                }
+                let key = match ip {
+                    IpAddr::V4(ip) => (claim.iface.clone(), ip),
+                    IpAddr::V6(_) => continue,
+                };
+                let interval = Duration::from_secs(claim.keepalive_interval);
+                let next = self
+                    .keepalives
+                    .get(&key)
+                    .map_or(now + interval, |keepalive| keepalive.next);
+                keepalives.entry(key).or_insert(Keepalive {
+                    mac,
+                    src: claim.frame_src(mac, iface.mac),
+                    gateways: claim.keepalive.clone(),
+                    interval,
+                    next,
+                });
+            }
+        }
+        self.keepalives = keepalives;
+        self.kernel_answered = None;
+        self.answer_in_kernel();
+        Ok(())
+    }
+
+    // Announce claimed addresses for another MAC address, such as the one of their original
+    // owner or of a successor, repeated as configured for the claims and even while paused,
+    // no longer answering for them until resuming
+    pub fn hand_over(&mut self, action: &str, target: impl Fn(&Claim) -> Option<MacAddress>) {
+        let now = Instant::now();
+        let mut announcements = Vec::new();
+        for (claim, claimed) in self.claims.iter().zip(&self.claimed) {
+            let (target, iface) = match (target(claim), self.interfaces.get(&claim.iface)) {
+                (Some(target), Some(iface)) => (target, iface),
+                _ => continue,
+            };
+            for ip in claim.hosts(claimed) {
+                let mac = claim.mac_for(ip, iface.mac);
+                if self.claimed_mac(&claim.iface, ip) != Some(mac)
+                    || self.is_suspended(&claim.iface, ip)
+                {
+                    continue;
+                }
+                log::info!("{} IP {} on {} to {}", action, ip, iface.name, target);
+                // Frames are sent from the interface's MAC address, so switches don't
+                // learn the target's on this port
+                let announcement = Announcement {
+                    interval: None,
+                    ..Announcement::new(claim, target, iface.mac, now)
+                };
+                announcements.push(((claim.iface.clone(), ip), announcement));
+            }
+        }
+        for (key, announcement) in announcements {
+            self.handed_over.insert(key.clone());
+            self.announcements.insert(key, announcement);
+        }
+        self.answer_in_kernel();
+    }
+
+    // Whether announcements of hand_over are still to be sent
+    pub fn is_handing_over(&self) -> bool {
+        self.announcements
+            .keys()
+            .any(|key| self.handed_over.contains(key))
+    }
+
+    fn is_handed_over(&self, name: &str, ip: IpAddr) -> bool {
+        !self.handed_over.is_empty() && self.handed_over.contains(&(name.to_owned(), ip))
+    }
+
+    // When probing or announcing needs to continue
+    pub fn deadline(&self) -> Option<Instant> {
+        let probes = self.probes.values().filter_map(|probe| probe.next);
+        let announcements = self
+            .announcements
+            .iter()
+            .filter(|(key, _)| !self.is_silenced() || self.handed_over.contains(*key))
+            .map(|(_, a)| a.next);
+        let keepalives = self
+            .keepalives
+            .values()
+            .map(|k| k.next)
+            .filter(|_| !self.is_silenced());
+        let grace = Some(self.grace_until).filter(|_| self.in_grace());
+        let pending = self.pending.iter().map(|reply| reply.at);
+        probes
+            .chain(announcements)
+            .chain(keepalives)
+            .chain(grace)
+            .chain(pending)
+            .chain(self.sponge.deadline())
+            .min()
+    }
+
+    // Send held back replies that are due
+    fn reply(&mut self) {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|reply| reply.at <= now);
+        self.pending = pending;
+        for reply in due {
+            let socket = match self.interfaces.get(&reply.iface) {
+                Some(iface) if reply.ndp => iface.ndp.as_ref(),
+                Some(iface) => Some(&iface.arp),
+                None => None,
+            };
+            if let Some(socket) = socket {
+                log::debug!("sending delayed reply on {}", reply.iface);
+                match socket.send_from(&reply.frame, reply.src, &reply.to) {
+                    Ok(_) => self.replies += 1,
+                    Err(err) => log::error!("{}", Error::send(&reply.iface, "delayed reply", err)),
+                }
+            }
+        }
+    }
+
+    fn in_grace(&self) -> bool {
+        Instant::now() < self.grace_until
+    }
+
+    // Requests are neither answered nor announced during the grace period or while paused
+    fn is_silenced(&self) -> bool {
+        self.in_grace() || !self.paused.is_empty()
+    }
+
+    pub fn set_paused(&mut self, reason: &'static str, paused: bool) {
+        let was_paused = !self.paused.is_empty();
+        if paused {
+            self.paused.insert(reason);
+        } else {
+            self.paused.remove(reason);
+        }
+        match (was_paused, !self.paused.is_empty()) {
+            (false, true) if self.worker.is_some() => self.pending.clear(),
+            (false, true) => {
+                log::info!("Pausing due to {}", reason);
+                self.pending.clear();
+                if self.restore_on_pause {
+                    self.hand_over("Restoring", |claim| claim.restore_mac);
+                }
+            }
+            (true, false) if self.worker.is_none() => {
+                log::info!("Resuming due to {}", reason);
+                let handed_over = std::mem::take(&mut self.handed_over);
+                self.announcements
+                    .retain(|key, _| !handed_over.contains(key));
+                self.reannounce();
+            }
+            _ => {}
+        }
+        self.answer_in_kernel();
+    }
+
+    // Announce all claimed addresses again, e.g. after having been paused
+    fn reannounce(&mut self) {
+        let now = Instant::now();
+        for (claim, claimed) in self
+            .claims
+            .iter()
+            .zip(&self.claimed)
+            .filter(|(claim, _)| claim.announce)
+        {
+            let iface = match self.interfaces.get(&claim.iface) {
+                Some(iface) => iface,
+                None => continue,
+            };
+            for ip in claim.hosts(claimed) {
+                let key = (claim.iface.clone(), ip);
+                let mac = claim.mac_for(ip, iface.mac);
+                if self.claimed_mac(&claim.iface, ip) == Some(mac)
+                    && !self.is_suspended(&claim.iface, ip)
+                {
+                    let src = claim.frame_src(mac, iface.mac);
+                    self.announcements
+                        .insert(key, Announcement::new(claim, mac, src, now));
+                }
+            }
+        }
+    }
+
+    // Re-announce claimed addresses whose interval has passed, only handing over addresses
+    // while silenced
+    fn announce(&mut self) {
+        let now = Instant::now();
+        let silenced = self.is_silenced();
+        let due: Vec<(String, IpAddr)> = self
+            .announcements
+            .iter()
+            .filter(|(key, announcement)| {
+                announcement.next <= now && (!silenced || self.handed_over.contains(*key))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for (name, ip) in due {
+            let suspended = self.is_suspended(&name, ip);
+            let announcement = match self.announcements.get_mut(&(name.clone(), ip)) {
+                Some(announcement) => announcement,
+                None => continue,
+            };
+            if let (Some(iface), false) = (self.interfaces.get(&name), suspended) {
+                if let Err(err) = send_announcement(iface, ip, announcement, &mut self.wbuf) {
+                    log::error!("{}", err);
+                }
+            }
+            announcement.remaining = announcement.remaining.saturating_sub(1);
+            if announcement.remaining > 0 {
+                announcement.next = now + announcement.spacing;
+            } else if let Some(interval) = announcement.interval {
+                announcement.next = now + interval + jitter(announcement.jitter);
+            } else {
+                self.announcements.remove(&(name, ip));
+            }
+        }
+    }
+
+    // Send due probes and claim addresses that were probed without conflicts
+    fn probe(&mut self) {
+        let now = Instant::now();
+        let mut done = Vec::new();
+        for ((name, ip), probe) in self.probes.iter_mut() {
+            if probe.next.is_none_or(|next| next > now) {
+                continue;
+            }
+            let iface = match self.interfaces.get(name) {
+                Some(iface) => iface,
+                None => continue,
+            };
+            let holding = probe
+                .last_conflict
+                .is_some_and(|at| now.duration_since(at) < probe.hold);
+            if probe.sent < PROBE_NUM || holding {
+                if let Err(err) = send_probe(iface, probe.mac, probe.src, *ip, &mut self.wbuf) {
+                    log::error!("{}", err);
+                }
+                probe.sent += 1;
+                probe.next = Some(
+                    now + if probe.sent < PROBE_NUM || holding {
+                        PROBE_MIN + jitter(PROBE_MAX - PROBE_MIN)
+                    } else {
+                        ANNOUNCE_WAIT
+                    },
+                );
+            } else {
+                log::info!(
+                    "Claiming probed IP {} on {}[{}]",
+                    ip,
+                    iface.name,
+                    iface.index
+                );
+                done.push((name.clone(), *ip));
+            }
+        }
+        for (name, ip) in done {
+            if let Some(probe) = self.probes.remove(&(name.clone(), ip)) {
+                let claim = claiming(&self.claims, &self.claimed, &name, IpAddr::V4(ip)).next();
+                if let Some(claim) = claim.filter(|claim| claim.announce) {
+                    self.announcements.insert(
+                        (name, IpAddr::V4(ip)),
+                        Announcement::new(claim, probe.mac, probe.src, now),
+                    );
+                }
+            }
+        }
+    }
+
+    // Block until none of the claimed IPv4 addresses were used by other hosts for a round of
+    // probes, returning false when they are still in use by the deadline or when interrupted
+    pub fn wait_free(
+        &mut self,
+        deadline: Option<Instant>,
+        interrupted: impl Fn() -> bool,
+    ) -> Result<bool, Error> {
+        // The claimed IPv4 hosts with the MAC addresses they are answered with and frames
+        // are sent from
+        let mut hosts = Vec::new();
+        for (claim, claimed) in self.claims.iter().zip(&self.claimed) {
+            let iface = &self.interfaces[&claim.iface];
+            for ip in claim.hosts(claimed) {
+                let first = host_claim(&self.claims, &self.claimed, &claim.iface, ip)
+                    .is_some_and(|first| std::ptr::eq(first, claim));
+                if let (IpAddr::V4(ip), true) = (ip, first) {
+                    let mac = claim.mac_for(ip.into(), iface.mac);
+                    let src = claim.frame_src(mac, iface.mac);
+                    hosts.push((claim.iface.clone(), ip, mac, src));
+                }
+            }
+        }
+        let mut in_use = HashSet::new();
+        let mut rbuf = [0u8; 500];
+        let mut free = 0;
+        while free < PROBE_NUM {
+            if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                return Ok(false);
+            }
+            for (name, ip, mac, src) in &hosts {
+                if let Err(err) =
+                    send_probe(&self.interfaces[name], *mac, *src, *ip, &mut self.wbuf)
+                {
+                    log::error!("{}", err);
+                }
+            }
+            free += 1;
+
+            // Listen for other hosts using the addresses until the next round
+            let until = Instant::now() + PROBE_MIN;
+            loop {
+                let now = Instant::now();
+                if until <= now {
+                    break;
+                }
+                if interrupted() {
+                    return Ok(false);
+                }
+                let timeout = (until - now).as_millis().try_into().unwrap_or(i32::MAX);
+                let ifaces: Vec<&Interface<S>> = self.interfaces.values().collect();
+                let mut fds: Vec<PollFd> = ifaces
+                    .iter()
+                    .map(|iface| PollFd::new(iface.arp.as_raw_fd(), PollFlags::POLLIN))
+                    .collect();
+                match S::poll(&mut fds, timeout) {
+                    Ok(_) | Err(sys::Error::EINTR) => {}
+                    Err(err) => return Err(Error::Poll(err)),
+                }
+                for (iface, fd) in ifaces.iter().zip(&fds) {
+                    if fd.revents().is_none_or(|ev| ev.is_empty()) {
+                        continue;
+                    }
+                    let pkt = match receive(iface, &iface.arp, &mut rbuf)? {
+                        Some((size, ..)) => arp::Arp::try_from(&rbuf[..size]),
+                        None => continue,
+                    };
+                    if let Some(pkt) = pkt.ok().filter(|pkt| pkt.is_ethernet_ipv4()) {
+                        let ip = if pkt.spa.is_unspecified() {
+                            pkt.tpa
+                        } else {
+                            pkt.spa
+                        };
+                        let mac = self.claimed_mac(&iface.name, IpAddr::V4(ip));
+                        if mac.is_some_and(|mac| mac != pkt.sha) {
+                            if in_use.insert((iface.name.clone(), ip)) {
+                                log::info!(
+                                    "IP {} on {} is in use by {}, waiting for it to be free",
+                                    ip,
+                                    iface.name,
+                                    pkt.sha
+                                );
+                            }
+                            free = 0;
+                        }
+                    }
+                }
+            }
+        }
+        for (name, ip) in in_use {
+            log::info!("IP {} on {} is free", ip, name);
+        }
+        Ok(true)
+    }
+
+    // Request the gateways of claimed addresses that are due
+    fn keepalive(&mut self) {
+        if self.is_silenced() {
+            return;
+        }
+        let now = Instant::now();
+        for ((name, ip), keepalive) in self.keepalives.iter_mut() {
+            if keepalive.next > now {
+                continue;
+            }
+            keepalive.next = now + keepalive.interval;
+            let key = (name.clone(), *ip);
+            if self.probes.contains_key(&key) || self.yielded.contains(&key) {
+                continue;
+            }
+            if let Some(iface) = self.interfaces.get(name) {
+                for gateway in &keepalive.gateways {
+                    if let Err(err) = send_keepalive(
+                        iface,
+                        keepalive.mac,
+                        keepalive.src,
+                        *ip,
+                        *gateway,
+                        &mut self.wbuf,
+                    ) {
+                        log::error!("{}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    // Probe for addresses nobody seems to answer for and sponge those that remain silent
+    fn sponge(&mut self) {
+        let now = Instant::now();
+        let due = self.sponge.due(now);
+        for (name, ip) in due.probes {
+            if let Some(iface) = self.interfaces.get(&name) {
+                if let Err(err) = send_probe(iface, iface.mac, iface.mac, ip, &mut self.wbuf) {
+                    log::error!("{}", err);
+                }
+            }
+        }
+        for (name, ip) in due.released {
+            if let Some(iface) = self.interfaces.get(&name) {
+                log::info!(
+                    "Releasing sponged IP {} on {}[{}], no longer requested",
+                    ip,
+                    iface.name,
+                    iface.index
+                );
+            }
+            self.announcements.remove(&(name, IpAddr::V4(ip)));
+        }
+        for (name, ip) in due.dead {
+            let (iface, claim) = match (
+                self.interfaces.get(&name),
+                claiming(&self.claims, &self.claimed, &name, IpAddr::V4(ip)).next(),
+            ) {
+                (Some(iface), Some(claim)) => (iface, claim),
+                _ => continue,
+            };
+            let mac = claim.mac_for(IpAddr::V4(ip), iface.mac);
+            log::info!(
+                "Sponging IP {} on {}[{}] for {}",
+                ip,
+                iface.name,
+                iface.index,
+                mac
+            );
+            if claim.announce {
+                let src = claim.frame_src(mac, iface.mac);
+                self.announcements.insert(
+                    (name, IpAddr::V4(ip)),
+                    Announcement::new(claim, mac, src, now),
+                );
+            }
+        }
+    }
+
+    pub fn log_stats(&self) {
+        let mut stats = format!("{} replies", self.replies);
+        if self.kernel_answers {
+            stats += &format!(" ({} by the kernel)", self.kernel_replies);
+        }
+        for (anomaly, count) in self.anomalies.counts() {
+            stats += &format!(", {} {}", count, anomaly);
+        }
+        match self.worker {
+            Some(id) => log::info!("Stats of worker {}: {}", id, stats),
+            None => log::info!("Stats: {}", stats),
+        }
+    }
+
+    // Whether answering for a claimed address has to wait for probing or was given up
+    fn is_suspended(&self, name: &str, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => {
+                let key = (name.to_owned(), ip);
+                self.probes.contains_key(&key) || self.yielded.contains(&key)
+            }
+            IpAddr::V6(_) => false,
+        }
+    }
+
+    fn receive_arp(&mut self, name: &str, rbuf: &mut [u8]) -> Result<(), Error> {
+        // The interface may have been closed since polling
+        let iface = match self.interfaces.get(name) {
+            Some(iface) => iface,
+            None => return Ok(()),
+        };
+
+        // Receive an ARP packet
+        let (size, from, broadcast) = match receive(iface, &iface.arp, rbuf)? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let pkt = &rbuf[0..size];
+        let from_mac = from.mac;
+
+        // Try to decode the ARP packet, classifying other link or protocol types as unusual
+        let pkt = match arp::Arp::try_from(pkt) {
+            Ok(pkt) if !pkt.is_ethernet_ipv4() => {
+                report_anomaly(
+                    &mut self.anomalies,
+                    anomaly::Anomaly::UnusualType,
+                    &iface.name,
+                    from_mac,
+                    &pkt,
+                );
+                return Ok(());
+            }
+            pkt => pkt,
+        };
+
+        // Spoofed or malformed senders
+        if let Ok(pkt) = &pkt {
+            if pkt.sha.is_multicast() {
+                report_anomaly(
+                    &mut self.anomalies,
+                    anomaly::Anomaly::MulticastSender,
+                    &iface.name,
+                    from_mac,
+                    pkt,
+                );
+            }
+            if pkt.sha != from_mac {
+                report_anomaly(
+                    &mut self.anomalies,
+                    anomaly::Anomaly::SenderMismatch,
+                    &iface.name,
+                    from_mac,
+                    pkt,
+                );
+            }
+        }
+
+        // Another host using, or probing for, an address that is being probed
+        if let Ok(pkt) = &pkt {
+            let ip = if pkt.spa.is_unspecified() {
+                pkt.tpa
+            } else {
+                pkt.spa
+            };
+            if let Some(probe) = self.probes.get_mut(&(iface.name.clone(), ip)) {
+                if probe.next.is_some() && pkt.sha != probe.mac {
+                    probe_conflict(probe, &iface.name, ip, pkt.sha, self.exit_on_conflict)?;
+                }
+            }
+        }
+
+        // A host returning to, or probing for, a sponged address takes it back
+        if let Ok(pkt) = &pkt {
+            let ip = if pkt.spa.is_unspecified() {
+                pkt.tpa
+            } else {
+                pkt.spa
+            };
+            let claim = claiming(&self.claims, &self.claimed, &iface.name, IpAddr::V4(ip))
+                .find(|claim| claim.sponge);
+            if let Some(claim) = claim {
+                if pkt.sha != claim.mac_for(IpAddr::V4(ip), iface.mac)
+                    && self.sponge.alive(&iface.name, ip)
+                {
+                    log::info!(
+                        "Releasing sponged IP {} on {}[{}] to {}",
+                        ip,
+                        iface.name,
+                        iface.index,
+                        pkt.sha
+                    );
+                    self.announcements
+                        .remove(&(iface.name.clone(), IpAddr::V4(ip)));
+                    // Hosts that didn't see the returning host's packet still use our MAC
+                    if !pkt.spa.is_unspecified() && !broadcast {
+                        if let Err(err) = send_garp(
+                            iface,
+                            pkt.sha,
+                            iface.mac,
+                            ip,
+                            claim.announce_op,
+                            MacAddress::broadcast(),
+                            &mut self.wbuf,
+                        ) {
+                            log::error!("{}", err);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Another host using a claimed address, which is expected for passive, proxy and
+        // sponge claims
+        if let Ok(pkt) = &pkt {
+            let spa = IpAddr::V4(pkt.spa);
+            let claim = claiming(&self.claims, &self.claimed, &iface.name, spa).next();
+            if let Some(claim) = claim {
+                let mac = claim.mac_for(spa, iface.mac);
+                let key = (iface.name.clone(), pkt.spa);
+                if pkt.sha != mac
+                    && !claim.passive
+                    && !claim.proxy
+                    && !claim.sponge
+                    && !self.probes.contains_key(&key)
+                    && !self.yielded.contains(&key)
+                {
+                    if pkt.spa == pkt.tpa {
+                        report_anomaly(
+                            &mut self.anomalies,
+                            anomaly::Anomaly::ForeignGratuitous,
+                            &iface.name,
+                            from_mac,
+                            pkt,
+                        );
+                    }
+                    let policy = if self.exit_on_conflict {
+                        Some(ConflictPolicy::Exit)
+                    } else {
+                        claim.on_conflict
+                    };
+                    match policy {
+                        None => report_competitor(
+                            self.monitor
+                                .observe(&iface.name, spa, pkt.sha, Instant::now()),
+                            &iface.name,
+                            spa,
+                            pkt.sha,
+                        ),
+                        // Defend at most once per interval, so two defending hosts don't
+                        // keep announcing the address to each other
+                        Some(ConflictPolicy::Defend) => {
+                            let now = Instant::now();
+                            let recently = self
+                                .defended
+                                .get(&key)
+                                .is_some_and(|at| now.duration_since(*at) < self.defend_interval);
+                            if recently {
+                                log::warn!(
+                                    "IP {} on {} is also used by {}, recently defended",
+                                    pkt.spa,
+                                    iface.name,
+                                    pkt.sha
+                                );
+                            } else {
+                                log::warn!(
+                                    "IP {} on {} is also used by {}, defending",
+                                    pkt.spa,
+                                    iface.name,
+                                    pkt.sha
+                                );
+                                if let Err(err) = send_garp(
+                                    iface,
+                                    mac,
+                                    claim.frame_src(mac, iface.mac),
+                                    pkt.spa,
+                                    claim.announce_op,
+                                    MacAddress::broadcast(),
+                                    &mut self.wbuf,
+                                ) {
+                                    log::error!("{}", err);
+                                }
+                                self.defended.insert(key, now);
+                            }
+                        }
+                        Some(ConflictPolicy::Yield) => {
+                            log::warn!(
+                                "IP {} on {} is also used by {}, yielding",
+                                pkt.spa,
+                                iface.name,
+                                pkt.sha
+                            );
+                            self.yielded.insert(key);
+                        }
+                        Some(ConflictPolicy::Exit) => {
+                            return Err(Error::Conflict {
+                                iface: iface.name.clone(),
+                                ip: spa,
+                                mac: pkt.sha,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        match pkt {
+            // Process ARP requests
+            Ok(req) if req.op == arp::ArpOp::Request => {
+                log::trace!("received arp {}", req);
+
+                // Reply to ARP requests for the claimed IP addresses
+                let tpa = IpAddr::V4(req.tpa);
+                let on_link = req.spa.is_unspecified()
+                    || iface.subnets.iter().any(|net| net.contains(req.spa));
+                if self.is_suspended(&iface.name, tpa)
+                    || self.is_handed_over(&iface.name, tpa)
+                    || self.is_silenced()
+                {
+                    return Ok(());
+                }
+                // Like the kernel, proxies (and sponges) leave address probes to the hosts
+                // themselves
+                if let Some(claim) =
+                    claiming(&self.claims, &self.claimed, &iface.name, tpa).find(|claim| {
+                        claim.answers(req.sha, IpAddr::V4(req.spa))
+                            && (!claim.on_link || on_link)
+                            && claim.delivery.allows(broadcast)
+                            && !((claim.proxy || claim.sponge) && req.spa.is_unspecified())
+                    })
+                {
+                    if claim.proxy_routes && !routed_elsewhere(&mut self.routes, tpa, iface.index) {
+                        return Ok(());
+                    }
+                    // Another host is about to configure a claimed address
+                    if req.spa.is_unspecified() {
+                        match claim.on_probe {
+                            ArpProbePolicy::Defend => log::warn!(
+                                "defending {} against arp probe from {} on {}",
+                                tpa,
+                                req.sha,
+                                iface.name
+                            ),
+                            ArpProbePolicy::Ignore => {
+                                log::debug!(
+                                    "ignoring arp probe for {} from {} on {}",
+                                    tpa,
+                                    req.sha,
+                                    iface.name
+                                );
+                                return Ok(());
+                            }
+                            ArpProbePolicy::Alert => {
+                                log::warn!(
+                                    "arp probe for {} from {} on {}",
+                                    tpa,
+                                    req.sha,
+                                    iface.name
+                                );
+                                return Ok(());
+                            }
+                        }
+                    }
+
+                    // Requests also keep sponged addresses from ageing
+                    if claim.sponge {
+                        let settings = sponge_settings(claim);
+                        self.sponge
+                            .query(&iface.name, req.tpa, settings, Instant::now());
+                        if !self.sponge.is_dead(&iface.name, req.tpa) {
+                            return Ok(());
+                        }
+                    }
+                    if claim.wake {
+                        let mac = claim.mac_for(tpa, iface.mac);
+                        wake(&mut self.woken, iface, tpa, mac, &mut self.wbuf);
+                    }
+                    if !self.limiter.allow(req.sha, Instant::now()) {
+                        return Ok(());
+                    }
+                    let mac = claim.reply_mac(tpa, req.sha, iface.mac);
+                    let src = claim.frame_src(mac, iface.mac);
+                    let reply = req
+                        .reply(mac)
+                        .expect("requests can be replied to")
+                        .fill(&mut self.wbuf)
+                        .expect("ARP packets fit the write buffer");
+                    // Some devices only refresh their caches from broadcast replies
+                    let to = if claim.broadcast_reply {
+                        iface.arp.link_addr_to(MacAddress::broadcast())
+                    } else {
+                        from
+                    };
+                    let delay = reply_delay(claim);
+                    if delay > Duration::ZERO {
+                        log::debug!("delaying arp reply by {:?}", delay);
+                        self.pending.push(PendingReply {
+                            at: Instant::now() + delay,
+                            iface: iface.name.clone(),
+                            ndp: false,
+                            src,
+                            to,
+                            frame: reply.to_vec(),
+                            passive: claim.passive,
+                            ip: tpa,
+                            dst: IpAddr::V4(req.spa),
+                        });
+                        return Ok(());
+                    }
+                    log::debug!("sending arp reply");
+                    match iface.arp.send_from(reply, src, &to) {
+                        Ok(_) => self.replies += 1,
+                        Err(err) => log::error!("{}", Error::send(&iface.name, "arp reply", err)),
+                    }
+                }
+            }
+
+            // Replies by other hosts make passive claims hold back their own
+            Ok(reply) if reply.op == arp::ArpOp::Reply => {
+                let spa = IpAddr::V4(reply.spa);
+                let ours = self.claims.iter().any(|claim| {
+                    claim.iface == iface.name && claim.mac_for(spa, iface.mac) == reply.sha
+                });
+                if !ours {
+                    cancel_passive(&mut self.pending, &iface.name, spa, IpAddr::V4(reply.tpa));
+                }
+            }
+
+            // Ignore other ARP operations, like RARP and InARP
+            Ok(pkt) => {
+                log::trace!("ignoring arp {}", pkt);
+            }
+
+            // ARP for other link or protocol types isn't ours to answer
+            Err(arp::ArpError::UnsupportedAddressLength { .. }) => {
+                log::trace!("ignoring arp packet with unsupported address lengths");
+            }
+
+            // Report ARP packet decoding errors
+            Err(_) => {
+                log::warn!("failed to decode arp packet");
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_ndp(&mut self, name: &str, rbuf: &mut [u8]) -> Result<(), Error> {
+        // The interface or its neighbor discovery socket may have been closed since polling
+        let (iface, socket) = match self.interfaces.get(name) {
+            Some(iface @ Interface { ndp: Some(ndp), .. }) => (iface, ndp),
+            _ => return Ok(()),
+        };
+
+        // Receive a neighbor discovery packet
+        let (size, from, broadcast) = match receive(iface, socket, rbuf)? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let pkt = &rbuf[0..size];
+        let from_mac = from.mac;
+
+        // Try to decode the neighbor discovery packet
+        match ndp::Ndp::try_from(pkt) {
+            // Process neighbor solicitations
+            Ok(req) if req.op == ndp::NdpOp::Solicitation => {
+                log::trace!("received neighbor solicitation: {:x?}", req);
+
+                // Reply to solicitations for the claimed IP addresses
+                let target = IpAddr::V6(req.target);
+                if self.is_silenced() || self.is_handed_over(&iface.name, target) {
+                    return Ok(());
+                }
+                let requester = req.lladdr.unwrap_or(from_mac);
+                if let Some(claim) = claiming(&self.claims, &self.claimed, &iface.name, target)
+                    .find(|claim| {
+                        claim.answers(requester, IpAddr::V6(req.src))
+                            && claim.delivery.allows(broadcast)
+                    })
+                {
+                    if claim.proxy_routes
+                        && !routed_elsewhere(&mut self.routes, target, iface.index)
+                    {
+                        return Ok(());
+                    }
+
+                    // Another host is about to configure a claimed address
+                    if req.is_dad() {
+                        match claim.dad {
+                            DadPolicy::Defend => log::warn!(
+                                "defending {} against duplicate address detection from {} on {}",
+                                target,
+                                from_mac,
+                                iface.name
+                            ),
+                            DadPolicy::Alert => {
+                                log::warn!(
+                                    "duplicate address detection for {} from {} on {}",
+                                    target,
+                                    from_mac,
+                                    iface.name
+                                );
+                                return Ok(());
+                            }
+                        }
+                    }
+
+                    if claim.wake {
+                        let mac = claim.mac_for(target, iface.mac);
+                        wake(&mut self.woken, iface, target, mac, &mut self.wbuf);
+                    }
+                    if !self.limiter.allow(requester, Instant::now()) {
+                        return Ok(());
+                    }
+                    let mac = claim.reply_mac(target, requester, iface.mac);
+                    let src = claim.frame_src(mac, iface.mac);
+                    let reply = req
+                        .advertise(mac, claim.na_flags)
+                        .expect("solicitations can be advertised to");
+                    let dst = IpAddr::V6(reply.dst);
+                    let to = if reply.dst == ndp::ALL_NODES {
+                        socket.link_addr_to(ndp::multicast_mac(ndp::ALL_NODES))
+                    } else {
+                        socket.link_addr_to(requester)
+                    };
+                    let reply = reply
+                        .fill(&mut self.wbuf)
+                        .expect("NDP packets fit the write buffer");
+                    let delay = reply_delay(claim);
+                    if delay > Duration::ZERO {
+                        log::debug!("delaying neighbor advertisement by {:?}", delay);
+                        self.pending.push(PendingReply {
+                            at: Instant::now() + delay,
+                            iface: iface.name.clone(),
+                            ndp: true,
+                            src,
+                            to,
+                            frame: reply.to_vec(),
+                            passive: claim.passive,
+                            ip: target,
+                            dst,
+                        });
+                        return Ok(());
+                    }
+                    log::debug!("sending neighbor advertisement");
+                    match socket.send_from(reply, src, &to) {
+                        Ok(_) => self.replies += 1,
+                        Err(err) => log::error!(
+                            "{}",
+                            Error::send(&iface.name, "neighbor advertisement", err)
+                        ),
+                    }
+                }
+            }
+
+            // Advertisements by other hosts make passive claims hold back their own
+            Ok(adv) if adv.op == ndp::NdpOp::Advertisement => {
+                let target = IpAddr::V6(adv.target);
+                let mac = adv.lladdr.unwrap_or(from_mac);
+                let ours = self.claims.iter().any(|claim| {
+                    claim.iface == iface.name && claim.mac_for(target, iface.mac) == mac
+                });
+                if !ours {
+                    cancel_passive(&mut self.pending, &iface.name, target, IpAddr::V6(adv.dst));
+                    let competing = claiming(&self.claims, &self.claimed, &iface.name, target)
+                        .any(|claim| !claim.passive);
+                    if competing && self.exit_on_conflict {
+                        return Err(Error::Conflict {
+                            iface: iface.name.clone(),
+                            ip: target,
+                            mac,
+                        });
+                    }
+                    if competing {
+                        report_competitor(
+                            self.monitor
+                                .observe(&iface.name, target, mac, Instant::now()),
+                            &iface.name,
+                            target,
+                            mac,
+                        );
+                    }
+                }
+            }
+
+            // Ignore other neighbor discovery packets
+            Ok(_) => {}
+
+            // Report neighbor discovery packet decoding errors
+            Err(err) => {
+                log::warn!("failed to decode neighbor discovery packet: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_rarp(&mut self, name: &str, rbuf: &mut [u8]) -> Result<(), Error> {
+        // The interface or its Reverse ARP socket may have been closed since polling
+        let (iface, socket) = match self.interfaces.get(name) {
+            Some(
+                iface @ Interface {
+                    rarp: Some(rarp), ..
+                },
+            ) => (iface, rarp),
+            _ => return Ok(()),
+        };
+
+        // Receive a Reverse ARP packet, which has the same format as ARP
+        let (size, from, _) = match receive(iface, socket, rbuf)? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let req = match arp::Arp::try_from(&rbuf[0..size]) {
+            Ok(req) if req.op == arp::ArpOp::RarpRequest && req.is_ethernet_ipv4() => req,
+            Ok(pkt) => {
+                log::trace!("ignoring rarp packet: {}", pkt);
+                return Ok(());
+            }
+            Err(_) => {
+                log::warn!("failed to decode rarp packet");
+                return Ok(());
+            }
+        };
+        log::trace!("received {}", req);
+        if self.is_silenced() {
+            return Ok(());
+        }
+
+        // Reply from the first claim that knows the address of the target hardware address
+        let ethers = &self.ethers;
+        let found = self
+            .claims
+            .iter()
+            .zip(&self.claimed)
+            .find_map(|(claim, claimed)| {
+                if claim.iface != iface.name {
+                    return None;
+                }
+                let ip = ethers.get(claim.rarp.as_ref()?)?.get(req.tha)?;
+                Some((claim, claim.rarp_server(claimed)?, ip))
+            });
+        let (claim, server, ip) = match found {
+            Some(found) => found,
+            None => {
+                log::debug!(
+                    "no address for {} in rarp request on {}",
+                    req.tha,
+                    iface.name
+                );
+                return Ok(());
+            }
+        };
+        if self.is_suspended(&iface.name, IpAddr::V4(server))
+            || !self.limiter.allow(req.sha, Instant::now())
+        {
+            return Ok(());
+        }
+        let mac = claim.mac_for(IpAddr::V4(server), iface.mac);
+        let reply = arp::Arp {
+            htype: arp::HTYPE_ETHERNET,
+            ptype: arp::PTYPE_IPV4,
+            op: arp::ArpOp::RarpReply,
+            sha: mac,
+            spa: server,
+            tha: req.tha,
+            tpa: ip,
+        };
+        let reply = reply
+            .fill(&mut self.wbuf)
+            .expect("ARP packets fit the write buffer");
+        log::debug!("sending rarp reply for {} with {}", req.tha, ip);
+        match socket.send_from(reply, claim.frame_src(mac, iface.mac), &from) {
+            Ok(_) => self.replies += 1,
+            Err(err) => log::error!("{}", Error::send(&iface.name, "rarp reply", err)),
+        }
+        Ok(())
+    }
+}
+
+// Receive a packet, with its sender and whether it was sent to a broadcast or multicast
+// address, if there is one
+fn receive(
+    iface: &Interface<impl PacketIo>,
+    socket: &impl PacketIo,
+    rbuf: &mut [u8],
+) -> Result<Option<(usize, LinkAddr, bool)>, Error> {
+    let (size, from, header) = match socket.recv_from(rbuf) {
+        Ok(r) => r,
+        Err(sys::Error::EINTR) | Err(sys::Error::EAGAIN) => return Ok(None),
+        Err(sys::Error::ENETDOWN) => {
+            log::warn!("interface {} went down", iface.name);
+            return Ok(None);
+        }
+        Err(sys::Error::ENODEV) => {
+            log::error!("interface {} disappeared", iface.name);
+            return Ok(None);
+        }
+        Err(source) => {
+            return Err(Error::Receive {
+                iface: iface.name.clone(),
+                source,
+            })
+        }
+    };
+    log::trace!(
+        "received packet on {} from {}: {:x?}",
+        iface.name,
+        from.mac,
+        &rbuf[..size]
+    );
+
+    // Raw sockets show the frame's actual destination, instead of the kernel's
+    // classification of it
+    let broadcast = match header {
+        Some(header) => {
+            log::trace!("frame sent to {} from {}", header.dst, header.src);
+            header.dst.is_multicast()
+        }
+        None => matches!(
+            from.packet_type,
+            PacketType::Broadcast | PacketType::Multicast
+        ),
+    };
+    Ok(Some((size, from, broadcast)))
+}
+
+fn probe_conflict(
+    probe: &mut Probe,
+    name: &str,
+    ip: Ipv4Addr,
+    mac: MacAddress,
+    stop: bool,
+) -> Result<(), Error> {
+    let now = Instant::now();
+    match probe.policy {
+        ProbePolicy::Abort if stop => {
+            return Err(Error::InUse {
+                iface: name.to_owned(),
+                ip: IpAddr::V4(ip),
+                mac,
+            })
+        }
+        ProbePolicy::Abort => {
+            log::error!("IP {} on {} is in use by {}, not claiming", ip, name, mac);
+            probe.next = None;
+        }
+        ProbePolicy::Wait => {
+            log::warn!("IP {} on {} is in use by {}, probing again", ip, name, mac);
+            probe.conflicts += 1;
+            probe.sent = 0;
+            probe.next = Some(
+                now + if probe.conflicts >= MAX_CONFLICTS {
+                    RATE_LIMIT_INTERVAL
+                } else {
+                    jitter(PROBE_WAIT)
+                },
+            );
+        }
+        ProbePolicy::Takeover => {
+            if probe.last_conflict.is_none() {
+                log::warn!(
+                    "IP {} on {} is in use by {}, waiting for it to go away",
+                    ip,
+                    name,
+                    mac
+                );
+            }
+            probe.last_conflict = Some(now);
+        }
+        ProbePolicy::Force => {
+            log::warn!(
+                "IP {} on {} is in use by {}, claiming anyway",
+                ip,
+                name,
+                mac
+            );
+            probe.sent = PROBE_NUM;
+            probe.next = Some(now);
+        }
+    }
+    Ok(())
+}
+
+fn send_probe(
+    iface: &Interface<impl PacketIo>,
+    mac: MacAddress,
+    src: MacAddress,
+    ip: Ipv4Addr,
+    wbuf: &mut [u8],
+) -> Result<(), Error> {
+    let probe = arp::Arp::probe(mac, ip);
+    log::debug!("sending arp probe for {} on {}", ip, iface.name);
+    iface
+        .arp
+        .send_from(
+            probe.fill(wbuf).expect("ARP packets fit the write buffer"),
+            src,
+            &iface.arp.link_addr_to(MacAddress::broadcast()),
+        )
+        .map_err(|err| Error::send(&iface.name, "arp probe", err))?;
+    Ok(())
+}
+
+fn send_keepalive(
+    iface: &Interface<impl PacketIo>,
+    mac: MacAddress,
+    src: MacAddress,
+    ip: Ipv4Addr,
+    gateway: Ipv4Addr,
+    wbuf: &mut [u8],
+) -> Result<(), Error> {
+    let request = arp::Arp::request(mac, ip, gateway);
+    log::debug!(
+        "sending keepalive for {} to {} on {}",
+        ip,
+        gateway,
+        iface.name
+    );
+    iface
+        .arp
+        .send_from(
+            request
+                .fill(wbuf)
+                .expect("ARP packets fit the write buffer"),
+            src,
+            &iface.arp.link_addr_to(MacAddress::broadcast()),
+        )
+        .map_err(|err| Error::send(&iface.name, "keepalive", err))?;
+    Ok(())
+}
+
+fn send_garp(
+    iface: &Interface<impl PacketIo>,
+    mac: MacAddress,
+    src: MacAddress,
+    ip: Ipv4Addr,
+    op: AnnounceOp,
+    to: MacAddress,
+    wbuf: &mut [u8],
+) -> Result<(), Error> {
+    let announce = arp::Arp::announce(mac, ip);
+    // Replies are addressed to their receiver
+    let reply = arp::Arp {
+        tha: to,
+        ..arp::Arp::gratuitous_reply(mac, ip)
+    };
+    let garps = match op {
+        AnnounceOp::Request => vec![announce],
+        AnnounceOp::Reply => vec![reply],
+        AnnounceOp::Both => vec![announce, reply],
+    };
+    for garp in garps {
+        log::debug!(
+            "sending gratuitous arp {:?} for {} on {} to {}",
+            garp.op,
+            ip,
+            iface.name,
+            to
+        );
+        iface
+            .arp
+            .send_from(
+                garp.fill(wbuf).expect("ARP packets fit the write buffer"),
+                src,
+                &iface.arp.link_addr_to(to),
+            )
+            .map_err(|err| Error::send(&iface.name, "gratuitous arp", err))?;
+    }
+    Ok(())
+}
+
+// Ethernet protocol of Wake-on-LAN magic packets
+const ETH_P_WOL: u16 = 0x0842;
+
+// Magic packets are sent at most this often for each address
+const WAKE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Wake the sleeping host of an address with a Wake-on-LAN magic packet, so it is up by the
+// time the requester retransmits whatever it resolved the address for
+fn wake(
+    woken: &mut HashMap<(String, IpAddr), Instant>,
+    iface: &Interface<impl PacketIo>,
+    ip: IpAddr,
+    mac: MacAddress,
+    wbuf: &mut [u8],
+) {
+    let now = Instant::now();
+    let key = (iface.name.clone(), ip);
+    if woken
+        .get(&key)
+        .is_some_and(|at| now.duration_since(*at) < WAKE_INTERVAL)
+    {
+        return;
+    }
+    woken.retain(|_, at| now.duration_since(*at) < WAKE_INTERVAL);
+    woken.insert(key, now);
+
+    // Six bytes of all ones followed by sixteen repetitions of the MAC address
+    let packet = &mut wbuf[..102];
+    packet[..6].fill(0xff);
+    for chunk in packet[6..].chunks_mut(6) {
+        chunk.copy_from_slice(mac.as_bytes());
+    }
+    let mut to = iface.arp.link_addr_to(MacAddress::broadcast());
+    to.protocol = ETH_P_WOL;
+    log::info!("Waking {} for IP {} on {}", mac, ip, iface.name);
+    if let Err(err) = iface.arp.send_to(packet, &to) {
+        log::error!("{}", Error::send(&iface.name, "wake-on-lan packet", err));
+    }
+}
+
+fn send_announcement(
+    iface: &Interface<impl PacketIo>,
+    ip: IpAddr,
+    announcement: &Announcement,
+    wbuf: &mut [u8],
+) -> Result<(), Error> {
+    // Some routers ignore broadcast announcements, so they get a directed copy
+    let (mac, src) = (announcement.mac, announcement.src);
+    let bcast_mac = MacAddress::broadcast();
+    for to in std::iter::once(&bcast_mac).chain(&announcement.routers) {
+        match ip {
+            IpAddr::V4(ip) => send_garp(iface, mac, src, ip, announcement.op, *to, wbuf)?,
+            IpAddr::V6(ip) => {
+                send_unsolicited_na(iface, mac, src, ip, announcement.na_flags, *to, wbuf)?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn send_unsolicited_na(
+    iface: &Interface<impl PacketIo>,
+    mac: MacAddress,
+    src: MacAddress,
+    ip: Ipv6Addr,
+    flags: ndp::NaFlags,
+    to: MacAddress,
+    wbuf: &mut [u8],
+) -> Result<(), Error> {
+    let socket = match &iface.ndp {
+        Some(socket) => socket,
+        None => return Ok(()),
+    };
+    let na = ndp::Ndp {
+        op: ndp::NdpOp::Advertisement,
+        src: ip,
+        dst: ndp::ALL_NODES,
+        flags: flags.0 & !ndp::NA_SOLICITED,
+        target: ip,
+        lladdr: Some(mac),
+    };
+    log::debug!(
+        "sending unsolicited neighbor advertisement for {} on {}",
+        ip,
+        iface.name
+    );
+    socket
+        .send_from(
+            na.fill(wbuf).expect("NDP packets fit the write buffer"),
+            src,
+            &socket.link_addr_to(if to.is_broadcast() {
+                ndp::multicast_mac(ndp::ALL_NODES)
+            } else {
+                to
+            }),
+        )
+        .map_err(|err| Error::send(&iface.name, "unsolicited neighbor advertisement", err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::link::{BpfInsn, Membership};
+    use crate::sys::AsRawFd;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    // A frame with its protocol and the sender or destination
+    type Frame = (u16, Vec<u8>, MacAddress);
+
+    thread_local! {
+        // Frames queued for and sent by mock sockets, and how received frames were addressed
+        static RECEIVED: RefCell<VecDeque<(Frame, PacketType)>> = RefCell::default();
+        static SENT: RefCell<Vec<Frame>> = RefCell::default();
+    }
+
+    // Receives and sends through the queues above instead of the network
+    struct Mock {
+        link_addr: LinkAddr,
+    }
+
+    impl AsRawFd for Mock {
+        #[cfg(unix)]
+        fn as_raw_fd(&self) -> RawFd {
+            -1
+        }
+
+        #[cfg(windows)]
+        fn as_raw_fd(&self) -> RawFd {
+            std::ptr::null_mut()
+        }
+    }
+
+    impl PacketIo for Mock {
+        fn open(
+            link_addr: &LinkAddr,
+            protocol: u16,
+            _raw: bool,
+            _vlan: Option<crate::ether::Vlan>,
+            _min_len: usize,
+            _filter: &[BpfInsn],
+            _capture: Option<pcap::Capture>,
+        ) -> sys::Result<Self> {
+            let link_addr = LinkAddr {
+                protocol,
+                ..*link_addr
+            };
+            Ok(Self { link_addr })
+        }
+
+        fn link_addr(&self) -> LinkAddr {
+            self.link_addr
+        }
+
+        fn is_raw(&self) -> bool {
+            false
+        }
+
+        fn set_filter(&self, _filter: &[BpfInsn]) -> sys::Result<()> {
+            Ok(())
+        }
+
+        fn recv_from(
+            &self,
+            buf: &mut [u8],
+        ) -> sys::Result<(usize, LinkAddr, Option<crate::ether::Header>)> {
+            let protocol = self.link_addr.protocol;
+            let ((_, frame, from), packet_type) = RECEIVED.with(|received| {
+                let mut received = received.borrow_mut();
+                let n = received.iter().position(|((p, ..), _)| *p == protocol);
+                n.and_then(|n| received.remove(n)).ok_or(sys::Error::EAGAIN)
+            })?;
+            buf[..frame.len()].copy_from_slice(&frame);
+            let from = LinkAddr {
+                packet_type,
+                ..self.link_addr_to(from)
+            };
+            Ok((frame.len(), from, None))
+        }
+
+        fn send_from(&self, buf: &[u8], _src: MacAddress, to: &LinkAddr) -> sys::Result<usize> {
+            SENT.with(|sent| sent.borrow_mut().push((to.protocol, buf.to_vec(), to.mac)));
+            Ok(buf.len())
+        }
+
+        fn set_membership(&self, _membership: Membership, _join: bool) -> sys::Result<()> {
+            Ok(())
+        }
+
+        fn join_fanout(&self, id: Option<u16>) -> sys::Result<u16> {
+            Ok(id.unwrap_or(1))
+        }
+    }
+
+    const MAC: MacAddress = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    const OTHER: MacAddress = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x42]);
+    const REQUESTER: MacAddress = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x99]);
+
+    fn mock_responder(config: &str, defend_interval: Duration) -> Responder<Mock> {
+        let config: Config = config.parse().unwrap();
+        let mut responder = Responder::<Mock>::with_packet_io(
+            defend_interval,
+            Duration::ZERO,
+            None,
+            false,
+            false,
+            false,
+        );
+        responder.apply(config.claims).unwrap();
+        responder
+    }
+
+    // Have the responder receive an ARP packet, addressed like the packet type
+    fn receive_arp(
+        responder: &mut Responder<Mock>,
+        pkt: &arp::Arp,
+        from: MacAddress,
+        packet_type: PacketType,
+    ) -> Result<(), Error> {
+        RECEIVED.with(|received| {
+            received
+                .borrow_mut()
+                .push_back(((ether::ETH_P_ARP, pkt.to_vec(), from), packet_type))
+        });
+        responder.receive("lo", Protocol::Arp, &mut [0u8; 100])
+    }
+
+    // The ARP packets sent since last taking them, with their destination
+    fn sent_arp() -> Vec<(arp::Arp, MacAddress)> {
+        SENT.with(|sent| sent.take())
+            .into_iter()
+            .map(|(_, frame, to)| (arp::Arp::try_from(&frame[..]).unwrap(), to))
+            .collect()
+    }
+
+    #[test]
+    fn mock() {
+        let config: Config = r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.0/30"]
+            exclude = ["10.9.8.2"]
+            mac = "02:00:00:00:00:01"
+        "#
+        .parse()
+        .unwrap();
+        let mut responder = Responder::<Mock>::with_packet_io(
+            Duration::from_secs(10),
+            Duration::ZERO,
+            None,
+            false,
+            false,
+            false,
+        );
+        responder.apply(config.claims).unwrap();
+
+        let requester = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x99]);
+        let spa = Ipv4Addr::new(10, 9, 8, 100);
+        let mut rbuf = [0u8; 100];
+        for tpa in [1, 2, 3] {
+            let request = arp::Arp::request(requester, spa, Ipv4Addr::new(10, 9, 8, tpa));
+            let protocol = ether::ETH_P_ARP;
+            RECEIVED.with(|received| {
+                received
+                    .borrow_mut()
+                    .push_back(((protocol, request.to_vec(), requester), PacketType::Host))
+            });
+            responder.receive("lo", Protocol::Arp, &mut rbuf).unwrap();
+        }
+
+        let sent = SENT.with(|sent| sent.take());
+        let replies: Vec<arp::Arp> = sent
+            .iter()
+            .map(|(_, frame, to)| {
+                assert_eq!(*to, requester);
+                arp::Arp::try_from(&frame[..]).unwrap()
+            })
+            .collect();
+        let mac = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(
+            replies,
+            [
+                arp::Arp::request(requester, spa, Ipv4Addr::new(10, 9, 8, 1))
+                    .reply(mac)
+                    .unwrap(),
+            ]
+        );
+        assert_eq!(responder.replies(), 1);
+    }
+
+    #[test]
+    fn worker() {
+        let config: Config = r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            announce = true
+        "#
+        .parse()
+        .unwrap();
+        let mut responder = Responder::<Mock>::with_packet_io(
+            Duration::from_secs(10),
+            Duration::ZERO,
+            None,
+            false,
+            false,
+            false,
+        );
+        responder.set_fanout(iface::Fanout::default());
+        responder.apply(config.claims.clone()).unwrap();
+        let mut worker = responder.worker(1);
+        worker.apply(config.claims).unwrap();
+
+        // Only the responder the worker was created from announces
+        assert!(responder.deadline().is_some());
+        assert_eq!(worker.deadline(), None);
+
+        let requester = MacAddress::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x99]);
+        let request = arp::Arp::request(
+            requester,
+            Ipv4Addr::new(10, 9, 8, 100),
+            Ipv4Addr::new(10, 9, 8, 1),
+        );
+        RECEIVED.with(|received| {
+            received.borrow_mut().push_back((
+                (ether::ETH_P_ARP, request.to_vec(), requester),
+                PacketType::Host,
+            ))
+        });
+        let mut rbuf = [0u8; 100];
+        worker.receive("lo", Protocol::Arp, &mut rbuf).unwrap();
+        assert_eq!(SENT.with(|sent| sent.take()).len(), 1);
+        assert_eq!((responder.replies(), worker.replies()), (0, 1));
+    }
+
+    #[test]
+    fn conflict_defend() {
+        let mut responder = mock_responder(
+            r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            on_conflict = "defend"
+            "#,
+            Duration::from_secs(10),
+        );
+        let ip = Ipv4Addr::new(10, 9, 8, 1);
+        let garp = arp::Arp::gratuitous_reply(OTHER, ip);
+        receive_arp(&mut responder, &garp, OTHER, PacketType::Broadcast).unwrap();
+
+        // The address is announced again for the other hosts, and kept
+        let sent = sent_arp();
+        assert_eq!(sent.len(), 1);
+        assert_eq!((sent[0].0.sha, sent[0].0.spa), (MAC, ip));
+        assert_eq!(sent[0].1, MacAddress::broadcast());
+        assert!(responder.defended.contains_key(&("lo".to_owned(), ip)));
+        assert!(!responder.is_suspended("lo", IpAddr::V4(ip)));
+    }
+
+    #[test]
+    fn defend_interval() {
+        let config = r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            on_conflict = "defend"
+        "#;
+        let garp = arp::Arp::gratuitous_reply(OTHER, Ipv4Addr::new(10, 9, 8, 1));
+        for (interval, defended) in [(Duration::from_secs(10), 1), (Duration::ZERO, 2)] {
+            let mut responder = mock_responder(config, interval);
+            for _ in 0..2 {
+                receive_arp(&mut responder, &garp, OTHER, PacketType::Broadcast).unwrap();
+            }
+            assert_eq!(sent_arp().len(), defended);
+        }
+    }
+
+    #[test]
+    fn conflict_yield() {
+        let mut responder = mock_responder(
+            r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            on_conflict = "yield"
+            "#,
+            Duration::from_secs(10),
+        );
+        let ip = Ipv4Addr::new(10, 9, 8, 1);
+        let garp = arp::Arp::gratuitous_reply(OTHER, ip);
+        receive_arp(&mut responder, &garp, OTHER, PacketType::Broadcast).unwrap();
+        assert!(sent_arp().is_empty());
+        assert!(responder.yielded.contains(&("lo".to_owned(), ip)));
+
+        // Requests are left to the other host
+        let request = arp::Arp::request(REQUESTER, Ipv4Addr::new(10, 9, 8, 100), ip);
+        receive_arp(&mut responder, &request, REQUESTER, PacketType::Broadcast).unwrap();
+        assert!(sent_arp().is_empty());
+    }
+
+    #[test]
+    fn conflict_exit() {
+        let mut responder = mock_responder(
+            r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            on_conflict = "exit"
+            "#,
+            Duration::from_secs(10),
+        );
+        let ip = Ipv4Addr::new(10, 9, 8, 1);
+        let garp = arp::Arp::gratuitous_reply(OTHER, ip);
+        match receive_arp(&mut responder, &garp, OTHER, PacketType::Broadcast) {
+            Err(Error::Conflict {
+                iface,
+                ip: used,
+                mac,
+            }) => {
+                assert_eq!((iface.as_str(), used, mac), ("lo", IpAddr::V4(ip), OTHER))
+            }
+            res => panic!("expected a conflict, got {:?}", res),
+        }
+        assert!(sent_arp().is_empty());
+    }
+
+    #[test]
+    fn passive() {
+        let mut responder = mock_responder(
+            r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            passive = true
+            "#,
+            Duration::from_secs(10),
+        );
+        let ip = Ipv4Addr::new(10, 9, 8, 1);
+        let request = arp::Arp::request(REQUESTER, Ipv4Addr::new(10, 9, 8, 100), ip);
+        receive_arp(&mut responder, &request, REQUESTER, PacketType::Broadcast).unwrap();
+
+        // The reply waits for other hosts to answer first, which they do
+        assert!(sent_arp().is_empty());
+        assert_eq!(responder.pending.len(), 1);
+        let reply = request.reply(OTHER).unwrap();
+        receive_arp(&mut responder, &reply, OTHER, PacketType::Host).unwrap();
+        assert!(responder.pending.is_empty());
+        assert!(responder.yielded.is_empty());
+        assert!(sent_arp().is_empty());
+    }
+
+    #[test]
+    fn delivery() {
+        let mut responder = mock_responder(
+            r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+            delivery = "unicast"
+            "#,
+            Duration::from_secs(10),
+        );
+        let request = arp::Arp::request(
+            REQUESTER,
+            Ipv4Addr::new(10, 9, 8, 100),
+            Ipv4Addr::new(10, 9, 8, 1),
+        );
+        receive_arp(&mut responder, &request, REQUESTER, PacketType::Broadcast).unwrap();
+        assert!(sent_arp().is_empty());
+
+        // Only requests refreshing a cached entry are answered
+        receive_arp(&mut responder, &request, REQUESTER, PacketType::Host).unwrap();
+        assert_eq!(sent_arp(), [(request.reply(MAC).unwrap(), REQUESTER)]);
+    }
+
+    #[test]
+    fn on_probe() {
+        let mut responder = mock_responder(
+            r#"
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.1"]
+            mac = "02:00:00:00:00:01"
+
+            [[claim]]
+            iface = "lo"
+            ip = ["10.9.8.2", "10.9.8.3"]
+            mac = "02:00:00:00:00:01"
+            on_probe = "ignore"
+            "#,
+            Duration::from_secs(10),
+        );
+
+        // Probes are defended against by default, by replying to them
+        let probe = arp::Arp::probe(OTHER, Ipv4Addr::new(10, 9, 8, 1));
+        receive_arp(&mut responder, &probe, OTHER, PacketType::Broadcast).unwrap();
+        assert_eq!(sent_arp(), [(probe.reply(MAC).unwrap(), OTHER)]);
+
+        // Unless ignored, while requests are still answered
+        let probe = arp::Arp::probe(OTHER, Ipv4Addr::new(10, 9, 8, 2));
+        receive_arp(&mut responder, &probe, OTHER, PacketType::Broadcast).unwrap();
+        assert!(sent_arp().is_empty());
+        let request = arp::Arp::request(
+            REQUESTER,
+            Ipv4Addr::new(10, 9, 8, 100),
+            Ipv4Addr::new(10, 9, 8, 3),
+        );
+        receive_arp(&mut responder, &request, REQUESTER, PacketType::Broadcast).unwrap();
+        assert_eq!(sent_arp(), [(request.reply(MAC).unwrap(), REQUESTER)]);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::ether;
+use crate::iface::{PacketIo, PacketSocket};
+use crate::link::{BpfInsn, LinkAddr, Membership};
+use crate::pcap;
+use eui48::MacAddress;
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use std::cell::Cell;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr::NonNull;
+use std::sync::atomic::{fence, Ordering};
+
+// Socket options and block states, from linux/if_packet.h
+const PACKET_RX_RING: nix::libc::c_int = 5;
+const PACKET_VERSION: nix::libc::c_int = 10;
+const TPACKET_V3: nix::libc::c_int = 2;
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+
+// Offsets of block_status, num_pkts and offset_to_first_pkt in struct tpacket_block_desc
+const BLOCK_STATUS: usize = 8;
+const BLOCK_NUM_PKTS: usize = 12;
+const BLOCK_FIRST_PKT: usize = 16;
+// Offsets of tp_next_offset, tp_snaplen and tp_mac in struct tpacket3_hdr, which is followed
+// by the sender's address
+const FRAME_NEXT: usize = 0;
+const FRAME_SNAPLEN: usize = 12;
+const FRAME_MAC: usize = 24;
+const FRAME_ADDR: usize = 48;
+
+// Blocks are handed over once full or after the timeout, so frames wait at most that long
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_NR: u32 = 16;
+const FRAME_SIZE: u32 = 2048;
+const RETIRE_TIMEOUT_MS: u32 = 2;
+
+#[repr(C)]
+struct TpacketReq3 {
+    block_size: u32,
+    block_nr: u32,
+    frame_size: u32,
+    frame_nr: u32,
+    retire_blk_tov: u32,
+    sizeof_priv: u32,
+    feature_req_word: u32,
+}
+
+// The blocks of frames of a TPACKET_V3 ring, read in turn and handed back to the kernel
+// once all their frames were read
+struct Ring {
+    base: NonNull<u8>,
+    block_size: usize,
+    block_nr: usize,
+    // The block being read, the number of its frames read and the offset of the next one
+    next: Cell<(usize, u32, usize)>,
+}
+
+impl Ring {
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { std::ptr::read_volatile(self.base.as_ptr().add(offset) as *const u32) }
+    }
+
+    // Whether the kernel handed the block being read over
+    fn is_ready(&self) -> bool {
+        let (block, _, _) = self.next.get();
+        let status = self.read_u32(block * self.block_size + BLOCK_STATUS);
+        fence(Ordering::Acquire);
+        status & TP_STATUS_USER != 0
+    }
+
+    fn release(&self, block: usize) {
+        fence(Ordering::Release);
+        unsafe {
+            let status = self
+                .base
+                .as_ptr()
+                .add(block * self.block_size + BLOCK_STATUS);
+            std::ptr::write_volatile(status as *mut u32, TP_STATUS_KERNEL);
+        }
+        self.next.set(((block + 1) % self.block_nr, 0, 0));
+    }
+
+    // Copy the next frame into buf, returning its length and sender
+    fn next(&self, buf: &mut [u8]) -> Option<(usize, nix::libc::sockaddr_ll)> {
+        while self.is_ready() {
+            let (block, read, offset) = self.next.get();
+            let start = block * self.block_size;
+            let count = self.read_u32(start + BLOCK_NUM_PKTS);
+            if read >= count {
+                self.release(block);
+                continue;
+            }
+            let offset = match read {
+                0 => self.read_u32(start + BLOCK_FIRST_PKT) as usize,
+                _ => offset,
+            };
+            let frame = start + offset;
+            let len = (self.read_u32(frame + FRAME_SNAPLEN) as usize).min(buf.len());
+            let (data, from) = unsafe {
+                let frame = self.base.as_ptr().add(frame);
+                let mac = std::ptr::read_unaligned(frame.add(FRAME_MAC) as *const u16);
+                let from = std::ptr::read_unaligned(
+                    frame.add(FRAME_ADDR) as *const nix::libc::sockaddr_ll
+                );
+                (frame.add(usize::from(mac)), from)
+            };
+            buf[..len].copy_from_slice(unsafe { std::slice::from_raw_parts(data, len) });
+
+            // Blocks are handed back right away, so polling doesn't report them readable
+            if read + 1 == count {
+                self.release(block);
+            } else {
+                let next = offset + self.read_u32(frame + FRAME_NEXT) as usize;
+                self.next.set((block, read + 1, next));
+            }
+            return Some((len, from));
+        }
+        None
+    }
+}
+
+// A packet socket receiving frames through a ring of blocks shared with the kernel
+// (PACKET_MMAP with TPACKET_V3) instead of a system call per frame, for segments where
+// many frames are received, like when sponging for thousands of addresses. Frames are
+// sent through the socket as usual.
+pub struct RingSocket {
+    socket: PacketSocket,
+    ring: Ring,
+}
+
+// The ring is only accessed through the socket it belongs to
+unsafe impl Send for RingSocket {}
+
+impl Drop for RingSocket {
+    fn drop(&mut self) {
+        let len = self.ring.block_size * self.ring.block_nr;
+        let _ = unsafe { munmap(self.ring.base.as_ptr() as *mut _, len) };
+    }
+}
+
+impl AsRawFd for RingSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+fn set_option<T>(fd: RawFd, option: nix::libc::c_int, value: &T) -> nix::Result<()> {
+    let res = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            nix::libc::SOL_PACKET,
+            option,
+            value as *const T as *const nix::libc::c_void,
+            std::mem::size_of::<T>() as nix::libc::socklen_t,
+        )
+    };
+    nix::Error::result(res).map(drop)
+}
+
+impl PacketIo for RingSocket {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        let socket = PacketSocket::open(link_addr, protocol, raw, vlan, min_len, filter, capture)?;
+        let fd = socket.as_raw_fd();
+        set_option(fd, PACKET_VERSION, &TPACKET_V3)?;
+        let req = TpacketReq3 {
+            block_size: BLOCK_SIZE,
+            block_nr: BLOCK_NR,
+            frame_size: FRAME_SIZE,
+            frame_nr: BLOCK_SIZE / FRAME_SIZE * BLOCK_NR,
+            retire_blk_tov: RETIRE_TIMEOUT_MS,
+            sizeof_priv: 0,
+            feature_req_word: 0,
+        };
+        set_option(fd, PACKET_RX_RING, &req)?;
+        let len = (BLOCK_SIZE * BLOCK_NR) as usize;
+        let base = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                fd,
+                0,
+            )?
+        };
+        let ring = Ring {
+            base: NonNull::new(base as *mut u8).ok_or(nix::Error::ENOMEM)?,
+            block_size: BLOCK_SIZE as usize,
+            block_nr: BLOCK_NR as usize,
+            next: Cell::new((0, 0, 0)),
+        };
+
+        // Frames queued while the socket was bound before the ring existed would keep it
+        // polling readable
+        let mut buf = [0u8; 64];
+        while nix::sys::socket::recv(fd, &mut buf, nix::sys::socket::MsgFlags::MSG_DONTWAIT).is_ok()
+        {
+        }
+        Ok(Self { socket, ring })
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.socket.link_addr()
+    }
+
+    fn is_raw(&self) -> bool {
+        self.socket.is_raw()
+    }
+
+    fn set_filter(&self, filter: &[BpfInsn]) -> nix::Result<()> {
+        self.socket.set_filter(filter)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let (size, from) = self.ring.next(buf).ok_or(nix::Error::EAGAIN)?;
+        self.socket.received(buf, size, LinkAddr::from(&from))
+    }
+
+    fn pending(&self) -> bool {
+        self.ring.is_ready()
+    }
+
+    fn defer_sends(&self, defer: bool) -> nix::Result<()> {
+        self.socket.defer_sends(defer)
+    }
+
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        self.socket.send_from(buf, src, to)
+    }
+
+    fn set_membership(&self, membership: Membership, join: bool) -> nix::Result<()> {
+        self.socket.set_membership(membership, join)
+    }
+
+    fn join_fanout(&self, id: Option<u16>) -> nix::Result<u16> {
+        self.socket.join_fanout(id)
+    }
+
+    fn set_busy_poll(&self, usecs: u32) -> nix::Result<()> {
+        self.socket.set_busy_poll(usecs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        // Two blocks of 256 bytes, the first holding two frames and the second none yet
+        let mut memory = vec![0u64; 64];
+        let base = memory.as_mut_ptr() as *mut u8;
+        let bytes = unsafe { std::slice::from_raw_parts_mut(base, 512) };
+        let put = |bytes: &mut [u8], offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_ne_bytes())
+        };
+        put(bytes, BLOCK_STATUS, TP_STATUS_USER);
+        put(bytes, BLOCK_NUM_PKTS, 2);
+        put(bytes, BLOCK_FIRST_PKT, 48);
+        for (frame, next, data) in [(48, 112, &b"first"[..]), (160, 0, &b"second"[..])] {
+            put(bytes, frame + FRAME_NEXT, next);
+            put(bytes, frame + FRAME_SNAPLEN, data.len() as u32);
+            bytes[frame + FRAME_MAC..frame + FRAME_MAC + 2].copy_from_slice(&64u16.to_ne_bytes());
+            bytes[frame + FRAME_ADDR + 4..frame + FRAME_ADDR + 8].copy_from_slice(&[0, 0, 0, 7]);
+            bytes[frame + 64..frame + 64 + data.len()].copy_from_slice(data);
+        }
+
+        let ring = Ring {
+            base: NonNull::new(base).unwrap(),
+            block_size: 256,
+            block_nr: 2,
+            next: Cell::new((0, 0, 0)),
+        };
+        let mut buf = [0u8; 100];
+        assert!(ring.is_ready());
+        let (len, from) = ring.next(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"first");
+        assert_eq!(from.sll_ifindex, i32::from_ne_bytes([0, 0, 0, 7]));
+        let (len, _) = ring.next(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"second");
+
+        // The read block is handed back, and the next isn't ready
+        assert_eq!(ring.read_u32(BLOCK_STATUS), TP_STATUS_KERNEL);
+        assert!(!ring.is_ready());
+        assert!(ring.next(&mut buf).is_none());
+    }
+}
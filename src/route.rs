@@ -0,0 +1,150 @@
+use crate::error::Error;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::{
+    recv, send, socket, AddressFamily, MsgFlags, SockFlag, SockProtocol, SockType,
+};
+#[cfg(target_os = "linux")]
+use std::convert::TryInto;
+use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+
+#[cfg(target_os = "linux")]
+const NLMSG_HDRLEN: usize = 16;
+#[cfg(target_os = "linux")]
+const NLMSG_ERROR: u16 = 2;
+#[cfg(target_os = "linux")]
+const NLM_F_REQUEST: u16 = 1;
+#[cfg(target_os = "linux")]
+const RTM_NEWROUTE: u16 = 24;
+#[cfg(target_os = "linux")]
+const RTM_GETROUTE: u16 = 26;
+#[cfg(target_os = "linux")]
+const RTMSG_LEN: usize = 12;
+#[cfg(target_os = "linux")]
+const RTA_DST: u16 = 1;
+#[cfg(target_os = "linux")]
+const RTA_OIF: u16 = 4;
+
+// Ask the kernel which interface it would use to reach an IP address
+#[cfg(target_os = "linux")]
+pub fn lookup_oif(ip: IpAddr) -> Result<usize, Error> {
+    let result = socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkRoute,
+    )
+    .map_err(Box::from)
+    .and_then(|socket| {
+        let result = request_oif(socket, ip);
+        let _ = nix::unistd::close(socket);
+        result
+    });
+    result.map_err(|err| Error::Route {
+        ip,
+        message: err.to_string(),
+    })
+}
+
+// Ask IP Helper instead on Windows
+#[cfg(windows)]
+pub fn lookup_oif(ip: IpAddr) -> Result<usize, Error> {
+    crate::iphlpapi::best_interface(ip).map_err(|err| Error::Route {
+        ip,
+        message: err.to_string(),
+    })
+}
+
+// Without netlink, the interface to reach addresses through has to be configured
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn lookup_oif(ip: IpAddr) -> Result<usize, Error> {
+    Err(Error::Route {
+        ip,
+        message: "route lookups are only supported on Linux and Windows".to_owned(),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn request_oif(socket: RawFd, ip: IpAddr) -> Result<usize, Box<dyn std::error::Error>> {
+    let (family, addr) = match ip {
+        IpAddr::V4(ip) => (nix::libc::AF_INET, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (nix::libc::AF_INET6, ip.octets().to_vec()),
+    };
+    let len = NLMSG_HDRLEN + RTMSG_LEN + 4 + addr.len();
+    let mut req = Vec::with_capacity(len);
+    req.extend_from_slice(&(len as u32).to_ne_bytes());
+    req.extend_from_slice(&RTM_GETROUTE.to_ne_bytes());
+    req.extend_from_slice(&NLM_F_REQUEST.to_ne_bytes());
+    req.extend_from_slice(&1u32.to_ne_bytes()); // sequence number
+    req.extend_from_slice(&0u32.to_ne_bytes()); // port id
+    let dst_len = 8 * addr.len() as u8;
+    req.extend_from_slice(&[family as u8, dst_len, 0, 0, 0, 0, 0, 0]);
+    req.extend_from_slice(&0u32.to_ne_bytes()); // rtm_flags
+    req.extend_from_slice(&(4 + addr.len() as u16).to_ne_bytes());
+    req.extend_from_slice(&RTA_DST.to_ne_bytes());
+    req.extend_from_slice(&addr);
+    send(socket, &req, MsgFlags::empty())?;
+
+    let mut buf = [0u8; 4096];
+    let size = recv(socket, &mut buf, MsgFlags::empty())?;
+    parse_oif(&buf[..size])
+}
+
+#[cfg(target_os = "linux")]
+fn parse_oif(msg: &[u8]) -> Result<usize, Box<dyn std::error::Error>> {
+    if msg.len() < NLMSG_HDRLEN {
+        return Err("truncated netlink response".into());
+    }
+    let len = u32::from_ne_bytes(msg[0..4].try_into()?) as usize;
+    let msg = msg.get(..len).ok_or("truncated netlink response")?;
+    match u16::from_ne_bytes(msg[4..6].try_into()?) {
+        NLMSG_ERROR => {
+            let errno = i32::from_ne_bytes(
+                msg.get(16..20)
+                    .ok_or("truncated netlink error")?
+                    .try_into()?,
+            );
+            return Err(nix::Error::from_i32(-errno).into());
+        }
+        RTM_NEWROUTE => {}
+        other => return Err(format!("unexpected netlink message type {}", other).into()),
+    }
+
+    // Walk the route attributes looking for the output interface
+    let mut attrs = msg.get(NLMSG_HDRLEN + RTMSG_LEN..).unwrap_or_default();
+    while attrs.len() >= 4 {
+        let rta_len = u16::from_ne_bytes(attrs[0..2].try_into()?) as usize;
+        let rta_type = u16::from_ne_bytes(attrs[2..4].try_into()?);
+        if rta_len < 4 || rta_len > attrs.len() {
+            break;
+        }
+        if rta_type == RTA_OIF && rta_len >= 8 {
+            return Ok(u32::from_ne_bytes(attrs[4..8].try_into()?) as usize);
+        }
+        attrs = &attrs[((rta_len + 3) & !3).min(attrs.len())..];
+    }
+    Err("route has no output interface".into())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oif() {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&(16u32 + 12 + 8 + 8).to_ne_bytes());
+        msg.extend_from_slice(&RTM_NEWROUTE.to_ne_bytes());
+        msg.extend_from_slice(&[0u8; 10]);
+        msg.extend_from_slice(&[2, 32, 0, 0, 254, 0, 0, 1, 0, 0, 0, 0]);
+        msg.extend_from_slice(&8u16.to_ne_bytes());
+        msg.extend_from_slice(&RTA_DST.to_ne_bytes());
+        msg.extend_from_slice(&[10, 0, 0, 1]);
+        msg.extend_from_slice(&8u16.to_ne_bytes());
+        msg.extend_from_slice(&RTA_OIF.to_ne_bytes());
+        msg.extend_from_slice(&3u32.to_ne_bytes());
+        assert_eq!(parse_oif(&msg).unwrap(), 3);
+        assert!(parse_oif(&msg[..20]).is_err());
+    }
+}
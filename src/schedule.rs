@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+// A daily window of local time in which a claim is active, e.g. 08:00-18:00. Windows
+// ending before they start wrap around midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Option<u32> {
+    let (hour, minute) = s.trim().split_once(':')?;
+    let (hour, minute): (u32, u32) = (hour.parse().ok()?, minute.parse().ok()?);
+    if hour > 24 || minute > 59 || (hour == 24 && minute != 0) {
+        return None;
+    }
+    Some(hour * 60 + minute)
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid time window {} (expected HH:MM-HH:MM)", s);
+        let (start, end) = s.split_once('-').ok_or_else(err)?;
+        Ok(Self {
+            start: parse_time(start).ok_or_else(err)?,
+            end: parse_time(end).ok_or_else(err)?,
+        })
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Window {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+// The current local time as minute of the day and second within that minute
+#[cfg(unix)]
+pub fn local_time() -> (u32, u32) {
+    let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let now = nix::libc::time(std::ptr::null_mut());
+        nix::libc::localtime_r(&now, &mut tm);
+    }
+    ((tm.tm_hour * 60 + tm.tm_min) as u32, tm.tm_sec as u32)
+}
+
+#[cfg(windows)]
+pub fn local_time() -> (u32, u32) {
+    let mut time = crate::sys::SystemTime::default();
+    unsafe { crate::sys::GetLocalTime(&mut time) };
+    (
+        u32::from(time.hour) * 60 + u32::from(time.minute),
+        u32::from(time.second),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows() {
+        let day: Window = "08:00-18:30".parse().unwrap();
+        assert_eq!(day.to_string(), "08:00-18:30");
+        assert!(!day.contains(7 * 60 + 59));
+        assert!(day.contains(8 * 60));
+        assert!(day.contains(18 * 60 + 29));
+        assert!(!day.contains(18 * 60 + 30));
+
+        let night: Window = "22:00-06:00".parse().unwrap();
+        assert!(night.contains(23 * 60));
+        assert!(night.contains(60));
+        assert!(!night.contains(12 * 60));
+
+        assert!("00:00-24:00".parse::<Window>().unwrap().contains(12 * 60));
+        assert!("08:00".parse::<Window>().is_err());
+        assert!("08:60-09:00".parse::<Window>().is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+// Requests for an address are counted over this window
+const WINDOW: Duration = Duration::from_secs(60);
+const PROBE_NUM: u32 = 3;
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Detects dead hosts by the rate of requests nobody answers for them, like arpsponge, so
+// their addresses can be claimed to stop the requests from being flooded
+#[derive(Default)]
+pub struct Sponge {
+    hosts: HashMap<Key, State>,
+}
+
+type Key = (String, Ipv4Addr);
+
+// When to sponge an address and for how long
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub threshold: u32,
+    // Release sponged addresses nobody requested for this long
+    pub age: Option<Duration>,
+    // Keep probing sponged addresses this often, to notice hosts returning silently
+    pub recheck: Option<Duration>,
+}
+
+enum State {
+    Queried {
+        since: Instant,
+        count: u32,
+    },
+    // Verifying nobody uses the address by probing for it
+    Probing {
+        sent: u32,
+        next: Instant,
+        settings: Settings,
+    },
+    Dead {
+        queried: Instant,
+        recheck: Option<Instant>,
+        settings: Settings,
+    },
+}
+
+// What is due after some time passed
+#[derive(Default)]
+pub struct Due {
+    pub probes: Vec<Key>,
+    pub dead: Vec<Key>,
+    pub released: Vec<Key>,
+}
+
+impl Sponge {
+    // A request for the address was seen, which starts probing once there were too many
+    pub fn query(&mut self, name: &str, ip: Ipv4Addr, settings: Settings, now: Instant) {
+        let key = (name.to_owned(), ip);
+        if !self.hosts.contains_key(&key) {
+            self.hosts.retain(|_, state| match state {
+                State::Queried { since, .. } => now.duration_since(*since) < WINDOW,
+                _ => true,
+            });
+        }
+        let state = self.hosts.entry(key).or_insert(State::Queried {
+            since: now,
+            count: 0,
+        });
+        match state {
+            State::Queried { since, count } => {
+                if now.duration_since(*since) >= WINDOW {
+                    *since = now;
+                    *count = 0;
+                }
+                *count += 1;
+                if *count >= settings.threshold {
+                    log::debug!("{} requests for {} on {}, probing", count, ip, name);
+                    *state = State::Probing {
+                        sent: 0,
+                        next: now,
+                        settings,
+                    };
+                }
+            }
+            State::Dead { queried, .. } => *queried = now,
+            State::Probing { .. } => {}
+        }
+    }
+
+    // The address was seen in use, returning whether it was sponged and is now released
+    pub fn alive(&mut self, name: &str, ip: Ipv4Addr) -> bool {
+        matches!(
+            self.hosts.remove(&(name.to_owned(), ip)),
+            Some(State::Dead { .. })
+        )
+    }
+
+    pub fn is_dead(&self, name: &str, ip: Ipv4Addr) -> bool {
+        matches!(
+            self.hosts.get(&(name.to_owned(), ip)),
+            Some(State::Dead { .. })
+        )
+    }
+
+    pub fn due(&mut self, now: Instant) -> Due {
+        let mut due = Due::default();
+        for (key, state) in self.hosts.iter_mut() {
+            match state {
+                State::Probing {
+                    sent,
+                    next,
+                    settings,
+                } if *next <= now => {
+                    if *sent < PROBE_NUM {
+                        *sent += 1;
+                        *next = now + PROBE_INTERVAL;
+                        due.probes.push(key.clone());
+                    } else {
+                        *state = State::Dead {
+                            queried: now,
+                            recheck: settings.recheck.map(|recheck| now + recheck),
+                            settings: *settings,
+                        };
+                        due.dead.push(key.clone());
+                    }
+                }
+                State::Dead {
+                    queried, settings, ..
+                } if settings
+                    .age
+                    .is_some_and(|age| now.duration_since(*queried) >= age) =>
+                {
+                    due.released.push(key.clone());
+                }
+                State::Dead {
+                    recheck: Some(recheck),
+                    settings,
+                    ..
+                } if *recheck <= now => {
+                    *recheck = now + settings.recheck.unwrap_or_default();
+                    due.probes.push(key.clone());
+                }
+                _ => {}
+            }
+        }
+        for key in &due.released {
+            self.hosts.remove(key);
+        }
+        due
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.hosts
+            .values()
+            .filter_map(|state| match state {
+                State::Probing { next, .. } => Some(*next),
+                State::Dead {
+                    queried,
+                    recheck,
+                    settings,
+                } => settings
+                    .age
+                    .map(|age| *queried + age)
+                    .into_iter()
+                    .chain(*recheck)
+                    .min(),
+                _ => None,
+            })
+            .min()
+    }
+
+    // Forget addresses that are no longer sponged for
+    pub fn retain(&mut self, mut f: impl FnMut(&str, Ipv4Addr) -> bool) {
+        self.hosts.retain(|(name, ip), _| f(name, *ip));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sponge() {
+        let ip = Ipv4Addr::new(10, 0, 0, 5);
+        let now = Instant::now();
+        let mut sponge = Sponge::default();
+        let settings = Settings {
+            threshold: 3,
+            age: None,
+            recheck: None,
+        };
+
+        for _ in 0..3 {
+            sponge.query("eth0", ip, settings, now);
+        }
+        assert_eq!(sponge.deadline(), Some(now));
+
+        // A host answering probes is left alone
+        assert_eq!(sponge.due(now).probes, [("eth0".to_owned(), ip)]);
+        assert!(!sponge.alive("eth0", ip));
+        assert_eq!(sponge.deadline(), None);
+
+        for _ in 0..3 {
+            sponge.query("eth0", ip, settings, now);
+        }
+        for i in 0..PROBE_NUM {
+            let due = sponge.due(now + PROBE_INTERVAL * i);
+            assert_eq!(due.probes.len(), 1);
+            assert!(due.dead.is_empty());
+        }
+        let due = sponge.due(now + PROBE_INTERVAL * PROBE_NUM);
+        assert!(due.probes.is_empty());
+        assert_eq!(due.dead, [("eth0".to_owned(), ip)]);
+        assert!(sponge.is_dead("eth0", ip));
+        assert!(!sponge.is_dead("eth1", ip));
+
+        // The host returning takes its address back
+        assert!(sponge.alive("eth0", ip));
+        assert!(!sponge.is_dead("eth0", ip));
+    }
+
+    #[test]
+    fn ageing() {
+        let ip = Ipv4Addr::new(10, 0, 0, 5);
+        let now = Instant::now();
+        let mut sponge = Sponge::default();
+        let settings = Settings {
+            threshold: 1,
+            age: Some(Duration::from_secs(600)),
+            recheck: Some(Duration::from_secs(60)),
+        };
+
+        sponge.query("eth0", ip, settings, now);
+        for i in 0..=PROBE_NUM {
+            sponge.due(now + PROBE_INTERVAL * i);
+        }
+        let dead = now + PROBE_INTERVAL * PROBE_NUM;
+        assert!(sponge.is_dead("eth0", ip));
+        assert_eq!(sponge.deadline(), Some(dead + Duration::from_secs(60)));
+
+        // Sponged addresses keep being probed
+        let due = sponge.due(dead + Duration::from_secs(60));
+        assert_eq!(due.probes, [("eth0".to_owned(), ip)]);
+        assert!(due.released.is_empty());
+
+        // And released once nobody requests them anymore
+        sponge.query("eth0", ip, settings, dead + Duration::from_secs(300));
+        assert!(sponge
+            .due(dead + Duration::from_secs(600))
+            .released
+            .is_empty());
+        let due = sponge.due(dead + Duration::from_secs(900));
+        assert_eq!(due.released, [("eth0".to_owned(), ip)]);
+        assert!(!sponge.is_dead("eth0", ip));
+        assert_eq!(sponge.deadline(), None);
+    }
+}
@@ -0,0 +1,324 @@
+// What the platform-neutral code needs from the operating system beyond std: errno values,
+// raw descriptors and waiting for them like poll(2). On Unix these are nix's and std's, on
+// Windows stand-ins of the same shape, waiting for event handles like those of Npcap.
+#[cfg(unix)]
+pub use nix::poll::{poll, PollFd, PollFlags};
+#[cfg(unix)]
+pub use nix::{Error, Result};
+#[cfg(unix)]
+pub use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(windows)]
+pub use windows::*;
+
+#[cfg(windows)]
+mod windows {
+    use std::convert::TryFrom;
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::sync::OnceLock;
+
+    pub type Result<T> = std::result::Result<T, Error>;
+
+    // An errno value, as in the C runtime's errno.h, with Linux's for ELIBACC, which it
+    // lacks
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Error(c_int);
+
+    const ERRNOS: [(Error, &str, &str); 16] = [
+        (Error::EPERM, "EPERM", "Operation not permitted"),
+        (Error::ENOENT, "ENOENT", "No such file or directory"),
+        (Error::EINTR, "EINTR", "Interrupted system call"),
+        (Error::EIO, "EIO", "I/O error"),
+        (Error::EAGAIN, "EAGAIN", "Try again"),
+        (Error::ENOMEM, "ENOMEM", "Out of memory"),
+        (Error::EACCES, "EACCES", "Permission denied"),
+        (Error::EBUSY, "EBUSY", "Device or resource busy"),
+        (Error::ENODEV, "ENODEV", "No such device"),
+        (Error::EINVAL, "EINVAL", "Invalid argument"),
+        (Error::ENETDOWN, "ENETDOWN", "Network is down"),
+        (Error::ENOBUFS, "ENOBUFS", "No buffer space available"),
+        (Error::EOPNOTSUPP, "EOPNOTSUPP", "Operation not supported"),
+        (
+            Error::EPROTONOSUPPORT,
+            "EPROTONOSUPPORT",
+            "Protocol not supported",
+        ),
+        (Error::ETIMEDOUT, "ETIMEDOUT", "Connection timed out"),
+        (
+            Error::ELIBACC,
+            "ELIBACC",
+            "Can not access a needed shared library",
+        ),
+    ];
+
+    impl Error {
+        pub const EPERM: Self = Self(1);
+        pub const ENOENT: Self = Self(2);
+        pub const EINTR: Self = Self(4);
+        pub const EIO: Self = Self(5);
+        pub const EAGAIN: Self = Self(11);
+        pub const ENOMEM: Self = Self(12);
+        pub const EACCES: Self = Self(13);
+        pub const EBUSY: Self = Self(16);
+        pub const ENODEV: Self = Self(19);
+        pub const EINVAL: Self = Self(22);
+        pub const ELIBACC: Self = Self(79);
+        pub const ENETDOWN: Self = Self(116);
+        pub const ENOBUFS: Self = Self(119);
+        pub const EOPNOTSUPP: Self = Self(130);
+        pub const EPROTONOSUPPORT: Self = Self(135);
+        pub const ETIMEDOUT: Self = Self(138);
+
+        fn entry(self) -> (&'static str, &'static str) {
+            match ERRNOS.iter().find(|(errno, _, _)| *errno == self) {
+                Some((_, name, desc)) => (name, desc),
+                None => ("UnknownErrno", "Unknown errno"),
+            }
+        }
+
+        pub fn desc(self) -> &'static str {
+            self.entry().1
+        }
+
+        // The errno closest to a Win32 error code, from winerror.h
+        pub fn from_win32(code: u32) -> Self {
+            match code {
+                5 => Self::EACCES,
+                8 | 14 => Self::ENOMEM,
+                50 => Self::EOPNOTSUPP,
+                87 => Self::EINVAL,
+                1168 => Self::ENOENT,
+                1231 | 1232 => Self::ENETDOWN,
+                _ => Self::EIO,
+            }
+        }
+    }
+
+    impl std::fmt::Debug for Error {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str(self.entry().0)
+        }
+    }
+
+    impl std::fmt::Display for Error {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{:?}: {}", self, self.desc())
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    // Event handles, which are what there is to wait for
+    pub type RawFd = std::os::windows::io::RawHandle;
+
+    pub trait AsRawFd {
+        fn as_raw_fd(&self) -> RawFd;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PollFlags(u16);
+
+    impl PollFlags {
+        pub const POLLIN: Self = Self(1);
+
+        pub fn empty() -> Self {
+            Self(0)
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.0 == 0
+        }
+    }
+
+    // An event handle to wait for, which is readable once signaled
+    #[derive(Debug, Clone, Copy)]
+    pub struct PollFd {
+        fd: RawFd,
+        revents: PollFlags,
+    }
+
+    impl PollFd {
+        pub fn new(fd: RawFd, _events: PollFlags) -> Self {
+            Self {
+                fd,
+                revents: PollFlags::empty(),
+            }
+        }
+
+        pub fn revents(&self) -> Option<PollFlags> {
+            Some(self.revents)
+        }
+    }
+
+    const INFINITE: u32 = 0xffff_ffff;
+    const WAIT_OBJECT_0: u32 = 0;
+    const WAIT_TIMEOUT: u32 = 0x102;
+    const MAXIMUM_WAIT_OBJECTS: usize = 64;
+
+    // Wait like poll(2) for up to timeout milliseconds (forever when negative) until one of
+    // the handles is signaled, for at most 64 of them
+    pub fn poll(fds: &mut [PollFd], timeout: c_int) -> Result<c_int> {
+        if fds.len() > MAXIMUM_WAIT_OBJECTS {
+            return Err(Error::EINVAL);
+        }
+        let millis = u32::try_from(timeout).unwrap_or(INFINITE);
+        if fds.is_empty() {
+            unsafe { Sleep(millis) };
+            return Ok(0);
+        }
+        let handles: Vec<RawFd> = fds.iter().map(|fd| fd.fd).collect();
+        let res =
+            unsafe { WaitForMultipleObjects(handles.len() as u32, handles.as_ptr(), 0, millis) };
+        if res == WAIT_TIMEOUT {
+            return Ok(0);
+        }
+        if res >= WAIT_OBJECT_0 + handles.len() as u32 {
+            return Err(Error::EIO);
+        }
+        // Only the first signaled handle is reported, so the others are checked in turn
+        let mut ready = 0;
+        for fd in fds.iter_mut() {
+            let signaled = unsafe { WaitForSingleObject(fd.fd, 0) } == WAIT_OBJECT_0;
+            fd.revents = if signaled {
+                ready += 1;
+                PollFlags::POLLIN
+            } else {
+                PollFlags::empty()
+            };
+        }
+        fds[(res - WAIT_OBJECT_0) as usize].revents = PollFlags::POLLIN;
+        Ok(ready.max(1))
+    }
+
+    // Console control events, which are reported like the signals the C runtime raises for
+    // them: SIGINT for Ctrl+C, SIGBREAK for Ctrl+Break and SIGTERM for closing the console,
+    // logging off and shutting down
+    pub const SIGINT: c_int = 2;
+    pub const SIGTERM: c_int = 15;
+    pub const SIGBREAK: c_int = 21;
+
+    // The handler and an event signaled after calling it, kept as an address as handles
+    // aren't Sync
+    static CONSOLE_HANDLER: OnceLock<(extern "C" fn(c_int), usize)> = OnceLock::new();
+
+    extern "system" fn console_handler(event: u32) -> i32 {
+        let signo = match event {
+            0 => SIGINT,
+            1 => SIGBREAK,
+            _ => SIGTERM,
+        };
+        match CONSOLE_HANDLER.get() {
+            Some((handler, event)) => {
+                handler(signo);
+                unsafe { SetEvent(*event as RawFd) };
+                1
+            }
+            None => 0,
+        }
+    }
+
+    // Call the handler for console control events instead of terminating, from a thread of
+    // its own, once per process
+    pub fn set_console_handler(handler: extern "C" fn(c_int)) -> Result<()> {
+        let event = unsafe { CreateEventW(std::ptr::null_mut(), 0, 0, std::ptr::null()) };
+        if event.is_null() {
+            return Err(Error::from_win32(unsafe { GetLastError() }));
+        }
+        if CONSOLE_HANDLER.set((handler, event as usize)).is_err() {
+            unsafe { CloseHandle(event) };
+            return Err(Error::EBUSY);
+        }
+        match unsafe { SetConsoleCtrlHandler(Some(console_handler), 1) } {
+            0 => Err(Error::from_win32(unsafe { GetLastError() })),
+            _ => Ok(()),
+        }
+    }
+
+    // An event signaled after each console control event was handled, to wait for along
+    // with other handles the way signals interrupt poll(2)
+    pub fn console_event() -> Option<RawFd> {
+        CONSOLE_HANDLER.get().map(|(_, event)| *event as RawFd)
+    }
+
+    // An event other threads signal to end a wait for it, where Unix would use a pipe
+    pub struct Waker(RawFd);
+
+    // Event handles can be signaled from any thread
+    unsafe impl Send for Waker {}
+    unsafe impl Sync for Waker {}
+
+    impl Waker {
+        pub fn new() -> Result<Self> {
+            let event = unsafe { CreateEventW(std::ptr::null_mut(), 0, 0, std::ptr::null()) };
+            if event.is_null() {
+                return Err(Error::from_win32(unsafe { GetLastError() }));
+            }
+            Ok(Self(event))
+        }
+
+        pub fn wake(&self) {
+            unsafe { SetEvent(self.0) };
+        }
+    }
+
+    impl AsRawFd for Waker {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    impl Drop for Waker {
+        fn drop(&mut self) {
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub(crate) struct SystemTime {
+        pub year: u16,
+        pub month: u16,
+        pub day_of_week: u16,
+        pub day: u16,
+        pub hour: u16,
+        pub minute: u16,
+        pub second: u16,
+        pub milliseconds: u16,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn WaitForMultipleObjects(
+            count: u32,
+            handles: *const RawFd,
+            wait_all: i32,
+            millis: u32,
+        ) -> u32;
+        fn WaitForSingleObject(handle: RawFd, millis: u32) -> u32;
+        fn Sleep(millis: u32);
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+        pub(crate) fn CreateEventW(
+            attributes: *mut c_void,
+            manual_reset: i32,
+            initial_state: i32,
+            name: *const u16,
+        ) -> RawFd;
+        pub(crate) fn SetEvent(event: RawFd) -> i32;
+        pub(crate) fn ResetEvent(event: RawFd) -> i32;
+        pub(crate) fn CloseHandle(handle: RawFd) -> i32;
+        pub(crate) fn LoadLibraryExW(name: *const u16, file: RawFd, flags: u32) -> RawFd;
+        pub(crate) fn GetProcAddress(module: RawFd, name: *const c_char) -> *mut c_void;
+        pub(crate) fn GetSystemDirectoryW(buf: *mut u16, size: u32) -> u32;
+        pub(crate) fn GetLastError() -> u32;
+        pub(crate) fn GetLocalTime(time: *mut SystemTime);
+    }
+
+    // Whether an event is signaled, resetting it if it is
+    pub(crate) fn take_event(event: RawFd) -> bool {
+        let signaled = unsafe { WaitForSingleObject(event, 0) } == WAIT_OBJECT_0;
+        if signaled {
+            unsafe { ResetEvent(event) };
+        }
+        signaled
+    }
+}
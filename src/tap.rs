@@ -0,0 +1,136 @@
+use crate::ether;
+use crate::iface::PacketIo;
+use crate::link::{self, BpfInsn, LinkAddr, Membership};
+use crate::pcap;
+use eui48::MacAddress;
+use nix::fcntl::{open, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, read, write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+const TUNSETIFF: nix::libc::c_ulong = 0x4004_54ca;
+
+// A tap device attached to through /dev/net/tun, on which claim-ip is the host at the far
+// end of the interface, like a virtual machine or a userspace network stack would be. The
+// device isn't a socket: all frames pass through one file descriptor without a kernel
+// filter, so only ARP is served and a tap carries a single VLAN (or none).
+pub struct TapDevice {
+    link_addr: LinkAddr,
+    fd: RawFd,
+    vlan: Option<ether::Vlan>,
+    min_len: usize,
+    capture: Option<pcap::Capture>,
+}
+
+impl Drop for TapDevice {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+impl AsRawFd for TapDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl PacketIo for TapDevice {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        _raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        _filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        if protocol != ether::ETH_P_ARP {
+            return Err(nix::Error::EPROTONOSUPPORT);
+        }
+
+        #[repr(C)]
+        struct IfReq {
+            name: [u8; nix::libc::IFNAMSIZ],
+            flags: nix::libc::c_short,
+            _pad: [u8; 22],
+        }
+
+        let mut ifr = IfReq {
+            name: [0; nix::libc::IFNAMSIZ],
+            flags: (nix::libc::IFF_TAP | nix::libc::IFF_NO_PI) as nix::libc::c_short,
+            _pad: [0; 22],
+        };
+        let res = unsafe {
+            nix::libc::if_indextoname(
+                link_addr.ifindex as nix::libc::c_uint,
+                ifr.name.as_mut_ptr() as *mut nix::libc::c_char,
+            )
+        };
+        if res.is_null() {
+            return Err(nix::Error::last());
+        }
+        let fd = open(
+            "/dev/net/tun",
+            OFlag::O_RDWR | OFlag::O_NONBLOCK | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        let res = unsafe { nix::libc::ioctl(fd, TUNSETIFF, &mut ifr) };
+        if let Err(err) = nix::Error::result(res) {
+            let _ = close(fd);
+            return Err(err);
+        }
+        let link_addr = LinkAddr {
+            protocol,
+            ..*link_addr
+        };
+        Ok(Self {
+            link_addr,
+            fd,
+            vlan,
+            min_len,
+            capture,
+        })
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.link_addr
+    }
+
+    fn is_raw(&self) -> bool {
+        true
+    }
+
+    // Frames are only told apart by protocol and VLAN, in recv_from
+    fn set_filter(&self, _filter: &[BpfInsn]) -> nix::Result<()> {
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let mut frame = [0u8; 1600];
+        let size = read(self.fd, &mut frame)?;
+        let frame = &frame[..size];
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, frame);
+        }
+        link::raw_payload(&self.link_addr, self.vlan, frame, buf)
+    }
+
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        let (len, frame) = link::raw_frame(&self.link_addr, self.vlan, self.min_len, buf, src, to);
+        let size = write(self.fd, &frame)?;
+        if let Some(capture) = &self.capture {
+            pcap::record(capture, &frame);
+        }
+        Ok(size.saturating_sub(len).min(buf.len()))
+    }
+
+    // Every frame sent through the interface arrives on the device already
+    fn set_membership(&self, _membership: Membership, _join: bool) -> nix::Result<()> {
+        Ok(())
+    }
+
+    // A tap device is attached to by one file descriptor only
+    fn join_fanout(&self, _id: Option<u16>) -> nix::Result<u16> {
+        Err(nix::Error::EOPNOTSUPP)
+    }
+}
@@ -0,0 +1,291 @@
+use nix::libc::{c_char, c_int, c_long, c_ulong, c_void};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::net::{IpAddr, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::OnceLock;
+
+// OpenSSL 3 and 1.1, by the names distributions install them under
+const LIBRARIES: [&[u8]; 3] = [b"libssl.so.3\0", b"libssl.so.1.1\0", b"libssl.so\0"];
+
+// Modes, controls and errors, from openssl/ssl.h and openssl/x509_vfy.h
+const SSL_VERIFY_PEER: c_int = 1;
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+const X509_V_OK: c_long = 0;
+
+#[repr(C)]
+struct Method {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Context {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct Ssl {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct VerifyParam {
+    _private: [u8; 0],
+}
+
+// The functions of libssl (and the libcrypto it depends on), loaded when first connecting
+// rather than linked, so that the binary runs without it
+struct Library {
+    client_method: unsafe extern "C" fn() -> *const Method,
+    ctx_new: unsafe extern "C" fn(*const Method) -> *mut Context,
+    ctx_set_default_verify_paths: unsafe extern "C" fn(*mut Context) -> c_int,
+    ctx_set_verify: unsafe extern "C" fn(*mut Context, c_int, *const c_void),
+    ctx_free: unsafe extern "C" fn(*mut Context),
+    new: unsafe extern "C" fn(*mut Context) -> *mut Ssl,
+    set_fd: unsafe extern "C" fn(*mut Ssl, c_int) -> c_int,
+    set1_host: unsafe extern "C" fn(*mut Ssl, *const c_char) -> c_int,
+    get0_param: unsafe extern "C" fn(*mut Ssl) -> *mut VerifyParam,
+    param_set1_ip_asc: unsafe extern "C" fn(*mut VerifyParam, *const c_char) -> c_int,
+    ctrl: unsafe extern "C" fn(*mut Ssl, c_int, c_long, *mut c_void) -> c_long,
+    connect: unsafe extern "C" fn(*mut Ssl) -> c_int,
+    read: unsafe extern "C" fn(*mut Ssl, *mut c_void, c_int) -> c_int,
+    write: unsafe extern "C" fn(*mut Ssl, *const c_void, c_int) -> c_int,
+    get_error: unsafe extern "C" fn(*const Ssl, c_int) -> c_int,
+    get_verify_result: unsafe extern "C" fn(*const Ssl) -> c_long,
+    free: unsafe extern "C" fn(*mut Ssl),
+    clear_error: unsafe extern "C" fn(),
+    get_error_code: unsafe extern "C" fn() -> c_ulong,
+    error_string_n: unsafe extern "C" fn(c_ulong, *mut c_char, usize),
+    verify_error_string: unsafe extern "C" fn(c_long) -> *const c_char,
+}
+
+fn dlerror() -> String {
+    let err = unsafe { nix::libc::dlerror() };
+    if err.is_null() {
+        return "unknown error".to_string();
+    }
+    unsafe { CStr::from_ptr(err) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Library {
+    fn load() -> Result<Self, String> {
+        let handle = LIBRARIES
+            .iter()
+            .map(|name| unsafe {
+                nix::libc::dlopen(
+                    name.as_ptr() as *const c_char,
+                    nix::libc::RTLD_NOW | nix::libc::RTLD_LOCAL,
+                )
+            })
+            .find(|handle| !handle.is_null())
+            .ok_or_else(dlerror)?;
+        // Symbols of libcrypto are found through libssl depending on it
+        let symbol = |name: &str| {
+            let symbol = CString::new(name).unwrap();
+            let address = unsafe { nix::libc::dlsym(handle, symbol.as_ptr()) };
+            if address.is_null() {
+                Err(dlerror())
+            } else {
+                Ok(address)
+            }
+        };
+        // The symbols are the functions declared by the fields they are assigned to
+        unsafe {
+            Ok(Self {
+                client_method: std::mem::transmute_copy(&symbol("TLS_client_method")?),
+                ctx_new: std::mem::transmute_copy(&symbol("SSL_CTX_new")?),
+                ctx_set_default_verify_paths: std::mem::transmute_copy(&symbol(
+                    "SSL_CTX_set_default_verify_paths",
+                )?),
+                ctx_set_verify: std::mem::transmute_copy(&symbol("SSL_CTX_set_verify")?),
+                ctx_free: std::mem::transmute_copy(&symbol("SSL_CTX_free")?),
+                new: std::mem::transmute_copy(&symbol("SSL_new")?),
+                set_fd: std::mem::transmute_copy(&symbol("SSL_set_fd")?),
+                set1_host: std::mem::transmute_copy(&symbol("SSL_set1_host")?),
+                get0_param: std::mem::transmute_copy(&symbol("SSL_get0_param")?),
+                param_set1_ip_asc: std::mem::transmute_copy(&symbol(
+                    "X509_VERIFY_PARAM_set1_ip_asc",
+                )?),
+                ctrl: std::mem::transmute_copy(&symbol("SSL_ctrl")?),
+                connect: std::mem::transmute_copy(&symbol("SSL_connect")?),
+                read: std::mem::transmute_copy(&symbol("SSL_read")?),
+                write: std::mem::transmute_copy(&symbol("SSL_write")?),
+                get_error: std::mem::transmute_copy(&symbol("SSL_get_error")?),
+                get_verify_result: std::mem::transmute_copy(&symbol("SSL_get_verify_result")?),
+                free: std::mem::transmute_copy(&symbol("SSL_free")?),
+                clear_error: std::mem::transmute_copy(&symbol("ERR_clear_error")?),
+                get_error_code: std::mem::transmute_copy(&symbol("ERR_get_error")?),
+                error_string_n: std::mem::transmute_copy(&symbol("ERR_error_string_n")?),
+                verify_error_string: std::mem::transmute_copy(&symbol(
+                    "X509_verify_cert_error_string",
+                )?),
+            })
+        }
+    }
+
+    // The errors OpenSSL queued for this thread, oldest first
+    fn errors(&self) -> String {
+        let mut errors = Vec::new();
+        loop {
+            let code = unsafe { (self.get_error_code)() };
+            if code == 0 {
+                break;
+            }
+            let mut buf = [0 as c_char; 256];
+            unsafe { (self.error_string_n)(code, buf.as_mut_ptr(), buf.len()) };
+            errors.push(
+                unsafe { CStr::from_ptr(buf.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        if errors.is_empty() {
+            "unknown error".to_owned()
+        } else {
+            errors.join(", ")
+        }
+    }
+}
+
+fn library() -> Result<&'static Library, String> {
+    static LIBRARY: OnceLock<Result<Library, String>> = OnceLock::new();
+    LIBRARY
+        .get_or_init(Library::load)
+        .as_ref()
+        .map_err(|err| format!("failed to load libssl: {}", err))
+}
+
+// A TLS client connection over a TCP stream, verifying the server's certificate against the
+// system's trusted certificates (or SSL_CERT_FILE and SSL_CERT_DIR) and host name. Reads and
+// writes time out like the stream's.
+pub struct TlsStream {
+    library: &'static Library,
+    ctx: *mut Context,
+    ssl: *mut Ssl,
+    // Closed once the connection using it is freed
+    stream: TcpStream,
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        unsafe {
+            (self.library.free)(self.ssl);
+            (self.library.ctx_free)(self.ctx);
+        }
+    }
+}
+
+impl TlsStream {
+    pub fn connect(stream: TcpStream, host: &str) -> Result<Self, String> {
+        let library = library()?;
+        let host_c = CString::new(host).map_err(|_| format!("invalid host name {:?}", host))?;
+        unsafe {
+            (library.clear_error)();
+            let ctx = (library.ctx_new)((library.client_method)());
+            if ctx.is_null() {
+                return Err(format!("failed to set up TLS: {}", library.errors()));
+            }
+            (library.ctx_set_verify)(ctx, SSL_VERIFY_PEER, std::ptr::null());
+            if (library.ctx_set_default_verify_paths)(ctx) != 1 {
+                let err = library.errors();
+                (library.ctx_free)(ctx);
+                return Err(format!("failed to load trusted certificates: {}", err));
+            }
+            let ssl = (library.new)(ctx);
+            if ssl.is_null() {
+                let err = library.errors();
+                (library.ctx_free)(ctx);
+                return Err(format!("failed to set up TLS: {}", err));
+            }
+            let tls = Self {
+                library,
+                ctx,
+                ssl,
+                stream,
+            };
+            // Addresses are verified against the certificate's IP addresses, and aren't sent
+            // as server name
+            let named = if host.parse::<IpAddr>().is_ok() {
+                (library.param_set1_ip_asc)((library.get0_param)(ssl), host_c.as_ptr()) == 1
+            } else {
+                (library.set1_host)(ssl, host_c.as_ptr()) == 1
+                    && (library.ctrl)(
+                        ssl,
+                        SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                        TLSEXT_NAMETYPE_HOST_NAME,
+                        host_c.as_ptr() as *mut c_void,
+                    ) == 1
+            };
+            if !named || (library.set_fd)(ssl, tls.stream.as_raw_fd()) != 1 {
+                return Err(format!("failed to set up TLS: {}", library.errors()));
+            }
+            let res = (library.connect)(ssl);
+            if res != 1 {
+                let verified = (library.get_verify_result)(ssl);
+                if verified != X509_V_OK {
+                    let reason = CStr::from_ptr((library.verify_error_string)(verified));
+                    return Err(format!(
+                        "certificate verification failed: {}",
+                        reason.to_string_lossy()
+                    ));
+                }
+                return Err(format!("TLS handshake failed: {}", tls.error(res)));
+            }
+            Ok(tls)
+        }
+    }
+
+    // The error of a failed read, write or handshake returning res
+    fn error(&self, res: c_int) -> io::Error {
+        match unsafe { (self.library.get_error)(self.ssl, res) } {
+            // Blocking sockets only need to be retried once their timeout passed
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => {
+                io::Error::new(io::ErrorKind::TimedOut, "timed out")
+            }
+            SSL_ERROR_SYSCALL => match io::Error::last_os_error() {
+                err if err.raw_os_error() != Some(0) => err,
+                _ => io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"),
+            },
+            _ => io::Error::other(self.library.errors()),
+        }
+    }
+}
+
+impl io::Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        unsafe { (self.library.clear_error)() };
+        let res = unsafe { (self.library.read)(self.ssl, buf.as_mut_ptr() as *mut c_void, len) };
+        if res > 0 {
+            return Ok(res as usize);
+        }
+        match unsafe { (self.library.get_error)(self.ssl, res) } {
+            SSL_ERROR_ZERO_RETURN => Ok(0),
+            _ => Err(self.error(res)),
+        }
+    }
+}
+
+impl io::Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        unsafe { (self.library.clear_error)() };
+        let res = unsafe { (self.library.write)(self.ssl, buf.as_ptr() as *const c_void, len) };
+        if res > 0 {
+            Ok(res as usize)
+        } else {
+            Err(self.error(res))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,681 @@
+use crate::ether;
+use crate::iface::{PacketIo, PacketSocket};
+use crate::link::{BpfInsn, LinkAddr, Membership};
+use crate::pcap;
+use eui48::MacAddress;
+use nix::poll::PollFd;
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::unistd::close;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+// Operations, flags, features and offsets, from linux/io_uring.h
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_POLL_REMOVE: u8 = 7;
+const IORING_OP_SENDMSG: u8 = 9;
+const IORING_OP_RECVMSG: u8 = 10;
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_ENTER_EXT_ARG: u32 = 8;
+const IORING_FEAT_EXT_ARG: u32 = 1 << 8;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+
+// Receives kept in flight, and sends in flight before sending on the socket directly
+const RECVS: usize = 16;
+const SENDS: usize = 64;
+const FRAME_LEN: usize = 1600;
+const SOCKET_ENTRIES: u32 = 128;
+// Polls are removed and added again each time, so this waits for half as many descriptors
+const POLL_ENTRIES: u32 = 1024;
+// The user data of operations whose completion is of no interest
+const IGNORED: u64 = u64::MAX;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    // The flags of the operation, like msg_flags or poll32_events
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    pad: u32,
+    ts: u64,
+}
+
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        let _ = close(self.0);
+    }
+}
+
+// Part of the ring's file mapped into memory, unmapped on drop
+struct Mapping {
+    base: NonNull<u8>,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> nix::Result<Self> {
+        let base = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED | MapFlags::MAP_POPULATE,
+                fd,
+                offset,
+            )?
+        };
+        Ok(Self {
+            base: NonNull::new(base as *mut u8).ok_or(nix::Error::ENOMEM)?,
+            len,
+        })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.base.as_ptr().add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.base.as_ptr() as *mut _, self.len) };
+    }
+}
+
+// An io_uring instance, with its submission queue filled and its completion queue emptied
+// by a single thread at a time
+struct Uring {
+    fd: Fd,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    // Operations queued since last submitted
+    queued: Cell<u32>,
+    _mappings: [Mapping; 3],
+}
+
+impl Uring {
+    fn new(entries: u32) -> nix::Result<Self> {
+        let mut params = Params::default();
+        let res = unsafe {
+            nix::libc::syscall(
+                nix::libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        let fd = Fd(nix::Error::result(res as nix::libc::c_int)?);
+        // Waiting with a timeout needs Linux 5.11
+        if params.features & IORING_FEAT_EXT_ARG == 0 {
+            return Err(nix::Error::EOPNOTSUPP);
+        }
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        let sq = Mapping::new(fd.0, sq_len, IORING_OFF_SQ_RING)?;
+        let cq = Mapping::new(fd.0, cq_len, IORING_OFF_CQ_RING)?;
+        let sqes = Mapping::new(fd.0, sqes_len, IORING_OFF_SQES)?;
+        let (sq_off, cq_off) = (&params.sq_off, &params.cq_off);
+        Ok(Self {
+            sq_head: sq.at(sq_off.head),
+            sq_tail: sq.at(sq_off.tail),
+            sq_mask: unsafe { *sq.at::<u32>(sq_off.ring_mask) },
+            sq_entries: params.sq_entries,
+            sq_array: sq.at(sq_off.array),
+            sqes: sqes.at(0),
+            cq_head: cq.at(cq_off.head),
+            cq_tail: cq.at(cq_off.tail),
+            cq_mask: unsafe { *cq.at::<u32>(cq_off.ring_mask) },
+            cqes: cq.at(cq_off.cqes),
+            queued: Cell::new(0),
+            fd,
+            _mappings: [sq, cq, sqes],
+        })
+    }
+
+    // Queue an operation to be submitted, returning false when the queue is full
+    fn push(&self, sqe: Sqe) -> bool {
+        let (head, tail) = unsafe {
+            (
+                (*self.sq_head).load(Ordering::Acquire),
+                (*self.sq_tail).load(Ordering::Relaxed),
+            )
+        };
+        if tail.wrapping_sub(head) == self.sq_entries {
+            return false;
+        }
+        let index = tail & self.sq_mask;
+        unsafe {
+            *self.sqes.add(index as usize) = sqe;
+            *self.sq_array.add(index as usize) = index;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.queued.set(self.queued.get() + 1);
+        true
+    }
+
+    // The next completion, if any
+    fn pop(&self) -> Option<Cqe> {
+        let (head, tail) = unsafe {
+            (
+                (*self.cq_head).load(Ordering::Relaxed),
+                (*self.cq_tail).load(Ordering::Acquire),
+            )
+        };
+        if head == tail {
+            return None;
+        }
+        let cqe = unsafe { *self.cqes.add((head & self.cq_mask) as usize) };
+        unsafe { (*self.cq_head).store(head.wrapping_add(1), Ordering::Release) };
+        Some(cqe)
+    }
+
+    fn has_completions(&self) -> bool {
+        unsafe {
+            (*self.cq_head).load(Ordering::Relaxed) != (*self.cq_tail).load(Ordering::Acquire)
+        }
+    }
+
+    // Submit the queued operations and, when waiting, wait for a completion up to the
+    // timeout (forever when None)
+    fn enter(&self, wait: bool, timeout: Option<Duration>) -> nix::Result<()> {
+        if !wait && self.queued.get() == 0 {
+            return Ok(());
+        }
+        let ts = timeout.map(|timeout| nix::libc::timespec {
+            tv_sec: timeout.as_secs() as nix::libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as nix::libc::c_long,
+        });
+        let arg = GeteventsArg {
+            sigmask: 0,
+            sigmask_sz: 0,
+            pad: 0,
+            ts: ts.as_ref().map_or(0, |ts| ts as *const _ as u64),
+        };
+        let (min_complete, flags) = match wait {
+            true => (1, IORING_ENTER_GETEVENTS | IORING_ENTER_EXT_ARG),
+            false => (0, IORING_ENTER_EXT_ARG),
+        };
+        let res = unsafe {
+            nix::libc::syscall(
+                nix::libc::SYS_io_uring_enter,
+                self.fd.0,
+                self.queued.get(),
+                min_complete,
+                flags,
+                &arg as *const GeteventsArg,
+                std::mem::size_of_val(&arg),
+            )
+        };
+        match nix::Error::result(res as nix::libc::c_int) {
+            Ok(submitted) => {
+                self.queued.set(self.queued.get() - submitted as u32);
+                Ok(())
+            }
+            // The timeout passed without completions
+            Err(nix::Error::ETIME) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// Waits for file descriptors like poll(2) through one-shot polls on a ring, together with
+// the completions of any other rings polled, like those of sockets. Polls still armed
+// are removed before adding new ones, as descriptors may have been closed and reused.
+struct Poller {
+    ring: Uring,
+    generation: Cell<u32>,
+    // The user data of polls added but not completed yet
+    armed: RefCell<Vec<u64>>,
+}
+
+thread_local! {
+    // Each thread waits through a ring of its own, or poll(2) when it can't have one
+    static POLLER: Option<Poller> = Uring::new(POLL_ENTRIES).ok().map(|ring| Poller {
+        ring,
+        generation: Cell::new(0),
+        armed: RefCell::default(),
+    });
+}
+
+impl Poller {
+    fn poll(&self, fds: &mut [PollFd], timeout: Option<Duration>) -> nix::Result<usize> {
+        let pollfds = unsafe {
+            std::slice::from_raw_parts_mut(fds.as_mut_ptr() as *mut nix::libc::pollfd, fds.len())
+        };
+        let generation = self.generation.get().wrapping_add(1);
+        self.generation.set(generation);
+        self.reap(generation, pollfds);
+        let mut armed = self.armed.borrow_mut();
+        for user_data in armed.drain(..) {
+            self.ring.push(Sqe {
+                opcode: IORING_OP_POLL_REMOVE,
+                fd: -1,
+                addr: user_data,
+                user_data: IGNORED,
+                ..Sqe::default()
+            });
+        }
+        for (index, pollfd) in pollfds.iter_mut().enumerate() {
+            pollfd.revents = 0;
+            let user_data = u64::from(generation) << 32 | index as u64;
+            self.ring.push(Sqe {
+                opcode: IORING_OP_POLL_ADD,
+                fd: pollfd.fd,
+                op_flags: pollfd.events as u32,
+                user_data,
+                ..Sqe::default()
+            });
+            armed.push(user_data);
+        }
+        drop(armed);
+
+        // Submitted before waiting, as removals complete right away and an interrupted wait
+        // only reports EINTR when nothing was submitted
+        self.ring.enter(false, None)?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let ready = self.reap(generation, pollfds);
+            let remaining = deadline.map(|at| at.saturating_duration_since(Instant::now()));
+            if ready > 0 || remaining == Some(Duration::ZERO) {
+                return Ok(ready);
+            }
+            self.ring.enter(true, remaining)?;
+        }
+    }
+
+    // Handle the completions so far, returning the number of descriptors of this
+    // generation that are ready
+    fn reap(&self, generation: u32, pollfds: &mut [nix::libc::pollfd]) -> usize {
+        let mut armed = self.armed.borrow_mut();
+        while let Some(cqe) = self.ring.pop() {
+            if cqe.user_data == IGNORED {
+                continue;
+            }
+            armed.retain(|user_data| *user_data != cqe.user_data);
+            let index = (cqe.user_data & 0xffff_ffff) as usize;
+            if (cqe.user_data >> 32) as u32 == generation && index < pollfds.len() {
+                pollfds[index].revents = match cqe.res {
+                    res if res >= 0 => res as i16,
+                    _ => nix::libc::POLLERR,
+                };
+            }
+        }
+        pollfds.iter().filter(|pollfd| pollfd.revents != 0).count()
+    }
+}
+
+// Wait like poll(2) through the thread's ring
+fn poll(fds: &mut [PollFd], timeout: nix::libc::c_int) -> nix::Result<nix::libc::c_int> {
+    let timeout = u64::try_from(timeout).ok().map(Duration::from_millis);
+    POLLER.with(|poller| match poller {
+        // Each descriptor takes a removal and a poll at most
+        Some(poller) if fds.len() * 2 <= POLL_ENTRIES as usize => poller
+            .poll(fds, timeout)
+            .map(|ready| ready as nix::libc::c_int),
+        _ => nix::poll::poll(
+            fds,
+            timeout.map_or(-1, |timeout| timeout.as_millis() as nix::libc::c_int),
+        ),
+    })
+}
+
+// A buffer with the message pointing at it, of a receive or send in flight
+struct Slot {
+    frame: [u8; FRAME_LEN],
+    addr: nix::libc::sockaddr_ll,
+    iov: nix::libc::iovec,
+    msg: nix::libc::msghdr,
+}
+
+// A packet socket receiving and sending through an io_uring instance of its own instead of
+// a system call per frame, keeping receives in flight so frames are received as soon as
+// they arrive, and polling readable when they were. Combined with waiting through a ring
+// (PacketIo::poll), a thread waits for timeouts, other descriptors and the completions of
+// all its sockets with a single system call.
+pub struct UringSocket {
+    socket: PacketSocket,
+    ring: Uring,
+    // The first RECVS receive, the others send
+    slots: Box<[UnsafeCell<Slot>]>,
+    // Send slots not in flight
+    free: RefCell<Vec<usize>>,
+    deferred: Cell<bool>,
+}
+
+// The ring and slots are only used through the socket they belong to
+unsafe impl Send for UringSocket {}
+
+impl AsRawFd for UringSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring.fd.0
+    }
+}
+
+impl Drop for UringSocket {
+    // The kernel writes into the slots of receives in flight, so they're only freed once
+    // cancelled
+    fn drop(&mut self) {
+        for slot in 0..RECVS {
+            self.ring.push(Sqe {
+                opcode: IORING_OP_ASYNC_CANCEL,
+                fd: -1,
+                addr: slot as u64,
+                user_data: IGNORED,
+                ..Sqe::default()
+            });
+        }
+        let mut receiving = RECVS;
+        let mut sending = SENDS - self.free.borrow().len();
+        while receiving + sending > 0 {
+            if self.ring.enter(true, None).is_err() {
+                std::mem::forget(std::mem::take(&mut self.slots));
+                return;
+            }
+            while let Some(cqe) = self.ring.pop() {
+                match cqe.user_data as usize {
+                    slot if slot < RECVS => receiving -= 1,
+                    slot if slot < RECVS + SENDS => sending -= 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl UringSocket {
+    fn slot(&self, slot: usize) -> *mut Slot {
+        self.slots[slot].get()
+    }
+
+    // Point the slot's message at its buffer and address, of len bytes
+    fn message(&self, slot: usize, len: usize) -> u64 {
+        let slot = self.slot(slot);
+        unsafe {
+            (*slot).iov = nix::libc::iovec {
+                iov_base: (*slot).frame.as_mut_ptr() as *mut _,
+                iov_len: len,
+            };
+            let mut msg: nix::libc::msghdr = std::mem::zeroed();
+            msg.msg_name = &mut (*slot).addr as *mut _ as *mut _;
+            msg.msg_namelen = std::mem::size_of::<nix::libc::sockaddr_ll>() as nix::libc::socklen_t;
+            msg.msg_iov = &mut (*slot).iov;
+            msg.msg_iovlen = 1;
+            (*slot).msg = msg;
+            &(*slot).msg as *const _ as u64
+        }
+    }
+
+    fn receive(&self, slot: usize) {
+        let msg = self.message(slot, FRAME_LEN);
+        self.ring.push(Sqe {
+            opcode: IORING_OP_RECVMSG,
+            fd: self.socket.as_raw_fd(),
+            addr: msg,
+            len: 1,
+            user_data: slot as u64,
+            ..Sqe::default()
+        });
+    }
+
+    fn sent(&self, cqe: Cqe) {
+        let slot = cqe.user_data as usize;
+        if (RECVS..RECVS + SENDS).contains(&slot) {
+            self.free.borrow_mut().push(slot);
+            if cqe.res < 0 {
+                log::warn!("failed to send: {}", nix::Error::from_i32(-cqe.res));
+            }
+        }
+    }
+}
+
+impl PacketIo for UringSocket {
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        let socket = PacketSocket::open(link_addr, protocol, raw, vlan, min_len, filter, capture)?;
+        let ring = Uring::new(SOCKET_ENTRIES)?;
+        let slots = (0..RECVS + SENDS)
+            .map(|_| {
+                UnsafeCell::new(Slot {
+                    frame: [0; FRAME_LEN],
+                    addr: unsafe { std::mem::zeroed() },
+                    iov: unsafe { std::mem::zeroed() },
+                    msg: unsafe { std::mem::zeroed() },
+                })
+            })
+            .collect();
+        let uring = Self {
+            socket,
+            ring,
+            slots,
+            free: RefCell::new((RECVS..RECVS + SENDS).collect()),
+            deferred: Cell::new(false),
+        };
+        for slot in 0..RECVS {
+            uring.receive(slot);
+        }
+        uring.ring.enter(false, None)?;
+        Ok(uring)
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.socket.link_addr()
+    }
+
+    fn is_raw(&self) -> bool {
+        self.socket.is_raw()
+    }
+
+    fn set_filter(&self, filter: &[BpfInsn]) -> nix::Result<()> {
+        self.socket.set_filter(filter)
+    }
+
+    // Receives are received into again right away, and submitted together once there are
+    // no more completions, which is before polling again
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        let received = loop {
+            let cqe = match self.ring.pop() {
+                Some(cqe) => cqe,
+                None => break Err(nix::Error::EAGAIN),
+            };
+            let slot = cqe.user_data as usize;
+            if slot >= RECVS {
+                self.sent(cqe);
+                continue;
+            }
+            if cqe.res < 0 {
+                self.receive(slot);
+                break Err(nix::Error::from_i32(-cqe.res));
+            }
+            let len = (cqe.res as usize).min(buf.len());
+            let from = unsafe {
+                let slot = self.slot(slot);
+                buf[..len].copy_from_slice(&(&(*slot).frame)[..len]);
+                (*slot).addr
+            };
+            self.receive(slot);
+            break Ok((len, from));
+        };
+        if !self.ring.has_completions() {
+            self.ring.enter(false, None)?;
+        }
+        let (size, from) = received?;
+        self.socket.received(buf, size, LinkAddr::from(&from))
+    }
+
+    fn pending(&self) -> bool {
+        self.ring.has_completions()
+    }
+
+    fn defer_sends(&self, defer: bool) -> nix::Result<()> {
+        self.deferred.set(defer);
+        match defer {
+            true => Ok(()),
+            false => self.ring.enter(false, None),
+        }
+    }
+
+    // Frames are sent directly on the socket while all send slots are in flight
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        let (len, frame) = self.socket.outgoing(buf, src, to);
+        let slot = match self.free.borrow_mut().pop() {
+            Some(slot) if frame.len() <= FRAME_LEN => slot,
+            Some(slot) => {
+                self.free.borrow_mut().push(slot);
+                return self.socket.send_from(buf, src, to);
+            }
+            None => return self.socket.send_from(buf, src, to),
+        };
+        let msg = self.message(slot, frame.len());
+        unsafe {
+            let slot = self.slot(slot);
+            (&mut (*slot).frame)[..frame.len()].copy_from_slice(&frame);
+            (*slot).addr = to.into();
+        }
+        self.ring.push(Sqe {
+            opcode: IORING_OP_SENDMSG,
+            fd: self.socket.as_raw_fd(),
+            addr: msg,
+            len: 1,
+            user_data: slot as u64,
+            ..Sqe::default()
+        });
+        if !self.deferred.get() {
+            self.ring.enter(false, None)?;
+        }
+        self.socket.capture_sent(&frame, to);
+        Ok(frame.len().saturating_sub(len).min(buf.len()))
+    }
+
+    fn set_membership(&self, membership: Membership, join: bool) -> nix::Result<()> {
+        self.socket.set_membership(membership, join)
+    }
+
+    fn join_fanout(&self, id: Option<u16>) -> nix::Result<u16> {
+        self.socket.join_fanout(id)
+    }
+
+    fn set_busy_poll(&self, usecs: u32) -> nix::Result<()> {
+        self.socket.set_busy_poll(usecs)
+    }
+
+    fn poll(fds: &mut [PollFd], timeout: nix::libc::c_int) -> nix::Result<nix::libc::c_int> {
+        poll(fds, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::poll::PollFlags;
+
+    #[test]
+    fn poll_pipe() {
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let mut fds = [PollFd::new(read, PollFlags::POLLIN)];
+        let start = Instant::now();
+        assert_eq!(poll(&mut fds, 50), Ok(0));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Readable descriptors stay readable until read, like with poll(2)
+        nix::unistd::write(write, b"x").unwrap();
+        for _ in 0..2 {
+            assert_eq!(poll(&mut fds, -1), Ok(1));
+            assert_eq!(fds[0].revents(), Some(PollFlags::POLLIN));
+        }
+        nix::unistd::read(read, &mut [0u8; 1]).unwrap();
+        assert_eq!(poll(&mut fds, 0), Ok(0));
+        let _ = (close(read), close(write));
+    }
+}
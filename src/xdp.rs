@@ -0,0 +1,1131 @@
+use crate::ether;
+use crate::iface::{KernelAnswer, PacketIo, PacketSocket};
+use crate::link::{self, BpfInsn, LinkAddr, Membership};
+use crate::pcap;
+use eui48::MacAddress;
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::unistd::close;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+// Socket options and ring offsets of AF_XDP sockets, from linux/if_xdp.h
+const AF_XDP: nix::libc::c_int = 44;
+const SOL_XDP: nix::libc::c_int = 283;
+const XDP_MMAP_OFFSETS: nix::libc::c_int = 1;
+const XDP_RX_RING: nix::libc::c_int = 2;
+const XDP_TX_RING: nix::libc::c_int = 3;
+const XDP_UMEM_REG: nix::libc::c_int = 4;
+const XDP_UMEM_FILL_RING: nix::libc::c_int = 5;
+const XDP_UMEM_COMPLETION_RING: nix::libc::c_int = 6;
+const XDP_PGOFF_RX_RING: i64 = 0;
+const XDP_PGOFF_TX_RING: i64 = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: i64 = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: i64 = 0x1_8000_0000;
+
+// Commands, map and program types and helpers of the bpf system call, from linux/bpf.h
+const BPF_MAP_CREATE: nix::libc::c_int = 0;
+const BPF_MAP_UPDATE_ELEM: nix::libc::c_int = 2;
+const BPF_MAP_DELETE_ELEM: nix::libc::c_int = 3;
+const BPF_PROG_LOAD: nix::libc::c_int = 5;
+const BPF_LINK_CREATE: nix::libc::c_int = 28;
+const BPF_MAP_TYPE_HASH: u32 = 1;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_MAP_TYPE_LPM_TRIE: u32 = 11;
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_F_NO_PREALLOC: u32 = 1;
+const BPF_F_MMAPABLE: u32 = 1 << 10;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const XDP_PASS: i32 = 2;
+const XDP_TX: i32 = 3;
+
+// Instructions of eBPF programs
+const BPF_LDX_W: u8 = 0x61;
+const BPF_LDX_H: u8 = 0x69;
+const BPF_LDX_B: u8 = 0x71;
+const BPF_ST_W: u8 = 0x62;
+const BPF_ST_H: u8 = 0x6a;
+const BPF_STX_W: u8 = 0x63;
+const BPF_STX_H: u8 = 0x6b;
+const BPF_ATOMIC_DW: u8 = 0xdb;
+const BPF_ADD: i32 = 0;
+const BPF_LD_DW: u8 = 0x18;
+const BPF_MOV64_K: u8 = 0xb7;
+const BPF_MOV64_X: u8 = 0xbf;
+const BPF_ADD64_K: u8 = 0x07;
+const BPF_AND64_K: u8 = 0x57;
+const BPF_JEQ_K: u8 = 0x15;
+const BPF_JEQ_X: u8 = 0x1d;
+const BPF_JNE_K: u8 = 0x55;
+const BPF_JNE_X: u8 = 0x5d;
+const BPF_JGT_X: u8 = 0x2d;
+const BPF_CALL: u8 = 0x85;
+const BPF_EXIT: u8 = 0x95;
+
+// Frames received in the first half of the umem and sent from the second, with as many
+// descriptors in each ring as there are frames in a half
+const FRAME_SIZE: u64 = 2048;
+const RING_SIZE: u32 = 2048;
+const FRAME_NR: u64 = 2 * RING_SIZE as u64;
+// Binding to a queue still busy is retried for up to half a second
+const BIND_ATTEMPTS: u32 = 50;
+const BIND_RETRY: std::time::Duration = std::time::Duration::from_millis(10);
+// Most prefixes of claimed addresses taken away from the kernel, and most addresses it
+// answers for by itself
+const TRIE_SIZE: u32 = 4096;
+const ANSWERS_SIZE: u32 = 65536;
+
+#[repr(C)]
+struct UmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+    tx_metadata_len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct RingOffset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MmapOffsets {
+    rx: RingOffset,
+    tx: RingOffset,
+    fr: RingOffset,
+    cr: RingOffset,
+}
+
+#[repr(C)]
+struct SockaddrXdp {
+    family: u16,
+    flags: u16,
+    ifindex: u32,
+    queue_id: u32,
+    shared_umem_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct XdpDesc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+#[repr(C)]
+struct MapCreate {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct MapElem {
+    map_fd: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct ProgLoad {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+}
+
+#[repr(C)]
+struct LinkCreate {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+// An IPv4 prefix in an LPM trie, with the address in network byte order
+#[repr(C)]
+struct TrieKey {
+    prefix_len: u32,
+    addr: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+const fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    Insn {
+        code,
+        regs: src << 4 | dst,
+        off,
+        imm,
+    }
+}
+
+// A file descriptor closed when dropped
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        let _ = close(self.0);
+    }
+}
+
+fn bpf<T>(cmd: nix::libc::c_int, attr: &T) -> nix::Result<nix::libc::c_int> {
+    let res = unsafe {
+        nix::libc::syscall(
+            nix::libc::SYS_bpf,
+            cmd,
+            attr as *const T,
+            std::mem::size_of::<T>(),
+        )
+    };
+    nix::Error::result(res as nix::libc::c_int)
+}
+
+fn map_create(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+) -> nix::Result<Fd> {
+    let attr = MapCreate {
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        map_flags,
+    };
+    bpf(BPF_MAP_CREATE, &attr).map(Fd)
+}
+
+// Update an element, or delete it when there's no value
+fn map_update<K, V>(map: &Fd, key: &K, value: Option<&V>) -> nix::Result<()> {
+    let attr = MapElem {
+        map_fd: map.0 as u32,
+        key: key as *const K as u64,
+        value: value.map_or(0, |value| value as *const V as u64),
+        flags: 0,
+    };
+    let cmd = match value {
+        Some(_) => BPF_MAP_UPDATE_ELEM,
+        None => BPF_MAP_DELETE_ELEM,
+    };
+    bpf(cmd, &attr).map(drop)
+}
+
+// Instructions, with jumps to labels resolved once the program is complete
+#[derive(Default)]
+struct Asm {
+    insns: Vec<Insn>,
+    labels: HashMap<&'static str, usize>,
+    jumps: Vec<(usize, &'static str)>,
+}
+
+impl Asm {
+    fn push(&mut self, insns: &[Insn]) {
+        self.insns.extend_from_slice(insns);
+    }
+
+    fn jump(&mut self, insn: Insn, label: &'static str) {
+        self.jumps.push((self.insns.len(), label));
+        self.insns.push(insn);
+    }
+
+    fn label(&mut self, label: &'static str) {
+        self.labels.insert(label, self.insns.len());
+    }
+
+    fn ld_map(&mut self, dst: u8, map: &Fd) {
+        self.push(&[
+            insn(BPF_LD_DW, dst, BPF_PSEUDO_MAP_FD, 0, map.0),
+            insn(0, 0, 0, 0, 0),
+        ]);
+    }
+
+    // Look up the address at the offset in the frame, with the key on the stack at key
+    // (which for tries starts with the prefix length), leaving the value or 0 in r0
+    fn lookup(&mut self, map: &Fd, offset: i16, key: i32) {
+        self.push(&[
+            insn(BPF_LDX_W, 4, 6, offset, 0),
+            insn(BPF_STX_W, 10, 4, -4, 0),
+        ]);
+        self.ld_map(1, map);
+        self.push(&[
+            insn(BPF_MOV64_X, 2, 10, 0, 0),
+            insn(BPF_ADD64_K, 2, 0, 0, key),
+            insn(BPF_CALL, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM),
+        ]);
+    }
+
+    fn finish(mut self) -> Vec<Insn> {
+        for (at, label) in self.jumps {
+            self.insns[at].off = (self.labels[label] - at - 1) as i16;
+        }
+        self.insns
+    }
+}
+
+// Offsets of the ARP operation and addresses in untagged frames
+const ARP_OP: i16 = 20;
+const ARP_SHA: i16 = 22;
+const ARP_SPA: i16 = 28;
+const ARP_THA: i16 = 32;
+const ARP_TPA: i16 = 38;
+
+// The XDP program answering ARP requests for the addresses in the answers map by itself,
+// counting the replies in the replies map, and handing other untagged ARP packets about
+// addresses in the trie (by target or sender address) to the socket in the map for the
+// queue they were received on. Everything else is passed on to the kernel, including when
+// no socket is bound to the queue.
+fn program(maps: &Maps) -> Vec<Insn> {
+    let be = |value: u16| i32::from(u16::from_ne_bytes(value.to_be_bytes()));
+    let mut asm = Asm::default();
+    // The queue, and the frame if it holds a complete ARP packet for IPv4 over ethernet
+    asm.push(&[
+        insn(BPF_LDX_W, 7, 1, 16, 0),
+        insn(BPF_LDX_W, 2, 1, 0, 0),
+        insn(BPF_LDX_W, 3, 1, 4, 0),
+        insn(BPF_MOV64_X, 6, 2, 0, 0),
+        insn(BPF_ADD64_K, 2, 0, 0, 42),
+    ]);
+    asm.jump(insn(BPF_JGT_X, 2, 3, 0, 0), "pass");
+    asm.push(&[insn(BPF_LDX_H, 4, 6, 12, 0)]);
+    asm.jump(insn(BPF_JNE_K, 4, 0, 0, be(ether::ETH_P_ARP)), "pass");
+    asm.push(&[insn(BPF_LDX_H, 4, 6, 18, 0)]);
+    asm.jump(insn(BPF_JNE_K, 4, 0, 0, be(0x0604)), "pass");
+    asm.push(&[insn(BPF_ST_W, 10, 0, -8, 32)]);
+
+    // Requests that aren't probes or gratuitous, from a unicast sender in a frame of its
+    // own, for an answered address other than the sender's, which would be a conflict
+    asm.push(&[insn(BPF_LDX_H, 4, 6, ARP_OP, 0)]);
+    asm.jump(insn(BPF_JNE_K, 4, 0, 0, be(1)), "claimed");
+    asm.push(&[
+        insn(BPF_LDX_W, 4, 6, ARP_SPA, 0),
+        insn(BPF_LDX_W, 5, 6, ARP_TPA, 0),
+    ]);
+    asm.jump(insn(BPF_JEQ_K, 4, 0, 0, 0), "claimed");
+    asm.jump(insn(BPF_JEQ_X, 4, 5, 0, 0), "claimed");
+    asm.push(&[insn(BPF_LDX_B, 4, 6, 6, 0), insn(BPF_AND64_K, 4, 0, 0, 1)]);
+    asm.jump(insn(BPF_JNE_K, 4, 0, 0, 0), "claimed");
+    asm.push(&[
+        insn(BPF_LDX_W, 4, 6, 6, 0),
+        insn(BPF_LDX_W, 5, 6, ARP_SHA, 0),
+    ]);
+    asm.jump(insn(BPF_JNE_X, 4, 5, 0, 0), "claimed");
+    asm.push(&[
+        insn(BPF_LDX_H, 4, 6, 10, 0),
+        insn(BPF_LDX_H, 5, 6, ARP_SHA + 4, 0),
+    ]);
+    asm.jump(insn(BPF_JNE_X, 4, 5, 0, 0), "claimed");
+    asm.lookup(&maps.answers, ARP_TPA, -4);
+    asm.jump(insn(BPF_JEQ_K, 0, 0, 0, 0), "claimed");
+    asm.push(&[insn(BPF_MOV64_X, 8, 0, 0, 0)]);
+    asm.lookup(&maps.answers, ARP_SPA, -4);
+    asm.jump(insn(BPF_JNE_K, 0, 0, 0, 0), "claimed");
+
+    // Turn the request into the reply in place: the sender becomes the target, the answered
+    // MAC address the sender, and the frame goes back from the answer's source address
+    asm.push(&[
+        insn(BPF_ST_H, 6, 0, ARP_OP, be(2)),
+        insn(BPF_LDX_W, 4, 6, ARP_SHA, 0),
+        insn(BPF_STX_W, 6, 4, ARP_THA, 0),
+        insn(BPF_LDX_H, 4, 6, ARP_SHA + 4, 0),
+        insn(BPF_STX_H, 6, 4, ARP_THA + 4, 0),
+        insn(BPF_LDX_W, 4, 6, ARP_SPA, 0),
+        insn(BPF_LDX_W, 5, 6, ARP_TPA, 0),
+        insn(BPF_STX_W, 6, 5, ARP_SPA, 0),
+        insn(BPF_STX_W, 6, 4, ARP_TPA, 0),
+        insn(BPF_LDX_W, 4, 8, 0, 0),
+        insn(BPF_STX_W, 6, 4, ARP_SHA, 0),
+        insn(BPF_LDX_H, 4, 8, 4, 0),
+        insn(BPF_STX_H, 6, 4, ARP_SHA + 4, 0),
+        insn(BPF_LDX_W, 4, 8, 6, 0),
+        insn(BPF_STX_W, 6, 4, 6, 0),
+        insn(BPF_LDX_H, 4, 8, 10, 0),
+        insn(BPF_STX_H, 6, 4, 10, 0),
+        insn(BPF_LDX_W, 4, 6, ARP_THA, 0),
+        insn(BPF_STX_W, 6, 4, 0, 0),
+        insn(BPF_LDX_H, 4, 6, ARP_THA + 4, 0),
+        insn(BPF_STX_H, 6, 4, 4, 0),
+        insn(BPF_LDX_B, 4, 8, 12, 0),
+    ]);
+    asm.jump(insn(BPF_JEQ_K, 4, 0, 0, 0), "count");
+    asm.push(&[insn(BPF_ST_W, 6, 0, 0, -1), insn(BPF_ST_H, 6, 0, 4, -1)]);
+    asm.label("count");
+    asm.push(&[insn(BPF_ST_W, 10, 0, -4, 0)]);
+    asm.ld_map(1, &maps.replies);
+    asm.push(&[
+        insn(BPF_MOV64_X, 2, 10, 0, 0),
+        insn(BPF_ADD64_K, 2, 0, 0, -4),
+        insn(BPF_CALL, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM),
+    ]);
+    asm.jump(insn(BPF_JEQ_K, 0, 0, 0, 0), "send");
+    asm.push(&[
+        insn(BPF_MOV64_K, 1, 0, 0, 1),
+        insn(BPF_ATOMIC_DW, 0, 1, 0, BPF_ADD),
+    ]);
+    asm.label("send");
+    asm.push(&[
+        insn(BPF_MOV64_K, 0, 0, 0, XDP_TX),
+        insn(BPF_EXIT, 0, 0, 0, 0),
+    ]);
+
+    // ARP about claimed addresses
+    asm.label("claimed");
+    asm.lookup(&maps.trie, ARP_TPA, -8);
+    asm.jump(insn(BPF_JNE_K, 0, 0, 0, 0), "redirect");
+    asm.lookup(&maps.trie, ARP_SPA, -8);
+    asm.jump(insn(BPF_JEQ_K, 0, 0, 0, 0), "pass");
+    asm.label("redirect");
+    asm.ld_map(1, &maps.xsks);
+    asm.push(&[
+        insn(BPF_MOV64_X, 2, 7, 0, 0),
+        insn(BPF_MOV64_K, 3, 0, 0, XDP_PASS),
+        insn(BPF_CALL, 0, 0, 0, BPF_FUNC_REDIRECT_MAP),
+        insn(BPF_EXIT, 0, 0, 0, 0),
+    ]);
+    asm.label("pass");
+    asm.push(&[
+        insn(BPF_MOV64_K, 0, 0, 0, XDP_PASS),
+        insn(BPF_EXIT, 0, 0, 0, 0),
+    ]);
+    asm.finish()
+}
+
+// The CIDR prefixes covering exactly the addresses from first to last
+fn prefixes(first: Ipv4Addr, last: Ipv4Addr) -> Vec<(Ipv4Addr, u32)> {
+    let (mut first, last) = (u64::from(u32::from(first)), u64::from(u32::from(last)));
+    let mut prefixes = Vec::new();
+    while first <= last {
+        // The largest block aligned at first that ends within the range
+        let mut bits = first.trailing_zeros().min(32);
+        while first + (1 << bits) - 1 > last {
+            bits -= 1;
+        }
+        prefixes.push((Ipv4Addr::from(first as u32), 32 - bits));
+        first += 1 << bits;
+    }
+    prefixes
+}
+
+// A memory mapping, unmapped when dropped
+struct Mapping {
+    base: NonNull<u8>,
+    len: usize,
+}
+
+impl Mapping {
+    // Map part of the file, or anonymous memory without one
+    fn new(fd: Option<RawFd>, len: usize, offset: i64) -> nix::Result<Self> {
+        let flags = match fd {
+            Some(_) => MapFlags::MAP_SHARED | MapFlags::MAP_POPULATE,
+            None => MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
+        };
+        let base = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                flags,
+                fd.unwrap_or(-1),
+                offset,
+            )?
+        };
+        Ok(Self {
+            base: NonNull::new(base as *mut u8).ok_or(nix::Error::ENOMEM)?,
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.base.as_ptr() as *mut _, self.len) };
+    }
+}
+
+// A single producer, single consumer ring of descriptors shared with the kernel, which
+// produces on the receive and completion rings and consumes from the fill and transmit
+// rings
+struct XskRing {
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut u8,
+    size: u32,
+}
+
+impl XskRing {
+    fn map(
+        fd: RawFd,
+        offset: &RingOffset,
+        pgoff: i64,
+        entry: usize,
+    ) -> nix::Result<(Self, Mapping)> {
+        let len = offset.desc as usize + RING_SIZE as usize * entry;
+        let mapping = Mapping::new(Some(fd), len, pgoff)?;
+        let at = |offset: u64| unsafe { mapping.base.as_ptr().add(offset as usize) };
+        let ring = Self {
+            producer: at(offset.producer) as *const AtomicU32,
+            consumer: at(offset.consumer) as *const AtomicU32,
+            descs: at(offset.desc),
+            size: RING_SIZE,
+        };
+        Ok((ring, mapping))
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        unsafe { &*self.producer }
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        unsafe { &*self.consumer }
+    }
+
+    fn entry<T>(&self, index: u32) -> *mut T {
+        unsafe { (self.descs as *mut T).add((index % self.size) as usize) }
+    }
+
+    // The index of the next descriptor to consume, if any
+    fn peek(&self) -> Option<u32> {
+        let consumer = self.consumer().load(Ordering::Relaxed);
+        let producer = self.producer().load(Ordering::Acquire);
+        (producer != consumer).then_some(consumer)
+    }
+
+    fn consume(&self) {
+        let consumer = self.consumer().load(Ordering::Relaxed);
+        self.consumer()
+            .store(consumer.wrapping_add(1), Ordering::Release);
+    }
+
+    // The index of the next descriptor to produce, if there's room
+    fn reserve(&self) -> Option<u32> {
+        let producer = self.producer().load(Ordering::Relaxed);
+        let consumer = self.consumer().load(Ordering::Acquire);
+        (producer.wrapping_sub(consumer) < self.size).then_some(producer)
+    }
+
+    fn submit(&self) {
+        let producer = self.producer().load(Ordering::Relaxed);
+        self.producer()
+            .store(producer.wrapping_add(1), Ordering::Release);
+    }
+}
+
+fn set_option<T>(
+    fd: RawFd,
+    level: nix::libc::c_int,
+    option: nix::libc::c_int,
+    value: &T,
+) -> nix::Result<()> {
+    let res = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            level,
+            option,
+            value as *const T as *const nix::libc::c_void,
+            std::mem::size_of::<T>() as nix::libc::socklen_t,
+        )
+    };
+    nix::Error::result(res).map(drop)
+}
+
+// The maps of the XDP program
+struct Maps {
+    xsks: Fd,
+    trie: Fd,
+    answers: Fd,
+    // The number of replies sent, in memory mapped by userspace
+    replies: Fd,
+}
+
+// How the program answers for an address, as a value of its answers map
+#[repr(C)]
+struct AnswerValue {
+    mac: [u8; 6],
+    src: [u8; 6],
+    broadcast: u8,
+    _pad: [u8; 3],
+}
+
+// An AF_XDP socket bound to the first queue of an interface, which is where NICs steer ARP,
+// as it has no IP header or ports to spread by, with the XDP program handing it ARP about
+// the claimed addresses and answering requests it can answer by itself
+struct Xsk {
+    // Detaches the program when closed
+    _link: Fd,
+    // Polls both the socket and the packet socket the program passes other frames on to
+    epoll: Fd,
+    trie: Fd,
+    answers: Fd,
+    replies: NonNull<AtomicU64>,
+    // The number of replies sent when last read
+    replied: Cell<u64>,
+    answered: RefCell<BTreeMap<Ipv4Addr, KernelAnswer>>,
+    fd: Fd,
+    umem: NonNull<u8>,
+    rx: XskRing,
+    tx: XskRing,
+    fill: XskRing,
+    completion: XskRing,
+    // Frames of the second half of the umem not being sent
+    free: RefCell<Vec<u64>>,
+    // The number of frames sent while sends are deferred
+    deferred: Cell<Option<usize>>,
+    prefixes: RefCell<BTreeSet<(Ipv4Addr, u32)>>,
+    // The umem and rings, unmapped once the socket is closed
+    _mappings: Vec<Mapping>,
+}
+
+impl Xsk {
+    fn open(ifindex: usize, socket: RawFd) -> nix::Result<Self> {
+        let umem = Mapping::new(None, (FRAME_NR * FRAME_SIZE) as usize, 0)?;
+        let res =
+            unsafe { nix::libc::socket(AF_XDP, nix::libc::SOCK_RAW | nix::libc::SOCK_CLOEXEC, 0) };
+        let fd = Fd(nix::Error::result(res)?);
+        let reg = UmemReg {
+            addr: umem.base.as_ptr() as u64,
+            len: umem.len as u64,
+            chunk_size: FRAME_SIZE as u32,
+            headroom: 0,
+            flags: 0,
+            tx_metadata_len: 0,
+        };
+        set_option(fd.0, SOL_XDP, XDP_UMEM_REG, &reg)?;
+        for option in [
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            set_option(fd.0, SOL_XDP, option, &RING_SIZE)?;
+        }
+        let mut offsets = MmapOffsets::default();
+        let mut len = std::mem::size_of_val(&offsets) as nix::libc::socklen_t;
+        let res = unsafe {
+            nix::libc::getsockopt(
+                fd.0,
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut offsets as *mut _ as *mut nix::libc::c_void,
+                &mut len,
+            )
+        };
+        nix::Error::result(res)?;
+        let desc = std::mem::size_of::<XdpDesc>();
+        let addr = std::mem::size_of::<u64>();
+        let (rx, rx_mapping) = XskRing::map(fd.0, &offsets.rx, XDP_PGOFF_RX_RING, desc)?;
+        let (tx, tx_mapping) = XskRing::map(fd.0, &offsets.tx, XDP_PGOFF_TX_RING, desc)?;
+        let (fill, fill_mapping) = XskRing::map(fd.0, &offsets.fr, XDP_UMEM_PGOFF_FILL_RING, addr)?;
+        let (completion, completion_mapping) =
+            XskRing::map(fd.0, &offsets.cr, XDP_UMEM_PGOFF_COMPLETION_RING, addr)?;
+
+        // The kernel receives into the first half of the frames
+        for frame in 0..u64::from(RING_SIZE) {
+            let index = fill.reserve().ok_or(nix::Error::ENOBUFS)?;
+            unsafe { *fill.entry::<u64>(index) = frame * FRAME_SIZE };
+            fill.submit();
+        }
+        let addr = SockaddrXdp {
+            family: AF_XDP as u16,
+            flags: 0,
+            ifindex: ifindex as u32,
+            queue_id: 0,
+            shared_umem_fd: 0,
+        };
+        // The queue stays busy for a moment after the socket bound to it before is closed,
+        // e.g. when restarting
+        let mut attempts = BIND_ATTEMPTS;
+        loop {
+            let res = unsafe {
+                nix::libc::bind(
+                    fd.0,
+                    &addr as *const _ as *const nix::libc::sockaddr,
+                    std::mem::size_of_val(&addr) as nix::libc::socklen_t,
+                )
+            };
+            match nix::Error::result(res) {
+                Err(nix::Error::EBUSY) if attempts > 1 => {
+                    attempts -= 1;
+                    std::thread::sleep(BIND_RETRY);
+                }
+                res => break res.map(drop)?,
+            };
+        }
+
+        let maps = Maps {
+            xsks: map_create(BPF_MAP_TYPE_XSKMAP, 4, 4, 1, 0)?,
+            trie: map_create(
+                BPF_MAP_TYPE_LPM_TRIE,
+                std::mem::size_of::<TrieKey>() as u32,
+                1,
+                TRIE_SIZE,
+                BPF_F_NO_PREALLOC,
+            )?,
+            answers: map_create(
+                BPF_MAP_TYPE_HASH,
+                4,
+                std::mem::size_of::<AnswerValue>() as u32,
+                ANSWERS_SIZE,
+                BPF_F_NO_PREALLOC,
+            )?,
+            replies: map_create(BPF_MAP_TYPE_ARRAY, 4, 8, 1, BPF_F_MMAPABLE)?,
+        };
+        map_update(&maps.xsks, &0u32, Some(&(fd.0 as u32)))?;
+        let replies = Mapping::new(Some(maps.replies.0), 8, 0)?;
+        let insns = program(&maps);
+        let license = b"MIT\0";
+        let prog = Fd(bpf(
+            BPF_PROG_LOAD,
+            &ProgLoad {
+                prog_type: BPF_PROG_TYPE_XDP,
+                insn_cnt: insns.len() as u32,
+                insns: insns.as_ptr() as u64,
+                license: license.as_ptr() as u64,
+            },
+        )?);
+        let link = Fd(bpf(
+            BPF_LINK_CREATE,
+            &LinkCreate {
+                prog_fd: prog.0 as u32,
+                target_ifindex: ifindex as u32,
+                attach_type: BPF_XDP,
+                flags: 0,
+            },
+        )?);
+
+        let epoll = Fd(epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?);
+        for watched in [fd.0, socket] {
+            let mut event = EpollEvent::new(EpollFlags::EPOLLIN, 0);
+            epoll_ctl(epoll.0, EpollOp::EpollCtlAdd, watched, &mut event)?;
+        }
+        Ok(Self {
+            _link: link,
+            epoll,
+            trie: maps.trie,
+            answers: maps.answers,
+            replies: replies.base.cast(),
+            replied: Cell::new(0),
+            answered: RefCell::default(),
+            fd,
+            umem: umem.base,
+            rx,
+            tx,
+            fill,
+            completion,
+            free: RefCell::new(
+                (u64::from(RING_SIZE)..FRAME_NR)
+                    .map(|frame| frame * FRAME_SIZE)
+                    .collect(),
+            ),
+            deferred: Cell::new(None),
+            prefixes: RefCell::default(),
+            _mappings: vec![
+                rx_mapping,
+                tx_mapping,
+                fill_mapping,
+                completion_mapping,
+                umem,
+                replies,
+            ],
+        })
+    }
+
+    // Copy the next frame received into buf, handing its frame back to the kernel
+    fn recv(&self, buf: &mut [u8]) -> Option<usize> {
+        let index = self.rx.peek()?;
+        let desc = unsafe { *self.rx.entry::<XdpDesc>(index) };
+        let len = (desc.len as usize).min(buf.len());
+        buf[..len].copy_from_slice(unsafe {
+            std::slice::from_raw_parts(self.umem.as_ptr().add(desc.addr as usize), len)
+        });
+        self.rx.consume();
+
+        // There's room for every frame received into
+        if let Some(index) = self.fill.reserve() {
+            unsafe { *self.fill.entry::<u64>(index) = desc.addr - desc.addr % FRAME_SIZE };
+            self.fill.submit();
+        }
+        Some(len)
+    }
+
+    // Queue the frame for sending, returning false when all frames are being sent already
+    fn send(&self, frame: &[u8]) -> nix::Result<bool> {
+        let mut free = self.free.borrow_mut();
+        while let Some(index) = self.completion.peek() {
+            free.push(unsafe { *self.completion.entry::<u64>(index) });
+            self.completion.consume();
+        }
+        let len = frame.len().min(FRAME_SIZE as usize);
+        let (addr, index) = match (free.last(), self.tx.reserve()) {
+            (Some(addr), Some(index)) => (*addr, index),
+            _ => return Ok(false),
+        };
+        free.pop();
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                frame.as_ptr(),
+                self.umem.as_ptr().add(addr as usize),
+                len,
+            );
+            *self.tx.entry::<XdpDesc>(index) = XdpDesc {
+                addr,
+                len: len as u32,
+                options: 0,
+            };
+        }
+        self.tx.submit();
+        match self.deferred.get() {
+            Some(count) => self.deferred.set(Some(count + 1)),
+            None => self.kick()?,
+        }
+        Ok(true)
+    }
+
+    // Have the kernel send the queued frames
+    fn kick(&self) -> nix::Result<()> {
+        let res = unsafe {
+            nix::libc::sendto(
+                self.fd.0,
+                std::ptr::null(),
+                0,
+                nix::libc::MSG_DONTWAIT,
+                std::ptr::null(),
+                0,
+            )
+        };
+        match nix::Error::result(res) {
+            // Frames still queued are sent with the next ones
+            Err(nix::Error::EAGAIN | nix::Error::EBUSY | nix::Error::ENOBUFS) => Ok(()),
+            res => res.map(drop),
+        }
+    }
+
+    fn defer_sends(&self, defer: bool) -> nix::Result<()> {
+        let queued = self.deferred.replace(defer.then_some(0));
+        match queued {
+            Some(count) if count > 0 => self.kick(),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_ranges(&self, ranges: &[(Ipv4Addr, Ipv4Addr)]) -> nix::Result<()> {
+        let wanted: BTreeSet<_> = ranges
+            .iter()
+            .flat_map(|(first, last)| prefixes(*first, *last))
+            .collect();
+        if wanted.len() > TRIE_SIZE as usize {
+            return Err(nix::Error::E2BIG);
+        }
+        let key = |(addr, prefix_len): (Ipv4Addr, u32)| TrieKey {
+            prefix_len,
+            addr: addr.octets(),
+        };
+        let mut prefixes = self.prefixes.borrow_mut();
+        let stale: Vec<_> = prefixes.difference(&wanted).copied().collect();
+        for prefix in stale {
+            map_update::<_, u8>(&self.trie, &key(prefix), None)?;
+            prefixes.remove(&prefix);
+        }
+        for prefix in wanted {
+            if !prefixes.contains(&prefix) {
+                map_update(&self.trie, &key(prefix), Some(&1u8))?;
+                prefixes.insert(prefix);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_answers(&self, answers: &BTreeMap<Ipv4Addr, KernelAnswer>) -> nix::Result<()> {
+        if answers.len() > ANSWERS_SIZE as usize {
+            return Err(nix::Error::E2BIG);
+        }
+        let mut answered = self.answered.borrow_mut();
+        let stale: Vec<_> = answered
+            .keys()
+            .filter(|ip| !answers.contains_key(ip))
+            .copied()
+            .collect();
+        for ip in stale {
+            map_update::<_, u8>(&self.answers, &ip.octets(), None)?;
+            answered.remove(&ip);
+        }
+        for (ip, answer) in answers {
+            if answered.get(ip) != Some(answer) {
+                let mut value = AnswerValue {
+                    mac: [0; 6],
+                    src: [0; 6],
+                    broadcast: u8::from(answer.broadcast),
+                    _pad: [0; 3],
+                };
+                value.mac.copy_from_slice(answer.mac.as_bytes());
+                value.src.copy_from_slice(answer.src.as_bytes());
+                map_update(&self.answers, &ip.octets(), Some(&value))?;
+                answered.insert(*ip, *answer);
+            }
+        }
+        Ok(())
+    }
+
+    fn replies(&self) -> u64 {
+        let replies = unsafe { self.replies.as_ref() }.load(Ordering::Relaxed);
+        replies - self.replied.replace(replies)
+    }
+}
+
+// A packet socket with an AF_XDP socket next to it for the ARP packets about claimed
+// addresses, which an XDP program hands over before the kernel allocates anything for them
+// and which are received and sent through rings of frames in memory shared with the kernel
+// (the umem), for extremely high ARP rates. Other frames, like ARP in VLANs, in SNAP frames
+// or for other addresses, still reach the packet socket, as do the frames of other
+// protocols, whose sockets are packet sockets only. An interface only has one XDP program,
+// so only one responder can use it at a time.
+pub struct XdpSocket {
+    socket: PacketSocket,
+    xsk: Option<Xsk>,
+    min_len: usize,
+}
+
+// The socket and its rings are only accessed through the socket itself
+unsafe impl Send for XdpSocket {}
+
+impl AsRawFd for XdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.xsk
+            .as_ref()
+            .map_or(self.socket.as_raw_fd(), |xsk| xsk.epoll.0)
+    }
+}
+
+impl XdpSocket {
+    // The link address a frame received through the AF_XDP socket was sent from
+    fn sender(&self, frame: &[u8]) -> LinkAddr {
+        let link_addr = self.socket.link_addr();
+        let dst = MacAddress::from_bytes(&frame[..6]).unwrap_or_else(|_| MacAddress::nil());
+        LinkAddr {
+            mac: MacAddress::from_bytes(&frame[6..12]).unwrap_or_else(|_| MacAddress::nil()),
+            packet_type: link::packet_type(&link_addr, dst),
+            ..link_addr
+        }
+    }
+}
+
+impl PacketIo for XdpSocket {
+    // The AF_XDP socket receives and sends whole frames, so the packet socket next to it
+    // is raw too
+    fn open(
+        link_addr: &LinkAddr,
+        protocol: u16,
+        raw: bool,
+        vlan: Option<ether::Vlan>,
+        min_len: usize,
+        filter: &[BpfInsn],
+        capture: Option<pcap::Capture>,
+    ) -> nix::Result<Self> {
+        let arp = protocol == ether::ETH_P_ARP && vlan.is_none();
+        let socket = PacketSocket::open(
+            link_addr,
+            protocol,
+            raw || arp,
+            vlan,
+            min_len,
+            filter,
+            capture,
+        )?;
+        let xsk = if arp {
+            Some(Xsk::open(link_addr.ifindex, socket.as_raw_fd())?)
+        } else {
+            None
+        };
+        Ok(Self {
+            socket,
+            xsk,
+            min_len,
+        })
+    }
+
+    fn link_addr(&self) -> LinkAddr {
+        self.socket.link_addr()
+    }
+
+    fn is_raw(&self) -> bool {
+        self.socket.is_raw()
+    }
+
+    // Only frames reaching the packet socket are filtered, the program only hands over
+    // ARP for the ranges
+    fn set_filter(&self, filter: &[BpfInsn]) -> nix::Result<()> {
+        self.socket.set_filter(filter)
+    }
+
+    fn set_arp_ranges(&self, ranges: &[(Ipv4Addr, Ipv4Addr)]) -> nix::Result<()> {
+        match &self.xsk {
+            Some(xsk) => xsk.set_ranges(ranges),
+            None => Ok(()),
+        }
+    }
+
+    // Only untagged ARP is answered in the kernel
+    fn set_arp_answers(&self, answers: &BTreeMap<Ipv4Addr, KernelAnswer>) -> nix::Result<()> {
+        match &self.xsk {
+            Some(xsk) => xsk.set_answers(answers),
+            None => Err(nix::Error::EOPNOTSUPP),
+        }
+    }
+
+    fn kernel_replies(&self) -> u64 {
+        self.xsk.as_ref().map_or(0, Xsk::replies)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> nix::Result<(usize, LinkAddr, Option<ether::Header>)> {
+        if let Some(size) = self.xsk.as_ref().and_then(|xsk| xsk.recv(buf)) {
+            // The program only hands over complete ARP packets
+            let from = self.sender(&buf[..size]);
+            return self.socket.received(buf, size, from);
+        }
+        self.socket.recv_from(buf)
+    }
+
+    fn pending(&self) -> bool {
+        self.xsk.as_ref().is_some_and(|xsk| xsk.rx.peek().is_some()) || self.socket.pending()
+    }
+
+    fn defer_sends(&self, defer: bool) -> nix::Result<()> {
+        if let Some(xsk) = &self.xsk {
+            xsk.defer_sends(defer)?;
+        }
+        self.socket.defer_sends(defer)
+    }
+
+    // Sent through the packet socket when all frames of the umem are being sent already
+    fn send_from(&self, buf: &[u8], src: MacAddress, to: &LinkAddr) -> nix::Result<usize> {
+        let xsk = match &self.xsk {
+            Some(xsk) => xsk,
+            None => return self.socket.send_from(buf, src, to),
+        };
+        let link_addr = self.socket.link_addr();
+        let (_, frame) = link::raw_frame(&link_addr, None, self.min_len, buf, src, to);
+        if !xsk.send(&frame)? {
+            return self.socket.send_from(buf, src, to);
+        }
+        self.socket.write_capture(&frame);
+        Ok(buf.len())
+    }
+
+    fn set_membership(&self, membership: Membership, join: bool) -> nix::Result<()> {
+        self.socket.set_membership(membership, join)
+    }
+
+    // The program hands frames to a single socket per queue
+    fn join_fanout(&self, _id: Option<u16>) -> nix::Result<u16> {
+        Err(nix::Error::EOPNOTSUPP)
+    }
+
+    // The AF_XDP socket busy polls the queue it is bound to
+    fn set_busy_poll(&self, usecs: u32) -> nix::Result<()> {
+        self.socket.set_busy_poll(usecs)?;
+        match &self.xsk {
+            Some(xsk) => set_option(
+                xsk.fd.0,
+                nix::libc::SOL_SOCKET,
+                nix::libc::SO_BUSY_POLL,
+                &usecs,
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_to_prefixes() {
+        let addr = |addr: &str| addr.parse::<Ipv4Addr>().unwrap();
+        assert_eq!(
+            prefixes(addr("10.0.0.5"), addr("10.0.0.5")),
+            [(addr("10.0.0.5"), 32)]
+        );
+        assert_eq!(
+            prefixes(addr("10.0.0.1"), addr("10.0.0.8")),
+            [
+                (addr("10.0.0.1"), 32),
+                (addr("10.0.0.2"), 31),
+                (addr("10.0.0.4"), 30),
+                (addr("10.0.0.8"), 32),
+            ]
+        );
+        assert_eq!(
+            prefixes(addr("0.0.0.0"), addr("255.255.255.255")),
+            [(addr("0.0.0.0"), 0)]
+        );
+        assert_eq!(
+            prefixes(addr("255.255.255.254"), addr("255.255.255.255")),
+            [(addr("255.255.255.254"), 31)]
+        );
+    }
+
+    #[test]
+    fn ring() {
+        // The producer and consumer, followed by two descriptors
+        let mut memory = vec![0u64; 4];
+        let base = memory.as_mut_ptr();
+        let ring = XskRing {
+            producer: base as *const AtomicU32,
+            consumer: unsafe { base.add(1) } as *const AtomicU32,
+            descs: unsafe { base.add(2) } as *mut u8,
+            size: 2,
+        };
+        assert_eq!(ring.peek(), None);
+        for value in [7u64, 8] {
+            let index = ring.reserve().unwrap();
+            unsafe { *ring.entry::<u64>(index) = value };
+            ring.submit();
+        }
+        assert_eq!(ring.reserve(), None);
+
+        // Indexes wrap around the descriptors
+        let index = ring.peek().unwrap();
+        assert_eq!(unsafe { *ring.entry::<u64>(index) }, 7);
+        ring.consume();
+        let index = ring.reserve().unwrap();
+        unsafe { *ring.entry::<u64>(index) = 9 };
+        ring.submit();
+        let values: Vec<u64> = std::iter::from_fn(|| {
+            let index = ring.peek()?;
+            ring.consume();
+            Some(unsafe { *ring.entry::<u64>(index) })
+        })
+        .collect();
+        assert_eq!(values, [8, 9]);
+    }
+}